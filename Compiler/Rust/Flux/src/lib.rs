@@ -0,0 +1,13618 @@
+// Flux Programming Language Compiler
+// An advanced compiler with unique features including immutable dynamic typing,
+// flexible OOP, syntax pragma control, and temporal variable tracking
+
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// LEXER - Tokenization
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenType {
+    // Literals
+    Number(f64),
+    String(String),
+    /// Contents of a `'...'` literal; the parser checks it's one character
+    Char(String),
+    Boolean(bool),
+    Identifier(String),
+    
+    // Keywords
+    Let, Const, Func, Return, If, Else, While, For, Loop, Do, Break,
+    Class, Extends, New, This, Super,
+    Import, Export, Match, Case, Default,
+    Temporal, Freeze, Thaw, Timeline, On, Macro, Comptime, Spawn, Defer,
+    
+    // Operators
+    Plus, Minus, Multiply, Divide, Modulo,
+    Assign, Equal, NotEqual, Less, Greater,
+    LessEqual, GreaterEqual, And, Or, Not,
+    Arrow, FatArrow, Pipe, Compose,
+    
+    // Delimiters
+    LeftParen, RightParen, LeftBrace, RightBrace,
+    LeftBracket, RightBracket, Comma, Semicolon, ParallelPipe,
+    Colon, Dot, DotDot, Question, Bang,
+    
+    // Special
+    Newline, Indent, Dedent, EOF,
+    Pragma(String),
+    DocComment(String),
+}
+
+/// Where a token starts: 1-based line and display column
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+pub struct Lexer {
+    input: Vec<char>,
+    position: usize,
+    current_char: Option<char>,
+    line: usize,
+    column: usize,
+    use_braces: bool,
+    #[allow(dead_code)] // Reserved for indentation-based block tracking
+    indent_stack: Vec<usize>,
+    /// Characters that start no token; they're skipped
+    unexpected: Vec<(Span, char)>,
+    /// Flags `#pragma if` conditions test, e.g. `debug` => `true`
+    defines: HashMap<String, String>,
+    /// Open `#pragma if` sections, innermost last
+    conditionals: Vec<Conditional>,
+    /// Malformed or unbalanced conditional pragmas
+    errors: Vec<(Span, String)>,
+}
+
+/// One open `#pragma if` ... `#pragma endif` section
+struct Conditional {
+    start: Span,
+    /// Whether the code around the section is being kept at all
+    enclosing_active: bool,
+    /// Whether the branch being read is kept
+    active: bool,
+    seen_else: bool,
+}
+
+impl Lexer {
+    pub fn new(input: &str) -> Self {
+        let chars: Vec<char> = input.chars().collect();
+        let current_char = chars.first().copied();
+        
+        Self {
+            input: chars,
+            position: 0,
+            current_char,
+            line: 1,
+            column: 1,
+            use_braces: true, // Default to braces
+            indent_stack: vec![0],
+            unexpected: Vec::new(),
+            defines: HashMap::new(),
+            conditionals: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+    
+    /// Flags for `#pragma if`; a flag is set unless it's missing or
+    /// `false`, `0` or empty
+    pub fn with_defines(mut self, defines: HashMap<String, String>) -> Self {
+        self.defines = defines;
+        self
+    }
+    
+    fn advance(&mut self) {
+        match self.current_char {
+            Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+            }
+            Some(ch) => self.column += Self::display_width(ch),
+            None => {}
+        }
+        
+        self.position += 1;
+        self.current_char = self.input.get(self.position).copied();
+    }
+    
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.input.get(self.position + offset).copied()
+    }
+    
+    fn skip_whitespace(&mut self) {
+        while let Some(ch) = self.current_char {
+            if ch == ' ' || ch == '\t' || ch == '\r' {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+    }
+    
+    fn read_number(&mut self) -> f64 {
+        let mut number_str = String::new();
+        
+        while let Some(ch) = self.current_char {
+            // `1..5` is a range, not a malformed number
+            if ch.is_ascii_digit() || (ch == '.' && self.peek(1) != Some('.')) {
+                number_str.push(ch);
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        
+        number_str.parse().unwrap_or(0.0)
+    }
+    
+    fn read_string(&mut self) -> String {
+        self.read_quoted('"')
+    }
+    
+    fn read_quoted(&mut self, quote: char) -> String {
+        let mut string_val = String::new();
+        self.advance(); // Skip opening quote
+        
+        while let Some(ch) = self.current_char {
+            if ch == quote {
+                self.advance(); // Skip closing quote
+                break;
+            } else if ch == '\\' {
+                self.advance();
+                match self.current_char {
+                    Some('n') => string_val.push('\n'),
+                    Some('t') => string_val.push('\t'),
+                    Some('r') => string_val.push('\r'),
+                    Some('\\') => string_val.push('\\'),
+                    Some('"') => string_val.push('"'),
+                    Some(other) => string_val.push(other),
+                    None => break,
+                }
+                self.advance();
+            } else {
+                string_val.push(ch);
+                self.advance();
+            }
+        }
+        
+        string_val
+    }
+    
+    /// Identifiers follow Unicode's XID rules (UAX #31), approximated with
+    /// the standard library's character classes: a letter or `_`, then
+    /// letters, digits, combining marks and joiners
+    fn is_identifier_start(ch: char) -> bool {
+        ch == '_' || ch.is_alphabetic()
+    }
+    
+    fn is_identifier_continue(ch: char) -> bool {
+        Self::is_identifier_start(ch) || ch.is_numeric() || Self::is_combining_mark(ch)
+            || matches!(ch, '\u{200C}' | '\u{200D}')
+    }
+    
+    fn is_combining_mark(ch: char) -> bool {
+        matches!(ch, '\u{0300}'..='\u{036F}' | '\u{1AB0}'..='\u{1AFF}' | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}' | '\u{FE20}'..='\u{FE2F}')
+    }
+    
+    /// Terminal cells a character takes up, so reported columns match what
+    /// an editor shows: combining marks take none, East Asian wide
+    /// characters and emoji take two
+    fn display_width(ch: char) -> usize {
+        match ch {
+            _ if Self::is_combining_mark(ch) => 0,
+            '\u{200B}'..='\u{200D}' | '\u{FEFF}' => 0,
+            '\u{1100}'..='\u{115F}' | '\u{2E80}'..='\u{303E}' | '\u{3041}'..='\u{A4CF}'
+            | '\u{AC00}'..='\u{D7A3}' | '\u{F900}'..='\u{FAFF}' | '\u{FE30}'..='\u{FE4F}'
+            | '\u{FF00}'..='\u{FF60}' | '\u{FFE0}'..='\u{FFE6}' | '\u{1F300}'..='\u{1F64F}'
+            | '\u{1F900}'..='\u{1F9FF}' | '\u{20000}'..='\u{3FFFD}' => 2,
+            _ => 1,
+        }
+    }
+    
+    fn read_identifier(&mut self) -> String {
+        let mut identifier = String::new();
+        
+        while let Some(ch) = self.current_char {
+            if Self::is_identifier_continue(ch) {
+                identifier.push(ch);
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        
+        identifier
+    }
+    
+    /// Reads a `//` or `///` comment up to the end of the line, returning its
+    /// text without the slashes and the first leading space
+    fn read_line_comment(&mut self) -> String {
+        while self.current_char == Some('/') {
+            self.advance();
+        }
+        if self.current_char == Some(' ') {
+            self.advance();
+        }
+        
+        let mut comment = String::new();
+        while let Some(ch) = self.current_char {
+            if ch == '\n' {
+                break;
+            }
+            comment.push(ch);
+            self.advance();
+        }
+        
+        comment.trim_end().to_string()
+    }
+    
+    /// Whether code at the current position is kept rather than left out
+    /// by a `#pragma if`
+    fn active(&self) -> bool {
+        self.conditionals.last().is_none_or(|conditional| conditional.active)
+    }
+    
+    /// Applies `#pragma if <cond>`, `#pragma else` or `#pragma endif`;
+    /// returns false for any other pragma
+    fn conditional_pragma(&mut self, pragma_content: &str, start: Span) -> bool {
+        let (keyword, condition) = pragma_content.split_once(' ').unwrap_or((pragma_content, ""));
+        match keyword {
+            "if" => {
+                let enclosing_active = self.active();
+                let active = match self.evaluate_condition(condition.trim()) {
+                    Ok(holds) => enclosing_active && holds,
+                    Err(error) => {
+                        self.errors.push((start, error));
+                        false
+                    }
+                };
+                self.conditionals.push(Conditional { start, enclosing_active, active, seen_else: false });
+            }
+            "else" => match self.conditionals.last_mut() {
+                Some(conditional) if !conditional.seen_else => {
+                    conditional.seen_else = true;
+                    conditional.active = conditional.enclosing_active && !conditional.active;
+                }
+                Some(_) => self.errors.push((start, "Second `#pragma else` in one `#pragma if` section".to_string())),
+                None => self.errors.push((start, "`#pragma else` without a matching `#pragma if`".to_string())),
+            },
+            "endif" => {
+                if self.conditionals.pop().is_none() {
+                    self.errors.push((start, "`#pragma endif` without a matching `#pragma if`".to_string()));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+    
+    /// `name`, `!name`, `name == value` or `name != value`
+    fn evaluate_condition(&self, condition: &str) -> Result<bool, String> {
+        let value = |name: &str| self.defines.get(name.trim()).map(String::as_str).unwrap_or("");
+        let is_name = |name: &str| !name.is_empty() && name.chars().all(Lexer::is_identifier_continue);
+        
+        if let Some((name, expected)) = condition.split_once("==")
+            && is_name(name.trim())
+        {
+            return Ok(value(name) == expected.trim());
+        }
+        if let Some((name, expected)) = condition.split_once("!=")
+            && is_name(name.trim())
+        {
+            return Ok(value(name) != expected.trim());
+        }
+        let (negated, name) = match condition.strip_prefix('!') {
+            Some(name) => (true, name.trim()),
+            None => (false, condition),
+        };
+        if !is_name(name) {
+            return Err(format!("Invalid #pragma if condition '{}'", condition));
+        }
+        Ok(!matches!(value(name), "" | "false" | "0") != negated)
+    }
+    
+    fn handle_pragma(&mut self, pragma_content: &str) {
+        match pragma_content.trim() {
+            "braces" => self.use_braces = true,
+            "indent" | "no_braces" => self.use_braces = false,
+            _ => {} // Ignore unknown pragmas
+        }
+    }
+    
+    /// Tokens of the whole input; characters that start no token are
+    /// reported on stderr and skipped. Conditional pragma problems are left
+    /// in `errors`.
+    pub fn tokenize(&mut self) -> Vec<TokenType> {
+        let (tokens, _) = self.tokenize_with_spans();
+        for (span, ch) in &self.unexpected {
+            eprintln!("Unexpected character: {} at line {}, column {}", ch, span.line, span.column);
+        }
+        tokens
+    }
+    
+    /// Tokens along with where each one starts; skipped characters are left
+    /// in `unexpected` rather than printed
+    pub fn tokenize_with_spans(&mut self) -> (Vec<TokenType>, Vec<Span>) {
+        let mut tokens = Vec::new();
+        let mut spans = Vec::new();
+        
+        while self.current_char.is_some() {
+            let start = Span { line: self.line, column: self.column };
+            // Inside a section a `#pragma if` leaves out, only pragmas are read
+            if !self.active() && !matches!(self.current_char, Some(' ' | '\t' | '\r' | '#')) {
+                while self.current_char.is_some_and(|ch| ch != '\n') {
+                    self.advance();
+                }
+                self.advance();
+                continue;
+            }
+            match self.current_char.unwrap() {
+                ' ' | '\t' | '\r' => self.skip_whitespace(),
+                
+                '\n' => {
+                    if !self.use_braces {
+                        tokens.push(TokenType::Newline);
+                    }
+                    self.advance();
+                }
+                
+                '#' => {
+                    // Handle pragma or comments
+                    self.advance();
+                    if self.current_char == Some('p') {
+                        let pragma = self.read_identifier();
+                        if pragma == "pragma" {
+                            self.skip_whitespace();
+                            let mut pragma_content = self.read_identifier();
+                            // Arguments run to the end of the line
+                            while let Some(ch) = self.current_char.filter(|ch| *ch != '\n') {
+                                pragma_content.push(ch);
+                                self.advance();
+                            }
+                            let pragma_content = pragma_content.trim_end().to_string();
+                            if !self.conditional_pragma(&pragma_content, start) && self.active() {
+                                self.handle_pragma(&pragma_content);
+                                tokens.push(TokenType::Pragma(pragma_content));
+                            }
+                        }
+                    } else {
+                        // Skip comment
+                        while self.current_char.is_some() && self.current_char != Some('\n') {
+                            self.advance();
+                        }
+                    }
+                }
+                
+                '+' => {
+                    tokens.push(TokenType::Plus);
+                    self.advance();
+                }
+                
+                '-' => {
+                    self.advance();
+                    if self.current_char == Some('>') {
+                        tokens.push(TokenType::Arrow);
+                        self.advance();
+                    } else {
+                        tokens.push(TokenType::Minus);
+                    }
+                }
+                
+                '*' => {
+                    tokens.push(TokenType::Multiply);
+                    self.advance();
+                }
+                
+                '/' => {
+                    if self.peek(1) == Some('/') {
+                        let is_doc = self.peek(2) == Some('/') && self.peek(3) != Some('/');
+                        let comment = self.read_line_comment();
+                        if is_doc {
+                            tokens.push(TokenType::DocComment(comment));
+                        }
+                    } else {
+                        tokens.push(TokenType::Divide);
+                        self.advance();
+                    }
+                }
+                
+                '%' => {
+                    tokens.push(TokenType::Modulo);
+                    self.advance();
+                }
+                
+                '=' => {
+                    self.advance();
+                    if self.current_char == Some('=') {
+                        tokens.push(TokenType::Equal);
+                        self.advance();
+                    } else if self.current_char == Some('>') {
+                        tokens.push(TokenType::FatArrow);
+                        self.advance();
+                    } else {
+                        tokens.push(TokenType::Assign);
+                    }
+                }
+                
+                '!' => {
+                    // `!` hugging the end of an operand, as in `n!`, is postfix
+                    // and lexes as `Bang`; anywhere else it is prefix negation
+                    let attached = self.position > 0
+                        && !self.input[self.position - 1].is_whitespace()
+                        && matches!(tokens.last(), Some(TokenType::Identifier(_) | TokenType::Number(_)
+                            | TokenType::String(_) | TokenType::Char(_) | TokenType::Boolean(_) | TokenType::This
+                            | TokenType::RightParen | TokenType::RightBracket));
+                    self.advance();
+                    if self.current_char == Some('=') {
+                        tokens.push(TokenType::NotEqual);
+                        self.advance();
+                    } else if attached {
+                        tokens.push(TokenType::Bang);
+                    } else {
+                        tokens.push(TokenType::Not);
+                    }
+                }
+                
+                '<' => {
+                    self.advance();
+                    if self.current_char == Some('=') {
+                        tokens.push(TokenType::LessEqual);
+                        self.advance();
+                    } else {
+                        tokens.push(TokenType::Less);
+                    }
+                }
+                
+                '>' => {
+                    self.advance();
+                    if self.current_char == Some('=') {
+                        tokens.push(TokenType::GreaterEqual);
+                        self.advance();
+                    } else {
+                        tokens.push(TokenType::Greater);
+                    }
+                }
+                
+                '&' => {
+                    self.advance();
+                    if self.current_char == Some('&') {
+                        tokens.push(TokenType::And);
+                        self.advance();
+                    }
+                }
+                
+                '|' => {
+                    self.advance();
+                    if self.current_char == Some('|') {
+                        self.advance();
+                        if self.current_char == Some('>') {
+                            tokens.push(TokenType::ParallelPipe);
+                            self.advance();
+                        } else {
+                            tokens.push(TokenType::Or);
+                        }
+                    } else {
+                        tokens.push(TokenType::Pipe);
+                    }
+                }
+                
+                '(' => {
+                    tokens.push(TokenType::LeftParen);
+                    self.advance();
+                }
+                
+                ')' => {
+                    tokens.push(TokenType::RightParen);
+                    self.advance();
+                }
+                
+                '{' => {
+                    if self.use_braces {
+                        tokens.push(TokenType::LeftBrace);
+                    }
+                    self.advance();
+                }
+                
+                '}' => {
+                    if self.use_braces {
+                        tokens.push(TokenType::RightBrace);
+                    }
+                    self.advance();
+                }
+                
+                '[' => {
+                    tokens.push(TokenType::LeftBracket);
+                    self.advance();
+                }
+                
+                ']' => {
+                    tokens.push(TokenType::RightBracket);
+                    self.advance();
+                }
+                
+                ',' => {
+                    tokens.push(TokenType::Comma);
+                    self.advance();
+                }
+                
+                ';' => {
+                    tokens.push(TokenType::Semicolon);
+                    self.advance();
+                }
+                
+                ':' => {
+                    tokens.push(TokenType::Colon);
+                    self.advance();
+                }
+                
+                '.' => {
+                    if let Some(next_char) = self.peek(1) {
+                        if next_char == '.' {
+                            tokens.push(TokenType::DotDot);
+                            self.advance();
+                            self.advance();
+                        } else if next_char.is_ascii_digit() {
+                            let number = self.read_number();
+                            tokens.push(TokenType::Number(number));
+                        } else {
+                            tokens.push(TokenType::Dot);
+                            self.advance();
+                        }
+                    } else {
+                        tokens.push(TokenType::Dot);
+                        self.advance();
+                    }
+                }
+                
+                '?' => {
+                    tokens.push(TokenType::Question);
+                    self.advance();
+                }
+                
+                '"' => {
+                    let string_val = self.read_string();
+                    tokens.push(TokenType::String(string_val));
+                }
+                
+                '\'' => {
+                    let char_val = self.read_quoted('\'');
+                    tokens.push(TokenType::Char(char_val));
+                }
+                
+                ch if ch.is_ascii_digit() => {
+                    let number = self.read_number();
+                    tokens.push(TokenType::Number(number));
+                }
+                
+                ch if Self::is_identifier_start(ch) => {
+                    let identifier = self.read_identifier();
+                    let token = match identifier.as_str() {
+                        "let" => TokenType::Let,
+                        "const" => TokenType::Const,
+                        "func" => TokenType::Func,
+                        "return" => TokenType::Return,
+                        "defer" => TokenType::Defer,
+                        "if" => TokenType::If,
+                        "else" => TokenType::Else,
+                        "while" => TokenType::While,
+                        "for" => TokenType::For,
+                        "loop" => TokenType::Loop,
+                        "do" => TokenType::Do,
+                        "break" => TokenType::Break,
+                        "class" => TokenType::Class,
+                        "extends" => TokenType::Extends,
+                        "new" => TokenType::New,
+                        "this" => TokenType::This,
+                        "super" => TokenType::Super,
+                        "import" => TokenType::Import,
+                        "export" => TokenType::Export,
+                        "match" => TokenType::Match,
+                        "case" => TokenType::Case,
+                        "default" => TokenType::Default,
+                        "temporal" => TokenType::Temporal,
+                        "freeze" => TokenType::Freeze,
+                        "thaw" => TokenType::Thaw,
+                        "timeline" => TokenType::Timeline,
+                        "on" => TokenType::On,
+                        "macro" => TokenType::Macro,
+                        "comptime" => TokenType::Comptime,
+                        "spawn" => TokenType::Spawn,
+                        "true" => TokenType::Boolean(true),
+                        "false" => TokenType::Boolean(false),
+                        _ => TokenType::Identifier(identifier),
+                    };
+                    tokens.push(token);
+                }
+                
+                ch => {
+                    self.unexpected.push((start, ch));
+                    self.advance();
+                }
+            }
+            spans.resize(tokens.len(), start);
+        }
+        
+        for conditional in std::mem::take(&mut self.conditionals) {
+            self.errors.push((conditional.start, "`#pragma if` is never closed by `#pragma endif`".to_string()));
+        }
+        
+        tokens.push(TokenType::EOF);
+        spans.push(Span { line: self.line, column: self.column });
+        (tokens, spans)
+    }
+    
+    /// Problems with `#pragma if` sections, which leave the token stream
+    /// unreliable
+    pub fn errors(&self) -> &[(Span, String)] {
+        &self.errors
+    }
+    
+    pub fn unexpected(&self) -> &[(Span, char)] {
+        &self.unexpected
+    }
+}
+
+// ============================================================================
+// AST - Abstract Syntax Tree
+// ============================================================================
+
+#[derive(Debug, Clone)]
+pub enum ASTNode {
+    Program(Vec<ASTNode>),
+    
+    // Statements
+    VarDecl { 
+        name: String, 
+        value: Box<ASTNode>, 
+        is_const: bool,
+        is_temporal: bool,
+        doc: Option<String>,
+        /// `temporal(domain) let` records against that clock domain
+        /// rather than the default clock
+        clock: Option<String>,
+    },
+    /// `timeline domain name` declares a clock that only ticks when a
+    /// variable recording against it is updated
+    ClockDomain(String),
+    Assignment { name: String, value: Box<ASTNode> },
+    /// `object.field = value`
+    FieldAssign { object: Box<ASTNode>, field: String, value: Box<ASTNode> },
+    FunctionDecl { 
+        name: String, 
+        params: Vec<String>, 
+        body: Vec<ASTNode>,
+        doc: Option<String>,
+        /// `export func` is linked as a public symbol under its own name
+        exported: bool,
+    },
+    ClassDecl { 
+        name: String, 
+        superclass: Option<String>, 
+        methods: Vec<ASTNode>,
+        doc: Option<String>,
+    },
+    Return(Box<ASTNode>),
+    If { 
+        condition: Box<ASTNode>, 
+        then_branch: Vec<ASTNode>, 
+        /// `else if` arms in source order, kept flat rather than nested
+        else_ifs: Vec<(ASTNode, Vec<ASTNode>)>,
+        else_branch: Option<Vec<ASTNode>> 
+    },
+    While { condition: Box<ASTNode>, body: Vec<ASTNode> },
+    /// `loop { ... }` runs until a `break` (or `return`) leaves it
+    Loop(Vec<ASTNode>),
+    /// `do { ... } while cond` checks its condition after each pass
+    DoWhile { body: Vec<ASTNode>, condition: Box<ASTNode> },
+    /// `name: while ...` names a loop, so `break name` can leave it from
+    /// inside nested loops
+    Labeled { label: String, body: Box<ASTNode> },
+    /// `break` leaves the innermost loop; `break name` the loop labeled `name`
+    Break(Option<String>),
+    Block(Vec<ASTNode>),
+    
+    // Expressions
+    Binary { 
+        left: Box<ASTNode>, 
+        operator: String, 
+        right: Box<ASTNode> 
+    },
+    Unary { operator: String, operand: Box<ASTNode> },
+    Call { callee: Box<ASTNode>, args: Vec<ASTNode> },
+    MemberAccess { object: Box<ASTNode>, property: String },
+    /// `new Foo(args)` allocates an instance and runs the class's `init`
+    New { class: String, args: Vec<ASTNode> },
+    /// `s[i]` on a string (yielding a one-character string) or an array;
+    /// the same syntax on a temporal variable is a `TemporalAccess`
+    Index { object: Box<ASTNode>, index: Box<ASTNode> },
+    
+    // Literals
+    Number(f64),
+    String(String),
+    Boolean(bool),
+    Identifier(String),
+    
+    // Unique Features
+    TemporalAccess { 
+        var: String, 
+        timestamp: Box<ASTNode> 
+    },
+    Pipeline(Vec<ASTNode>),
+    /// A pipeline stage after `||>`, mapped over array inputs in parallel
+    ParallelStage(Box<ASTNode>),
+    Array(Vec<ASTNode>),
+    Match { 
+        expr: Box<ASTNode>, 
+        cases: Vec<(ASTNode, Vec<ASTNode>)> 
+    },
+    /// `start..end` as a `match` pattern: numbers from `start` to `end` inclusive
+    Range {
+        start: Box<ASTNode>,
+        end: Box<ASTNode>,
+    },
+    /// `a, b, c` as a `match` pattern: matches when any alternative does
+    Alternatives(Vec<ASTNode>),
+    Comptime(Vec<ASTNode>),
+    /// `spawn { ... }` runs its body concurrently, evaluating to a task handle
+    Spawn(Vec<ASTNode>),
+    /// `on change x { ... }` runs its body after every update to `x`
+    Watch {
+        var: String,
+        body: Vec<ASTNode>,
+    },
+    /// `defer expr` runs `expr` when the enclosing function returns or
+    /// fails, after any later defers
+    Defer(Box<ASTNode>),
+    /// `#pragma strict_const` or `#pragma checked_math`, kept in the tree
+    /// where it appears; no call can stand in for one
+    Pragma(String),
+}
+
+impl ASTNode {
+    /// Direct child nodes, in source order
+    pub fn children(&self) -> Vec<&ASTNode> {
+        match self {
+            ASTNode::Program(stmts) | ASTNode::Block(stmts) | ASTNode::Comptime(stmts)
+            | ASTNode::Spawn(stmts) | ASTNode::Loop(stmts) => {
+                stmts.iter().collect()
+            }
+            ASTNode::VarDecl { value, .. } | ASTNode::Assignment { value, .. } => vec![value],
+            ASTNode::FieldAssign { object, value, .. } => vec![object, value],
+            ASTNode::Labeled { body, .. } => vec![body],
+            ASTNode::FunctionDecl { body, .. } | ASTNode::Watch { body, .. } => body.iter().collect(),
+            ASTNode::ClassDecl { methods, .. } => methods.iter().collect(),
+            ASTNode::Return(value) | ASTNode::Defer(value) => vec![value],
+            ASTNode::If { condition, then_branch, else_ifs, else_branch } => {
+                let mut children = vec![condition.as_ref()];
+                children.extend(then_branch);
+                for (condition, body) in else_ifs {
+                    children.push(condition);
+                    children.extend(body);
+                }
+                children.extend(else_branch.iter().flatten());
+                children
+            }
+            ASTNode::While { condition, body } => {
+                let mut children = vec![condition.as_ref()];
+                children.extend(body);
+                children
+            }
+            ASTNode::DoWhile { body, condition } => {
+                let mut children: Vec<&ASTNode> = body.iter().collect();
+                children.push(condition);
+                children
+            }
+            ASTNode::Binary { left, right, .. } => vec![left, right],
+            ASTNode::Unary { operand, .. } => vec![operand],
+            ASTNode::Call { callee, args } => {
+                let mut children = vec![callee.as_ref()];
+                children.extend(args);
+                children
+            }
+            ASTNode::MemberAccess { object, .. } => vec![object],
+            ASTNode::Index { object, index } => vec![object, index],
+            ASTNode::TemporalAccess { timestamp, .. } => vec![timestamp],
+            ASTNode::Pipeline(exprs) | ASTNode::Array(exprs) | ASTNode::Alternatives(exprs) => exprs.iter().collect(),
+            ASTNode::Range { start, end } => vec![start, end],
+            ASTNode::New { args, .. } => args.iter().collect(),
+            ASTNode::ParallelStage(stage) => vec![stage],
+            ASTNode::Match { expr, cases } => {
+                let mut children = vec![expr.as_ref()];
+                for (pattern, body) in cases {
+                    children.push(pattern);
+                    children.extend(body);
+                }
+                children
+            }
+            ASTNode::Number(_) | ASTNode::String(_) | ASTNode::Boolean(_) 
+            | ASTNode::Identifier(_) | ASTNode::Break(_) | ASTNode::ClockDomain(_) | ASTNode::Pragma(_) => Vec::new(),
+        }
+    }
+    
+    /// Mutable counterpart of [`ASTNode::children`] for rewriting passes
+    pub fn children_mut(&mut self) -> Vec<&mut ASTNode> {
+        match self {
+            ASTNode::Program(stmts) | ASTNode::Block(stmts) | ASTNode::Comptime(stmts)
+            | ASTNode::Spawn(stmts) | ASTNode::Loop(stmts) => {
+                stmts.iter_mut().collect()
+            }
+            ASTNode::VarDecl { value, .. } | ASTNode::Assignment { value, .. } => vec![value],
+            ASTNode::FieldAssign { object, value, .. } => vec![object, value],
+            ASTNode::Labeled { body, .. } => vec![body],
+            ASTNode::FunctionDecl { body, .. } | ASTNode::Watch { body, .. } => body.iter_mut().collect(),
+            ASTNode::ClassDecl { methods, .. } => methods.iter_mut().collect(),
+            ASTNode::Return(value) | ASTNode::Defer(value) => vec![value],
+            ASTNode::If { condition, then_branch, else_ifs, else_branch } => {
+                let mut children = vec![condition.as_mut()];
+                children.extend(then_branch);
+                for (condition, body) in else_ifs {
+                    children.push(condition);
+                    children.extend(body);
+                }
+                children.extend(else_branch.iter_mut().flatten());
+                children
+            }
+            ASTNode::While { condition, body } => {
+                let mut children = vec![condition.as_mut()];
+                children.extend(body);
+                children
+            }
+            ASTNode::DoWhile { body, condition } => {
+                let mut children: Vec<&mut ASTNode> = body.iter_mut().collect();
+                children.push(condition);
+                children
+            }
+            ASTNode::Binary { left, right, .. } => vec![left, right],
+            ASTNode::Unary { operand, .. } => vec![operand],
+            ASTNode::Call { callee, args } => {
+                let mut children = vec![callee.as_mut()];
+                children.extend(args);
+                children
+            }
+            ASTNode::MemberAccess { object, .. } => vec![object],
+            ASTNode::Index { object, index } => vec![object, index],
+            ASTNode::TemporalAccess { timestamp, .. } => vec![timestamp],
+            ASTNode::Pipeline(exprs) | ASTNode::Array(exprs) | ASTNode::Alternatives(exprs) => exprs.iter_mut().collect(),
+            ASTNode::Range { start, end } => vec![start, end],
+            ASTNode::New { args, .. } => args.iter_mut().collect(),
+            ASTNode::ParallelStage(stage) => vec![stage],
+            ASTNode::Match { expr, cases } => {
+                let mut children = vec![expr.as_mut()];
+                for (pattern, body) in cases {
+                    children.push(pattern);
+                    children.extend(body);
+                }
+                children
+            }
+            ASTNode::Number(_) | ASTNode::String(_) | ASTNode::Boolean(_) 
+            | ASTNode::Identifier(_) | ASTNode::Break(_) | ASTNode::ClockDomain(_) | ASTNode::Pragma(_) => Vec::new(),
+        }
+    }
+    
+    /// For a program with a top-level `func main`, the number of parameters
+    /// it declares; drivers call it after the top-level statements, passing
+    /// the command-line arguments. A program that calls `main` itself
+    /// decides when it runs, so it has no entry point.
+    pub fn entry_params(&self) -> Option<usize> {
+        fn calls_main(node: &ASTNode) -> bool {
+            matches!(node, ASTNode::Call { callee, .. } if matches!(callee.as_ref(), ASTNode::Identifier(name) if name == "main"))
+                || node.children().into_iter().any(calls_main)
+        }
+        
+        let ASTNode::Program(statements) = self else {
+            return None;
+        };
+        let params = statements.iter().find_map(|stmt| match stmt {
+            ASTNode::FunctionDecl { name, params, .. } if name == "main" => Some(params.len()),
+            _ => None,
+        })?;
+        (!calls_main(self)).then_some(params)
+    }
+    
+    /// Whether the program has `#pragma checked_math`, which covers the
+    /// whole file wherever it appears
+    pub fn checks_math(&self) -> bool {
+        let ASTNode::Program(statements) = self else {
+            return false;
+        };
+        statements.iter().any(|stmt| matches!(stmt, ASTNode::Pragma(name) if name == "checked_math"))
+    }
+    
+    /// Whether this node or any node inside it satisfies `found`
+    pub fn contains(&self, found: &impl Fn(&ASTNode) -> bool) -> bool {
+        found(self) || self.children().into_iter().any(|child| child.contains(found))
+    }
+}
+
+/// Renders nodes as compact, source-like text for diagnostics
+impl fmt::Display for ASTNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ASTNode::Number(n) => write!(f, "{}", n),
+            ASTNode::String(s) => write!(f, "{:?}", s),
+            ASTNode::Boolean(b) => write!(f, "{}", b),
+            ASTNode::Identifier(name) => write!(f, "{}", name),
+            ASTNode::Binary { left, operator, right } => {
+                let wrap = |node: &ASTNode| match node {
+                    ASTNode::Binary { .. } | ASTNode::Pipeline(_) => format!("({})", node),
+                    _ => node.to_string(),
+                };
+                write!(f, "{} {} {}", wrap(left), operator, wrap(right))
+            }
+            ASTNode::Unary { operator, operand } => write!(f, "{}{}", operator, operand),
+            ASTNode::Call { callee, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", callee, args.join(", "))
+            }
+            ASTNode::MemberAccess { object, property } => write!(f, "{}.{}", object, property),
+            ASTNode::New { class, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "new {}({})", class, args.join(", "))
+            }
+            ASTNode::TemporalAccess { var, timestamp } => write!(f, "{}[{}]", var, timestamp),
+            ASTNode::Index { object, index } => write!(f, "{}[{}]", object, index),
+            ASTNode::Pipeline(stages) => {
+                for (i, stage) in stages.iter().enumerate() {
+                    match stage {
+                        ASTNode::ParallelStage(inner) => write!(f, " ||> {}", inner)?,
+                        _ if i > 0 => write!(f, " | {}", stage)?,
+                        _ => write!(f, "{}", stage)?,
+                    }
+                }
+                Ok(())
+            }
+            ASTNode::ParallelStage(stage) => write!(f, "||> {}", stage),
+            ASTNode::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            ASTNode::VarDecl { name, value, is_const, is_temporal, clock, .. } => {
+                let temporal = match clock {
+                    Some(clock) => format!("temporal({}) ", clock),
+                    None if *is_temporal => "temporal ".to_string(),
+                    None => String::new(),
+                };
+                let keyword = if *is_const { "const" } else { "let" };
+                write!(f, "{}{} {} = {}", temporal, keyword, name, value)
+            }
+            ASTNode::ClockDomain(name) => write!(f, "timeline domain {}", name),
+            ASTNode::Pragma(name) => write!(f, "#pragma {}", name),
+            ASTNode::Assignment { name, value } => write!(f, "{} = {}", name, value),
+            ASTNode::FieldAssign { object, field, value } => write!(f, "{}.{} = {}", object, field, value),
+            ASTNode::FunctionDecl { name, params, .. } => write!(f, "func {}({})", name, params.join(", ")),
+            ASTNode::ClassDecl { name, .. } => write!(f, "class {}", name),
+            ASTNode::Return(value) => write!(f, "return {}", value),
+            ASTNode::Defer(value) => write!(f, "defer {}", value),
+            ASTNode::If { condition, .. } => write!(f, "if {}", condition),
+            ASTNode::While { condition, .. } => write!(f, "while {}", condition),
+            ASTNode::Loop(_) => write!(f, "loop {{ ... }}"),
+            ASTNode::DoWhile { condition, .. } => write!(f, "do {{ ... }} while {}", condition),
+            ASTNode::Labeled { label, body } => write!(f, "{}: {}", label, body),
+            ASTNode::Break(None) => write!(f, "break"),
+            ASTNode::Break(Some(label)) => write!(f, "break {}", label),
+            ASTNode::Match { expr, .. } => write!(f, "match {}", expr),
+            ASTNode::Range { start, end } => write!(f, "{}..{}", start, end),
+            ASTNode::Alternatives(patterns) => {
+                let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+                write!(f, "{}", patterns.join(", "))
+            }
+            ASTNode::Comptime(_) => write!(f, "comptime {{ ... }}"),
+            ASTNode::Spawn(_) => write!(f, "spawn {{ ... }}"),
+            ASTNode::Watch { var, .. } => write!(f, "on change {}", var),
+            ASTNode::Block(_) | ASTNode::Program(_) => write!(f, "{{ ... }}"),
+        }
+    }
+}
+
+// ============================================================================
+// PARSER - Syntax Analysis
+// ============================================================================
+
+/// Binding power of each binary operator, pipelines included; higher binds
+/// tighter and every operator is left-associative. By default the pipeline
+/// operators bind loosest, so `a | f == b` pipes `a` into `f == b`;
+/// `#pragma precedence | above ==` makes it `(a | f) == b` instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrecedenceTable {
+    levels: HashMap<&'static str, u8>,
+}
+
+impl PrecedenceTable {
+    const DEFAULT_LEVELS: [(&'static str, u8); 15] = [
+        ("|", 10), ("||>", 10),
+        ("||", 20),
+        ("&&", 30),
+        ("==", 40), ("!=", 40),
+        ("<", 50), (">", 50), ("<=", 50), (">=", 50),
+        ("+", 60), ("-", 60),
+        ("*", 70), ("/", 70), ("%", 70),
+    ];
+    
+    pub fn new() -> Self {
+        Self { levels: Self::DEFAULT_LEVELS.into_iter().collect() }
+    }
+    
+    pub fn level(&self, op: &str) -> Option<u8> {
+        self.levels.get(op).copied()
+    }
+    
+    /// Applies the arguments of `#pragma precedence <op> above|below|with <op>`.
+    /// `above` and `below` place the operator just beside the other one's
+    /// level; `with` puts it on the same level.
+    pub fn apply_pragma(&mut self, args: &str) -> Result<(), String> {
+        let parts: Vec<&str> = args.split_whitespace().collect();
+        let [op, relation, other] = parts[..] else {
+            return Err(format!("Expected '<op> above|below|with <op>' after #pragma precedence, found '{}'", args.trim()));
+        };
+        
+        let op = self.levels.get_key_value(op).map(|(op, _)| *op)
+            .ok_or_else(|| format!("Unknown operator '{}' in #pragma precedence", op))?;
+        let target = self.level(other)
+            .ok_or_else(|| format!("Unknown operator '{}' in #pragma precedence", other))?;
+        
+        let level = match relation {
+            "above" => target.saturating_add(5),
+            "below" => target.saturating_sub(5).max(1),
+            "with" => target,
+            _ => return Err(format!("Expected 'above', 'below' or 'with' in #pragma precedence, found '{}'", relation)),
+        };
+        self.levels.insert(op, level);
+        Ok(())
+    }
+}
+
+impl Default for PrecedenceTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Parser {
+    tokens: Vec<TokenType>,
+    current: usize,
+    macros: HashMap<String, MacroDef>,
+    macro_expansions: usize,
+    doc_comments: HashMap<usize, String>, // token index -> attached doc text
+    /// Variables declared `temporal` so far; `x[i]` on one of these is a
+    /// temporal access rather than indexing
+    temporal_vars: HashSet<String>,
+    precedence: PrecedenceTable,
+    depth: usize,
+    max_depth: usize,
+    /// Index in the lexer's output of each token kept here
+    origins: Vec<usize>,
+    /// Index in the lexer's output of the `func` (or `export`) token that
+    /// starts each function declaration, for source maps
+    function_starts: HashMap<String, usize>,
+    /// Index in the lexer's output of the token starting each statement,
+    /// keyed by the statement's address in the finished AST
+    statement_starts: HashMap<*const ASTNode, usize>,
+}
+
+impl Parser {
+    /// Default limit on statement/expression nesting, keeping recursive
+    /// descent (and later AST walks) well inside the native stack
+    pub const DEFAULT_MAX_DEPTH: usize = 128;
+    
+
+    pub fn new(tokens: Vec<TokenType>) -> Self {
+        // Pull doc comments out of the token stream, remembering which token
+        // each run of `///` lines precedes
+        let mut filtered = Vec::with_capacity(tokens.len());
+        let mut origins = Vec::with_capacity(tokens.len());
+        let mut doc_comments = HashMap::new();
+        let mut pending: Vec<String> = Vec::new();
+        
+        for (origin, token) in tokens.into_iter().enumerate() {
+            match token {
+                TokenType::DocComment(text) => pending.push(text),
+                TokenType::Newline => {
+                    filtered.push(token);
+                    origins.push(origin);
+                }
+                _ => {
+                    if !pending.is_empty() {
+                        doc_comments.insert(filtered.len(), pending.join("\n"));
+                        pending.clear();
+                    }
+                    filtered.push(token);
+                    origins.push(origin);
+                }
+            }
+        }
+        
+        Self {
+            tokens: filtered,
+            current: 0,
+            macros: HashMap::new(),
+            macro_expansions: 0,
+            doc_comments,
+            temporal_vars: HashSet::new(),
+            precedence: PrecedenceTable::new(),
+            depth: 0,
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            origins,
+            function_starts: HashMap::new(),
+            statement_starts: HashMap::new(),
+        }
+    }
+    
+    /// Index in the lexer's output of the token being parsed, which is
+    /// where a parse error points
+    pub fn token_index(&self) -> usize {
+        self.origins.get(self.current).or(self.origins.last()).copied().unwrap_or(0)
+    }
+    
+    /// Index in the lexer's output of the token starting each function
+    /// declaration parsed so far; the first declaration of a name wins
+    pub fn function_starts(&self) -> &HashMap<String, usize> {
+        &self.function_starts
+    }
+    
+    /// Index in the lexer's output of the token starting each statement of
+    /// the AST `parse` returned, keyed by the statement's address. Moving
+    /// the AST keeps them valid; replacing a statement list doesn't
+    pub fn statement_starts(&self) -> &HashMap<*const ASTNode, usize> {
+        &self.statement_starts
+    }
+    
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+    
+    /// Runs `parse` one nesting level deeper, failing cleanly past the limit
+    fn nested<T>(&mut self, levels: usize, parse: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        if self.depth + levels > self.max_depth {
+            return Err(format!("Nesting depth exceeds the limit of {} levels", self.max_depth));
+        }
+        
+        self.depth += levels;
+        let result = parse(self);
+        self.depth -= levels;
+        result
+    }
+    
+    fn take_doc_comment(&mut self) -> Option<String> {
+        self.doc_comments.remove(&self.current)
+    }
+    
+    fn peek(&self) -> &TokenType {
+        self.tokens.get(self.current).unwrap_or(&TokenType::EOF)
+    }
+    
+    fn peek_next(&self) -> &TokenType {
+        self.tokens.get(self.current + 1).unwrap_or(&TokenType::EOF)
+    }
+    
+    fn advance(&mut self) -> &TokenType {
+        if self.current < self.tokens.len() {
+            self.current += 1;
+        }
+        self.peek()
+    }
+    
+    fn consume(&mut self, expected: TokenType) -> Result<(), String> {
+        if std::mem::discriminant(self.peek()) == std::mem::discriminant(&expected) {
+            self.advance();
+            Ok(())
+        } else {
+            Err(format!("Expected {:?}, found {:?}", expected, self.peek()))
+        }
+    }
+    
+    pub fn parse(&mut self) -> Result<ASTNode, String> {
+        let mut statements = Vec::new();
+        let mut starts = Vec::new();
+        
+        while !matches!(self.peek(), TokenType::EOF) {
+            if let TokenType::Pragma(pragma) = self.peek() {
+                // `#pragma numeric_format` takes effect where it appears,
+                // as shorthand for `set_precision`, and `#pragma precedence`
+                // for the expressions after it; `keep_timeline` becomes a
+                // marker call and `strict_const` and `checked_math` pragma
+                // nodes; the rest are lexer-only
+                if let Some(args) = pragma.strip_prefix("numeric_format") {
+                    let digits = NumericFormat::from_pragma(args)?.precision_code();
+                    statements.push(ASTNode::Call {
+                        callee: Box::new(ASTNode::Identifier("set_precision".to_string())),
+                        args: vec![ASTNode::Number(digits as f64)],
+                    });
+                } else if let Some(args) = pragma.strip_prefix("precedence") {
+                    let args = args.to_string();
+                    self.precedence.apply_pragma(&args)?;
+                } else if let Some(names) = pragma.strip_prefix("keep_timeline") {
+                    // Shorthand for `keep_timeline(names...)`; no names keeps them all
+                    statements.push(ASTNode::Call {
+                        callee: Box::new(ASTNode::Identifier("keep_timeline".to_string())),
+                        args: names.split(|ch: char| ch == ',' || ch.is_whitespace())
+                            .filter(|name| !name.is_empty())
+                            .map(|name| ASTNode::Identifier(name.to_string()))
+                            .collect(),
+                    });
+                } else if pragma == "strict_const" || pragma == "checked_math" {
+                    // `strict_const` marks where `const` initializers start
+                    // having to fold
+                    statements.push(ASTNode::Pragma(pragma.clone()));
+                }
+                self.advance();
+                continue;
+            }
+            self.parse_statement_into(&mut statements, &mut starts)?;
+        }
+        self.locate(&statements, starts);
+        
+        let program = ASTNode::Program(statements);
+        // Macro arguments are copied into each expansion, so the statements
+        // parsed for them aren't part of the program
+        fn collect(node: &ASTNode, nodes: &mut HashSet<*const ASTNode>) {
+            nodes.insert(node as *const ASTNode);
+            for child in node.children() {
+                collect(child, nodes);
+            }
+        }
+        let mut reachable = HashSet::new();
+        collect(&program, &mut reachable);
+        self.statement_starts.retain(|node, _| reachable.contains(node));
+        Ok(program)
+    }
+    
+    fn parse_statement(&mut self) -> Result<ASTNode, String> {
+        self.nested(1, Self::parse_statement_inner)
+    }
+    
+    /// Parses a statement onto the end of `statements`, noting its position
+    /// and the token it starts at in `starts`
+    fn parse_statement_into(&mut self, statements: &mut Vec<ASTNode>, starts: &mut Vec<(usize, usize)>) -> Result<(), String> {
+        starts.push((statements.len(), self.token_index()));
+        statements.push(self.parse_statement()?);
+        Ok(())
+    }
+    
+    /// Records where the statements of a finished list start; the list
+    /// won't grow again, so they keep their addresses
+    fn locate(&mut self, statements: &[ASTNode], starts: Vec<(usize, usize)>) {
+        for (index, start) in starts {
+            self.statement_starts.insert(&statements[index] as *const ASTNode, start);
+        }
+    }
+    
+    fn parse_statement_inner(&mut self) -> Result<ASTNode, String> {
+        let doc = self.take_doc_comment();
+        
+        match self.peek() {
+            TokenType::Let => self.parse_var_decl(false, false, doc, None),
+            TokenType::Const => self.parse_var_decl(true, false, doc, None),
+            TokenType::Temporal => {
+                self.advance(); // consume 'temporal'
+                // `temporal(domain)` names the clock the variable records against
+                let clock = if matches!(self.peek(), TokenType::LeftParen) {
+                    self.advance();
+                    let TokenType::Identifier(clock) = self.peek() else {
+                        return Err(format!("Expected a clock domain after 'temporal(', found {:?}", self.peek()));
+                    };
+                    let clock = clock.clone();
+                    self.advance();
+                    self.consume(TokenType::RightParen)?;
+                    Some(clock)
+                } else {
+                    None
+                };
+                match self.peek() {
+                    TokenType::Let => self.parse_var_decl(false, true, doc, clock),
+                    TokenType::Const => self.parse_var_decl(true, true, doc, clock),
+                    _ => Err("Expected 'let' or 'const' after 'temporal'".to_string()),
+                }
+            },
+            // `domain` stays usable as a name everywhere else
+            TokenType::Timeline => {
+                self.advance(); // consume 'timeline'
+                if !matches!(self.peek(), TokenType::Identifier(word) if word == "domain") {
+                    return Err(format!("Expected 'domain' after 'timeline', found {:?}", self.peek()));
+                }
+                self.advance(); // consume 'domain'
+                let TokenType::Identifier(name) = self.peek() else {
+                    return Err(format!("Expected a name after 'timeline domain', found {:?}", self.peek()));
+                };
+                let name = name.clone();
+                self.advance();
+                Ok(ASTNode::ClockDomain(name))
+            }
+            TokenType::Func => {
+                let start = self.token_index();
+                self.parse_function(doc, false).map(|decl| self.record_function_start(decl, start))
+            }
+            TokenType::Export => {
+                let start = self.token_index();
+                self.advance(); // consume 'export'
+                if !matches!(self.peek(), TokenType::Func) {
+                    return Err("Expected 'func' after 'export'".to_string());
+                }
+                self.parse_function(doc, true).map(|decl| self.record_function_start(decl, start))
+            }
+            TokenType::Class => self.parse_class(doc),
+            TokenType::Return => self.parse_return(),
+            TokenType::If => self.parse_if(),
+            TokenType::While => self.parse_while(),
+            TokenType::Loop => {
+                self.advance(); // consume 'loop'
+                Ok(ASTNode::Loop(self.parse_block()?))
+            }
+            TokenType::Do => self.parse_do_while(),
+            // `freeze x` is a call the analyzer and interpreter treat specially
+            TokenType::Freeze => {
+                self.advance(); // consume 'freeze'
+                let TokenType::Identifier(name) = self.peek() else {
+                    return Err(format!("Expected a variable name after 'freeze', found {:?}", self.peek()));
+                };
+                let name = name.clone();
+                self.advance();
+                Ok(ASTNode::Call {
+                    callee: Box::new(ASTNode::Identifier("freeze".to_string())),
+                    args: vec![ASTNode::Identifier(name)],
+                })
+            }
+            TokenType::Break => {
+                self.advance(); // consume 'break'
+                // A name right after `break` is its target, unless it starts
+                // another statement
+                if let TokenType::Identifier(label) = self.peek()
+                    && Self::binary_operator(self.peek_next()).is_none()
+                    && !matches!(self.peek_next(), TokenType::Assign | TokenType::LeftParen | TokenType::Dot
+                        | TokenType::LeftBracket | TokenType::Bang)
+                {
+                    let label = label.clone();
+                    self.advance();
+                    return Ok(ASTNode::Break(Some(label)));
+                }
+                Ok(ASTNode::Break(None))
+            }
+            TokenType::Identifier(label) if matches!(self.peek_next(), TokenType::Colon) => {
+                let label = label.clone();
+                self.advance(); // consume label
+                self.advance(); // consume ':'
+                let body = match self.peek() {
+                    TokenType::While => self.parse_while()?,
+                    TokenType::Do => self.parse_do_while()?,
+                    TokenType::Loop => {
+                        self.advance(); // consume 'loop'
+                        ASTNode::Loop(self.parse_block()?)
+                    }
+                    other => return Err(format!("Only loops can be labeled, found {:?} after '{}:'", other, label)),
+                };
+                Ok(ASTNode::Labeled { label, body: Box::new(body) })
+            }
+            TokenType::Match => self.parse_match(),
+            TokenType::Defer => {
+                self.advance(); // consume 'defer'
+                Ok(ASTNode::Defer(Box::new(self.parse_expression()?)))
+            }
+            TokenType::Macro => self.parse_macro_decl(),
+            TokenType::On => self.parse_watch(),
+            TokenType::Identifier(_) if matches!(self.peek_next(), TokenType::Assign) => {
+                self.parse_assignment()
+            }
+            _ => {
+                let expr = self.parse_expression()?;
+                
+                if matches!(self.peek(), TokenType::Assign)
+                    && let ASTNode::MemberAccess { object, property } = expr
+                {
+                    self.advance();
+                    let value = self.parse_expression()?;
+                    return Ok(ASTNode::FieldAssign { object, field: property, value: Box::new(value) });
+                }
+                
+                Ok(expr)
+            }
+        }
+    }
+    
+    fn parse_assignment(&mut self) -> Result<ASTNode, String> {
+        let name = if let TokenType::Identifier(name) = self.peek() {
+            let n = name.clone();
+            self.advance();
+            n
+        } else {
+            return Err("Expected identifier in assignment".to_string());
+        };
+        
+        self.consume(TokenType::Assign)?;
+        let value = self.parse_expression()?;
+        
+        Ok(ASTNode::Assignment { name, value: Box::new(value) })
+    }
+    
+    fn parse_macro_decl(&mut self) -> Result<ASTNode, String> {
+        self.advance(); // consume 'macro'
+        
+        let name = if let TokenType::Identifier(name) = self.peek() {
+            let n = name.clone();
+            self.advance();
+            n
+        } else {
+            return Err("Expected macro name".to_string());
+        };
+        
+        self.consume(TokenType::LeftParen)?;
+        let mut params = Vec::new();
+        
+        while !matches!(self.peek(), TokenType::RightParen) {
+            if let TokenType::Identifier(param) = self.peek() {
+                params.push(param.clone());
+                self.advance();
+                
+                if matches!(self.peek(), TokenType::Comma) {
+                    self.advance();
+                }
+            } else {
+                return Err("Expected macro parameter name".to_string());
+            }
+        }
+        
+        self.consume(TokenType::RightParen)?;
+        let body = self.parse_block()?;
+        
+        self.macros.insert(name, MacroDef { params, body });
+        
+        // Macros only exist at parse time; nothing is left in the AST
+        Ok(ASTNode::Block(Vec::new()))
+    }
+    
+    fn parse_block(&mut self) -> Result<Vec<ASTNode>, String> {
+        self.consume(TokenType::LeftBrace)?;
+        
+        let mut statements = Vec::new();
+        let mut starts = Vec::new();
+        while !matches!(self.peek(), TokenType::RightBrace) {
+            if matches!(self.peek(), TokenType::EOF) {
+                return Err("Unexpected end of input, expected '}'".to_string());
+            }
+            self.parse_statement_into(&mut statements, &mut starts)?;
+        }
+        
+        self.consume(TokenType::RightBrace)?;
+        self.locate(&statements, starts);
+        Ok(statements)
+    }
+    
+    fn parse_macro_invocation(&mut self, name: String) -> Result<ASTNode, String> {
+        let expected = self.macros[&name].params.len();
+        let mut args = Vec::new();
+        
+        if matches!(self.peek(), TokenType::LeftParen) {
+            // Call form: name(arg, { block }, ...)
+            self.advance();
+            while !matches!(self.peek(), TokenType::RightParen) {
+                if matches!(self.peek(), TokenType::LeftBrace) {
+                    args.push(MacroArg::Block(self.parse_block()?));
+                } else {
+                    args.push(MacroArg::Expr(self.parse_expression()?));
+                }
+                
+                if matches!(self.peek(), TokenType::Comma) {
+                    self.advance();
+                }
+            }
+            self.consume(TokenType::RightParen)?;
+        } else {
+            // Bare form: name arg, arg { block }
+            while args.len() < expected && !matches!(self.peek(), TokenType::LeftBrace | TokenType::EOF) {
+                args.push(MacroArg::Expr(self.parse_expression()?));
+                
+                if matches!(self.peek(), TokenType::Comma) {
+                    self.advance();
+                }
+            }
+        }
+        
+        // A trailing block fills the last parameter: `repeat 5 { ... }`
+        if args.len() < expected && matches!(self.peek(), TokenType::LeftBrace) {
+            args.push(MacroArg::Block(self.parse_block()?));
+        }
+        
+        if args.len() != expected {
+            return Err(format!("Macro '{}' expects {} arguments, found {}", 
+                              name, expected, args.len()));
+        }
+        
+        self.macro_expansions += 1;
+        let suffix = format!("{}{}", name, self.macro_expansions);
+        let expander = MacroExpander::new(&self.macros[&name], args, &suffix);
+        
+        let mut expanded = expander.expand_block(&self.macros[&name].body);
+        if expanded.len() == 1 {
+            Ok(expanded.remove(0))
+        } else {
+            Ok(ASTNode::Block(expanded))
+        }
+    }
+    
+    fn parse_var_decl(&mut self, is_const: bool, is_temporal: bool, doc: Option<String>,
+                      clock: Option<String>) -> Result<ASTNode, String> {
+        self.advance(); // consume 'let' or 'const'
+        
+        if let TokenType::Identifier(name) = self.peek() {
+            let var_name = name.clone();
+            self.advance();
+            
+            self.consume(TokenType::Assign)?;
+            let value = self.parse_expression()?;
+            if is_temporal {
+                self.temporal_vars.insert(var_name.clone());
+            }
+            
+            Ok(ASTNode::VarDecl {
+                name: var_name,
+                value: Box::new(value),
+                is_const,
+                is_temporal,
+                doc,
+                clock,
+            })
+        } else {
+            Err("Expected identifier after variable declaration".to_string())
+        }
+    }
+    
+    fn record_function_start(&mut self, decl: ASTNode, start: usize) -> ASTNode {
+        if let ASTNode::FunctionDecl { name, .. } = &decl {
+            self.function_starts.entry(name.clone()).or_insert(start);
+        }
+        decl
+    }
+    
+    fn parse_function(&mut self, doc: Option<String>, exported: bool) -> Result<ASTNode, String> {
+        self.advance(); // consume 'func'
+        
+        let name = if let TokenType::Identifier(name) = self.peek() {
+            let n = name.clone();
+            self.advance();
+            n
+        } else {
+            return Err("Expected function name".to_string());
+        };
+        
+        self.consume(TokenType::LeftParen)?;
+        let mut params = Vec::new();
+        
+        while !matches!(self.peek(), TokenType::RightParen) {
+            if let TokenType::Identifier(param) = self.peek() {
+                params.push(param.clone());
+                self.advance();
+                
+                if matches!(self.peek(), TokenType::Comma) {
+                    self.advance();
+                }
+            } else {
+                return Err("Expected parameter name".to_string());
+            }
+        }
+        
+        self.consume(TokenType::RightParen)?;
+        let body = self.parse_block()?;
+        
+        Ok(ASTNode::FunctionDecl { name, params, body, doc, exported })
+    }
+    
+    fn parse_class(&mut self, doc: Option<String>) -> Result<ASTNode, String> {
+        self.advance(); // consume 'class'
+        
+        let name = if let TokenType::Identifier(name) = self.peek() {
+            let n = name.clone();
+            self.advance();
+            n
+        } else {
+            return Err("Expected class name".to_string());
+        };
+        
+        let superclass = if matches!(self.peek(), TokenType::Extends) {
+            self.advance();
+            if let TokenType::Identifier(super_name) = self.peek() {
+                let s = super_name.clone();
+                self.advance();
+                Some(s)
+            } else {
+                return Err("Expected superclass name".to_string());
+            }
+        } else {
+            None
+        };
+        
+        self.consume(TokenType::LeftBrace)?;
+        
+        let mut methods = Vec::new();
+        while !matches!(self.peek(), TokenType::RightBrace) {
+            let method_doc = self.take_doc_comment();
+            methods.push(self.parse_function(method_doc, false)?);
+        }
+        
+        self.consume(TokenType::RightBrace)?;
+        
+        Ok(ASTNode::ClassDecl { name, superclass, methods, doc })
+    }
+    
+    fn parse_return(&mut self) -> Result<ASTNode, String> {
+        self.advance(); // consume 'return'
+        let value = self.parse_expression()?;
+        Ok(ASTNode::Return(Box::new(value)))
+    }
+    
+    fn parse_if(&mut self) -> Result<ASTNode, String> {
+        self.advance(); // consume 'if'
+        
+        let condition = self.parse_expression()?;
+        let then_branch = self.parse_block()?;
+        
+        let mut else_ifs = Vec::new();
+        let mut else_branch = None;
+        while matches!(self.peek(), TokenType::Else) {
+            self.advance();
+            
+            // `else if` adds another arm to this chain instead of nesting
+            if matches!(self.peek(), TokenType::If) {
+                self.advance();
+                let condition = self.parse_expression()?;
+                else_ifs.push((condition, self.parse_block()?));
+                continue;
+            }
+            
+            else_branch = Some(self.parse_block()?);
+            break;
+        }
+        
+        Ok(ASTNode::If {
+            condition: Box::new(condition),
+            then_branch,
+            else_ifs,
+            else_branch,
+        })
+    }
+    
+    fn parse_while(&mut self) -> Result<ASTNode, String> {
+        self.advance(); // consume 'while'
+        
+        let condition = self.parse_expression()?;
+        let body = self.parse_block()?;
+        
+        Ok(ASTNode::While {
+            condition: Box::new(condition),
+            body,
+        })
+    }
+    
+    fn parse_do_while(&mut self) -> Result<ASTNode, String> {
+        self.advance(); // consume 'do'
+        
+        let body = self.parse_block()?;
+        if !matches!(self.peek(), TokenType::While) {
+            return Err("Expected 'while' after 'do' block".to_string());
+        }
+        self.advance(); // consume 'while'
+        let condition = self.parse_expression()?;
+        
+        Ok(ASTNode::DoWhile {
+            body,
+            condition: Box::new(condition),
+        })
+    }
+    
+    fn parse_watch(&mut self) -> Result<ASTNode, String> {
+        self.advance(); // consume 'on'
+        
+        match self.peek() {
+            TokenType::Identifier(event) if event == "change" => self.advance(),
+            _ => return Err("Expected 'change' after 'on'".to_string()),
+        };
+        
+        let var = if let TokenType::Identifier(name) = self.peek() {
+            let n = name.clone();
+            self.advance();
+            n
+        } else {
+            return Err("Expected variable name after 'on change'".to_string());
+        };
+        
+        let body = self.parse_block()?;
+        Ok(ASTNode::Watch { var, body })
+    }
+    
+    fn parse_match(&mut self) -> Result<ASTNode, String> {
+        self.advance(); // consume 'match'
+        
+        let expr = self.parse_expression()?;
+        self.consume(TokenType::LeftBrace)?;
+        
+        let mut cases = Vec::new();
+        
+        while !matches!(self.peek(), TokenType::RightBrace) {
+            if matches!(self.peek(), TokenType::Case) {
+                self.advance();
+            }
+            // `default` is a keyword, but later passes see it as a pattern
+            let pattern = if matches!(self.peek(), TokenType::Default) {
+                self.advance();
+                ASTNode::Identifier("default".to_string())
+            } else {
+                self.parse_pattern()?
+            };
+            self.consume(TokenType::FatArrow)?;
+            
+            let case_body = if matches!(self.peek(), TokenType::LeftBrace) {
+                self.parse_block()?
+            } else {
+                let (mut case_body, mut starts) = (Vec::new(), Vec::new());
+                self.parse_statement_into(&mut case_body, &mut starts)?;
+                self.locate(&case_body, starts);
+                case_body
+            };
+            
+            cases.push((pattern, case_body));
+        }
+        
+        self.consume(TokenType::RightBrace)?;
+        
+        Ok(ASTNode::Match {
+            expr: Box::new(expr),
+            cases,
+        })
+    }
+    
+    /// `value`, `start..end`, or several of them separated by commas
+    fn parse_pattern(&mut self) -> Result<ASTNode, String> {
+        let mut alternatives = Vec::new();
+        loop {
+            let value = self.parse_expression()?;
+            if matches!(self.peek(), TokenType::DotDot) {
+                self.advance();
+                let end = self.parse_expression()?;
+                alternatives.push(ASTNode::Range { start: Box::new(value), end: Box::new(end) });
+            } else {
+                alternatives.push(value);
+            }
+            if !matches!(self.peek(), TokenType::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            ASTNode::Alternatives(alternatives)
+        })
+    }
+    
+    fn parse_expression(&mut self) -> Result<ASTNode, String> {
+        self.nested(1, |parser| parser.parse_binary(0).map(|(expr, _)| expr))
+    }
+    
+    fn binary_operator(token: &TokenType) -> Option<&'static str> {
+        match token {
+            TokenType::Pipe => Some("|"),
+            TokenType::ParallelPipe => Some("||>"),
+            TokenType::Or => Some("||"),
+            TokenType::And => Some("&&"),
+            TokenType::Equal => Some("=="),
+            TokenType::NotEqual => Some("!="),
+            TokenType::Less => Some("<"),
+            TokenType::Greater => Some(">"),
+            TokenType::LessEqual => Some("<="),
+            TokenType::GreaterEqual => Some(">="),
+            TokenType::Plus => Some("+"),
+            TokenType::Minus => Some("-"),
+            TokenType::Multiply => Some("*"),
+            TokenType::Divide => Some("/"),
+            TokenType::Modulo => Some("%"),
+            _ => None,
+        }
+    }
+    
+    /// Whether `stage` is a call with a `_` argument marking where the piped
+    /// value goes, as in `value | clamp(0, _, 100)`
+    fn has_placeholder(stage: &ASTNode) -> bool {
+        matches!(stage, ASTNode::Call { args, .. } if args.iter().any(Self::is_placeholder))
+    }
+    
+    fn is_placeholder(arg: &ASTNode) -> bool {
+        matches!(arg, ASTNode::Identifier(name) if name == "_")
+    }
+    
+    /// Desugars `value | f(a, _, b)` to `f(a, value, b)`, where `value` is
+    /// everything piped in so far
+    fn fill_placeholder(stage: ASTNode, value: ASTNode) -> Result<ASTNode, String> {
+        let ASTNode::Call { callee, args } = stage else {
+            unreachable!("only calls have placeholders");
+        };
+        let placeholders = args.iter().filter(|arg| Self::is_placeholder(arg)).count();
+        if placeholders > 1 {
+            let stage = ASTNode::Call { callee, args };
+            return Err(format!("The pipeline stage `{}` has {} `_` placeholders, but takes the piped value once",
+                               stage, placeholders));
+        }
+        let mut value = Some(value);
+        let args = args.into_iter()
+            .map(|arg| if Self::is_placeholder(&arg) { value.take().unwrap() } else { arg })
+            .collect();
+        Ok(ASTNode::Call { callee, args })
+    }
+    
+    /// Pratt parser over the precedence table: folds operators binding
+    /// tighter than `min_level` into the expression. A chain on one level is
+    /// folded by the loop rather than by recursion, but later passes recurse
+    /// over the left-nested tree it builds, so its height counts toward the
+    /// nesting limit. Returns that height.
+    fn parse_binary(&mut self, min_level: u8) -> Result<(ASTNode, usize), String> {
+        let (mut left, mut height) = (self.parse_unary()?, 1);
+        // Whether `left` is a pipeline this loop is still extending, so
+        // `a | f | g` stays flat while `(a | f) | g` keeps its grouping
+        let mut extending_pipeline = false;
+        
+        while let Some(op) = Self::binary_operator(self.peek())
+            && let Some(level) = self.precedence.level(op)
+            && level > min_level
+        {
+            self.advance();
+            let (right, right_height) = self.parse_binary(level)?;
+            let is_pipe = matches!(op, "|" | "||>");
+            
+            // Stages sit side by side, so a flat pipeline only grows as tall
+            // as its tallest stage
+            height = if is_pipe && extending_pipeline {
+                height.max(right_height + 1)
+            } else {
+                height.max(right_height) + 1
+            };
+            if self.depth + height > self.max_depth {
+                return Err(format!("Nesting depth exceeds the limit of {} levels", self.max_depth));
+            }
+            
+            left = if is_pipe && Self::has_placeholder(&right) {
+                if op == "||>" {
+                    return Err(format!("`_` can't be used in the `||>` stage `{}`; each item is passed first", right));
+                }
+                Self::fill_placeholder(right, left)?
+            } else if is_pipe {
+                let stage = if op == "||>" { ASTNode::ParallelStage(Box::new(right)) } else { right };
+                match left {
+                    ASTNode::Pipeline(mut stages) if extending_pipeline => {
+                        stages.push(stage);
+                        ASTNode::Pipeline(stages)
+                    }
+                    first => ASTNode::Pipeline(vec![first, stage]),
+                }
+            } else {
+                ASTNode::Binary {
+                    left: Box::new(left),
+                    operator: op.to_string(),
+                    right: Box::new(right),
+                }
+            };
+            // A filled-in placeholder stage is a plain call the rest of the
+            // pipeline starts from
+            extending_pipeline = is_pipe && matches!(left, ASTNode::Pipeline(_));
+        }
+        
+        Ok((left, height))
+    }
+    
+    fn parse_unary(&mut self) -> Result<ASTNode, String> {
+        // Prefix operators are collected iteratively rather than by recursion
+        let mut operators = Vec::new();
+        
+        while matches!(self.peek(), TokenType::Not | TokenType::Minus) {
+            let op = match self.peek() {
+                TokenType::Not => "!".to_string(),
+                TokenType::Minus => "-".to_string(),
+                _ => unreachable!(),
+            };
+            self.advance();
+            operators.push(op);
+        }
+        
+        // Each operator still adds a level to the resulting tree
+        let mut expr = self.nested(operators.len(), Self::parse_call)?;
+        
+        for op in operators.into_iter().rev() {
+            expr = ASTNode::Unary {
+                operator: op,
+                operand: Box::new(expr),
+            };
+        }
+        
+        Ok(expr)
+    }
+    
+    fn parse_call(&mut self) -> Result<ASTNode, String> {
+        let mut expr = self.parse_primary()?;
+        
+        loop {
+            match self.peek() {
+                TokenType::LeftParen => {
+                    self.advance();
+                    let mut args = Vec::new();
+                    
+                    while !matches!(self.peek(), TokenType::RightParen) {
+                        args.push(self.parse_expression()?);
+                        if matches!(self.peek(), TokenType::Comma) {
+                            self.advance();
+                        }
+                    }
+                    
+                    self.consume(TokenType::RightParen)?;
+                    expr = ASTNode::Call {
+                        callee: Box::new(expr),
+                        args,
+                    };
+                }
+                TokenType::Dot => {
+                    self.advance();
+                    if let TokenType::Identifier(property) = self.peek() {
+                        let prop = property.clone();
+                        self.advance();
+                        expr = ASTNode::MemberAccess {
+                            object: Box::new(expr),
+                            property: prop,
+                        };
+                    } else {
+                        return Err("Expected property name after '.'".to_string());
+                    }
+                }
+                TokenType::LeftBracket => {
+                    // Temporal access var[timestamp], or indexing
+                    self.advance();
+                    let index = self.parse_expression()?;
+                    self.consume(TokenType::RightBracket)?;
+                    
+                    expr = match expr {
+                        ASTNode::Identifier(var) if self.temporal_vars.contains(&var) => ASTNode::TemporalAccess {
+                            var,
+                            timestamp: Box::new(index),
+                        },
+                        object => ASTNode::Index {
+                            object: Box::new(object),
+                            index: Box::new(index),
+                        },
+                    };
+                }
+                TokenType::Bang => {
+                    return Err(format!("Unexpected postfix '!' after `{}`; `!` negates the expression that follows it", expr));
+                }
+                _ => break,
+            }
+        }
+        
+        Ok(expr)
+    }
+    
+    fn parse_primary(&mut self) -> Result<ASTNode, String> {
+        match self.peek() {
+            TokenType::Number(n) => {
+                let num = *n;
+                self.advance();
+                Ok(ASTNode::Number(num))
+            }
+            TokenType::String(s) => {
+                let string = s.clone();
+                self.advance();
+                Ok(ASTNode::String(string))
+            }
+            TokenType::Char(text) => {
+                let mut chars = text.chars();
+                let (Some(ch), None) = (chars.next(), chars.next()) else {
+                    return Err(format!("Character literal '{}' must contain exactly one character", text));
+                };
+                self.advance();
+                Ok(ASTNode::String(ch.to_string()))
+            }
+            TokenType::Boolean(b) => {
+                let boolean = *b;
+                self.advance();
+                Ok(ASTNode::Boolean(boolean))
+            }
+            TokenType::Identifier(name) => {
+                let id = name.clone();
+                self.advance();
+                
+                if self.macros.contains_key(&id) {
+                    return self.parse_macro_invocation(id);
+                }
+                
+                Ok(ASTNode::Identifier(id))
+            }
+            TokenType::LeftParen => {
+                self.advance();
+                let expr = self.parse_expression()?;
+                self.consume(TokenType::RightParen)?;
+                Ok(expr)
+            }
+            // In expression position these yield the value of the arm taken
+            TokenType::If => self.parse_if(),
+            TokenType::Match => self.parse_match(),
+            TokenType::Comptime => {
+                self.advance();
+                Ok(ASTNode::Comptime(self.parse_block()?))
+            }
+            TokenType::Spawn => {
+                self.advance();
+                Ok(ASTNode::Spawn(self.parse_block()?))
+            }
+            TokenType::This => {
+                self.advance();
+                Ok(ASTNode::Identifier("this".to_string()))
+            }
+            TokenType::New => {
+                self.advance();
+                let TokenType::Identifier(class) = self.peek() else {
+                    return Err("Expected class name after 'new'".to_string());
+                };
+                let class = class.clone();
+                self.advance();
+                
+                self.consume(TokenType::LeftParen)?;
+                let mut args = Vec::new();
+                while !matches!(self.peek(), TokenType::RightParen) {
+                    args.push(self.parse_expression()?);
+                    if !matches!(self.peek(), TokenType::RightParen) {
+                        self.consume(TokenType::Comma)?;
+                    }
+                }
+                self.consume(TokenType::RightParen)?;
+                Ok(ASTNode::New { class, args })
+            }
+            TokenType::LeftBracket => {
+                self.advance();
+                let mut items = Vec::new();
+                while !matches!(self.peek(), TokenType::RightBracket) {
+                    items.push(self.parse_expression()?);
+                    if !matches!(self.peek(), TokenType::RightBracket) {
+                        self.consume(TokenType::Comma)?;
+                    }
+                }
+                self.consume(TokenType::RightBracket)?;
+                Ok(ASTNode::Array(items))
+            }
+            _ => Err(format!("Unexpected token in expression: {:?}", self.peek())),
+        }
+    }
+}
+
+// ============================================================================
+// MACRO EXPANSION - Parse-time syntax transformers
+// ============================================================================
+
+/// A user-defined macro: named syntax fragments and a template body
+#[derive(Debug, Clone)]
+pub struct MacroDef {
+    params: Vec<String>,
+    body: Vec<ASTNode>,
+}
+
+/// A syntax fragment passed to a macro invocation
+#[derive(Debug, Clone)]
+pub enum MacroArg {
+    Expr(ASTNode),
+    Block(Vec<ASTNode>),
+}
+
+/// Substitutes arguments into a macro template.
+///
+/// Expansion is hygienic: every local the template declares is renamed to a
+/// fresh name, so it can neither capture nor shadow identifiers that appear in
+/// the caller's fragments. Each splice of a block argument is renamed the
+/// same way, so its locals stay inside it and a block spliced twice doesn't
+/// declare them twice.
+pub struct MacroExpander {
+    bindings: HashMap<String, MacroArg>,
+    renames: HashMap<String, String>,
+    suffix: String,
+    splices: Cell<usize>,
+}
+
+impl MacroExpander {
+    pub fn new(def: &MacroDef, args: Vec<MacroArg>, suffix: &str) -> Self {
+        let bindings = def.params.iter().cloned().zip(args).collect();
+        
+        let mut locals = Vec::new();
+        Self::collect_locals(&def.body, &mut locals);
+        let renames = locals.into_iter()
+            .map(|local| {
+                let fresh = format!("{}__{}", local, suffix);
+                (local, fresh)
+            })
+            .collect();
+        
+        Self { bindings, renames, suffix: suffix.to_string(), splices: Cell::new(0) }
+    }
+    
+    fn collect_locals(statements: &[ASTNode], locals: &mut Vec<String>) {
+        for stmt in statements {
+            match stmt {
+                ASTNode::VarDecl { name, .. } => locals.push(name.clone()),
+                ASTNode::If { then_branch, else_ifs, else_branch, .. } => {
+                    Self::collect_locals(then_branch, locals);
+                    for (_, body) in else_ifs {
+                        Self::collect_locals(body, locals);
+                    }
+                    if let Some(else_stmts) = else_branch {
+                        Self::collect_locals(else_stmts, locals);
+                    }
+                }
+                ASTNode::While { body, .. } | ASTNode::DoWhile { body, .. }
+                | ASTNode::Loop(body) | ASTNode::Block(body) => {
+                    Self::collect_locals(body, locals);
+                }
+                ASTNode::Labeled { body, .. } => Self::collect_locals(std::slice::from_ref(body), locals),
+                ASTNode::Match { cases, .. } => {
+                    for (_, case_body) in cases {
+                        Self::collect_locals(case_body, locals);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    
+    pub fn expand_block(&self, statements: &[ASTNode]) -> Vec<ASTNode> {
+        let mut expanded = Vec::new();
+        
+        for stmt in statements {
+            // A block parameter used as a statement is spliced in place
+            if let ASTNode::Identifier(name) = stmt
+                && let Some(MacroArg::Block(block)) = self.bindings.get(name)
+            {
+                expanded.extend(self.splice(block));
+                continue;
+            }
+            expanded.push(self.expand(stmt));
+        }
+        
+        expanded
+    }
+    
+    /// A copy of a block argument with fresh names for the locals it
+    /// declares, distinct from every other splice's
+    fn splice(&self, block: &[ASTNode]) -> Vec<ASTNode> {
+        let splice = self.splices.get() + 1;
+        self.splices.set(splice);
+        let mut locals = Vec::new();
+        Self::collect_locals(block, &mut locals);
+        let renames = locals.into_iter()
+            .map(|local| {
+                let fresh = format!("{}__{}_{}", local, self.suffix, splice);
+                (local, fresh)
+            })
+            .collect();
+        let expander = Self { bindings: HashMap::new(), renames, suffix: self.suffix.clone(), splices: Cell::new(0) };
+        expander.expand_block(block)
+    }
+    
+    fn rename(&self, name: &str) -> String {
+        match self.bindings.get(name) {
+            Some(MacroArg::Expr(ASTNode::Identifier(target))) => target.clone(),
+            _ => self.renames.get(name).cloned().unwrap_or_else(|| name.to_string()),
+        }
+    }
+    
+    fn expand_boxed(&self, node: &ASTNode) -> Box<ASTNode> {
+        Box::new(self.expand(node))
+    }
+    
+    pub fn expand(&self, node: &ASTNode) -> ASTNode {
+        match node {
+            ASTNode::Identifier(name) => match self.bindings.get(name) {
+                Some(MacroArg::Expr(expr)) => expr.clone(),
+                Some(MacroArg::Block(block)) => ASTNode::Block(self.splice(block)),
+                None => ASTNode::Identifier(self.rename(name)),
+            },
+            
+            ASTNode::Program(statements) => ASTNode::Program(self.expand_block(statements)),
+            ASTNode::Block(statements) => ASTNode::Block(self.expand_block(statements)),
+            ASTNode::Comptime(statements) => ASTNode::Comptime(self.expand_block(statements)),
+            ASTNode::Spawn(statements) => ASTNode::Spawn(self.expand_block(statements)),
+            
+            ASTNode::VarDecl { name, value, is_const, is_temporal, doc, clock } => ASTNode::VarDecl {
+                name: self.rename(name),
+                value: self.expand_boxed(value),
+                is_const: *is_const,
+                is_temporal: *is_temporal,
+                doc: doc.clone(),
+                clock: clock.clone(),
+            },
+            
+            ASTNode::Assignment { name, value } => ASTNode::Assignment {
+                name: self.rename(name),
+                value: self.expand_boxed(value),
+            },
+            
+            ASTNode::FieldAssign { object, field, value } => ASTNode::FieldAssign {
+                object: self.expand_boxed(object),
+                field: field.clone(),
+                value: self.expand_boxed(value),
+            },
+            
+            ASTNode::FunctionDecl { name, params, body, doc, exported } => ASTNode::FunctionDecl {
+                name: name.clone(),
+                params: params.clone(),
+                body: self.expand_block(body),
+                doc: doc.clone(),
+                exported: *exported,
+            },
+            
+            ASTNode::ClassDecl { name, superclass, methods, doc } => ASTNode::ClassDecl {
+                name: name.clone(),
+                superclass: superclass.clone(),
+                methods: methods.iter().map(|m| self.expand(m)).collect(),
+                doc: doc.clone(),
+            },
+            
+            ASTNode::Return(value) => ASTNode::Return(self.expand_boxed(value)),
+            ASTNode::Defer(value) => ASTNode::Defer(self.expand_boxed(value)),
+            
+            ASTNode::If { condition, then_branch, else_ifs, else_branch } => ASTNode::If {
+                condition: self.expand_boxed(condition),
+                then_branch: self.expand_block(then_branch),
+                else_ifs: else_ifs.iter()
+                    .map(|(condition, body)| (self.expand(condition), self.expand_block(body)))
+                    .collect(),
+                else_branch: else_branch.as_ref().map(|stmts| self.expand_block(stmts)),
+            },
+            
+            ASTNode::While { condition, body } => ASTNode::While {
+                condition: self.expand_boxed(condition),
+                body: self.expand_block(body),
+            },
+            
+            ASTNode::Loop(body) => ASTNode::Loop(self.expand_block(body)),
+            
+            ASTNode::DoWhile { body, condition } => ASTNode::DoWhile {
+                body: self.expand_block(body),
+                condition: self.expand_boxed(condition),
+            },
+            
+            ASTNode::Watch { var, body } => ASTNode::Watch {
+                var: self.rename(var),
+                body: self.expand_block(body),
+            },
+            
+            ASTNode::Binary { left, operator, right } => ASTNode::Binary {
+                left: self.expand_boxed(left),
+                operator: operator.clone(),
+                right: self.expand_boxed(right),
+            },
+            
+            ASTNode::Unary { operator, operand } => ASTNode::Unary {
+                operator: operator.clone(),
+                operand: self.expand_boxed(operand),
+            },
+            
+            ASTNode::Call { callee, args } => ASTNode::Call {
+                callee: self.expand_boxed(callee),
+                args: args.iter().map(|arg| self.expand(arg)).collect(),
+            },
+            
+            ASTNode::MemberAccess { object, property } => ASTNode::MemberAccess {
+                object: self.expand_boxed(object),
+                property: property.clone(),
+            },
+            
+            ASTNode::New { class, args } => ASTNode::New {
+                class: class.clone(),
+                args: args.iter().map(|arg| self.expand(arg)).collect(),
+            },
+            
+            ASTNode::Index { object, index } => ASTNode::Index {
+                object: self.expand_boxed(object),
+                index: self.expand_boxed(index),
+            },
+            
+            ASTNode::TemporalAccess { var, timestamp } => ASTNode::TemporalAccess {
+                var: self.rename(var),
+                timestamp: self.expand_boxed(timestamp),
+            },
+            
+            ASTNode::Pipeline(exprs) => {
+                ASTNode::Pipeline(exprs.iter().map(|expr| self.expand(expr)).collect())
+            }
+            ASTNode::ParallelStage(stage) => ASTNode::ParallelStage(self.expand_boxed(stage)),
+            ASTNode::Array(items) => ASTNode::Array(items.iter().map(|item| self.expand(item)).collect()),
+            
+            ASTNode::Range { start, end } => ASTNode::Range {
+                start: self.expand_boxed(start),
+                end: self.expand_boxed(end),
+            },
+            ASTNode::Alternatives(patterns) => {
+                ASTNode::Alternatives(patterns.iter().map(|pattern| self.expand(pattern)).collect())
+            }
+            
+            ASTNode::Match { expr, cases } => ASTNode::Match {
+                expr: self.expand_boxed(expr),
+                cases: cases.iter()
+                    .map(|(pattern, body)| (self.expand(pattern), self.expand_block(body)))
+                    .collect(),
+            },
+            
+            ASTNode::Labeled { label, body } => ASTNode::Labeled {
+                label: label.clone(),
+                body: self.expand_boxed(body),
+            },
+            
+            ASTNode::Number(_) | ASTNode::String(_) | ASTNode::Boolean(_) 
+            | ASTNode::Break(_) | ASTNode::ClockDomain(_) | ASTNode::Pragma(_) => node.clone(),
+        }
+    }
+}
+
+// ============================================================================
+// SEMANTIC ANALYZER & TYPE CHECKER
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FluxType {
+    Number,
+    String, 
+    Boolean,
+    Function(Vec<FluxType>, Box<FluxType>),
+    Object(HashMap<String, FluxType>),
+    Temporal(Box<FluxType>),
+    Array(Box<FluxType>),
+    /// A `spawn` handle, with the type `join` yields
+    Task(Box<FluxType>),
+    Channel,
+    Any,
+}
+
+impl fmt::Display for FluxType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FluxType::Number => write!(f, "number"),
+            FluxType::String => write!(f, "string"),
+            FluxType::Boolean => write!(f, "boolean"),
+            FluxType::Function(..) => write!(f, "function"),
+            FluxType::Object(_) => write!(f, "object"),
+            FluxType::Temporal(inner) => write!(f, "temporal {}", inner),
+            FluxType::Array(inner) => write!(f, "{}[]", inner),
+            FluxType::Task(_) => write!(f, "task"),
+            FluxType::Channel => write!(f, "channel"),
+            FluxType::Any => write!(f, "any"),
+        }
+    }
+}
+
+/// How completely a statement list returns a value
+#[derive(Debug, Clone, PartialEq)]
+enum ReturnCoverage {
+    Always,
+    /// Some paths fall through; describes the last branch that does
+    Partial(String),
+    Never,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Variable {
+    name: String,
+    flux_type: FluxType,
+    is_const: bool,
+    is_temporal: bool,
+    is_frozen: bool,
+    timeline: Vec<(usize, FluxType)>, // (timestamp, value_type)
+}
+
+/// The result of semantic analysis: the checked AST plus the type inferred
+/// for each of its nodes. Nodes are keyed by address, so the table is only
+/// meaningful while the AST it was computed from stays borrowed and unchanged.
+pub struct TypedAST<'a> {
+    pub ast: &'a ASTNode,
+    types: HashMap<*const ASTNode, FluxType>,
+    /// Each function's inferred `FluxType::Function` signature
+    signatures: HashMap<String, FluxType>,
+}
+
+impl TypedAST<'_> {
+    pub fn type_of(&self, node: &ASTNode) -> FluxType {
+        self.types.get(&(node as *const ASTNode)).cloned().unwrap_or(FluxType::Any)
+    }
+}
+
+#[derive(Clone)]
+pub struct SemanticAnalyzer {
+    symbol_table: HashMap<String, Variable>,
+    current_scope: usize,
+    timestamp: usize,
+    errors: Vec<Diagnostic>,
+    /// Suspicious but valid code, such as overlapping `match` cases
+    warnings: Vec<Diagnostic>,
+    /// Where each statement of the program starts, when the parser's
+    /// positions were handed over
+    spans: HashMap<*const ASTNode, Span>,
+    /// Where the innermost statement being checked starts, which is
+    /// where its errors point
+    span: Option<Span>,
+    types: HashMap<*const ASTNode, FluxType>,
+    /// Loops enclosing the current statement, for checking `break`
+    loop_depth: usize,
+    loop_labels: Vec<String>,
+    /// Set while visiting a statement directly in a function body or the
+    /// program, the only places `defer` may appear
+    defer_allowed: bool,
+    /// Set while visiting a statement directly in the program, the only
+    /// place a clock domain may be declared
+    top_level: bool,
+    /// Clock domains declared so far
+    clock_domains: HashSet<String>,
+    /// Each declared function's signature, as a `FluxType::Function`. Types
+    /// are learned from call sites and `return`s, so `analyze` repeats until
+    /// no more can be learned
+    signatures: HashMap<String, FluxType>,
+    /// The type each `spawn` block's trailing expression yields, learned
+    /// the same way
+    task_results: HashMap<*const ASTNode, FluxType>,
+    /// Variables declared at the top level anywhere in the program, which
+    /// a function can read as long as it's called after the declaration
+    globals: HashSet<String>,
+    /// Functions provided by the runtime rather than the program
+    builtins: HashSet<String>,
+}
+
+impl Default for SemanticAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SemanticAnalyzer {
+    pub fn new() -> Self {
+        Self {
+            symbol_table: HashMap::new(),
+            current_scope: 0,
+            timestamp: 0,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            spans: HashMap::new(),
+            span: None,
+            types: HashMap::new(),
+            loop_depth: 0,
+            loop_labels: Vec::new(),
+            defer_allowed: false,
+            top_level: false,
+            clock_domains: HashSet::new(),
+            signatures: HashMap::new(),
+            task_results: HashMap::new(),
+            globals: HashSet::new(),
+            builtins: FluxStdLib::get_builtin_functions().into_keys()
+                .chain(Interpreter::INTRINSICS.iter().map(|name| name.to_string()))
+                .collect(),
+        }
+    }
+    
+    /// Points errors at the statements in `spans`, keyed by node address
+    /// like `Parser::statement_starts`
+    pub fn with_spans(mut self, spans: HashMap<*const ASTNode, Span>) -> Self {
+        self.spans = spans;
+        self
+    }
+    
+    pub fn analyze<'a>(&mut self, ast: &'a ASTNode) -> Result<TypedAST<'a>, Vec<Diagnostic>> {
+        // Only what was declared carries over from an earlier program
+        self.errors.clear();
+        self.warnings.clear();
+        if let Some(params) = ast.entry_params()
+            && params > 1
+        {
+            if let ASTNode::Program(statements) = ast {
+                self.span = statements.iter()
+                    .find(|stmt| matches!(stmt, ASTNode::FunctionDecl { name, .. } if name == "main"))
+                    .and_then(|main| self.spans.get(&(main as *const ASTNode)).copied());
+            }
+            self.error(Diagnostic::ARGUMENT_COUNT, format!(
+                "`func main` takes at most one parameter, the command-line arguments, but declares {}", params));
+            self.span = None;
+        }
+        self.declare_functions(ast);
+        // The entry point's parameter is the command-line arguments
+        if ast.entry_params() == Some(1)
+            && let Some(FluxType::Function(params, _)) = self.signatures.get_mut("main")
+        {
+            params[0] = FluxType::Array(Box::new(FluxType::String));
+        }
+        if let ASTNode::Program(statements) = ast {
+            self.globals.extend(statements.iter().filter_map(|stmt| match stmt {
+                ASTNode::VarDecl { name, .. } => Some(name.clone()),
+                _ => None,
+            }));
+        }
+        
+        // A later statement can tell an earlier one the type of a parameter
+        // or result, so each pass starts over with what the last one learned
+        let initial = self.clone();
+        loop {
+            let (signatures, task_results) = (self.signatures.clone(), self.task_results.clone());
+            self.visit(ast);
+            if self.signatures == signatures && self.task_results == task_results {
+                break;
+            }
+            let learned = (std::mem::take(&mut self.signatures), std::mem::take(&mut self.task_results));
+            *self = initial.clone();
+            (self.signatures, self.task_results) = learned;
+        }
+        
+        if self.errors.is_empty() {
+            Ok(TypedAST {
+                ast,
+                types: std::mem::take(&mut self.types),
+                signatures: self.signatures.clone(),
+            })
+        } else {
+            Err(self.errors.clone())
+        }
+    }
+    
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+    
+    /// Lets later programs call `name` as a function the host provides
+    pub fn declare_builtin(&mut self, name: &str) {
+        self.builtins.insert(name.to_string());
+    }
+    
+    /// Declares a global set by the host, which may hold any type
+    pub fn declare_global(&mut self, name: &str) {
+        self.globals.insert(name.to_string());
+        self.symbol_table.entry(name.to_string()).or_insert_with(|| Variable {
+            name: name.to_string(),
+            flux_type: FluxType::Any,
+            is_const: false,
+            is_temporal: false,
+            is_frozen: false,
+            timeline: vec![(0, FluxType::Any)],
+        });
+    }
+    
+    /// Forgets the globals an earlier program declared that `ast` declares
+    /// again, so it can; consts and frozen variables stay, keeping the
+    /// redeclaration an error
+    fn release_redeclared(&mut self, ast: &ASTNode) {
+        let ASTNode::Program(statements) = ast else {
+            return;
+        };
+        for statement in statements {
+            if let ASTNode::VarDecl { name, .. } = statement
+                && self.symbol_table.get(name).is_some_and(|var| !var.is_const && !var.is_frozen)
+            {
+                self.symbol_table.remove(name);
+            }
+        }
+    }
+    
+    /// Starts every function's signature off untyped, except that exports
+    /// keep the C signature `double f(double, ...)`
+    fn declare_functions(&mut self, node: &ASTNode) {
+        if let ASTNode::FunctionDecl { name, params, exported, .. } = node {
+            let known = if *exported { FluxType::Number } else { FluxType::Any };
+            self.signatures.entry(name.clone())
+                .or_insert_with(|| FluxType::Function(vec![known.clone(); params.len()], Box::new(known)));
+        }
+        for child in node.children() {
+            self.declare_functions(child);
+        }
+    }
+    
+    /// Fills in the parts of a function's signature that are still untyped;
+    /// the first type learned for each one sticks
+    fn learn_signature(&mut self, name: &str, params: &[FluxType], result: FluxType) {
+        let Some(FluxType::Function(known_params, known_result)) = self.signatures.get_mut(name) else {
+            return;
+        };
+        for (known, learned) in known_params.iter_mut().zip(params) {
+            if *known == FluxType::Any {
+                *known = learned.clone();
+            }
+        }
+        if **known_result == FluxType::Any {
+            **known_result = result;
+        }
+    }
+    
+    /// The type a call to the named user function returns
+    fn result_type(&self, name: &str) -> FluxType {
+        match self.signatures.get(name) {
+            Some(FluxType::Function(_, result)) => result.as_ref().clone(),
+            _ => FluxType::Any,
+        }
+    }
+    
+    fn visit(&mut self, node: &ASTNode) {
+        let enclosing = self.span;
+        if let Some(span) = self.spans.get(&(node as *const ASTNode)) {
+            self.span = Some(*span);
+        }
+        self.visit_node(node);
+        self.span = enclosing;
+    }
+    
+    fn visit_node(&mut self, node: &ASTNode) {
+        let defer_allowed = std::mem::take(&mut self.defer_allowed);
+        let top_level = std::mem::take(&mut self.top_level);
+        let node_type = self.infer_type(node);
+        self.types.insert(node as *const ASTNode, node_type);
+        
+        // Outside statement lists, an `if` or `match` is used for its value
+        if !matches!(node, ASTNode::Program(_) | ASTNode::Block(_) | ASTNode::Comptime(_) | ASTNode::Spawn(_)
+            | ASTNode::Loop(_) | ASTNode::While { .. } | ASTNode::DoWhile { .. } | ASTNode::If { .. }
+            | ASTNode::Match { .. } | ASTNode::FunctionDecl { .. } | ASTNode::ClassDecl { .. } | ASTNode::Watch { .. })
+        {
+            for child in node.children() {
+                self.check_value_arms(child);
+            }
+        }
+        
+        match node {
+            ASTNode::Program(statements) | ASTNode::Block(statements) => {
+                for stmt in statements {
+                    self.defer_allowed = matches!(node, ASTNode::Program(_));
+                    self.top_level = matches!(node, ASTNode::Program(_));
+                    self.visit(stmt);
+                }
+            }
+            
+            // Defers inside loops or branches would run a varying number
+            // of times, and could outlive the variables they read
+            ASTNode::Defer(value) => {
+                if !defer_allowed {
+                    self.error(Diagnostic::MISPLACED, format!(
+                        "`{}` must be a statement directly in a function body or at the top level", node));
+                }
+                self.visit(value);
+            }
+            
+            // Every task and function sees the same clocks, so they're
+            // declared once, up front
+            ASTNode::ClockDomain(name) => {
+                if !top_level {
+                    self.error(Diagnostic::MISPLACED, format!("`{}` must be at the top level", node));
+                } else if !self.clock_domains.insert(name.clone()) {
+                    self.error(Diagnostic::REDECLARED, format!("Clock domain '{}' is already declared", name));
+                }
+            }
+            
+            ASTNode::VarDecl { name, value, is_const, is_temporal, clock, .. } => {
+                let value_type = self.infer_type(value);
+                if let Some(clock) = clock
+                    && !self.clock_domains.contains(clock)
+                {
+                    self.error(Diagnostic::UNDEFINED_NAME, format!(
+                        "Unknown clock domain '{}' for '{}'; declare it first with `timeline domain {}`", clock, name, clock));
+                }
+                
+                if self.symbol_table.contains_key(name) {
+                    self.error(Diagnostic::REDECLARED, format!("Variable '{}' already declared", name));
+                    return;
+                }
+                
+                let var = Variable {
+                    name: name.clone(),
+                    flux_type: if *is_temporal { 
+                        FluxType::Temporal(Box::new(value_type)) 
+                    } else { 
+                        value_type 
+                    },
+                    is_const: *is_const,
+                    is_temporal: *is_temporal,
+                    is_frozen: false,
+                    timeline: vec![(self.timestamp, self.infer_type(value))],
+                };
+                
+                self.symbol_table.insert(name.clone(), var);
+                self.visit(value);
+            }
+            
+            ASTNode::Assignment { name, value } => {
+                if let Some(var) = self.symbol_table.get(name) {
+                    if var.is_const {
+                        let error = Diagnostic::error(Diagnostic::IMMUTABLE, format!("Cannot reassign to const variable '{}'", name))
+                            .with_suggestion("declare it with `let` to allow reassignment");
+                        self.report(error);
+                        return;
+                    }
+                    if var.is_frozen {
+                        self.error(Diagnostic::IMMUTABLE, format!("Cannot modify frozen variable '{}'", name));
+                        return;
+                    }
+                } else {
+                    self.undefined(format!("Undefined variable '{}'", name), name);
+                }
+                
+                self.visit(value);
+            }
+            
+            ASTNode::TemporalAccess { var, timestamp } => {
+                if let Some(variable) = self.symbol_table.get(var) {
+                    if !variable.is_temporal {
+                        self.error(Diagnostic::NOT_TEMPORAL, format!("Variable '{}' is not temporal", var));
+                    }
+                } else {
+                    self.undefined(format!("Undefined variable '{}'", var), var);
+                }
+                
+                // Indices count whole clock ticks, or updates when negative
+                let literal = match timestamp.as_ref() {
+                    ASTNode::Number(n) => Some(*n),
+                    ASTNode::Unary { operator, operand } if operator == "-" => match operand.as_ref() {
+                        ASTNode::Number(n) => Some(-n),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some(index) = literal
+                    && index.fract() != 0.0
+                {
+                    self.error(Diagnostic::INVALID_ARGUMENT, format!("Temporal index for '{}' must be a whole number, found {}", var, index));
+                }
+                // Strings name a checkpoint; anything else can't be a time
+                match self.infer_type(timestamp) {
+                    FluxType::Number | FluxType::String | FluxType::Any => {}
+                    other => self.error(Diagnostic::TYPE_MISMATCH, format!(
+                        "Temporal index for '{}' must be a number or checkpoint name, found {}", var, other)),
+                }
+                
+                self.visit(timestamp);
+            }
+            
+            ASTNode::Watch { var, body } => {
+                match self.symbol_table.get(var) {
+                    Some(variable) if !variable.is_temporal => {
+                        self.error(Diagnostic::NOT_TEMPORAL, format!("Cannot watch '{}': it is not a temporal variable", var));
+                    }
+                    Some(_) => {}
+                    None => self.undefined(format!("Undefined variable '{}'", var), var),
+                }
+                
+                for stmt in body {
+                    self.visit(stmt);
+                }
+            }
+            
+            ASTNode::FunctionDecl { name, params, body, exported, .. } => {
+                // Exports keep their plain name, so it must not clash with
+                // the C entry point, the runtime's own symbols or the C
+                // functions it calls
+                if *exported && (name == "main" || name.starts_with("flux_")
+                    || CodeGenerator::EXTERNAL_SYMBOLS.contains(&name.as_str()))
+                {
+                    self.error(Diagnostic::INVALID_EXPORT, format!("Cannot export '{}': the name is reserved for the runtime", name));
+                }
+                if *exported && !name.is_ascii() {
+                    self.error(Diagnostic::INVALID_EXPORT, format!("Cannot export '{}': exported names must be ASCII to form a C symbol", name));
+                }
+                if *exported && self.current_scope > 0 {
+                    self.error(Diagnostic::INVALID_EXPORT, format!("Cannot export '{}': only top-level functions can be exported", name));
+                }
+                
+                // Create new scope for function; `break` can't leave it
+                self.current_scope += 1;
+                let enclosing_loops = std::mem::take(&mut self.loop_depth);
+                let enclosing_labels = std::mem::take(&mut self.loop_labels);
+                
+                // Parameters hide any variables of the same name until the
+                // function ends, and its locals go away then
+                let outer: HashSet<String> = self.symbol_table.keys().cloned().collect();
+                let param_types = match self.signatures.get(name) {
+                    Some(FluxType::Function(param_types, _)) => param_types.clone(),
+                    _ => Vec::new(),
+                };
+                let mut hidden = Vec::new();
+                for (i, param) in params.iter().enumerate() {
+                    let flux_type = param_types.get(i).cloned().unwrap_or(FluxType::Any);
+                    let var = Variable {
+                        name: param.clone(),
+                        flux_type: flux_type.clone(),
+                        is_const: false,
+                        is_temporal: false,
+                        is_frozen: false,
+                        timeline: vec![(self.timestamp, flux_type)],
+                    };
+                    hidden.push((param, self.symbol_table.insert(param.clone(), var)));
+                }
+                
+                for stmt in body {
+                    self.defer_allowed = true;
+                    self.visit(stmt);
+                }
+                self.loop_depth = enclosing_loops;
+                self.loop_labels = enclosing_labels;
+                self.check_returns(name, body);
+                
+                let mut returns = Vec::new();
+                self.collect_returns(body, "the function body", &mut returns);
+                let result = returns.into_iter()
+                    .map(|(flux_type, _)| flux_type)
+                    .find(|flux_type| *flux_type != FluxType::Any)
+                    .unwrap_or(FluxType::Any);
+                self.learn_signature(name, &[], result);
+                
+                self.symbol_table.retain(|name, _| outer.contains(name));
+                for (param, var) in hidden {
+                    if let Some(var) = var {
+                        self.symbol_table.insert(param.clone(), var);
+                    }
+                }
+                self.current_scope -= 1;
+            }
+            
+            ASTNode::Spawn(body) => {
+                let enclosing_loops = std::mem::take(&mut self.loop_depth);
+                let enclosing_labels = std::mem::take(&mut self.loop_labels);
+                for stmt in body {
+                    self.visit(stmt);
+                }
+                self.loop_depth = enclosing_loops;
+                self.loop_labels = enclosing_labels;
+                
+                // Like the backends, a trailing expression is the task's result
+                if let Some(last) = body.last()
+                    && CodeGenerator::is_expression(last)
+                {
+                    let result = self.infer_type(last);
+                    let known = self.task_results.entry(node as *const ASTNode).or_insert(FluxType::Any);
+                    if *known == FluxType::Any {
+                        *known = result;
+                    }
+                }
+            }
+            
+            ASTNode::While { .. } | ASTNode::Loop(_) | ASTNode::DoWhile { .. } => {
+                self.loop_depth += 1;
+                for child in node.children() {
+                    self.visit(child);
+                }
+                self.loop_depth -= 1;
+            }
+            
+            ASTNode::Labeled { label, body } => {
+                if self.loop_labels.contains(label) {
+                    self.error(Diagnostic::REDECLARED, format!("Loop label '{}' is already in use by an enclosing loop", label));
+                }
+                self.loop_labels.push(label.clone());
+                self.visit(body);
+                self.loop_labels.pop();
+            }
+            
+            ASTNode::Break(None) => {
+                if self.loop_depth == 0 {
+                    self.error(Diagnostic::MISPLACED, "`break` outside of a loop".to_string());
+                }
+            }
+            
+            ASTNode::Break(Some(label)) => {
+                if !self.loop_labels.contains(label) {
+                    let error = Diagnostic::error(Diagnostic::UNKNOWN_LABEL, format!("Unknown loop label '{}' in `break {}`", label, label))
+                        .with_suggestion("label the enclosing loop, as in `outer: while ... { ... }`");
+                    self.report(error);
+                }
+            }
+            
+            ASTNode::Binary { left, operator: _, right } => {
+                self.visit(left);
+                self.visit(right);
+            }
+            
+            ASTNode::Call { callee, args } => {
+                if let ASTNode::Identifier(name) = callee.as_ref()
+                    && matches!(name.as_str(), "format" | "println")
+                {
+                    self.check_format_call(name, args);
+                }
+                if let ASTNode::Identifier(name) = callee.as_ref()
+                    && matches!(name.as_str(), "history" | "changes" | "value_at")
+                {
+                    self.check_temporal_call(name, args);
+                }
+                // Names a variable rather than reading it, so it may come
+                // before the declaration
+                if matches!(callee.as_ref(), ASTNode::Identifier(name) if name == "keep_timeline") {
+                    for arg in args.iter().filter(|arg| !matches!(arg, ASTNode::Identifier(_))) {
+                        self.error(Diagnostic::INVALID_ARGUMENT, format!("keep_timeline() expects variable names, found `{}`", arg));
+                    }
+                    return;
+                }
+                if matches!(callee.as_ref(), ASTNode::Identifier(name) if name == "freeze")
+                    && let [ASTNode::Identifier(name)] = args.as_slice()
+                {
+                    match self.symbol_table.get_mut(name) {
+                        Some(var) => var.is_frozen = true,
+                        None => self.undefined(format!("Cannot freeze undefined variable '{}'", name), name),
+                    }
+                }
+                // Compiled checkpoints are one global per label
+                if matches!(callee.as_ref(), ASTNode::Identifier(name) if name == "checkpoint")
+                    && !matches!(args.first(), Some(ASTNode::String(_)) | None)
+                {
+                    self.error(Diagnostic::INVALID_ARGUMENT, "checkpoint() requires a string literal label".to_string());
+                }
+                if let ASTNode::Identifier(name) = callee.as_ref() {
+                    let expected = match name.as_str() {
+                        "channel" | "time" | "random" | "input" => Some(0),
+                        "join" | "recv" | "set_precision" | "char_code" | "from_char_code"
+                        | "checkpoint" | "rollback" | "is_frozen" | "exit" | "panic" | "read_file"
+                        | "typeof" | "fields" | "methods" => Some(1),
+                        "send" => Some(2),
+                        _ => None,
+                    };
+                    if let Some(expected) = expected
+                        && args.len() != expected
+                    {
+                        self.error(Diagnostic::ARGUMENT_COUNT, format!("{}() takes exactly {} argument{}, found {}",
+                                                                      name, expected, if expected == 1 { "" } else { "s" }, args.len()));
+                    }
+                    
+                    let arg_types: Vec<FluxType> = args.iter().map(|arg| self.infer_type(arg)).collect();
+                    self.learn_signature(name, &arg_types, FluxType::Any);
+                }
+                
+                match callee.as_ref() {
+                    ASTNode::Identifier(name) => self.check_function(name),
+                    _ => self.visit(callee),
+                }
+                for arg in args {
+                    self.visit(arg);
+                }
+            }
+            
+            ASTNode::Pipeline(exprs) => {
+                // Each stage is called with the value piped into it first
+                let mut piped = exprs.first().map(|first| self.infer_type(first)).unwrap_or(FluxType::Any);
+                for stage in exprs.iter().skip(1) {
+                    let (name, args) = match stage {
+                        ASTNode::Identifier(name) => (name, &[][..]),
+                        ASTNode::Call { callee, args } => match callee.as_ref() {
+                            ASTNode::Identifier(name) => (name, args.as_slice()),
+                            _ => break,
+                        },
+                        _ => break,
+                    };
+                    let arg_types: Vec<FluxType> = std::iter::once(piped)
+                        .chain(args.iter().map(|arg| self.infer_type(arg)))
+                        .collect();
+                    self.learn_signature(name, &arg_types, FluxType::Any);
+                    piped = self.result_type(name);
+                }
+                
+                // A stage named on its own is the function it calls
+                for (i, expr) in exprs.iter().enumerate() {
+                    match expr {
+                        ASTNode::Identifier(name) if i > 0 => self.check_function(name),
+                        ASTNode::ParallelStage(stage) if matches!(stage.as_ref(), ASTNode::Identifier(_)) => {
+                            if let ASTNode::Identifier(name) = stage.as_ref() {
+                                self.check_function(name);
+                            }
+                        }
+                        _ => self.visit(expr),
+                    }
+                }
+            }
+            
+            ASTNode::FieldAssign { object, field, value } => {
+                // Freezing is deep, so fields of fields are frozen too
+                let mut root = object.as_ref();
+                while let ASTNode::MemberAccess { object, .. } | ASTNode::Index { object, .. } = root {
+                    root = object;
+                }
+                if let ASTNode::Identifier(name) = root
+                    && self.symbol_table.get(name).is_some_and(|var| var.is_frozen)
+                {
+                    self.error(Diagnostic::IMMUTABLE, format!("Cannot modify field '{}' of frozen object '{}'", field, name));
+                }
+                self.visit(object);
+                self.visit(value);
+            }
+            
+            ASTNode::Index { object, index } => {
+                match self.infer_type(object) {
+                    FluxType::String | FluxType::Array(_) | FluxType::Any => {}
+                    other => self.error(Diagnostic::TYPE_MISMATCH, format!("Cannot index into `{}` of type {}", object, other)),
+                }
+                match self.infer_type(index) {
+                    FluxType::Number | FluxType::Any => {}
+                    other => self.error(Diagnostic::TYPE_MISMATCH, format!("Index `{}` must be a number, found {}", index, other)),
+                }
+                self.visit(object);
+                self.visit(index);
+            }
+            
+            // The parser fills in every placeholder it can, so one left over
+            // isn't directly in a pipeline stage's arguments
+            ASTNode::Identifier(name) if name == "_" && !self.symbol_table.contains_key(name) => {
+                self.error(Diagnostic::MISPLACED,
+                    "`_` only stands for the piped value, as an argument of a `|` stage like `x | f(1, _)`".to_string());
+            }
+            
+            // `default` is the catch-all `match` case rather than a read
+            ASTNode::Identifier(name) => {
+                let defined = self.symbol_table.contains_key(name)
+                    || matches!(name.as_str(), "this" | "default")
+                    || (self.current_scope > 0 && self.globals.contains(name));
+                if !defined {
+                    self.undefined(format!("Undefined variable '{}'", name), name);
+                }
+            }
+            
+            ASTNode::Match { expr, cases } => {
+                for warning in PatternMatcher::overlaps(expr, cases) {
+                    let warning = Diagnostic::new(Diagnostic::OVERLAPPING_PATTERN, Severity::Warning, warning);
+                    self.warnings.push(self.located(warning));
+                }
+                for child in node.children() {
+                    self.visit(child);
+                }
+            }
+            
+            _ => {
+                for child in node.children() {
+                    self.visit(child);
+                }
+            }
+        }
+        
+        self.timestamp += 1;
+    }
+    
+    /// Reports an error at the statement being checked
+    fn error(&mut self, code: &'static str, message: String) {
+        self.report(Diagnostic::error(code, message));
+    }
+    
+    fn report(&mut self, error: Diagnostic) {
+        let error = self.located(error);
+        self.errors.push(error);
+    }
+    
+    fn located(&self, diagnostic: Diagnostic) -> Diagnostic {
+        match self.span {
+            Some(span) => diagnostic.at(span),
+            None => diagnostic,
+        }
+    }
+    
+    /// Reports that `name` isn't defined, suggesting the variable, function
+    /// or builtin closest to it
+    fn undefined(&mut self, message: String, name: &str) {
+        let candidates = self.symbol_table.keys()
+            .chain(self.signatures.keys())
+            .chain(&self.builtins)
+            .map(String::as_str);
+        let error = match Diagnostic::closest_name(name, candidates).map(str::to_string) {
+            Some(candidate) => Diagnostic::error(Diagnostic::UNDEFINED_NAME, format!("{} (did you mean '{}'?)", message, candidate))
+                .with_suggestion(format!("replace it with `{}`", candidate)),
+            None => Diagnostic::error(Diagnostic::UNDEFINED_NAME, message),
+        };
+        self.report(error);
+    }
+    
+    fn check_function(&mut self, name: &str) {
+        if !self.signatures.contains_key(name) && !self.builtins.contains(name) {
+            self.undefined(format!("Undefined function '{}'", name), name);
+        }
+    }
+    
+    /// Format templates must be literals so codegen can translate them
+    fn check_format_call(&mut self, name: &str, args: &[ASTNode]) {
+        let Some(ASTNode::String(template)) = args.first() else {
+            self.error(Diagnostic::INVALID_ARGUMENT, format!("{}() requires a string literal template", name));
+            return;
+        };
+        
+        match FluxStdLib::parse_format(template) {
+            Ok(pieces) => {
+                let placeholders = pieces.iter()
+                    .filter(|piece| matches!(piece, FormatPiece::Placeholder { .. }))
+                    .count();
+                if placeholders != args.len() - 1 {
+                    self.error(Diagnostic::ARGUMENT_COUNT, format!("{}() template has {} placeholders but {} arguments were given",
+                                                                  name, placeholders, args.len() - 1));
+                }
+            }
+            Err(error) => self.error(Diagnostic::INVALID_ARGUMENT, format!("{}(): {}", name, error)),
+        }
+    }
+    
+    /// Temporal introspection reads a variable's timeline, so it needs the
+    /// variable itself rather than its current value
+    fn check_temporal_call(&mut self, name: &str, args: &[ASTNode]) {
+        let expected = if name == "value_at" { 2 } else { 1 };
+        if args.len() != expected {
+            self.error(Diagnostic::ARGUMENT_COUNT, format!("{}() takes exactly {} argument{}, found {}",
+                                                          name, expected, if expected == 1 { "" } else { "s" }, args.len()));
+            return;
+        }
+        
+        let ASTNode::Identifier(var) = &args[0] else {
+            self.error(Diagnostic::NOT_TEMPORAL, format!("{}() expects a temporal variable, found `{}`", name, args[0]));
+            return;
+        };
+        if let Some(variable) = self.symbol_table.get(var)
+            && !variable.is_temporal
+        {
+            self.error(Diagnostic::NOT_TEMPORAL, format!("{}() expects a temporal variable, but '{}' is not temporal", name, var));
+        }
+    }
+    
+    /// The arms of an `if` or `match`, each with a label for diagnostics
+    fn value_arms(node: &ASTNode) -> Vec<(String, &[ASTNode])> {
+        match node {
+            ASTNode::If { condition, then_branch, else_ifs, else_branch } => {
+                let mut arms = vec![(format!("the then branch of `if {}`", condition), then_branch.as_slice())];
+                for (else_condition, body) in else_ifs {
+                    arms.push((format!("the branch of `else if {}`", else_condition), body));
+                }
+                if let Some(else_stmts) = else_branch {
+                    arms.push((format!("the else branch of `if {}`", condition), else_stmts));
+                }
+                arms
+            }
+            ASTNode::Match { expr, cases } => cases.iter()
+                .map(|(pattern, body)| (format!("case `{}` of `match {}`", pattern, expr), body.as_slice()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+    
+    /// The statement an arm's value comes from: its last one, unless that
+    /// is a declaration or loop, or leaves the arm with `return` or `break`
+    fn arm_value(body: &[ASTNode]) -> Option<&ASTNode> {
+        body.last().filter(|last| !matches!(last, ASTNode::VarDecl { .. } | ASTNode::Assignment { .. }
+            | ASTNode::FieldAssign { .. } | ASTNode::FunctionDecl { .. } | ASTNode::ClassDecl { .. }
+            | ASTNode::Return(_) | ASTNode::Break(_) | ASTNode::While { .. } | ASTNode::Loop(_)
+            | ASTNode::DoWhile { .. } | ASTNode::Labeled { .. } | ASTNode::Watch { .. }))
+    }
+    
+    fn diverges(body: &[ASTNode]) -> bool {
+        matches!(body.last(), Some(ASTNode::Return(_) | ASTNode::Break(_)))
+            || body.last().is_some_and(Self::ends_program)
+    }
+    
+    /// `exit(...)` and `panic(...)` never come back to their caller
+    fn ends_program(stmt: &ASTNode) -> bool {
+        matches!(stmt, ASTNode::Call { callee, .. }
+            if matches!(callee.as_ref(), ASTNode::Identifier(name) if name == "exit" || name == "panic"))
+    }
+    
+    /// An `if` or `match` used as a value must produce one on every path,
+    /// and its arms must agree on the type
+    fn check_value_arms(&mut self, node: &ASTNode) {
+        let (exhaustive, missing, fix) = match node {
+            ASTNode::If { condition, else_branch, .. } => (
+                else_branch.is_some(),
+                format!("`if {}` is used as a value, so it needs an `else` branch", condition),
+                "add an `else` branch",
+            ),
+            ASTNode::Match { expr, cases } => (
+                cases.iter().any(|(pattern, _)| matches!(pattern, ASTNode::Identifier(name) if name == "default")),
+                format!("`match {}` is used as a value, so it needs a `default` case", expr),
+                "add a `default => ...` arm",
+            ),
+            _ => return,
+        };
+        if !exhaustive {
+            self.report(Diagnostic::error(Diagnostic::MISSING_VALUE, missing).with_suggestion(fix));
+        }
+        
+        let mut first: Option<(FluxType, String)> = None;
+        for (label, body) in Self::value_arms(node) {
+            if Self::diverges(body) {
+                continue;
+            }
+            let Some(value) = Self::arm_value(body) else {
+                self.error(Diagnostic::MISSING_VALUE, format!("{} ends without a value", label));
+                continue;
+            };
+            self.check_value_arms(value);
+            
+            let arm_type = self.infer_type(value);
+            if arm_type == FluxType::Any {
+                continue;
+            }
+            match &first {
+                Some((first_type, first_label)) if *first_type != arm_type => {
+                    self.error(Diagnostic::TYPE_MISMATCH, format!("Arms yield conflicting types: {} in {}, but {} in {}",
+                                                                 first_type, first_label, arm_type, label));
+                }
+                Some(_) => {}
+                None => first = Some((arm_type, label)),
+            }
+        }
+    }
+    
+    /// Reports functions that only return a value on some paths, or whose
+    /// returns disagree on the type of value produced
+    fn check_returns(&mut self, function: &str, body: &[ASTNode]) {
+        let mut returns = Vec::new();
+        self.collect_returns(body, "the function body", &mut returns);
+        
+        if returns.is_empty() {
+            return; // Procedures never return a value
+        }
+        
+        if let ReturnCoverage::Partial(branch) = Self::return_coverage(body) {
+            self.error(Diagnostic::MISSING_VALUE, format!(
+                "Function '{}' does not return a value on all paths: missing return in {}",
+                function, branch));
+        }
+        
+        let mut concrete = returns.iter().filter(|(flux_type, _)| *flux_type != FluxType::Any);
+        if let Some((first_type, first_branch)) = concrete.next()
+            && let Some((other_type, other_branch)) = concrete.find(|(t, _)| t != first_type)
+        {
+            self.error(Diagnostic::TYPE_MISMATCH, format!(
+                "Function '{}' returns conflicting types: {} in {}, but {} in {}",
+                function, first_type, first_branch, other_type, other_branch));
+        }
+    }
+    
+    fn collect_returns(&self, stmts: &[ASTNode], branch: &str, returns: &mut Vec<(FluxType, String)>) {
+        for stmt in stmts {
+            match stmt {
+                ASTNode::Return(value) => returns.push((self.infer_type(value), branch.to_string())),
+                ASTNode::If { condition, then_branch, else_ifs, else_branch } => {
+                    self.collect_returns(then_branch, &format!("the then branch of `if {}`", condition), returns);
+                    for (else_condition, body) in else_ifs {
+                        self.collect_returns(body, &format!("the branch of `else if {}`", else_condition), returns);
+                    }
+                    if let Some(else_stmts) = else_branch {
+                        self.collect_returns(else_stmts, &format!("the else branch of `if {}`", condition), returns);
+                    }
+                }
+                ASTNode::While { condition, body } => {
+                    self.collect_returns(body, &format!("the body of `while {}`", condition), returns);
+                }
+                ASTNode::Loop(body) => self.collect_returns(body, "the body of `loop`", returns),
+                ASTNode::DoWhile { body, condition } => {
+                    self.collect_returns(body, &format!("the body of `do ... while {}`", condition), returns);
+                }
+                ASTNode::Match { expr, cases } => {
+                    for (pattern, case_body) in cases {
+                        self.collect_returns(case_body, &format!("case `{}` of `match {}`", pattern, expr), returns);
+                    }
+                }
+                ASTNode::Block(body) => self.collect_returns(body, branch, returns),
+                ASTNode::Labeled { body, .. } => self.collect_returns(std::slice::from_ref(body), branch, returns),
+                _ => {}
+            }
+        }
+    }
+    
+    fn return_coverage(stmts: &[ASTNode]) -> ReturnCoverage {
+        let mut coverage = ReturnCoverage::Never;
+        
+        for stmt in stmts {
+            match Self::statement_coverage(stmt) {
+                ReturnCoverage::Always => return ReturnCoverage::Always,
+                ReturnCoverage::Partial(branch) => coverage = ReturnCoverage::Partial(branch),
+                ReturnCoverage::Never => {}
+            }
+        }
+        
+        coverage
+    }
+    
+    fn statement_coverage(stmt: &ASTNode) -> ReturnCoverage {
+        // Combines the coverage of alternative branches into one result
+        fn merge(branches: Vec<(ReturnCoverage, String)>) -> ReturnCoverage {
+            if branches.iter().all(|(coverage, _)| *coverage == ReturnCoverage::Always) {
+                return ReturnCoverage::Always;
+            }
+            if branches.iter().all(|(coverage, _)| *coverage == ReturnCoverage::Never) {
+                return ReturnCoverage::Never;
+            }
+            
+            let missing = branches.into_iter()
+                .find_map(|(coverage, name)| match coverage {
+                    ReturnCoverage::Always => None,
+                    ReturnCoverage::Partial(inner) => Some(inner),
+                    ReturnCoverage::Never => Some(name),
+                });
+            ReturnCoverage::Partial(missing.unwrap_or_default())
+        }
+        
+        match stmt {
+            ASTNode::Return(_) => ReturnCoverage::Always,
+            // Nothing after them runs, so they need no return
+            _ if Self::ends_program(stmt) => ReturnCoverage::Always,
+            ASTNode::Block(body) => Self::return_coverage(body),
+            ASTNode::If { condition, then_branch, else_ifs, else_branch } => {
+                let mut branches = vec![
+                    (Self::return_coverage(then_branch), format!("the then branch of `if {}`", condition)),
+                ];
+                for (else_condition, body) in else_ifs {
+                    branches.push((Self::return_coverage(body), format!("the branch of `else if {}`", else_condition)));
+                }
+                branches.push(match else_branch {
+                    Some(else_stmts) => (Self::return_coverage(else_stmts), 
+                                         format!("the else branch of `if {}`", condition)),
+                    None => (ReturnCoverage::Never, 
+                             format!("the implicit else branch of `if {}`", condition)),
+                });
+                merge(branches)
+            }
+            ASTNode::Match { expr, cases } => {
+                let mut branches: Vec<(ReturnCoverage, String)> = cases.iter()
+                    .map(|(pattern, body)| {
+                        (Self::return_coverage(body), format!("case `{}` of `match {}`", pattern, expr))
+                    })
+                    .collect();
+                
+                let has_default = cases.iter()
+                    .any(|(pattern, _)| matches!(pattern, ASTNode::Identifier(name) if name == "default"));
+                if !has_default {
+                    branches.push((ReturnCoverage::Never, format!("unmatched values of `match {}`", expr)));
+                }
+                merge(branches)
+            }
+            // Like `loop`, `while true` only ends through a `break`
+            ASTNode::While { condition, body }
+                if matches!(condition.as_ref(), ASTNode::Boolean(true)) && !Self::breaks_out(body) => ReturnCoverage::Always,
+            ASTNode::While { condition, body } => match Self::return_coverage(body) {
+                // The loop may exit (or never run) without returning
+                ReturnCoverage::Never => ReturnCoverage::Never,
+                _ => ReturnCoverage::Partial(format!("the exit path of `while {}`", condition)),
+            },
+            // Only a `break` lets control fall out of `loop`
+            ASTNode::Labeled { body, .. } => Self::statement_coverage(body),
+            ASTNode::Loop(body) if !Self::breaks_out(body) => ReturnCoverage::Always,
+            ASTNode::Loop(body) => match Self::return_coverage(body) {
+                ReturnCoverage::Never => ReturnCoverage::Never,
+                _ => ReturnCoverage::Partial("the `break` out of `loop`".to_string()),
+            },
+            // The body runs at least once, so it alone decides unless it breaks
+            ASTNode::DoWhile { body, condition } => match Self::return_coverage(body) {
+                ReturnCoverage::Always if !Self::breaks_out(body) => ReturnCoverage::Always,
+                ReturnCoverage::Never => ReturnCoverage::Never,
+                _ => ReturnCoverage::Partial(format!("the exit path of `do ... while {}`", condition)),
+            },
+            _ => ReturnCoverage::Never,
+        }
+    }
+    
+    /// Whether a loop body contains a `break` that leaves that loop
+    fn breaks_out(stmts: &[ASTNode]) -> bool {
+        Self::breaks_past(stmts, false, &[])
+    }
+    
+    /// A plain `break` only leaves the innermost loop, while `break name`
+    /// leaves every loop up to the one labeled `name`. `nested` says whether
+    /// `stmts` sit in a loop inside the one being checked, and `inner` holds
+    /// the labels of such loops.
+    fn breaks_past(stmts: &[ASTNode], nested: bool, inner: &[&str]) -> bool {
+        stmts.iter().any(|stmt| match stmt {
+            ASTNode::Break(None) => !nested,
+            ASTNode::Break(Some(label)) => !inner.contains(&label.as_str()),
+            ASTNode::If { then_branch, else_ifs, else_branch, .. } => {
+                Self::breaks_past(then_branch, nested, inner)
+                    || else_ifs.iter().any(|(_, body)| Self::breaks_past(body, nested, inner))
+                    || else_branch.as_deref().is_some_and(|body| Self::breaks_past(body, nested, inner))
+            }
+            ASTNode::Match { cases, .. } => cases.iter().any(|(_, body)| Self::breaks_past(body, nested, inner)),
+            ASTNode::Block(body) => Self::breaks_past(body, nested, inner),
+            ASTNode::While { body, .. } | ASTNode::Loop(body) | ASTNode::DoWhile { body, .. } => {
+                Self::breaks_past(body, true, inner)
+            }
+            ASTNode::Labeled { label, body } => {
+                let inner: Vec<&str> = inner.iter().copied().chain([label.as_str()]).collect();
+                Self::breaks_past(std::slice::from_ref(body), nested, &inner)
+            }
+            _ => false,
+        })
+    }
+    
+    fn infer_type(&self, node: &ASTNode) -> FluxType {
+        match node {
+            ASTNode::Number(_) => FluxType::Number,
+            ASTNode::String(_) => FluxType::String,
+            ASTNode::Boolean(_) => FluxType::Boolean,
+            ASTNode::Identifier(name) | ASTNode::TemporalAccess { var: name, .. } => {
+                // Reading a temporal variable yields its current value
+                match self.symbol_table.get(name).map(|var| &var.flux_type) {
+                    Some(FluxType::Temporal(inner)) => inner.as_ref().clone(),
+                    Some(flux_type) => flux_type.clone(),
+                    None => FluxType::Any,
+                }
+            }
+            ASTNode::Unary { operator, .. } => {
+                if operator == "!" { FluxType::Boolean } else { FluxType::Number }
+            }
+            ASTNode::Spawn(_) => {
+                let result = self.task_results.get(&(node as *const ASTNode)).cloned().unwrap_or(FluxType::Any);
+                FluxType::Task(Box::new(result))
+            }
+            // The first arm of known type; `check_value_arms` makes the rest agree
+            ASTNode::If { .. } | ASTNode::Match { .. } => Self::value_arms(node).into_iter()
+                .filter_map(|(_, body)| Self::arm_value(body))
+                .map(|value| self.infer_type(value))
+                .find(|arm_type| *arm_type != FluxType::Any)
+                .unwrap_or(FluxType::Any),
+            ASTNode::New { .. } => FluxType::Object(HashMap::new()),
+            ASTNode::Index { object, .. } => match self.infer_type(object) {
+                FluxType::String => FluxType::String,
+                FluxType::Array(item) => *item,
+                _ => FluxType::Any,
+            },
+            ASTNode::Array(items) => {
+                let item_type = items.first().map(|item| self.infer_type(item)).unwrap_or(FluxType::Any);
+                FluxType::Array(Box::new(item_type))
+            }
+            // Mapping an array in parallel keeps it an array
+            ASTNode::Pipeline(stages) => match stages.first().map(|first| self.infer_type(first)) {
+                Some(FluxType::Array(_)) if stages.iter().any(|stage| matches!(stage, ASTNode::ParallelStage(_))) => {
+                    FluxType::Array(Box::new(FluxType::Any))
+                }
+                Some(_) => match stages.last() {
+                    Some(ASTNode::Identifier(name)) => self.result_type(name),
+                    Some(ASTNode::Call { callee, .. }) => match callee.as_ref() {
+                        ASTNode::Identifier(name) => self.result_type(name),
+                        _ => FluxType::Any,
+                    },
+                    _ => FluxType::Any,
+                },
+                None => FluxType::Any,
+            },
+            ASTNode::Call { callee, args } => match callee.as_ref() {
+                ASTNode::Identifier(name) 
+                    if matches!(name.as_str(), "len" | "byte_len" | "abs" | "max" | "min" | "sqrt") => FluxType::Number,
+                ASTNode::Identifier(name) if matches!(name.as_str(), "format" | "from_char_code" | "typeof") => FluxType::String,
+                ASTNode::Identifier(name) if matches!(name.as_str(), "fields" | "methods") => {
+                    FluxType::Array(Box::new(FluxType::String))
+                }
+                ASTNode::Identifier(name) 
+                    if matches!(name.as_str(), "char_code" | "checkpoint" | "snapshot" | "time" | "random") => FluxType::Number,
+                ASTNode::Identifier(name) if name == "changes" => FluxType::Number,
+                ASTNode::Identifier(name) if matches!(name.as_str(), "rollback" | "is_frozen") => FluxType::Boolean,
+                ASTNode::Identifier(name) if name == "channel" => FluxType::Channel,
+                ASTNode::Identifier(name) if name == "send" => FluxType::Boolean,
+                ASTNode::Identifier(name) if matches!(name.as_str(), "history" | "value_at") => {
+                    let value_type = match args.first() {
+                        Some(var @ ASTNode::Identifier(_)) => self.infer_type(var),
+                        _ => FluxType::Any,
+                    };
+                    if name == "history" { FluxType::Array(Box::new(value_type)) } else { value_type }
+                }
+                ASTNode::Identifier(name) if name == "join" => match args.first().map(|handle| self.infer_type(handle)) {
+                    Some(FluxType::Task(result)) => *result,
+                    _ => FluxType::Any,
+                },
+                ASTNode::Identifier(name) => self.result_type(name),
+                _ => FluxType::Any,
+            },
+            ASTNode::Binary { left, operator, right } => {
+                match operator.as_str() {
+                    "+" if self.infer_type(left) == FluxType::String 
+                        || self.infer_type(right) == FluxType::String => FluxType::String,
+                    "+" | "-" | "*" | "/" | "%" => FluxType::Number,
+                    "==" | "!=" | "<" | ">" | "<=" | ">=" => FluxType::Boolean,
+                    "&&" | "||" => FluxType::Boolean,
+                    _ => FluxType::Any,
+                }
+            }
+            _ => FluxType::Any,
+        }
+    }
+}
+
+// ============================================================================
+// CODE GENERATOR - LLVM IR / Assembly Output
+// ============================================================================
+
+/// An SSA register or constant operand together with its LLVM type
+#[derive(Debug, Clone)]
+struct IRValue {
+    reg: String,
+    ty: &'static str,
+}
+
+impl IRValue {
+    fn new(reg: impl Into<String>, ty: &'static str) -> Self {
+        Self { reg: reg.into(), ty }
+    }
+}
+
+pub struct CodeGenerator<'a> {
+    output: String,
+    functions: String,
+    /// Distinct string literals in first-use order; each becomes one global
+    strings: Vec<String>,
+    string_ids: HashMap<String, usize>,
+    label_counter: usize,
+    temp_counter: usize,
+    task_counter: usize,
+    types: HashMap<*const ASTNode, FluxType>,
+    /// Inferred signature of each user function
+    signatures: HashMap<String, FluxType>,
+    locals: HashMap<String, &'static str>,
+    /// Locals whose values are also recorded in a runtime timeline
+    timelines: HashSet<String>,
+    /// Clock domains declared with `timeline domain`, each ticking its own
+    /// `@flux_clock.<domain>` global
+    clock_domains: Vec<String>,
+    /// Domain of each timeline that records against one rather than
+    /// `@flux_clock`
+    timeline_clocks: HashMap<String, String>,
+    /// `on change` bodies, inlined after each later update to the variable
+    watchers: HashMap<String, Vec<&'a [ASTNode]>>,
+    notifying: HashSet<String>,
+    /// Element type of each channel variable, learned from its sends
+    channel_types: HashMap<String, &'static str>,
+    uses_temporal: bool,
+    uses_channels: bool,
+    uses_number_text: bool,
+    uses_chars: bool,
+    /// Set by `#pragma checked_math`: arithmetic reports division by zero,
+    /// NaN and infinity through the checks in `CHECKED_MATH_RUNTIME`
+    checked_math: bool,
+    /// Constructs the interpreter supports but this backend can't lower yet
+    errors: Vec<Diagnostic>,
+    dump_state: bool,
+    /// Where each statement starts, keyed by node address; each gets a
+    /// `; flux line:column` comment ahead of its code for `SourceMap`
+    statement_spans: HashMap<*const ASTNode, Span>,
+    return_type: &'static str,
+    /// End label of each enclosing loop; `break` jumps to the innermost
+    loop_exits: Vec<String>,
+    /// Each enclosing labeled loop's name and exit label
+    loop_labels: Vec<(String, String)>,
+    /// Function being emitted (`main` for top-level code), used to mangle locals
+    scope: String,
+    /// `alloca`s of the function being emitted, hoisted into its entry block
+    allocas: String,
+    /// Functions declared with `export func`
+    exports: HashSet<String>,
+    /// Checkpoint labels; each gets a global holding the clock when last reached
+    checkpoints: Vec<String>,
+    /// Variables declared in each enclosing block; their lifetimes end with it
+    block_locals: Vec<Vec<String>>,
+    /// Parameter count of the `func main` the C entry point calls, if any
+    entry_params: Option<usize>,
+    /// Defers of the function being emitted, each with the `i1` slot set
+    /// once it has been reached
+    deferred: Vec<(String, &'a ASTNode)>,
+    /// For a function that defers, the label of its cleanup block and the
+    /// slot holding its return value until then
+    cleanup: Option<(String, Option<String>)>,
+    /// Set for modules that both defer and panic: a panic raises
+    /// `@flux_panicking` and returns through every caller's cleanup block,
+    /// as the interpreter unwinds, and `main` aborts once it's back
+    unwinds: bool,
+}
+
+impl Default for CodeGenerator<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> CodeGenerator<'a> {
+    /// C functions modules declare, besides LLVM intrinsics; exports can't
+    /// take these names
+    const EXTERNAL_SYMBOLS: &'static [&'static str] = &[
+        "printf", "snprintf", "dprintf", "malloc", "realloc", "free", "strlen", "strcpy", "strcat",
+        "strcmp", "exit", "abort", "fflush", "strtod", "memcpy", "pthread_create", "pthread_join",
+        "pthread_mutex_init", "pthread_mutex_lock", "pthread_mutex_unlock", "pthread_cond_init",
+        "pthread_cond_wait", "pthread_cond_signal",
+    ];
+    
+    pub fn new() -> Self {
+        Self {
+            output: String::new(),
+            functions: String::new(),
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+            label_counter: 0,
+            temp_counter: 0,
+            task_counter: 0,
+            types: HashMap::new(),
+            signatures: HashMap::new(),
+            locals: HashMap::new(),
+            timelines: HashSet::new(),
+            clock_domains: Vec::new(),
+            timeline_clocks: HashMap::new(),
+            watchers: HashMap::new(),
+            notifying: HashSet::new(),
+            channel_types: HashMap::new(),
+            uses_temporal: false,
+            uses_channels: false,
+            uses_number_text: false,
+            uses_chars: false,
+            checked_math: false,
+            errors: Vec::new(),
+            dump_state: false,
+            statement_spans: HashMap::new(),
+            return_type: "void",
+            loop_exits: Vec::new(),
+            loop_labels: Vec::new(),
+            scope: "main".to_string(),
+            allocas: String::new(),
+            exports: HashSet::new(),
+            checkpoints: Vec::new(),
+            block_locals: Vec::new(),
+            entry_params: None,
+            deferred: Vec::new(),
+            cleanup: None,
+            unwinds: false,
+        }
+    }
+    
+    /// Ends `flux_main` by writing each scalar top-level variable to stderr
+    /// as `name=value`
+    pub fn with_state_dump(mut self) -> Self {
+        self.dump_state = true;
+        self
+    }
+    
+    /// Marks where the code of each statement in `spans` begins, like
+    /// `SemanticAnalyzer::with_spans`
+    pub fn with_source_marks(mut self, spans: HashMap<*const ASTNode, Span>) -> Self {
+        self.statement_spans = spans;
+        self
+    }
+    
+    pub fn generate(&mut self, typed: &TypedAST<'a>) -> Result<String, Vec<Diagnostic>> {
+        self.types = typed.types.clone();
+        self.signatures = typed.signatures.clone();
+        if let ASTNode::Program(statements) = typed.ast {
+            self.exports = statements.iter()
+                .filter_map(|stmt| match stmt {
+                    ASTNode::FunctionDecl { name, exported: true, .. } => Some(name.clone()),
+                    _ => None,
+                })
+                .collect();
+            // Checkpoints record every clock, including domains declared later
+            self.clock_domains = statements.iter()
+                .filter_map(|stmt| match stmt {
+                    ASTNode::ClockDomain(name) => Some(name.clone()),
+                    _ => None,
+                })
+                .collect();
+        }
+        self.entry_params = typed.ast.entry_params();
+        self.checked_math = typed.ast.checks_math();
+        self.unwinds = typed.ast.contains(&|node| matches!(node, ASTNode::Defer(_)))
+            && typed.ast.contains(&|node| matches!(node, ASTNode::Call { callee, .. }
+                if matches!(callee.as_ref(), ASTNode::Identifier(name) if name == "panic")));
+        self.visit(typed.ast);
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        
+        // Function bodies are complete; assemble the module around them
+        let functions = std::mem::take(&mut self.functions);
+        self.emit_header();
+        self.emit_string_table();
+        if self.uses_temporal {
+            self.output.push_str(Self::TEMPORAL_RUNTIME);
+            for domain in &self.clock_domains {
+                self.output.push_str(&format!("@flux_clock.{} = internal global double 0.0\n", Self::ascii_name(domain)));
+            }
+            for i in 0..self.checkpoints.len() {
+                self.output.push_str(&format!("@flux_checkpoint.{} = internal global double -1.0\n", i));
+                for domain in &self.clock_domains {
+                    self.output.push_str(&format!("@flux_checkpoint.{}.{} = internal global double -1.0\n",
+                                                 i, Self::ascii_name(domain)));
+                }
+            }
+            self.output.push('\n');
+        }
+        if self.uses_channels {
+            self.output.push_str(Self::CHANNEL_RUNTIME);
+        }
+        // Timelines show recorded numbers for `history`
+        if self.uses_number_text || self.uses_temporal {
+            self.output.push_str(Self::NUMBER_RUNTIME);
+        }
+        if self.uses_chars {
+            self.output.push_str(Self::CHAR_RUNTIME);
+        }
+        if self.checked_math {
+            self.output.push_str(Self::CHECKED_MATH_RUNTIME);
+        }
+        if self.entry_params == Some(1) {
+            self.output.push_str(Self::ARGS_RUNTIME);
+        }
+        if self.unwinds {
+            self.output.push_str("@flux_panicking = internal global i1 false\n");
+            self.output.push_str("@flux_panic_reported = internal global i1 false\n\n");
+        }
+        if self.task_counter > 0 {
+            self.output.push_str("declare i32 @pthread_create(i64*, i8*, i8* (i8*)*, i8*)\n");
+            self.output.push_str("declare i32 @pthread_join(i64, i8**)\n\n");
+        }
+        self.output.push_str(&functions);
+        self.emit_footer();
+        Ok(self.output.clone())
+    }
+    
+    fn emit_header(&mut self) {
+        self.output.push_str("; Flux Language - Generated LLVM IR\n");
+        self.output.push_str("target triple = \"x86_64-pc-linux-gnu\"\n\n");
+        
+        // Declare external functions
+        self.output.push_str("declare i32 @printf(i8*, ...)\n");
+        self.output.push_str("declare i32 @snprintf(i8*, i64, i8*, ...)\n");
+        self.output.push_str("declare i32 @dprintf(i32, i8*, ...)\n");
+        self.output.push_str("declare i8* @malloc(i64)\n");
+        self.output.push_str("declare i8* @realloc(i8*, i64)\n");
+        self.output.push_str("declare void @free(i8*)\n");
+        self.output.push_str("declare i64 @strlen(i8*)\n");
+        self.output.push_str("declare i8* @strcpy(i8*, i8*)\n");
+        self.output.push_str("declare i8* @strcat(i8*, i8*)\n");
+        self.output.push_str("declare i32 @strcmp(i8*, i8*)\n");
+        self.output.push_str("declare void @exit(i32)\n");
+        self.output.push_str("declare void @abort()\n");
+        self.output.push_str("declare i32 @fflush(i8*)\n");
+        self.output.push_str("declare void @llvm.lifetime.start.p0i8(i64 immarg, i8* nocapture)\n");
+        self.output.push_str("declare void @llvm.lifetime.end.p0i8(i64 immarg, i8* nocapture)\n\n");
+        
+        // Global format strings
+        self.output.push_str("@.str_str = private unnamed_addr constant [4 x i8] c\"%s\\0A\\00\"\n");
+        self.output.push_str("@.str_bool_true = private unnamed_addr constant [6 x i8] c\"true\\0A\\00\"\n");
+        self.output.push_str("@.str_bool_false = private unnamed_addr constant [7 x i8] c\"false\\0A\\00\"\n\n");
+        
+        // Temporal tracking structure
+        self.output.push_str("%temporal_entry = type { double, i8* }\n");
+        self.output.push_str("%temporal_var = type { i32, %temporal_entry* }\n\n");
+    }
+    
+    /// Renders numbers for `print` and `{}` placeholders. `@flux_precision`
+    /// holds the `set_precision` setting: fixed decimals when non-negative,
+    /// otherwise `@flux_number_string`, which converts numbers everywhere
+    /// else the way the interpreter does: whole numbers plainly and others
+    /// with the fewest significant digits that read back as the same value.
+    const NUMBER_RUNTIME: &'static str = r#"@flux_precision = internal global i32 -1
+@.str_fmt_fixed = private unnamed_addr constant [5 x i8] c"%.*f\00"
+@.str_fmt_shortest = private unnamed_addr constant [5 x i8] c"%.*g\00"
+
+declare double @strtod(i8*, i8**)
+declare double @llvm.trunc.f64(double)
+declare double @llvm.fabs.f64(double)
+
+define internal i8* @flux_number_text(double %n) {
+entry:
+  %precision = load i32, i32* @flux_precision
+  %fixed = icmp sge i32 %precision, 0
+  br i1 %fixed, label %print_fixed, label %print_shortest
+print_fixed:
+  %buffer = call i8* @malloc(i64 512)
+  %fixed_written = call i32 (i8*, i64, i8*, ...) @snprintf(i8* %buffer, i64 512, i8* getelementptr inbounds ([5 x i8], [5 x i8]* @.str_fmt_fixed, i32 0, i32 0), i32 %precision, double %n)
+  ret i8* %buffer
+print_shortest:
+  %text = call i8* @flux_number_string(double %n)
+  ret i8* %text
+}
+
+define internal i8* @flux_number_string(double %n) {
+entry:
+  %buffer = call i8* @malloc(i64 512)
+  br label %check_whole
+check_whole:
+  %truncated = call double @llvm.trunc.f64(double %n)
+  %whole = fcmp oeq double %truncated, %n
+  %magnitude = call double @llvm.fabs.f64(double %n)
+  %exact = fcmp olt double %magnitude, 1.000000e+16
+  %plain = and i1 %whole, %exact
+  br i1 %plain, label %print_whole, label %search
+print_whole:
+  %whole_written = call i32 (i8*, i64, i8*, ...) @snprintf(i8* %buffer, i64 512, i8* getelementptr inbounds ([5 x i8], [5 x i8]* @.str_fmt_fixed, i32 0, i32 0), i32 0, double %n)
+  ret i8* %buffer
+search:
+  %digits = phi i32 [1, %check_whole], [%more, %retry]
+  %written = call i32 (i8*, i64, i8*, ...) @snprintf(i8* %buffer, i64 512, i8* getelementptr inbounds ([5 x i8], [5 x i8]* @.str_fmt_shortest, i32 0, i32 0), i32 %digits, double %n)
+  %parsed = call double @strtod(i8* %buffer, i8** null)
+  %same = fcmp oeq double %parsed, %n
+  %exhausted = icmp sge i32 %digits, 17
+  %done = or i1 %same, %exhausted
+  br i1 %done, label %finish, label %retry
+retry:
+  %more = add i32 %digits, 1
+  br label %search
+finish:
+  ret i8* %buffer
+}
+
+"#;
+    
+    /// Checks for `#pragma checked_math`, passed the failing expression's
+    /// source text. Each reports the error and exits as the interpreter's
+    /// would; a value that isn't finite is the one value `x - x` isn't 0 for.
+    const CHECKED_MATH_RUNTIME: &'static str = r#"@.str_division_by_zero = private unnamed_addr constant [26 x i8] c"Division by zero in `%s`\0A\00"
+@.str_produced_nan = private unnamed_addr constant [19 x i8] c"`%s` produced NaN\0A\00"
+@.str_overflowed = private unnamed_addr constant [29 x i8] c"`%s` overflowed to infinity\0A\00"
+
+define internal double @flux_checked_divisor(double %divisor, i8* %expr) {
+entry:
+  %zero = fcmp oeq double %divisor, 0.0
+  br i1 %zero, label %fail, label %ok
+ok:
+  ret double %divisor
+fail:
+  %printed = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([26 x i8], [26 x i8]* @.str_division_by_zero, i32 0, i32 0), i8* %expr)
+  call void @exit(i32 1)
+  unreachable
+}
+
+define internal double @flux_checked_result(double %value, i8* %expr) {
+entry:
+  %difference = fsub double %value, %value
+  %finite = fcmp ord double %difference, 0.0
+  br i1 %finite, label %ok, label %check_nan
+ok:
+  ret double %value
+check_nan:
+  %nan = fcmp uno double %value, %value
+  br i1 %nan, label %report_nan, label %report_infinity
+report_nan:
+  %printed_nan = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([19 x i8], [19 x i8]* @.str_produced_nan, i32 0, i32 0), i8* %expr)
+  call void @exit(i32 1)
+  unreachable
+report_infinity:
+  %printed_infinity = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([29 x i8], [29 x i8]* @.str_overflowed, i32 0, i32 0), i8* %expr)
+  call void @exit(i32 1)
+  unreachable
+}
+
+"#;
+    
+    /// Character access for compiled strings, which count UTF-8 code points
+    /// as the interpreter counts chars; an index out of range reports the
+    /// error and exits, as the interpreter would
+    const CHAR_RUNTIME: &'static str = r#"@.str_index_error = private unnamed_addr constant [38 x i8] c"Index %g out of range for length %ld\0A\00"
+
+declare i8* @memcpy(i8*, i8*, i64)
+
+define internal i8* @flux_from_char_code(double %code) {
+entry:
+  %point = fptoui double %code to i32
+  %buffer = call i8* @malloc(i64 5)
+  %ascii = icmp ult i32 %point, 128
+  br i1 %ascii, label %single, label %multi
+single:
+  %byte = trunc i32 %point to i8
+  store i8 %byte, i8* %buffer
+  %single_end = getelementptr i8, i8* %buffer, i64 1
+  store i8 0, i8* %single_end
+  ret i8* %buffer
+multi:
+  %fits_two = icmp ult i32 %point, 2048
+  %fits_three = icmp ult i32 %point, 65536
+  %wide = select i1 %fits_three, i64 3, i64 4
+  %width = select i1 %fits_two, i64 2, i64 %wide
+  %wide_prefix = select i1 %fits_three, i32 224, i32 240
+  %prefix = select i1 %fits_two, i32 192, i32 %wide_prefix
+  br label %continuation
+continuation:
+  %i = phi i64 [ %width, %multi ], [ %previous, %continuation ]
+  %rest = phi i32 [ %point, %multi ], [ %shifted, %continuation ]
+  %previous = sub i64 %i, 1
+  %low = and i32 %rest, 63
+  %tagged = or i32 %low, 128
+  %tail_byte = trunc i32 %tagged to i8
+  %tail_ptr = getelementptr i8, i8* %buffer, i64 %previous
+  store i8 %tail_byte, i8* %tail_ptr
+  %shifted = lshr i32 %rest, 6
+  %more = icmp ugt i64 %previous, 1
+  br i1 %more, label %continuation, label %lead
+lead:
+  %lead_bits = or i32 %shifted, %prefix
+  %lead_byte = trunc i32 %lead_bits to i8
+  store i8 %lead_byte, i8* %buffer
+  %multi_end = getelementptr i8, i8* %buffer, i64 %width
+  store i8 0, i8* %multi_end
+  ret i8* %buffer
+}
+
+define internal double @flux_char_code(i8* %text) {
+entry:
+  %first = load i8, i8* %text
+  %lead = zext i8 %first to i32
+  %two = icmp uge i32 %lead, 192
+  %three = icmp uge i32 %lead, 224
+  %four = icmp uge i32 %lead, 240
+  %two_mask = select i1 %two, i32 31, i32 127
+  %three_mask = select i1 %three, i32 15, i32 %two_mask
+  %mask = select i1 %four, i32 7, i32 %three_mask
+  %start = and i32 %lead, %mask
+  br label %loop
+loop:
+  %i = phi i64 [ 1, %entry ], [ %next, %accumulate ]
+  %point = phi i32 [ %start, %entry ], [ %combined, %accumulate ]
+  %byte_ptr = getelementptr i8, i8* %text, i64 %i
+  %byte = load i8, i8* %byte_ptr
+  %high = and i8 %byte, -64
+  %is_continuation = icmp eq i8 %high, -128
+  br i1 %is_continuation, label %accumulate, label %finish
+accumulate:
+  %bits = zext i8 %byte to i32
+  %low = and i32 %bits, 63
+  %shifted = shl i32 %point, 6
+  %combined = or i32 %shifted, %low
+  %next = add i64 %i, 1
+  br label %loop
+finish:
+  %value = uitofp i32 %point to double
+  ret double %value
+}
+
+define internal i64 @flux_utf8_length(i8* %text) {
+entry:
+  br label %loop
+loop:
+  %i = phi i64 [ 0, %entry ], [ %next, %step ]
+  %count = phi i64 [ 0, %entry ], [ %counted, %step ]
+  %byte_ptr = getelementptr i8, i8* %text, i64 %i
+  %byte = load i8, i8* %byte_ptr
+  %done = icmp eq i8 %byte, 0
+  br i1 %done, label %finish, label %step
+step:
+  %high = and i8 %byte, -64
+  %is_continuation = icmp eq i8 %high, -128
+  %increment = select i1 %is_continuation, i64 0, i64 1
+  %counted = add i64 %count, %increment
+  %next = add i64 %i, 1
+  br label %loop
+finish:
+  ret i64 %count
+}
+
+define internal i8* @flux_char_at(i8* %text, double %index) {
+entry:
+  %length = call i64 @flux_utf8_length(i8* %text)
+  %position = fptosi double %index to i64
+  %rounded = sitofp i64 %position to double
+  %whole = fcmp oeq double %rounded, %index
+  %non_negative = icmp sge i64 %position, 0
+  %below_length = icmp slt i64 %position, %length
+  %in_range = and i1 %non_negative, %below_length
+  %valid = and i1 %whole, %in_range
+  br i1 %valid, label %seek, label %out_of_range
+seek:
+  %offset = phi i64 [ 0, %entry ], [ %after, %skip ]
+  %seen = phi i64 [ 0, %entry ], [ %seen_next, %skip ]
+  %source = getelementptr i8, i8* %text, i64 %offset
+  %byte = load i8, i8* %source
+  %high = and i8 %byte, -64
+  %is_continuation = icmp eq i8 %high, -128
+  %is_target = icmp eq i64 %seen, %position
+  %not_continuation = xor i1 %is_continuation, true
+  %found = and i1 %not_continuation, %is_target
+  br i1 %found, label %copy, label %skip
+skip:
+  %increment = select i1 %is_continuation, i64 0, i64 1
+  %seen_next = add i64 %seen, %increment
+  %after = add i64 %offset, 1
+  br label %seek
+copy:
+  %lead = zext i8 %byte to i32
+  %two = icmp uge i32 %lead, 192
+  %three = icmp uge i32 %lead, 224
+  %four = icmp uge i32 %lead, 240
+  %two_width = select i1 %two, i64 2, i64 1
+  %three_width = select i1 %three, i64 3, i64 %two_width
+  %width = select i1 %four, i64 4, i64 %three_width
+  %size = add i64 %width, 1
+  %result = call i8* @malloc(i64 %size)
+  %copied = call i8* @memcpy(i8* %result, i8* %source, i64 %width)
+  %end = getelementptr i8, i8* %result, i64 %width
+  store i8 0, i8* %end
+  ret i8* %result
+out_of_range:
+  %printed = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([38 x i8], [38 x i8]* @.str_index_error, i32 0, i32 0), double %index, i64 %length)
+  call void @exit(i32 1)
+  unreachable
+}
+
+"#;
+    
+    /// Timeline runtime linked into modules that use temporal variables.
+    /// Entries pair a clock reading with a heap box holding the value, and
+    /// negative lookups step back through entries instead of the clock;
+    /// history kinds are 0 for numbers, 1 for booleans and 2 for strings.
+    const TEMPORAL_RUNTIME: &'static str = r#"@flux_clock = internal global double 0.0
+@.str_checkpoint_error = private unnamed_addr constant [38 x i8] c"Checkpoint '%s' has not been reached\0A\00"
+@.str_history_short = private unnamed_addr constant [45 x i8] c"Variable '%s' has fewer than %g past values\0A\00"
+@.str_history_missing = private unnamed_addr constant [39 x i8] c"Variable '%s' has no value at time %g\0A\00"
+@.str_history_open = private unnamed_addr constant [2 x i8] c"[\00"
+@.str_history_close = private unnamed_addr constant [2 x i8] c"]\00"
+@.str_history_sep = private unnamed_addr constant [3 x i8] c", \00"
+@.str_history_none = private unnamed_addr constant [1 x i8] c"\00"
+@.str_history_true = private unnamed_addr constant [5 x i8] c"true\00"
+@.str_history_false = private unnamed_addr constant [6 x i8] c"false\00"
+
+define internal double @flux_checkpoint_time(double %time, i8* %label) {
+entry:
+  %reached = fcmp oge double %time, 0.0
+  br i1 %reached, label %ok, label %missing
+ok:
+  ret double %time
+missing:
+  %printed = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([38 x i8], [38 x i8]* @.str_checkpoint_error, i32 0, i32 0), i8* %label)
+  call void @exit(i32 1)
+  unreachable
+}
+
+define internal %temporal_var* @flux_temporal_new() {
+entry:
+  %raw = call i8* @malloc(i64 16)
+  %timeline = bitcast i8* %raw to %temporal_var*
+  %count_ptr = getelementptr %temporal_var, %temporal_var* %timeline, i32 0, i32 0
+  store i32 0, i32* %count_ptr
+  %entries_ptr = getelementptr %temporal_var, %temporal_var* %timeline, i32 0, i32 1
+  store %temporal_entry* null, %temporal_entry** %entries_ptr
+  ret %temporal_var* %timeline
+}
+
+define internal void @flux_temporal_record(%temporal_var* %timeline, i8* %value, double* %clock) {
+entry:
+  %count_ptr = getelementptr %temporal_var, %temporal_var* %timeline, i32 0, i32 0
+  %count = load i32, i32* %count_ptr
+  %entries_ptr = getelementptr %temporal_var, %temporal_var* %timeline, i32 0, i32 1
+  %entries = load %temporal_entry*, %temporal_entry** %entries_ptr
+  %old = bitcast %temporal_entry* %entries to i8*
+  %new_count = add i32 %count, 1
+  %wide = sext i32 %new_count to i64
+  %bytes = mul i64 %wide, 16
+  %raw = call i8* @realloc(i8* %old, i64 %bytes)
+  %grown = bitcast i8* %raw to %temporal_entry*
+  store %temporal_entry* %grown, %temporal_entry** %entries_ptr
+  %slot = getelementptr %temporal_entry, %temporal_entry* %grown, i32 %count
+  %time_ptr = getelementptr %temporal_entry, %temporal_entry* %slot, i32 0, i32 0
+  %now = load double, double* %clock
+  store double %now, double* %time_ptr
+  %value_ptr = getelementptr %temporal_entry, %temporal_entry* %slot, i32 0, i32 1
+  store i8* %value, i8** %value_ptr
+  store i32 %new_count, i32* %count_ptr
+  ret void
+}
+
+define internal i8* @flux_temporal_at(%temporal_var* %timeline, double %time, i8* %name) {
+entry:
+  %count_ptr = getelementptr %temporal_var, %temporal_var* %timeline, i32 0, i32 0
+  %count = load i32, i32* %count_ptr
+  %entries_ptr = getelementptr %temporal_var, %temporal_var* %timeline, i32 0, i32 1
+  %entries = load %temporal_entry*, %temporal_entry** %entries_ptr
+  %relative = fcmp olt double %time, 0.0
+  br i1 %relative, label %step_back, label %search
+step_back:
+  %steps = fptosi double %time to i32
+  %remaining_after = add i32 %count, %steps
+  %target = sub i32 %remaining_after, 1
+  %before_start = icmp slt i32 %target, 0
+  br i1 %before_start, label %missing, label %stepped
+stepped:
+  %past = getelementptr %temporal_entry, %temporal_entry* %entries, i32 %target
+  %past_value_ptr = getelementptr %temporal_entry, %temporal_entry* %past, i32 0, i32 1
+  %past_value = load i8*, i8** %past_value_ptr
+  ret i8* %past_value
+search:
+  %remaining = phi i32 [ %count, %entry ], [ %index, %check ]
+  %exhausted = icmp eq i32 %remaining, 0
+  br i1 %exhausted, label %missing, label %check
+check:
+  %index = sub i32 %remaining, 1
+  %candidate = getelementptr %temporal_entry, %temporal_entry* %entries, i32 %index
+  %time_ptr = getelementptr %temporal_entry, %temporal_entry* %candidate, i32 0, i32 0
+  %stamp = load double, double* %time_ptr
+  %reached = fcmp ole double %stamp, %time
+  br i1 %reached, label %found, label %search
+found:
+  %value_ptr = getelementptr %temporal_entry, %temporal_entry* %candidate, i32 0, i32 1
+  %value = load i8*, i8** %value_ptr
+  ret i8* %value
+missing:
+  br i1 %relative, label %too_short, label %too_early
+too_short:
+  %wanted = fneg double %time
+  %short = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([45 x i8], [45 x i8]* @.str_history_short, i32 0, i32 0), i8* %name, double %wanted)
+  call void @exit(i32 1)
+  unreachable
+too_early:
+  %early = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([39 x i8], [39 x i8]* @.str_history_missing, i32 0, i32 0), i8* %name, double %time)
+  call void @exit(i32 1)
+  unreachable
+}
+
+define internal double @flux_temporal_changes(%temporal_var* %timeline) {
+entry:
+  %count_ptr = getelementptr %temporal_var, %temporal_var* %timeline, i32 0, i32 0
+  %count = load i32, i32* %count_ptr
+  %updates = sub i32 %count, 1
+  %result = sitofp i32 %updates to double
+  ret double %result
+}
+
+define internal i8* @flux_temporal_show(i8* %value, i32 %kind) {
+entry:
+  switch i32 %kind, label %number [ i32 1, label %boolean
+                                    i32 2, label %string ]
+number:
+  %number_ptr = bitcast i8* %value to double*
+  %n = load double, double* %number_ptr
+  %shown = call i8* @flux_number_string(double %n)
+  ret i8* %shown
+boolean:
+  %flag_ptr = bitcast i8* %value to i1*
+  %flag = load i1, i1* %flag_ptr
+  %word = select i1 %flag, i8* getelementptr inbounds ([5 x i8], [5 x i8]* @.str_history_true, i32 0, i32 0), i8* getelementptr inbounds ([6 x i8], [6 x i8]* @.str_history_false, i32 0, i32 0)
+  br label %copy
+string:
+  %text_ptr = bitcast i8* %value to i8**
+  %text = load i8*, i8** %text_ptr
+  br label %copy
+copy:
+  %source = phi i8* [ %word, %boolean ], [ %text, %string ]
+  %source_length = call i64 @strlen(i8* %source)
+  %copy_size = add i64 %source_length, 1
+  %copied = call i8* @malloc(i64 %copy_size)
+  %result = call i8* @strcpy(i8* %copied, i8* %source)
+  ret i8* %copied
+}
+
+define internal i8* @flux_temporal_history(%temporal_var* %timeline, i32 %kind) {
+entry:
+  %count_ptr = getelementptr %temporal_var, %temporal_var* %timeline, i32 0, i32 0
+  %count = load i32, i32* %count_ptr
+  %entries_ptr = getelementptr %temporal_var, %temporal_var* %timeline, i32 0, i32 1
+  %entries = load %temporal_entry*, %temporal_entry** %entries_ptr
+  %start = call i8* @malloc(i64 2)
+  %opened = call i8* @strcpy(i8* %start, i8* getelementptr inbounds ([2 x i8], [2 x i8]* @.str_history_open, i32 0, i32 0))
+  br label %loop
+loop:
+  %i = phi i32 [ 0, %entry ], [ %next, %append ]
+  %text = phi i8* [ %start, %entry ], [ %joined, %append ]
+  %done = icmp eq i32 %i, %count
+  br i1 %done, label %finish, label %append
+append:
+  %item = getelementptr %temporal_entry, %temporal_entry* %entries, i32 %i
+  %value_ptr = getelementptr %temporal_entry, %temporal_entry* %item, i32 0, i32 1
+  %value = load i8*, i8** %value_ptr
+  %piece = call i8* @flux_temporal_show(i8* %value, i32 %kind)
+  %first = icmp eq i32 %i, 0
+  %separator = select i1 %first, i8* getelementptr inbounds ([1 x i8], [1 x i8]* @.str_history_none, i32 0, i32 0), i8* getelementptr inbounds ([3 x i8], [3 x i8]* @.str_history_sep, i32 0, i32 0)
+  %text_length = call i64 @strlen(i8* %text)
+  %piece_length = call i64 @strlen(i8* %piece)
+  %partial = add i64 %text_length, %piece_length
+  %size = add i64 %partial, 3
+  %joined = call i8* @realloc(i8* %text, i64 %size)
+  %with_separator = call i8* @strcat(i8* %joined, i8* %separator)
+  %with_piece = call i8* @strcat(i8* %joined, i8* %piece)
+  call void @free(i8* %piece)
+  %next = add i32 %i, 1
+  br label %loop
+finish:
+  %final_length = call i64 @strlen(i8* %text)
+  %final_size = add i64 %final_length, 2
+  %closed = call i8* @realloc(i8* %text, i64 %final_size)
+  %result = call i8* @strcat(i8* %closed, i8* getelementptr inbounds ([2 x i8], [2 x i8]* @.str_history_close, i32 0, i32 0))
+  ret i8* %closed
+}
+
+"#;
+    
+    /// Channel runtime: a mutex- and condvar-guarded linked list of boxed
+    /// values. The pthread objects are heap-allocated with generous sizes
+    /// rather than mirroring glibc's struct layouts.
+    const CHANNEL_RUNTIME: &'static str = r#"%channel = type { i8*, i8*, %channel_node*, %channel_node* }
+%channel_node = type { i8*, %channel_node* }
+
+declare i32 @pthread_mutex_init(i8*, i8*)
+declare i32 @pthread_mutex_lock(i8*)
+declare i32 @pthread_mutex_unlock(i8*)
+declare i32 @pthread_cond_init(i8*, i8*)
+declare i32 @pthread_cond_wait(i8*, i8*)
+declare i32 @pthread_cond_signal(i8*)
+
+define internal i8* @flux_channel_new() {
+entry:
+  %raw = call i8* @malloc(i64 32)
+  %channel = bitcast i8* %raw to %channel*
+  %mutex = call i8* @malloc(i64 64)
+  %mutex_ok = call i32 @pthread_mutex_init(i8* %mutex, i8* null)
+  %cond = call i8* @malloc(i64 64)
+  %cond_ok = call i32 @pthread_cond_init(i8* %cond, i8* null)
+  %mutex_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 0
+  store i8* %mutex, i8** %mutex_ptr
+  %cond_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 1
+  store i8* %cond, i8** %cond_ptr
+  %head_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 2
+  store %channel_node* null, %channel_node** %head_ptr
+  %tail_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 3
+  store %channel_node* null, %channel_node** %tail_ptr
+  ret i8* %raw
+}
+
+define internal void @flux_channel_send(i8* %raw, i8* %value) {
+entry:
+  %channel = bitcast i8* %raw to %channel*
+  %node_raw = call i8* @malloc(i64 16)
+  %node = bitcast i8* %node_raw to %channel_node*
+  %value_ptr = getelementptr %channel_node, %channel_node* %node, i32 0, i32 0
+  store i8* %value, i8** %value_ptr
+  %next_ptr = getelementptr %channel_node, %channel_node* %node, i32 0, i32 1
+  store %channel_node* null, %channel_node** %next_ptr
+  %mutex_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 0
+  %mutex = load i8*, i8** %mutex_ptr
+  %locked = call i32 @pthread_mutex_lock(i8* %mutex)
+  %tail_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 3
+  %tail = load %channel_node*, %channel_node** %tail_ptr
+  %empty = icmp eq %channel_node* %tail, null
+  br i1 %empty, label %first, label %append
+first:
+  %head_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 2
+  store %channel_node* %node, %channel_node** %head_ptr
+  br label %done
+append:
+  %tail_next = getelementptr %channel_node, %channel_node* %tail, i32 0, i32 1
+  store %channel_node* %node, %channel_node** %tail_next
+  br label %done
+done:
+  store %channel_node* %node, %channel_node** %tail_ptr
+  %cond_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 1
+  %cond = load i8*, i8** %cond_ptr
+  %signalled = call i32 @pthread_cond_signal(i8* %cond)
+  %unlocked = call i32 @pthread_mutex_unlock(i8* %mutex)
+  ret void
+}
+
+define internal i8* @flux_channel_recv(i8* %raw) {
+entry:
+  %channel = bitcast i8* %raw to %channel*
+  %mutex_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 0
+  %mutex = load i8*, i8** %mutex_ptr
+  %cond_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 1
+  %cond = load i8*, i8** %cond_ptr
+  %head_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 2
+  %tail_ptr = getelementptr %channel, %channel* %channel, i32 0, i32 3
+  %locked = call i32 @pthread_mutex_lock(i8* %mutex)
+  br label %check
+check:
+  %head = load %channel_node*, %channel_node** %head_ptr
+  %empty = icmp eq %channel_node* %head, null
+  br i1 %empty, label %wait, label %take
+wait:
+  %woken = call i32 @pthread_cond_wait(i8* %cond, i8* %mutex)
+  br label %check
+take:
+  %next_ptr = getelementptr %channel_node, %channel_node* %head, i32 0, i32 1
+  %next = load %channel_node*, %channel_node** %next_ptr
+  store %channel_node* %next, %channel_node** %head_ptr
+  %drained = icmp eq %channel_node* %next, null
+  br i1 %drained, label %clear_tail, label %finish
+clear_tail:
+  store %channel_node* null, %channel_node** %tail_ptr
+  br label %finish
+finish:
+  %unlocked = call i32 @pthread_mutex_unlock(i8* %mutex)
+  %value_ptr = getelementptr %channel_node, %channel_node* %head, i32 0, i32 0
+  %value = load i8*, i8** %value_ptr
+  %node_raw = bitcast %channel_node* %head to i8*
+  call void @free(i8* %node_raw)
+  ret i8* %value
+}
+
+"#;
+    
+    /// Renders the command-line arguments, less the program name, the way
+    /// the interpreter prints an array of strings: `[a, b]`. Compiled
+    /// arrays only exist as such strings so far, so indexing `args` is
+    /// reported like any other compiled array indexing.
+    const ARGS_RUNTIME: &'static str = r#"@.str_args_open = private unnamed_addr constant [2 x i8] c"[\00"
+@.str_args_sep = private unnamed_addr constant [3 x i8] c", \00"
+@.str_args_none = private unnamed_addr constant [1 x i8] c"\00"
+@.str_args_close = private unnamed_addr constant [2 x i8] c"]\00"
+
+define internal i8* @flux_args_text(i32 %argc, i8** %argv) {
+entry:
+  %start = call i8* @malloc(i64 2)
+  %opened = call i8* @strcpy(i8* %start, i8* getelementptr inbounds ([2 x i8], [2 x i8]* @.str_args_open, i32 0, i32 0))
+  br label %loop
+loop:
+  %i = phi i32 [ 1, %entry ], [ %next, %append ]
+  %text = phi i8* [ %start, %entry ], [ %joined, %append ]
+  %done = icmp sge i32 %i, %argc
+  br i1 %done, label %finish, label %append
+append:
+  %arg_ptr = getelementptr i8*, i8** %argv, i32 %i
+  %arg = load i8*, i8** %arg_ptr
+  %first = icmp eq i32 %i, 1
+  %separator = select i1 %first, i8* getelementptr inbounds ([1 x i8], [1 x i8]* @.str_args_none, i32 0, i32 0), i8* getelementptr inbounds ([3 x i8], [3 x i8]* @.str_args_sep, i32 0, i32 0)
+  %text_length = call i64 @strlen(i8* %text)
+  %arg_length = call i64 @strlen(i8* %arg)
+  %partial = add i64 %text_length, %arg_length
+  %size = add i64 %partial, 3
+  %joined = call i8* @realloc(i8* %text, i64 %size)
+  %with_separator = call i8* @strcat(i8* %joined, i8* %separator)
+  %with_arg = call i8* @strcat(i8* %joined, i8* %arg)
+  %next = add i32 %i, 1
+  br label %loop
+finish:
+  %final_length = call i64 @strlen(i8* %text)
+  %final_size = add i64 %final_length, 2
+  %closed = call i8* @realloc(i8* %text, i64 %final_size)
+  %result = call i8* @strcat(i8* %closed, i8* getelementptr inbounds ([2 x i8], [2 x i8]* @.str_args_close, i32 0, i32 0))
+  ret i8* %closed
+}
+
+"#;
+    
+    /// C entry point; weak so a C program linking in the module's exports
+    /// can supply its own `main`. It runs the top-level statements, then the
+    /// program's `func main` if it has one.
+    fn emit_footer(&mut self) {
+        match self.entry_params {
+            Some(params) => {
+                let (_, result_type) = self.signature("main");
+                self.output.push_str("define weak i32 @main(i32 %argc, i8** %argv) {\n");
+                self.output.push_str("entry:\n");
+                self.output.push_str("  call void @flux_main()\n");
+                self.emit_abort_if_panicking();
+                if params == 1 {
+                    self.output.push_str("  %args = call i8* @flux_args_text(i32 %argc, i8** %argv)\n");
+                    self.output.push_str(&format!("  %result = call {} @flux_user_main(i8* %args)\n", result_type));
+                } else {
+                    self.output.push_str(&format!("  %result = call {} @flux_user_main()\n", result_type));
+                }
+                self.emit_abort_if_panicking();
+            }
+            None => {
+                self.output.push_str("define weak i32 @main() {\n");
+                self.output.push_str("entry:\n");
+                self.output.push_str("  call void @flux_main()\n");
+                self.emit_abort_if_panicking();
+            }
+        }
+        self.output.push_str("  ret i32 0\n");
+        self.output.push_str("}\n");
+    }
+    
+    /// Ends the process once a panic has unwound back to the caller, the
+    /// way an immediate panic does
+    fn emit_abort_if_panicking(&mut self) {
+        if !self.unwinds {
+            return;
+        }
+        let panicking = self.new_temp();
+        let abort_label = self.new_label();
+        let done_label = self.new_label();
+        self.output.push_str(&format!("  %{} = load i1, i1* @flux_panicking\n", panicking));
+        self.output.push_str(&format!("  br i1 %{}, label %{}, label %{}\n", panicking, abort_label, done_label));
+        self.output.push_str(&format!("{}:\n", abort_label));
+        let flushed = self.new_temp();
+        self.output.push_str(&format!("  %{} = call i32 @fflush(i8* null)\n", flushed));
+        self.output.push_str("  call void @abort()\n");
+        self.output.push_str("  unreachable\n");
+        self.output.push_str(&format!("{}:\n", done_label));
+    }
+    
+    /// Leaves the current function mid-panic, through its cleanup block
+    /// if it has one; the result is never used
+    fn emit_unwind(&mut self) {
+        match &self.cleanup {
+            Some((label, _)) => self.output.push_str(&format!("  br label %{}\n", label)),
+            None if self.return_type == "void" => self.output.push_str("  ret void\n"),
+            None => {
+                let zero = Self::zero_value(self.return_type);
+                self.output.push_str(&format!("  ret {} {}\n", zero.ty, zero.reg));
+            }
+        }
+    }
+    
+    fn type_of(&self, node: &ASTNode) -> FluxType {
+        self.types.get(&(node as *const ASTNode)).cloned().unwrap_or(FluxType::Any)
+    }
+    
+    /// LLVM parameter and result types of a user function
+    fn signature(&self, name: &str) -> (Vec<&'static str>, &'static str) {
+        match self.signatures.get(name) {
+            Some(FluxType::Function(params, result)) => {
+                (params.iter().map(Self::llvm_type).collect(), Self::llvm_type(result))
+            }
+            _ => (Vec::new(), "double"),
+        }
+    }
+    
+    /// LLVM representation of a Flux type; untyped values stay doubles
+    fn llvm_type(flux_type: &FluxType) -> &'static str {
+        match flux_type {
+            FluxType::Boolean => "i1",
+            FluxType::String | FluxType::Object(_) => "i8*",
+            FluxType::Temporal(inner) => Self::llvm_type(inner),
+            // Compiled arrays only exist as rendered history strings so far
+            FluxType::Array(_) => "i8*",
+            // Tasks point to a heap-allocated pthread_t, channels to a %channel
+            FluxType::Task(_) | FluxType::Channel => "i8*",
+            FluxType::Number | FluxType::Function(..) | FluxType::Any => "double",
+        }
+    }
+    
+    /// Formats a double as an LLVM constant; the hex form is always exact
+    fn float_literal(n: f64) -> String {
+        format!("0x{:016X}", n.to_bits())
+    }
+    
+    fn zero_value(ty: &'static str) -> IRValue {
+        match ty {
+            "i1" => IRValue::new("false", ty),
+            "i8*" => IRValue::new("null", ty),
+            _ => IRValue::new("0.0", "double"),
+        }
+    }
+    
+    /// Starts a fresh (unreachable) block so code after `ret` stays valid
+    fn start_block_after_terminator(&mut self) {
+        let label = self.new_label();
+        self.output.push_str(&format!("{}:\n", label));
+    }
+    
+    /// Lowers a nested block. Its variables go out of scope at the end, as
+    /// in the interpreter, so their lifetimes end there too
+    fn visit_block(&mut self, statements: &'a [ASTNode]) {
+        self.block_locals.push(Vec::new());
+        for stmt in statements {
+            self.visit(stmt);
+        }
+        self.end_block();
+    }
+    
+    /// Like `visit_block`, but stores the value of the trailing expression
+    /// in `slot`, for an arm of an `if` or `match` used as a value
+    fn visit_value_block(&mut self, statements: &'a [ASTNode], slot: &str, ty: &'static str) {
+        self.block_locals.push(Vec::new());
+        let (last, rest) = match statements.split_last() {
+            Some((last, rest)) if Self::is_expression(last) 
+                || matches!(last, ASTNode::If { .. } | ASTNode::Match { .. }) => (Some(last), rest),
+            _ => (None, statements),
+        };
+        for stmt in rest {
+            self.visit(stmt);
+        }
+        if let Some(expr) = last {
+            let value = self.visit_expression(expr);
+            let value = self.coerce(value, ty);
+            self.output.push_str(&format!("  store {} {}, {}* {}\n", ty, value.reg, ty, slot));
+        }
+        self.end_block();
+    }
+    
+    /// Lowers `if` and `match`, testing each arm in turn. With a destination
+    /// slot the arms are lowered for their value.
+    fn emit_branches(&mut self, node: &'a ASTNode, dest: Option<(&str, &'static str)>) {
+        let end_label = self.new_label();
+        let arm = |generator: &mut Self, body: &'a [ASTNode]| match dest {
+            Some((slot, ty)) => generator.visit_value_block(body, slot, ty),
+            None => generator.visit_block(body),
+        };
+        
+        match node {
+            ASTNode::If { condition, then_branch, else_ifs, else_branch } => {
+                // Each arm tests its condition and falls through to the next
+                let arms = std::iter::once((condition.as_ref(), then_branch))
+                    .chain(else_ifs.iter().map(|(condition, body)| (condition, body)));
+                for (arm_condition, body) in arms {
+                    let cond = self.visit_expression(arm_condition);
+                    let cond = self.coerce(cond, "i1");
+                    let then_label = self.new_label();
+                    let next_label = self.new_label();
+                    self.output.push_str(&format!("  br i1 {}, label %{}, label %{}\n", 
+                                                 cond.reg, then_label, next_label));
+                    
+                    self.output.push_str(&format!("{}:\n", then_label));
+                    arm(self, body);
+                    self.output.push_str(&format!("  br label %{}\n", end_label));
+                    self.output.push_str(&format!("{}:\n", next_label));
+                }
+                
+                // Else branch
+                if let Some(else_stmts) = else_branch {
+                    arm(self, else_stmts);
+                }
+            }
+            ASTNode::Match { expr, cases } => {
+                // The subject is evaluated once and compared case by case
+                let subject = self.visit_expression(expr);
+                
+                for (pattern, body) in cases {
+                    if matches!(pattern, ASTNode::Identifier(name) if name == "default") {
+                        arm(self, body);
+                        break;
+                    }
+                    
+                    let matched = self.emit_pattern(subject.clone(), pattern);
+                    let body_label = self.new_label();
+                    let next_label = self.new_label();
+                    self.output.push_str(&format!("  br i1 {}, label %{}, label %{}\n", 
+                                                 matched.reg, body_label, next_label));
+                    
+                    self.output.push_str(&format!("{}:\n", body_label));
+                    arm(self, body);
+                    self.output.push_str(&format!("  br label %{}\n", end_label));
+                    self.output.push_str(&format!("{}:\n", next_label));
+                }
+            }
+            _ => {}
+        }
+        
+        self.output.push_str(&format!("  br label %{}\n", end_label));
+        self.output.push_str(&format!("{}:\n", end_label));
+    }
+    
+    /// Ends the lifetimes of the innermost block's locals
+    fn end_block(&mut self) {
+        for name in self.block_locals.pop().unwrap_or_default() {
+            if let Some(ty) = self.locals.remove(&name) {
+                let slot = self.slot(&name);
+                self.emit_lifetime("end", &slot, ty);
+            }
+        }
+    }
+    
+    /// Queues a stack slot for the entry block, so a declaration inside a
+    /// loop reuses one slot instead of growing the stack each iteration
+    fn emit_alloca(&mut self, slot: &str, ty: &str) {
+        self.allocas.push_str(&format!("  {} = alloca {}\n", slot, ty));
+    }
+    
+    /// A function whose body defers anything sends every exit through one
+    /// cleanup block
+    fn begin_defers(&mut self, body: &[ASTNode]) {
+        if body.iter().any(|stmt| matches!(stmt, ASTNode::Defer(_))) {
+            let result = (self.return_type != "void").then(|| {
+                let slot = format!("%{}", self.new_temp());
+                self.emit_alloca(&slot, self.return_type);
+                slot
+            });
+            self.cleanup = Some((self.new_label(), result));
+        }
+    }
+    
+    /// Returns from the current function, by way of its cleanup block if it has one
+    fn emit_return(&mut self, value: Option<IRValue>) {
+        let value = value.filter(|_| self.return_type != "void")
+            .map(|value| self.coerce(value, self.return_type));
+        match (&self.cleanup, value) {
+            (Some((label, result)), value) => {
+                if let (Some(slot), Some(value)) = (result, value) {
+                    self.output.push_str(&format!("  store {} {}, {}* {}\n", value.ty, value.reg, value.ty, slot));
+                }
+                self.output.push_str(&format!("  br label %{}\n", label));
+            }
+            (None, Some(value)) => self.output.push_str(&format!("  ret {} {}\n", value.ty, value.reg)),
+            (None, None) => self.output.push_str("  ret void\n"),
+        }
+    }
+    
+    /// The cleanup block runs every defer that was reached, newest first,
+    /// then returns the stored result
+    fn end_defers(&mut self) {
+        let Some((label, result)) = self.cleanup.take() else {
+            return;
+        };
+        self.output.push_str(&format!("{}:\n", label));
+        // As in the interpreter, every defer runs even during a panic, and
+        // one that panics only skips the rest of itself; the flag is
+        // lowered for each and raised again afterwards if any panicked
+        let mut panicking = self.unwinds.then(|| self.emit_lower_panic_flag(None));
+        for (reached, value) in std::mem::take(&mut self.deferred).into_iter().rev() {
+            let flag = self.new_temp();
+            let run_label = self.new_label();
+            let next_label = self.new_label();
+            self.output.push_str(&format!("  %{} = load i1, i1* {}\n", flag, reached));
+            self.output.push_str(&format!("  br i1 %{}, label %{}, label %{}\n", flag, run_label, next_label));
+            self.output.push_str(&format!("{}:\n", run_label));
+            if self.unwinds {
+                self.cleanup = Some((next_label.clone(), None));
+            }
+            self.visit(value);
+            self.output.push_str(&format!("  br label %{}\n", next_label));
+            self.output.push_str(&format!("{}:\n", next_label));
+            if let Some(earlier) = panicking {
+                panicking = Some(self.emit_lower_panic_flag(Some(earlier)));
+            }
+        }
+        self.cleanup = None;
+        if let Some(panicking) = panicking {
+            self.output.push_str(&format!("  store i1 %{}, i1* @flux_panicking\n", panicking));
+        }
+        match result {
+            Some(slot) => {
+                let ty = self.return_type;
+                let value = self.new_temp();
+                self.output.push_str(&format!("  %{} = load {}, {}* {}\n", value, ty, ty, slot));
+                self.output.push_str(&format!("  ret {} %{}\n", ty, value));
+            }
+            None => self.output.push_str("  ret void\n"),
+        }
+    }
+    
+    /// Lowers `@flux_panicking`, returning the register holding whether
+    /// it or `earlier` was raised
+    fn emit_lower_panic_flag(&mut self, earlier: Option<String>) -> String {
+        let raised = self.new_temp();
+        self.output.push_str(&format!("  %{} = load i1, i1* @flux_panicking\n", raised));
+        self.output.push_str("  store i1 false, i1* @flux_panicking\n");
+        match earlier {
+            Some(earlier) => {
+                let either = self.new_temp();
+                self.output.push_str(&format!("  %{} = or i1 %{}, %{}\n", either, earlier, raised));
+                either
+            }
+            None => raised,
+        }
+    }
+    
+    /// Moves the queued allocas to the top of the current function's entry block
+    fn hoist_allocas(&mut self) {
+        let allocas = std::mem::take(&mut self.allocas);
+        if let Some(entry) = self.output.find("entry:\n") {
+            self.output.insert_str(entry + "entry:\n".len(), &allocas);
+        }
+    }
+    
+    /// Marks where a block-scoped slot becomes live (`start`) or dead (`end`)
+    fn emit_lifetime(&mut self, marker: &str, slot: &str, ty: &str) {
+        let size = if ty == "i1" { 1 } else { 8 };
+        let bytes = self.new_temp();
+        self.output.push_str(&format!("  %{} = bitcast {}* {} to i8*\n", bytes, ty, slot));
+        self.output.push_str(&format!("  call void @llvm.lifetime.{}.p0i8(i64 {}, i8* %{})\n", 
+                                     marker, size, bytes));
+    }
+    
+    fn visit(&mut self, node: &'a ASTNode) {
+        // Functions are emitted elsewhere; their statements are marked there
+        if let Some(span) = self.statement_spans.get(&(node as *const ASTNode))
+            && !matches!(node, ASTNode::FunctionDecl { .. })
+        {
+            self.output.push_str(&format!("; flux {}:{}\n", span.line, span.column));
+        }
+        match node {
+            ASTNode::Program(statements) => {
+                self.output.push_str("define void @flux_main() {\n");
+                self.output.push_str("entry:\n");
+                self.return_type = "void";
+                self.begin_defers(statements);
+                
+                for stmt in statements {
+                    self.visit(stmt);
+                }
+                
+                if self.dump_state {
+                    self.emit_state_dump(statements);
+                }
+                
+                self.emit_return(None);
+                self.end_defers();
+                self.output.push_str("}\n\n");
+                self.hoist_allocas();
+                
+                let main_body = std::mem::take(&mut self.output);
+                self.functions.push_str(&main_body);
+            }
+            
+            ASTNode::VarDecl { name, value, is_temporal, clock, .. } => {
+                let value = self.visit_expression(value);
+                
+                if *is_temporal {
+                    // The timeline starts with the initial value
+                    self.uses_temporal = true;
+                    match clock {
+                        Some(clock) => self.timeline_clocks.insert(name.clone(), clock.clone()),
+                        None => self.timeline_clocks.remove(name),
+                    };
+                    self.output.push_str(&format!("  {}.timeline = call %temporal_var* @flux_temporal_new()\n", self.slot(name)));
+                    self.timelines.insert(name.clone());
+                    self.emit_temporal_record(name, value.clone());
+                }
+                
+                // Variables live in stack slots typed after their initializer
+                let slot = self.slot(name);
+                self.emit_alloca(&slot, value.ty);
+                if let Some(declared) = self.block_locals.last_mut() {
+                    declared.push(name.clone());
+                    self.emit_lifetime("start", &slot, value.ty);
+                }
+                self.output.push_str(&format!("  store {} {}, {}* {}\n", 
+                                             value.ty, value.reg, value.ty, slot));
+                self.locals.insert(name.clone(), value.ty);
+            }
+            
+            ASTNode::Assignment { name, value } => {
+                let Some(&slot_type) = self.locals.get(name) else {
+                    self.interpreter_only(format!(
+                        "`{}`: assigning a variable declared outside the function is only supported by the interpreter", node));
+                    return;
+                };
+                let value = self.visit_expression(value);
+                let value = self.coerce(value, slot_type);
+                self.output.push_str(&format!("  store {} {}, {}* {}\n", 
+                                             slot_type, value.reg, slot_type, self.slot(name)));
+                
+                // Every temporal update ticks its clock, as in the interpreter
+                if self.timelines.contains(name) {
+                    let clock = self.clock_of(name);
+                    let now = self.new_temp();
+                    let next = self.new_temp();
+                    self.output.push_str(&format!("  %{} = load double, double* {}\n", now, clock));
+                    self.output.push_str(&format!("  %{} = fadd double %{}, 1.0\n", next, now));
+                    self.output.push_str(&format!("  store double %{}, double* {}\n", next, clock));
+                    self.emit_temporal_record(name, value);
+                    self.emit_watchers(name);
+                }
+            }
+            
+            ASTNode::Watch { var, body } => {
+                self.watchers.entry(var.clone()).or_default().push(body);
+            }
+            
+            // Domains are collected up front, since checkpoints record
+            // every clock, and pragmas apply to the whole module
+            ASTNode::ClockDomain(_) | ASTNode::Pragma(_) => {}
+            
+            ASTNode::Block(statements) => {
+                for stmt in statements {
+                    self.visit(stmt);
+                }
+            }
+            
+            ASTNode::FunctionDecl { name, params, body, .. } => {
+                // Functions are emitted at module level, outside the caller
+                let caller_body = std::mem::take(&mut self.output);
+                let caller_locals = std::mem::take(&mut self.locals);
+                let caller_timelines = std::mem::take(&mut self.timelines);
+                let caller_watchers = std::mem::take(&mut self.watchers);
+                let caller_return = self.return_type;
+                let caller_scope = std::mem::replace(&mut self.scope, name.clone());
+                let caller_allocas = std::mem::take(&mut self.allocas);
+                let caller_blocks = std::mem::take(&mut self.block_locals);
+                let caller_deferred = std::mem::take(&mut self.deferred);
+                let caller_cleanup = self.cleanup.take();
+                
+                let (mut param_types, return_type) = self.signature(name);
+                param_types.resize(params.len(), "double");
+                self.return_type = return_type;
+                let param_list = param_types.iter()
+                    .enumerate()
+                    .map(|(i, ty)| format!("{} %arg{}", ty, i))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                
+                // Only exports are visible outside the module; the rest can be
+                // inlined or dropped by LLVM when unused
+                let linkage = if self.exports.contains(name) { "" } else { "internal " };
+                self.output.push_str(&format!("define {}{} {}({}) {{\n", 
+                                             linkage, return_type, self.function_symbol(name), param_list));
+                self.output.push_str("entry:\n");
+                
+                // Allocate space for parameters
+                for (i, (param, ty)) in params.iter().zip(param_types).enumerate() {
+                    let slot = self.slot(param);
+                    self.emit_alloca(&slot, ty);
+                    self.output.push_str(&format!("  store {} %arg{}, {}* {}\n", ty, i, ty, slot));
+                    self.locals.insert(param.clone(), ty);
+                }
+                
+                self.begin_defers(body);
+                for stmt in body {
+                    self.visit(stmt);
+                }
+                
+                // Default return if no explicit return
+                self.emit_return(Some(Self::zero_value(return_type)));
+                self.end_defers();
+                self.output.push_str("}\n\n");
+                self.hoist_allocas();
+                
+                let function_body = std::mem::replace(&mut self.output, caller_body);
+                self.functions.push_str(&function_body);
+                self.locals = caller_locals;
+                self.timelines = caller_timelines;
+                self.watchers = caller_watchers;
+                self.return_type = caller_return;
+                self.scope = caller_scope;
+                self.allocas = caller_allocas;
+                self.block_locals = caller_blocks;
+                self.deferred = caller_deferred;
+                self.cleanup = caller_cleanup;
+            }
+            
+            ASTNode::ClassDecl { .. } => {}
+            
+            ASTNode::Return(expr) => {
+                let value = self.visit_expression(expr);
+                self.emit_return(Some(value));
+                self.start_block_after_terminator();
+            }
+            
+            // Only marks the defer as reached; the cleanup block runs it
+            ASTNode::Defer(value) => {
+                let reached = format!("%{}", self.new_temp());
+                self.emit_alloca(&reached, "i1");
+                self.allocas.push_str(&format!("  store i1 false, i1* {}\n", reached));
+                self.output.push_str(&format!("  store i1 true, i1* {}\n", reached));
+                self.deferred.push((reached, value));
+            }
+            
+            ASTNode::If { .. } | ASTNode::Match { .. } => self.emit_branches(node, None),
+            
+            ASTNode::While { condition, body } => {
+                let loop_label = self.new_label();
+                let body_label = self.new_label();
+                let end_label = self.new_label();
+                
+                self.output.push_str(&format!("  br label %{}\n", loop_label));
+                
+                // Loop condition
+                self.output.push_str(&format!("{}:\n", loop_label));
+                let cond = self.visit_expression(condition);
+                let cond = self.coerce(cond, "i1");
+                self.output.push_str(&format!("  br i1 {}, label %{}, label %{}\n", 
+                                             cond.reg, body_label, end_label));
+                
+                // Loop body
+                self.output.push_str(&format!("{}:\n", body_label));
+                self.loop_exits.push(end_label.clone());
+                self.visit_block(body);
+                self.loop_exits.pop();
+                self.output.push_str(&format!("  br label %{}\n", loop_label));
+                
+                self.output.push_str(&format!("{}:\n", end_label));
+            }
+            
+            ASTNode::Loop(body) => {
+                let body_label = self.new_label();
+                let end_label = self.new_label();
+                
+                self.output.push_str(&format!("  br label %{}\n", body_label));
+                self.output.push_str(&format!("{}:\n", body_label));
+                self.loop_exits.push(end_label.clone());
+                self.visit_block(body);
+                self.loop_exits.pop();
+                self.output.push_str(&format!("  br label %{}\n", body_label));
+                
+                self.output.push_str(&format!("{}:\n", end_label));
+            }
+            
+            ASTNode::DoWhile { body, condition } => {
+                let body_label = self.new_label();
+                let cond_label = self.new_label();
+                let end_label = self.new_label();
+                
+                // The body runs once before the condition is first checked
+                self.output.push_str(&format!("  br label %{}\n", body_label));
+                self.output.push_str(&format!("{}:\n", body_label));
+                self.loop_exits.push(end_label.clone());
+                self.visit_block(body);
+                self.loop_exits.pop();
+                self.output.push_str(&format!("  br label %{}\n", cond_label));
+                
+                self.output.push_str(&format!("{}:\n", cond_label));
+                let cond = self.visit_expression(condition);
+                let cond = self.coerce(cond, "i1");
+                self.output.push_str(&format!("  br i1 {}, label %{}, label %{}\n", 
+                                             cond.reg, body_label, end_label));
+                
+                self.output.push_str(&format!("{}:\n", end_label));
+            }
+            
+            ASTNode::Labeled { label, body } => {
+                let end_label = self.new_label();
+                self.loop_labels.push((label.clone(), end_label.clone()));
+                self.visit(body);
+                self.loop_labels.pop();
+                self.output.push_str(&format!("  br label %{}\n", end_label));
+                self.output.push_str(&format!("{}:\n", end_label));
+            }
+            
+            ASTNode::Break(label) => {
+                // The analyzer rejects `break` outside a loop, and unknown labels
+                let end_label = match label {
+                    None => self.loop_exits.last(),
+                    Some(label) => self.loop_labels.iter().rev()
+                        .find(|(name, _)| name == label)
+                        .map(|(_, end_label)| end_label),
+                };
+                if let Some(end_label) = end_label {
+                    self.output.push_str(&format!("  br label %{}\n", end_label));
+                    self.start_block_after_terminator();
+                }
+            }
+            
+            _ => {
+                // Expression statement
+                self.visit_expression(node);
+            }
+        }
+    }
+    
+    fn visit_expression(&mut self, node: &'a ASTNode) -> IRValue {
+        match node {
+            ASTNode::Number(n) => {
+                let temp = self.new_temp();
+                self.output.push_str(&format!("  %{} = fadd double 0.0, {}\n", 
+                                             temp, Self::float_literal(*n)));
+                IRValue::new(format!("%{}", temp), "double")
+            }
+            
+            ASTNode::Boolean(b) => IRValue::new(b.to_string(), "i1"),
+            
+            // The taken arm stores its value in a temporary slot
+            ASTNode::If { .. } | ASTNode::Match { .. } => {
+                let ty = Self::llvm_type(&self.type_of(node));
+                let slot = format!("%{}", self.new_temp());
+                self.emit_alloca(&slot, ty);
+                self.emit_branches(node, Some((&slot, ty)));
+                let temp = self.new_temp();
+                self.output.push_str(&format!("  %{} = load {}, {}* {}\n", temp, ty, ty, slot));
+                IRValue::new(format!("%{}", temp), ty)
+            }
+            
+            ASTNode::String(s) => self.string_literal(s),
+            
+            // Each function only has stack slots for its own variables
+            ASTNode::Identifier(name) => {
+                let Some(&slot_type) = self.locals.get(name) else {
+                    self.interpreter_only(format!(
+                        "`{}`: reading a variable declared outside the function is only supported by the interpreter", name));
+                    return Self::zero_value(Self::llvm_type(&self.type_of(node)));
+                };
+                let temp = self.new_temp();
+                self.output.push_str(&format!("  %{} = load {}, {}* {}\n", 
+                                             temp, slot_type, slot_type, self.slot(name)));
+                IRValue::new(format!("%{}", temp), slot_type)
+            }
+            
+            ASTNode::Binary { left, operator, right } => {
+                let result_type = self.type_of(node);
+                let left = self.visit_expression(left);
+                let right = self.visit_expression(right);
+                
+                if operator == "+" && (result_type == FluxType::String 
+                                       || left.ty == "i8*" || right.ty == "i8*") {
+                    return self.concat_strings(left, right);
+                }
+                
+                if matches!(operator.as_str(), "==" | "!=") && left.ty == "i8*" && right.ty == "i8*" {
+                    let predicate = if operator == "==" { "eq" } else { "ne" };
+                    return self.compare_strings(left, right, predicate);
+                }
+                
+                if matches!(operator.as_str(), "&&" | "||") {
+                    let left = self.coerce(left, "i1");
+                    let right = self.coerce(right, "i1");
+                    let result = self.new_temp();
+                    let instr = if operator == "&&" { "and" } else { "or" };
+                    self.output.push_str(&format!("  %{} = {} i1 {}, {}\n", 
+                                                 result, instr, left.reg, right.reg));
+                    return IRValue::new(format!("%{}", result), "i1");
+                }
+                
+                let left = self.coerce(left, "double");
+                let mut right = self.coerce(right, "double");
+                let checked = self.checked_math && matches!(operator.as_str(), "+" | "-" | "*" | "/" | "%");
+                let text = if checked { Some(self.string_literal(&node.to_string())) } else { None };
+                if let Some(text) = &text
+                    && matches!(operator.as_str(), "/" | "%")
+                {
+                    let divisor = self.new_temp();
+                    self.output.push_str(&format!("  %{} = call double @flux_checked_divisor(double {}, i8* {})\n",
+                                                 divisor, right.reg, text.reg));
+                    right = IRValue::new(format!("%{}", divisor), "double");
+                }
+                let result = self.new_temp();
+                
+                let (instr, ty) = match operator.as_str() {
+                    "+" => ("fadd", "double"),
+                    "-" => ("fsub", "double"),
+                    "*" => ("fmul", "double"),
+                    "/" => ("fdiv", "double"),
+                    "%" => ("frem", "double"),
+                    "==" => ("fcmp oeq", "i1"),
+                    "!=" => ("fcmp une", "i1"),
+                    "<" => ("fcmp olt", "i1"),
+                    ">" => ("fcmp ogt", "i1"),
+                    "<=" => ("fcmp ole", "i1"),
+                    ">=" => ("fcmp oge", "i1"),
+                    _ => ("fadd", "double"),
+                };
+                
+                self.output.push_str(&format!("  %{} = {} double {}, {}\n", 
+                                             result, instr, left.reg, right.reg));
+                if let Some(text) = text {
+                    let value = self.new_temp();
+                    self.output.push_str(&format!("  %{} = call double @flux_checked_result(double %{}, i8* {})\n",
+                                                 value, result, text.reg));
+                    return IRValue::new(format!("%{}", value), ty);
+                }
+                IRValue::new(format!("%{}", result), ty)
+            }
+            
+            ASTNode::Unary { operator, operand } => {
+                let operand = self.visit_expression(operand);
+                let result = self.new_temp();
+                
+                if operator == "!" {
+                    let operand = self.coerce(operand, "i1");
+                    self.output.push_str(&format!("  %{} = xor i1 {}, true\n", result, operand.reg));
+                    IRValue::new(format!("%{}", result), "i1")
+                } else {
+                    let operand = self.coerce(operand, "double");
+                    self.output.push_str(&format!("  %{} = fneg double {}\n", result, operand.reg));
+                    IRValue::new(format!("%{}", result), "double")
+                }
+            }
+            
+            ASTNode::Call { callee, args } => {
+                let ASTNode::Identifier(func_name) = callee.as_ref() else {
+                    return Self::zero_value("double");
+                };
+                
+                if matches!(func_name.as_str(), "snapshot" | "rollback") {
+                    self.interpreter_only(format!("`{}`: snapshot and rollback are only supported by the interpreter", node));
+                    return Self::zero_value("double");
+                }
+                
+                if matches!(func_name.as_str(), "time" | "random" | "input" | "read_file" | "fields" | "methods") {
+                    self.interpreter_only(format!("`{}`: {}() is only supported by the interpreter", node, func_name));
+                    return Self::zero_value("double");
+                }
+                
+                // Compiled values are numbers, strings and booleans, whose
+                // types are known statically
+                if func_name == "typeof" && let [arg] = args.as_slice() {
+                    let name = match self.type_of(arg) {
+                        FluxType::Number => "number",
+                        FluxType::String => "string",
+                        FluxType::Boolean => "boolean",
+                        _ => {
+                            self.interpreter_only(format!("`{}`: the type of `{}` is only known to the interpreter", node, arg));
+                            return Self::zero_value("i8*");
+                        }
+                    };
+                    self.visit_expression(arg);
+                    return self.string_literal(name);
+                }
+                
+                if func_name == "keep_timeline" {
+                    return Self::zero_value("double");
+                }
+                
+                // Compiled values are never updated in place, so freezing
+                // only matters to the analyzer
+                if func_name == "freeze" {
+                    return Self::zero_value("double");
+                }
+                if func_name == "is_frozen" && let [arg] = args.as_slice() {
+                    self.visit_expression(arg);
+                    return IRValue::new("true", "i1");
+                }
+                
+                if func_name == "print" {
+                    let typed_args = args.iter()
+                        .map(|arg| {
+                            let flux_type = self.type_of(arg);
+                            (self.visit_expression(arg), flux_type)
+                        })
+                        .collect();
+                    return self.emit_print(typed_args);
+                }
+                
+                if matches!(func_name.as_str(), "format" | "println")
+                    && let Some((ASTNode::String(template), rest)) = args.split_first()
+                {
+                    let typed_args = rest.iter()
+                        .map(|arg| {
+                            let flux_type = self.type_of(arg);
+                            (self.visit_expression(arg), flux_type)
+                        })
+                        .collect();
+                    return self.emit_format(template, typed_args, func_name == "println");
+                }
+                
+                // Both end the process, so the value they yield is never used
+                if func_name == "exit" && let [code] = args.as_slice() {
+                    let code = self.visit_expression(code);
+                    let code = self.coerce(code, "double");
+                    let status = self.new_temp();
+                    self.output.push_str(&format!("  %{} = fptosi double {} to i32\n", status, code.reg));
+                    self.output.push_str(&format!("  call void @exit(i32 %{})\n", status));
+                    return Self::zero_value("double");
+                }
+                if func_name == "panic" && let [message] = args.as_slice() {
+                    let message = self.visit_expression(message);
+                    let message = self.coerce(message, "i8*");
+                    let format = self.string_literal("panic: %s\n");
+                    // Only the first panic is reported, as in the interpreter
+                    let (report_label, reported_label) = (self.new_label(), self.new_label());
+                    if self.unwinds {
+                        let reported = self.new_temp();
+                        self.output.push_str(&format!("  %{} = load i1, i1* @flux_panic_reported\n", reported));
+                        self.output.push_str(&format!("  br i1 %{}, label %{}, label %{}\n", reported, reported_label, report_label));
+                        self.output.push_str(&format!("{}:\n", report_label));
+                    }
+                    let written = self.new_temp();
+                    self.output.push_str(&format!("  %{} = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* {}, i8* {})\n",
+                                                 written, format.reg, message.reg));
+                    if self.unwinds {
+                        self.output.push_str("  store i1 true, i1* @flux_panic_reported\n");
+                        self.output.push_str(&format!("  br label %{}\n", reported_label));
+                        self.output.push_str(&format!("{}:\n", reported_label));
+                        self.output.push_str("  store i1 true, i1* @flux_panicking\n");
+                        self.emit_unwind();
+                        self.start_block_after_terminator();
+                        return Self::zero_value("double");
+                    }
+                    // Unlike `exit`, `abort` drops whatever `printf` still buffers
+                    let flushed = self.new_temp();
+                    self.output.push_str(&format!("  %{} = call i32 @fflush(i8* null)\n", flushed));
+                    self.output.push_str("  call void @abort()\n");
+                    return Self::zero_value("double");
+                }
+                
+                if func_name == "set_precision" && let [digits] = args.as_slice() {
+                    self.uses_number_text = true;
+                    let digits = self.visit_expression(digits);
+                    let digits = self.coerce(digits, "double");
+                    let code = self.new_temp();
+                    self.output.push_str(&format!("  %{} = fptosi double {} to i32\n", code, digits.reg));
+                    self.output.push_str(&format!("  store i32 %{}, i32* @flux_precision\n", code));
+                    return IRValue::new("true", "i1");
+                }
+                
+                if matches!(func_name.as_str(), "len" | "byte_len") && let [arg] = args.as_slice()
+                    && self.type_of(arg) == FluxType::String
+                {
+                    self.uses_chars = true;
+                    let text = self.visit_expression(arg);
+                    let text = self.coerce(text, "i8*");
+                    let length = self.new_temp();
+                    let callee = if func_name == "len" { "flux_utf8_length" } else { "strlen" };
+                    self.output.push_str(&format!("  %{} = call i64 @{}(i8* {})\n", length, callee, text.reg));
+                    let temp = self.new_temp();
+                    self.output.push_str(&format!("  %{} = uitofp i64 %{} to double\n", temp, length));
+                    return IRValue::new(format!("%{}", temp), "double");
+                }
+                
+                if matches!(func_name.as_str(), "char_code" | "from_char_code") && let [arg] = args.as_slice() {
+                    self.uses_chars = true;
+                    let value = self.visit_expression(arg);
+                    let temp = self.new_temp();
+                    if func_name == "char_code" {
+                        let text = self.coerce(value, "i8*");
+                        self.output.push_str(&format!("  %{} = call double @flux_char_code(i8* {})\n", temp, text.reg));
+                        return IRValue::new(format!("%{}", temp), "double");
+                    }
+                    let code = self.coerce(value, "double");
+                    self.output.push_str(&format!("  %{} = call i8* @flux_from_char_code(double {})\n", temp, code.reg));
+                    return IRValue::new(format!("%{}", temp), "i8*");
+                }
+                
+                if func_name == "checkpoint" && let [ASTNode::String(label)] = args.as_slice() {
+                    self.uses_temporal = true;
+                    let id = self.checkpoint_id(label);
+                    let now = self.new_temp();
+                    self.output.push_str(&format!("  %{} = load double, double* @flux_clock\n", now));
+                    self.output.push_str(&format!("  store double %{}, double* @flux_checkpoint.{}\n", now, id));
+                    for domain in self.clock_domains.clone() {
+                        let domain = Self::ascii_name(&domain);
+                        let time = self.new_temp();
+                        self.output.push_str(&format!("  %{} = load double, double* @flux_clock.{}\n", time, domain));
+                        self.output.push_str(&format!("  store double %{}, double* @flux_checkpoint.{}.{}\n", time, id, domain));
+                    }
+                    return IRValue::new(format!("%{}", now), "double");
+                }
+                
+                if func_name == "join" && let [handle] = args.as_slice() {
+                    let result_type = Self::llvm_type(&self.type_of(node));
+                    let handle = self.visit_expression(handle);
+                    return self.emit_join(handle, result_type);
+                }
+                
+                if let Some(value) = self.emit_channel_call(func_name, args) {
+                    return value;
+                }
+                
+                if let Some(ASTNode::Identifier(var)) = args.first()
+                    && self.timelines.contains(var)
+                {
+                    match (func_name.as_str(), &args[1..]) {
+                        ("value_at", [timestamp]) => {
+                            let timestamp = self.emit_timestamp(var, timestamp);
+                            if let Some(value) = self.emit_temporal_at(var, timestamp) {
+                                return value;
+                            }
+                        }
+                        ("changes", []) => {
+                            let temp = self.new_temp();
+                            self.output.push_str(&format!("  %{} = call double @flux_temporal_changes(%temporal_var* {}.timeline)\n", 
+                                                         temp, self.slot(var)));
+                            return IRValue::new(format!("%{}", temp), "double");
+                        }
+                        ("history", []) => {
+                            // Compiled history has no list type yet, so it renders as "[a, b, c]"
+                            let kind = match self.locals.get(var) {
+                                Some(&"i1") => 1,
+                                Some(&"i8*") => 2,
+                                _ => 0,
+                            };
+                            let temp = self.new_temp();
+                            self.output.push_str(&format!("  %{} = call i8* @flux_temporal_history(%temporal_var* {}.timeline, i32 {})\n", 
+                                                         temp, self.slot(var), kind));
+                            return IRValue::new(format!("%{}", temp), "i8*");
+                        }
+                        _ => {}
+                    }
+                }
+                
+                let values: Vec<IRValue> = args.iter()
+                    .map(|arg| self.visit_expression(arg))
+                    .collect();
+                self.emit_call(func_name, values)
+            }
+            
+            ASTNode::TemporalAccess { var, timestamp } => {
+                let timestamp = self.emit_timestamp(var, timestamp);
+                self.emit_temporal_at(var, timestamp)
+                    .unwrap_or_else(|| Self::zero_value(Self::llvm_type(&self.type_of(node))))
+            }
+            
+            ASTNode::Spawn(body) => self.emit_spawn(node, body),
+            
+            ASTNode::Index { object, index } => {
+                if matches!(self.type_of(object), FluxType::Array(_)) {
+                    self.interpreter_only(format!("`{}`: array indexing is only supported by the interpreter", node));
+                    return Self::zero_value(Self::llvm_type(&self.type_of(node)));
+                }
+                
+                self.uses_chars = true;
+                let text = self.visit_expression(object);
+                let text = self.coerce(text, "i8*");
+                let position = self.visit_expression(index);
+                let position = self.coerce(position, "double");
+                let temp = self.new_temp();
+                self.output.push_str(&format!("  %{} = call i8* @flux_char_at(i8* {}, double {})\n", 
+                                             temp, text.reg, position.reg));
+                IRValue::new(format!("%{}", temp), "i8*")
+            }
+            
+            ASTNode::Array(_) => {
+                self.interpreter_only(format!("`{}`: array literals are only supported by the interpreter", node));
+                Self::zero_value("i8*")
+            }
+            
+            ASTNode::New { .. } | ASTNode::FieldAssign { .. } => {
+                self.interpreter_only(format!("`{}`: class instances are only supported by the interpreter", node));
+                Self::zero_value("i8*")
+            }
+            
+            ASTNode::Pipeline(stages) => {
+                let Some((first, rest)) = stages.split_first() else {
+                    return Self::zero_value("double");
+                };
+                
+                if matches!(self.type_of(first), FluxType::Array(_))
+                    && rest.iter().any(|stage| matches!(stage, ASTNode::ParallelStage(_)))
+                {
+                    self.interpreter_only(format!("`{}`: parallel pipelines over arrays are only supported by the interpreter", node));
+                }
+                
+                // Thread each stage's result in as the next stage's first argument;
+                // without arrays, a `||>` stage is an ordinary call on one value
+                let mut value = self.visit_expression(first);
+                for stage in rest {
+                    let stage = match stage {
+                        ASTNode::ParallelStage(inner) => inner.as_ref(),
+                        _ => stage,
+                    };
+                    value = match stage {
+                        ASTNode::Identifier(name) => self.emit_call(name, vec![value]),
+                        ASTNode::Call { callee, args } => {
+                            let ASTNode::Identifier(name) = callee.as_ref() else {
+                                continue;
+                            };
+                            let mut values = vec![value];
+                            for arg in args {
+                                values.push(self.visit_expression(arg));
+                            }
+                            self.emit_call(name, values)
+                        }
+                        _ => value,
+                    };
+                }
+                value
+            }
+            
+            _ => Self::zero_value("double"),
+        }
+    }
+    
+    fn emit_call(&mut self, func_name: &str, args: Vec<IRValue>) -> IRValue {
+        // Handle built-in functions
+        match func_name {
+            "print" => {
+                let typed_args = args.into_iter()
+                    .map(|arg| (arg, FluxType::Any))
+                    .collect();
+                self.emit_print(typed_args)
+            }
+            // Builtins without a lowering of their own
+            _ if !self.signatures.contains_key(func_name) => {
+                self.interpreter_only(format!("{}() is only supported by the interpreter", func_name));
+                Self::zero_value("double")
+            }
+            _ => {
+                // User-defined function call
+                let (param_types, return_type) = self.signature(func_name);
+                let args_str = args.into_iter()
+                    .enumerate()
+                    .map(|(i, arg)| {
+                        let ty = param_types.get(i).copied().unwrap_or("double");
+                        format!("{} {}", ty, self.coerce(arg, ty).reg)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                
+                let temp = self.new_temp();
+                self.output.push_str(&format!("  %{} = call {} {}({})\n", 
+                                             temp, return_type, self.function_symbol(func_name), args_str));
+                // A panic in the callee keeps unwinding through this function
+                if self.unwinds {
+                    let panicking = self.new_temp();
+                    let unwind_label = self.new_label();
+                    let continue_label = self.new_label();
+                    self.output.push_str(&format!("  %{} = load i1, i1* @flux_panicking\n", panicking));
+                    self.output.push_str(&format!("  br i1 %{}, label %{}, label %{}\n", panicking, unwind_label, continue_label));
+                    self.output.push_str(&format!("{}:\n", unwind_label));
+                    self.emit_unwind();
+                    self.output.push_str(&format!("{}:\n", continue_label));
+                }
+                IRValue::new(format!("%{}", temp), return_type)
+            }
+        }
+    }
+    
+    /// Lowers `print(...)`, choosing a format per argument from its type.
+    /// Untyped arguments fall back to the LLVM type they were lowered to.
+    fn emit_print(&mut self, args: Vec<(IRValue, FluxType)>) -> IRValue {
+        let kind_of = |value: &IRValue, flux_type: &FluxType| match flux_type {
+            FluxType::String => "i8*",
+            FluxType::Boolean => "i1",
+            FluxType::Number => "double",
+            _ => value.ty,
+        };
+        
+        let temp = self.new_temp();
+        
+        // A single argument uses the shared newline-terminated formats
+        if let [(value, flux_type)] = args.as_slice() {
+            match kind_of(value, flux_type) {
+                "i8*" => {
+                    self.output.push_str(&format!("  %{} = call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @.str_str, i32 0, i32 0), i8* {})\n", temp, value.reg));
+                }
+                "i1" => {
+                    let value = self.coerce(value.clone(), "i1");
+                    let text = self.new_temp();
+                    self.output.push_str(&format!("  %{} = select i1 {}, i8* getelementptr inbounds ([6 x i8], [6 x i8]* @.str_bool_true, i32 0, i32 0), i8* getelementptr inbounds ([7 x i8], [7 x i8]* @.str_bool_false, i32 0, i32 0)\n", text, value.reg));
+                    self.output.push_str(&format!("  %{} = call i32 (i8*, ...) @printf(i8* %{})\n", temp, text));
+                }
+                _ => {
+                    let text = self.number_text(value.clone());
+                    self.output.push_str(&format!("  %{} = call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @.str_str, i32 0, i32 0), i8* {})\n", temp, text.reg));
+                }
+            }
+            return IRValue::new("true", "i1");
+        }
+        
+        // Several arguments are printed back to back by one combined format
+        let mut format = String::new();
+        let mut operands = Vec::new();
+        for (value, flux_type) in args {
+            match kind_of(&value, &flux_type) {
+                "i8*" => {
+                    format.push_str("%s");
+                    operands.push(format!("i8* {}", value.reg));
+                }
+                "i1" => {
+                    let text = self.coerce(value, "i8*");
+                    format.push_str("%s");
+                    operands.push(format!("i8* {}", text.reg));
+                }
+                _ => {
+                    let text = self.number_text(value);
+                    format.push_str("%s");
+                    operands.push(format!("i8* {}", text.reg));
+                }
+            }
+        }
+        format.push('\n');
+        
+        let format = self.string_literal(&format);
+        let mut call_args = vec![format!("i8* {}", format.reg)];
+        call_args.extend(operands);
+        self.output.push_str(&format!("  %{} = call i32 (i8*, ...) @printf({})\n", 
+                                     temp, call_args.join(", ")));
+        IRValue::new("true", "i1")
+    }
+    
+    fn emit_state_dump(&mut self, statements: &[ASTNode]) {
+        let mut names: Vec<&String> = statements.iter()
+            .filter_map(|stmt| match stmt {
+                ASTNode::VarDecl { name, value, .. }
+                    if matches!(self.type_of(value), FluxType::Number | FluxType::String | FluxType::Boolean) => Some(name),
+                _ => None,
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        
+        for name in names {
+            let Some(&ty) = self.locals.get(name) else {
+                continue;
+            };
+            let value = self.new_temp();
+            self.output.push_str(&format!("  %{} = load {}, {}* {}\n", value, ty, ty, self.slot(name)));
+            let value = IRValue::new(format!("%{}", value), ty);
+            let text = self.coerce(value, "i8*");
+            let format = self.string_literal(&format!("{}=%s\n", name));
+            let written = self.new_temp();
+            self.output.push_str(&format!("  %{} = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* {}, i8* {})\n", 
+                                         written, format.reg, text.reg));
+        }
+    }
+    
+    /// Copies a value into an 8-byte heap box, returning the box pointer
+    fn box_value(&mut self, value: IRValue) -> String {
+        let raw = self.new_temp();
+        let slot = self.new_temp();
+        self.output.push_str(&format!("  %{} = call i8* @malloc(i64 8)\n", raw));
+        self.output.push_str(&format!("  %{} = bitcast i8* %{} to {}*\n", slot, raw, value.ty));
+        self.output.push_str(&format!("  store {} {}, {}* %{}\n", value.ty, value.reg, value.ty, slot));
+        format!("%{}", raw)
+    }
+    
+    /// Reads a value of the given type back out of a heap box
+    fn unbox_value(&mut self, raw: &str, ty: &'static str) -> IRValue {
+        let slot = self.new_temp();
+        let value = self.new_temp();
+        self.output.push_str(&format!("  %{} = bitcast i8* {} to {}*\n", slot, raw, ty));
+        self.output.push_str(&format!("  %{} = load {}, {}* %{}\n", value, ty, ty, slot));
+        IRValue::new(format!("%{}", value), ty)
+    }
+    
+    /// Boxes a value on the heap and appends it to the variable's timeline
+    fn emit_temporal_record(&mut self, name: &str, value: IRValue) {
+        let boxed = self.box_value(value);
+        self.output.push_str(&format!("  call void @flux_temporal_record(%temporal_var* {}.timeline, i8* {}, double* {})\n", 
+                                     self.slot(name), boxed, self.clock_of(name)));
+    }
+    
+    /// The global holding the clock a timeline records against
+    fn clock_of(&self, name: &str) -> String {
+        match self.timeline_clocks.get(name) {
+            Some(domain) => format!("@flux_clock.{}", Self::ascii_name(domain)),
+            None => "@flux_clock".to_string(),
+        }
+    }
+    
+    fn emit_channel_call(&mut self, name: &str, args: &'a [ASTNode]) -> Option<IRValue> {
+        match (name, args) {
+            ("channel", []) => {
+                self.uses_channels = true;
+                let channel = self.new_temp();
+                self.output.push_str(&format!("  %{} = call i8* @flux_channel_new()\n", channel));
+                Some(IRValue::new(format!("%{}", channel), "i8*"))
+            }
+            ("send", [channel_arg, value]) => {
+                let channel = self.visit_expression(channel_arg);
+                let value = self.visit_expression(value);
+                if let ASTNode::Identifier(var) = channel_arg {
+                    self.channel_types.insert(var.clone(), value.ty);
+                }
+                let boxed = self.box_value(value);
+                self.output.push_str(&format!("  call void @flux_channel_send(i8* {}, i8* {})\n", 
+                                             channel.reg, boxed));
+                Some(IRValue::new("true", "i1"))
+            }
+            ("recv", [channel_arg]) => {
+                let channel = self.visit_expression(channel_arg);
+                let ty = match channel_arg {
+                    ASTNode::Identifier(var) => self.channel_types.get(var).copied().unwrap_or("double"),
+                    _ => "double",
+                };
+                let raw = self.new_temp();
+                self.output.push_str(&format!("  %{} = call i8* @flux_channel_recv(i8* {})\n", raw, channel.reg));
+                Some(self.unbox_value(&format!("%{}", raw), ty))
+            }
+            _ => None,
+        }
+    }
+    
+    /// Outlines a `spawn` body into its own function and starts it on a new
+    /// thread. The task gets a snapshot of the caller's locals, passed in a
+    /// heap environment with one 8-byte slot per variable.
+    fn emit_spawn(&mut self, node: &ASTNode, body: &'a [ASTNode]) -> IRValue {
+        let result_type = match self.type_of(node) {
+            FluxType::Task(result) => Self::llvm_type(&result),
+            _ => "double",
+        };
+        let task = format!("flux_task_{}", self.task_counter);
+        self.task_counter += 1;
+        
+        let mut captured: Vec<(String, &'static str)> = self.locals.iter()
+            .map(|(name, ty)| (name.clone(), *ty))
+            .collect();
+        captured.sort();
+        
+        let env = self.new_temp();
+        self.output.push_str(&format!("  %{} = call i8* @malloc(i64 {})\n", env, 8 * captured.len().max(1)));
+        for (i, (name, ty)) in captured.iter().enumerate() {
+            let value = self.new_temp();
+            let slot = self.emit_env_slot(&env, i, ty);
+            self.output.push_str(&format!("  %{} = load {}, {}* {}\n", value, ty, ty, self.slot(name)));
+            self.output.push_str(&format!("  store {} %{}, {}* {}\n", ty, value, ty, slot));
+        }
+        
+        let caller_body = std::mem::take(&mut self.output);
+        let caller_locals = std::mem::take(&mut self.locals);
+        let caller_timelines = std::mem::take(&mut self.timelines);
+        let caller_watchers = std::mem::take(&mut self.watchers);
+        let caller_return = self.return_type;
+        let caller_scope = std::mem::replace(&mut self.scope, task.clone());
+        let caller_allocas = std::mem::take(&mut self.allocas);
+        let caller_blocks = std::mem::take(&mut self.block_locals);
+        let caller_deferred = std::mem::take(&mut self.deferred);
+        let caller_cleanup = self.cleanup.take();
+        self.return_type = result_type;
+        
+        self.output.push_str(&format!("define internal {} @{}(i8* %env) {{\n", result_type, task));
+        self.output.push_str("entry:\n");
+        for (i, (name, ty)) in captured.iter().enumerate() {
+            let value = self.new_temp();
+            let slot = self.emit_env_slot("env", i, ty);
+            self.output.push_str(&format!("  %{} = load {}, {}* {}\n", value, ty, ty, slot));
+            let local = self.slot(name);
+            self.emit_alloca(&local, ty);
+            self.output.push_str(&format!("  store {} %{}, {}* {}\n", ty, value, ty, local));
+            self.locals.insert(name.clone(), ty);
+        }
+        
+        // Like the interpreter, a trailing expression is the task's result
+        let (last, rest) = match body.split_last() {
+            Some((last, rest)) if Self::is_expression(last) => (Some(last), rest),
+            _ => (None, body),
+        };
+        for stmt in rest {
+            self.visit(stmt);
+        }
+        match last {
+            Some(expr) => {
+                let value = self.visit_expression(expr);
+                let value = self.coerce(value, result_type);
+                self.output.push_str(&format!("  ret {} {}\n", result_type, value.reg));
+            }
+            None => {
+                let zero = Self::zero_value(result_type);
+                self.output.push_str(&format!("  ret {} {}\n", result_type, zero.reg));
+            }
+        }
+        self.output.push_str("}\n\n");
+        self.hoist_allocas();
+        
+        // pthread entry point boxing the result for pthread_join
+        self.output.push_str(&format!("define internal i8* @{}_entry(i8* %env) {{\n", task));
+        self.output.push_str("entry:\n");
+        self.output.push_str(&format!("  %result = call {} @{}(i8* %env)\n", result_type, task));
+        self.emit_abort_if_panicking();
+        self.output.push_str("  %box = call i8* @malloc(i64 8)\n");
+        self.output.push_str(&format!("  %slot = bitcast i8* %box to {}*\n", result_type));
+        self.output.push_str(&format!("  store {} %result, {}* %slot\n", result_type, result_type));
+        self.output.push_str("  ret i8* %box\n");
+        self.output.push_str("}\n\n");
+        
+        let task_body = std::mem::replace(&mut self.output, caller_body);
+        self.functions.push_str(&task_body);
+        self.locals = caller_locals;
+        self.timelines = caller_timelines;
+        self.watchers = caller_watchers;
+        self.return_type = caller_return;
+        self.scope = caller_scope;
+        self.allocas = caller_allocas;
+        self.block_locals = caller_blocks;
+        self.deferred = caller_deferred;
+        self.cleanup = caller_cleanup;
+        
+        let handle = self.new_temp();
+        let thread = self.new_temp();
+        let started = self.new_temp();
+        self.output.push_str(&format!("  %{} = call i8* @malloc(i64 8)\n", handle));
+        self.output.push_str(&format!("  %{} = bitcast i8* %{} to i64*\n", thread, handle));
+        self.output.push_str(&format!("  %{} = call i32 @pthread_create(i64* %{}, i8* null, i8* (i8*)* @{}_entry, i8* %{})\n", 
+                                     started, thread, task, env));
+        IRValue::new(format!("%{}", handle), "i8*")
+    }
+    
+    /// Typed pointer to the given slot of a task environment
+    fn emit_env_slot(&mut self, env: &str, index: usize, ty: &str) -> String {
+        let raw = self.new_temp();
+        let slot = self.new_temp();
+        self.output.push_str(&format!("  %{} = getelementptr i8, i8* %{}, i64 {}\n", raw, env, index * 8));
+        self.output.push_str(&format!("  %{} = bitcast i8* %{} to {}*\n", slot, raw, ty));
+        format!("%{}", slot)
+    }
+    
+    fn emit_join(&mut self, handle: IRValue, result_type: &'static str) -> IRValue {
+        let handle = self.coerce(handle, "i8*");
+        let thread_ptr = self.new_temp();
+        let thread = self.new_temp();
+        let result_slot = self.new_temp();
+        let joined = self.new_temp();
+        let raw = self.new_temp();
+        self.output.push_str(&format!("  %{} = bitcast i8* {} to i64*\n", thread_ptr, handle.reg));
+        self.output.push_str(&format!("  %{} = load i64, i64* %{}\n", thread, thread_ptr));
+        self.emit_alloca(&format!("%{}", result_slot), "i8*");
+        self.output.push_str(&format!("  %{} = call i32 @pthread_join(i64 %{}, i8** %{})\n", joined, thread, result_slot));
+        self.output.push_str(&format!("  %{} = load i8*, i8** %{}\n", raw, result_slot));
+        self.unbox_value(&format!("%{}", raw), result_type)
+    }
+    
+    fn is_expression(node: &ASTNode) -> bool {
+        matches!(node, ASTNode::Number(_) | ASTNode::String(_) | ASTNode::Boolean(_) 
+            | ASTNode::Identifier(_) | ASTNode::Binary { .. } | ASTNode::Unary { .. } 
+            | ASTNode::Call { .. } | ASTNode::MemberAccess { .. } | ASTNode::TemporalAccess { .. } 
+            | ASTNode::Pipeline(_) | ASTNode::Spawn(_))
+    }
+    
+    /// Inlines the variable's watchers, skipping any already running so a
+    /// watcher that updates its own variable doesn't expand forever
+    fn emit_watchers(&mut self, name: &str) {
+        let Some(bodies) = self.watchers.get(name).cloned() else {
+            return;
+        };
+        if !self.notifying.insert(name.to_string()) {
+            return;
+        }
+        
+        for body in bodies {
+            for stmt in body {
+                self.visit(stmt);
+            }
+        }
+        
+        self.notifying.remove(name);
+    }
+    
+    /// Lowers a temporal index; a string literal names a checkpoint, which
+    /// must have been reached by the time it's read
+    /// A time on the clock `var` records against: a checkpoint's reading of
+    /// that clock, or any other index as is
+    fn emit_timestamp(&mut self, var: &str, timestamp: &'a ASTNode) -> IRValue {
+        match timestamp {
+            ASTNode::String(label) => {
+                let id = self.checkpoint_id(label);
+                let domain = self.timeline_clocks.get(var)
+                    .map(|domain| format!(".{}", Self::ascii_name(domain)))
+                    .unwrap_or_default();
+                let label = self.string_literal(label);
+                let time = self.new_temp();
+                let checked = self.new_temp();
+                self.output.push_str(&format!("  %{} = load double, double* @flux_checkpoint.{}{}\n", time, id, domain));
+                self.output.push_str(&format!("  %{} = call double @flux_checkpoint_time(double %{}, i8* {})\n", 
+                                             checked, time, label.reg));
+                IRValue::new(format!("%{}", checked), "double")
+            }
+            _ => {
+                if self.type_of(timestamp) == FluxType::String {
+                    self.errors.push(Diagnostic::error(Diagnostic::CODEGEN,
+                        format!("`{}`: checkpoint names must be string literals to compile", timestamp)));
+                }
+                self.visit_expression(timestamp)
+            }
+        }
+    }
+    
+    fn checkpoint_id(&mut self, label: &str) -> usize {
+        match self.checkpoints.iter().position(|known| known == label) {
+            Some(id) => id,
+            None => {
+                self.checkpoints.push(label.to_string());
+                self.checkpoints.len() - 1
+            }
+        }
+    }
+    
+    /// Loads the value a temporal variable held at the given time
+    fn emit_temporal_at(&mut self, name: &str, timestamp: IRValue) -> Option<IRValue> {
+        if !self.timelines.contains(name) {
+            return None;
+        }
+        let slot_type = self.locals.get(name).copied()?;
+        let timestamp = self.coerce(timestamp, "double");
+        let label = self.string_literal(name);
+        
+        let raw = self.new_temp();
+        self.output.push_str(&format!("  %{} = call i8* @flux_temporal_at(%temporal_var* {}.timeline, double {}, i8* {})\n", 
+                                     raw, self.slot(name), timestamp.reg, label.reg));
+        Some(self.unbox_value(&format!("%{}", raw), slot_type))
+    }
+    
+    /// Lowers `format()` to snprintf into a heap buffer sized by a first,
+    /// measuring snprintf call; `println()` prints the same format directly
+    fn emit_format(&mut self, template: &str, args: Vec<(IRValue, FluxType)>, print_line: bool) -> IRValue {
+        let Ok(pieces) = FluxStdLib::parse_format(template) else {
+            return Self::zero_value("i8*");
+        };
+        
+        let mut format = String::new();
+        let mut operands = Vec::new();
+        let mut args = args.into_iter();
+        
+        for piece in pieces {
+            match piece {
+                FormatPiece::Literal(text) => format.push_str(&text.replace('%', "%%")),
+                FormatPiece::Placeholder { width, precision } => {
+                    let Some((value, flux_type)) = args.next() else {
+                        break;
+                    };
+                    let text_width = width;
+                    let width = width.map(|w| w.to_string()).unwrap_or_default();
+                    
+                    let is_number = match flux_type {
+                        FluxType::Number => true,
+                        FluxType::String | FluxType::Boolean => false,
+                        _ => value.ty == "double",
+                    };
+                    
+                    if is_number {
+                        match precision {
+                            Some(precision) => {
+                                let value = self.coerce(value, "double");
+                                format.push_str(&format!("%{}.{}f", width, precision));
+                                operands.push(format!("double {}", value.reg));
+                            }
+                            None => {
+                                let text = self.number_text(value);
+                                format.push_str(&format!("%{}s", width));
+                                operands.push(format!("i8* {}", text.reg));
+                            }
+                        }
+                    } else {
+                        let value = self.coerce(value, "i8*");
+                        match text_width {
+                            // snprintf pads to a width in bytes, the interpreter
+                            // in characters; widen by the bytes beyond one per
+                            // character so both pad the same
+                            Some(text_width) => {
+                                let width = self.emit_byte_width(&value, text_width);
+                                format.push_str("%-*s");
+                                operands.push(format!("i32 {}", width.reg));
+                            }
+                            None => format.push_str("%s"),
+                        }
+                        operands.push(format!("i8* {}", value.reg));
+                    }
+                }
+            }
+        }
+        
+        if print_line {
+            format.push('\n');
+        }
+        
+        let format = self.string_literal(&format);
+        let mut call_args = vec![format!("i8* {}", format.reg)];
+        call_args.extend(operands);
+        let call_args = call_args.join(", ");
+        
+        if print_line {
+            let temp = self.new_temp();
+            self.output.push_str(&format!("  %{} = call i32 (i8*, ...) @printf({})\n", temp, call_args));
+            return IRValue::new("true", "i1");
+        }
+        
+        let length = self.new_temp();
+        let length_wide = self.new_temp();
+        let size = self.new_temp();
+        let buffer = self.new_temp();
+        let written = self.new_temp();
+        
+        self.output.push_str(&format!("  %{} = call i32 (i8*, i64, i8*, ...) @snprintf(i8* null, i64 0, {})\n", 
+                                     length, call_args));
+        self.output.push_str(&format!("  %{} = sext i32 %{} to i64\n", length_wide, length));
+        self.output.push_str(&format!("  %{} = add i64 %{}, 1\n", size, length_wide));
+        self.output.push_str(&format!("  %{} = call i8* @malloc(i64 %{})\n", buffer, size));
+        self.output.push_str(&format!("  %{} = call i32 (i8*, i64, i8*, ...) @snprintf(i8* %{}, i64 %{}, {})\n", 
+                                     written, buffer, size, call_args));
+        IRValue::new(format!("%{}", buffer), "i8*")
+    }
+    
+    /// The snprintf field width that pads `text` to `width` characters
+    fn emit_byte_width(&mut self, text: &IRValue, width: usize) -> IRValue {
+        self.uses_chars = true;
+        let (bytes, chars, extra, total, field) =
+            (self.new_temp(), self.new_temp(), self.new_temp(), self.new_temp(), self.new_temp());
+        self.output.push_str(&format!("  %{} = call i64 @strlen(i8* {})
+", bytes, text.reg));
+        self.output.push_str(&format!("  %{} = call i64 @flux_utf8_length(i8* {})
+", chars, text.reg));
+        self.output.push_str(&format!("  %{} = sub i64 %{}, %{}
+", extra, bytes, chars));
+        self.output.push_str(&format!("  %{} = add i64 %{}, {}
+", total, extra, width));
+        self.output.push_str(&format!("  %{} = trunc i64 %{} to i32
+", field, total));
+        IRValue::new(format!("%{}", field), "i32")
+    }
+    
+    /// Converts a value to the requested LLVM type where a conversion exists
+    fn coerce(&mut self, value: IRValue, target: &'static str) -> IRValue {
+        if value.ty == target {
+            return value;
+        }
+        
+        let temp = self.new_temp();
+        match (value.ty, target) {
+            ("i1", "double") => {
+                self.output.push_str(&format!("  %{} = uitofp i1 {} to double\n", temp, value.reg));
+            }
+            ("double", "i1") => {
+                self.output.push_str(&format!("  %{} = fcmp une double {}, 0.0\n", temp, value.reg));
+            }
+            ("i8*", "i1") => {
+                self.output.push_str(&format!("  %{} = icmp ne i8* {}, null\n", temp, value.reg));
+            }
+            ("double", "i8*") | ("i1", "i8*") => return self.value_to_string(value),
+            _ => {
+                let describe = |ty| match ty {
+                    "double" => "number",
+                    "i1" => "boolean",
+                    _ => "string",
+                };
+                self.errors.push(Diagnostic::error(Diagnostic::CODEGEN, format!(
+                    "A {} can't be used where compiled code expects a {}", describe(value.ty), describe(target))));
+                return Self::zero_value(target);
+            }
+        }
+        IRValue::new(format!("%{}", temp), target)
+    }
+    
+    /// Reports a construct that only the interpreter can run
+    fn interpreter_only(&mut self, message: String) {
+        self.errors.push(Diagnostic::error(Diagnostic::INTERPRETER_ONLY, message)
+            .with_suggestion("run the program with `flux run` instead"));
+    }
+    
+    /// Formats a number according to the current `set_precision` setting
+    fn number_text(&mut self, value: IRValue) -> IRValue {
+        self.uses_number_text = true;
+        let value = self.coerce(value, "double");
+        let text = self.new_temp();
+        self.output.push_str(&format!("  %{} = call i8* @flux_number_text(double {})\n", text, value.reg));
+        IRValue::new(format!("%{}", text), "i8*")
+    }
+    
+    fn value_to_string(&mut self, value: IRValue) -> IRValue {
+        if value.ty == "i1" {
+            let true_str = self.string_literal("true");
+            let false_str = self.string_literal("false");
+            let temp = self.new_temp();
+            self.output.push_str(&format!("  %{} = select i1 {}, i8* {}, i8* {}\n", 
+                                         temp, value.reg, true_str.reg, false_str.reg));
+            return IRValue::new(format!("%{}", temp), "i8*");
+        }
+        
+        self.uses_number_text = true;
+        let text = self.new_temp();
+        self.output.push_str(&format!("  %{} = call i8* @flux_number_string(double {})\n", text, value.reg));
+        IRValue::new(format!("%{}", text), "i8*")
+    }
+    
+    fn compare_strings(&mut self, left: IRValue, right: IRValue, predicate: &str) -> IRValue {
+        let cmp = self.new_temp();
+        let result = self.new_temp();
+        self.output.push_str(&format!("  %{} = call i32 @strcmp(i8* {}, i8* {})\n", 
+                                     cmp, left.reg, right.reg));
+        self.output.push_str(&format!("  %{} = icmp {} i32 %{}, 0\n", 
+                                     result, predicate, cmp));
+        IRValue::new(format!("%{}", result), "i1")
+    }
+    
+    /// Tests a match pattern against the subject, following
+    /// [`PatternMatcher::condition`]: ranges compare numerically and
+    /// alternatives are or-ed together
+    fn emit_pattern(&mut self, subject: IRValue, pattern: &'a ASTNode) -> IRValue {
+        match pattern {
+            ASTNode::Range { start, end } => {
+                let value = self.coerce(subject, "double");
+                let start = self.visit_expression(start);
+                let start = self.coerce(start, "double");
+                let end = self.visit_expression(end);
+                let end = self.coerce(end, "double");
+                let (above, below, both) = (self.new_temp(), self.new_temp(), self.new_temp());
+                self.output.push_str(&format!("  %{} = fcmp oge double {}, {}\n", above, value.reg, start.reg));
+                self.output.push_str(&format!("  %{} = fcmp ole double {}, {}\n", below, value.reg, end.reg));
+                self.output.push_str(&format!("  %{} = and i1 %{}, %{}\n", both, above, below));
+                IRValue::new(format!("%{}", both), "i1")
+            }
+            ASTNode::Alternatives(patterns) => {
+                let mut matched = IRValue::new("false".to_string(), "i1");
+                for pattern in patterns {
+                    let alternative = self.emit_pattern(subject.clone(), pattern);
+                    let either = self.new_temp();
+                    self.output.push_str(&format!("  %{} = or i1 {}, {}\n", either, matched.reg, alternative.reg));
+                    matched = IRValue::new(format!("%{}", either), "i1");
+                }
+                matched
+            }
+            _ => {
+                let pattern = self.visit_expression(pattern);
+                self.emit_equals(subject, pattern)
+            }
+        }
+    }
+    
+    /// `==` for match patterns: strings by content, everything else numerically
+    fn emit_equals(&mut self, left: IRValue, right: IRValue) -> IRValue {
+        if left.ty == "i8*" && right.ty == "i8*" {
+            return self.compare_strings(left, right, "eq");
+        }
+        
+        let left = self.coerce(left, "double");
+        let right = self.coerce(right, "double");
+        let result = self.new_temp();
+        self.output.push_str(&format!("  %{} = fcmp oeq double {}, {}\n", 
+                                     result, left.reg, right.reg));
+        IRValue::new(format!("%{}", result), "i1")
+    }
+    
+    fn concat_strings(&mut self, left: IRValue, right: IRValue) -> IRValue {
+        let left = self.coerce(left, "i8*");
+        let right = self.coerce(right, "i8*");
+        
+        let left_len = self.new_temp();
+        let right_len = self.new_temp();
+        let total = self.new_temp();
+        let size = self.new_temp();
+        let buffer = self.new_temp();
+        let copied = self.new_temp();
+        let joined = self.new_temp();
+        
+        self.output.push_str(&format!("  %{} = call i64 @strlen(i8* {})\n", left_len, left.reg));
+        self.output.push_str(&format!("  %{} = call i64 @strlen(i8* {})\n", right_len, right.reg));
+        self.output.push_str(&format!("  %{} = add i64 %{}, %{}\n", total, left_len, right_len));
+        self.output.push_str(&format!("  %{} = add i64 %{}, 1\n", size, total));
+        self.output.push_str(&format!("  %{} = call i8* @malloc(i64 %{})\n", buffer, size));
+        self.output.push_str(&format!("  %{} = call i8* @strcpy(i8* %{}, i8* {})\n", copied, buffer, left.reg));
+        self.output.push_str(&format!("  %{} = call i8* @strcat(i8* %{}, i8* {})\n", joined, buffer, right.reg));
+        IRValue::new(format!("%{}", buffer), "i8*")
+    }
+    
+    /// Returns a pointer to the first byte of a string literal's global,
+    /// interning the literal so each distinct string is emitted once
+    fn string_literal(&mut self, s: &str) -> IRValue {
+        let id = match self.string_ids.get(s) {
+            Some(&id) => id,
+            None => {
+                self.strings.push(s.to_string());
+                self.string_ids.insert(s.to_string(), self.strings.len());
+                self.strings.len()
+            }
+        };
+        // Array lengths count UTF-8 bytes plus the terminator
+        let length = s.len() + 1;
+        
+        let temp = self.new_temp();
+        self.output.push_str(&format!("  %{} = getelementptr inbounds [{} x i8], [{} x i8]* @.str.{}, i32 0, i32 0\n", 
+                                     temp, length, length, id));
+        IRValue::new(format!("%{}", temp), "i8*")
+    }
+    
+    fn emit_string_table(&mut self) {
+        for (i, s) in self.strings.iter().enumerate() {
+            let mut escaped = String::new();
+            for byte in s.bytes() {
+                if byte.is_ascii_graphic() && byte != b'"' && byte != b'\\' || byte == b' ' {
+                    escaped.push(byte as char);
+                } else {
+                    escaped.push_str(&format!("\\{:02X}", byte));
+                }
+            }
+            self.output.push_str(&format!("@.str.{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"\n", 
+                                         i + 1, s.len() + 1, escaped));
+        }
+        self.output.push('\n');
+    }
+    
+    /// Stack slot of a Flux variable. Mangling by scope keeps user names
+    /// clear of generated registers like `%t1`, `%arg0` and `%env`
+    fn slot(&self, name: &str) -> String {
+        format!("%v_{}_{}", Self::ascii_name(&self.scope), Self::ascii_name(name))
+    }
+    
+    /// LLVM names are ASCII, so other characters become their code point in
+    /// hex between `$`s; `$` can't appear in a Flux identifier, so this
+    /// can't collide with another name
+    fn ascii_name(name: &str) -> String {
+        name.chars()
+            .map(|ch| if ch.is_ascii() { ch.to_string() } else { format!("${:x}$", ch as u32) })
+            .collect()
+    }
+    
+    /// Symbol of a user function. Exports keep their name as their C ABI
+    /// (`double name(double, ...)`); others are prefixed to stay clear of
+    /// `@main` and the runtime helpers
+    fn function_symbol(&self, name: &str) -> String {
+        if self.exports.contains(name) {
+            format!("@{}", name)
+        } else {
+            format!("@flux_user_{}", Self::ascii_name(name))
+        }
+    }
+    
+    fn new_temp(&mut self) -> String {
+        self.temp_counter += 1;
+        format!("t{}", self.temp_counter)
+    }
+    
+    fn new_label(&mut self) -> String {
+        self.label_counter += 1;
+        format!("L{}", self.label_counter)
+    }
+}
+
+// ============================================================================
+// MAIN COMPILER DRIVER
+// ============================================================================
+
+/// Code generation target; LLVM IR is the only one so far
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Llvm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// One compiler message in a form tools can consume: a stable code for its
+/// kind, where it points (when the stage that raised it tracks positions)
+/// and, for common mistakes, a suggested fix
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub span: Option<Span>,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    pub const UNEXPECTED_CHARACTER: &'static str = "W0001";
+    pub const PARSE: &'static str = "E0100";
+    pub const COMPTIME: &'static str = "E0200";
+    // Semantic errors are numbered E03xx, one code per kind of mistake
+    pub const UNDEFINED_NAME: &'static str = "E0301";
+    pub const REDECLARED: &'static str = "E0302";
+    pub const IMMUTABLE: &'static str = "E0303";
+    pub const NOT_TEMPORAL: &'static str = "E0304";
+    pub const TYPE_MISMATCH: &'static str = "E0305";
+    pub const ARGUMENT_COUNT: &'static str = "E0306";
+    pub const INVALID_ARGUMENT: &'static str = "E0307";
+    pub const MISSING_VALUE: &'static str = "E0308";
+    pub const MISPLACED: &'static str = "E0309";
+    pub const INVALID_EXPORT: &'static str = "E0310";
+    pub const UNKNOWN_LABEL: &'static str = "E0311";
+    pub const OVERLAPPING_PATTERN: &'static str = "W0300";
+    pub const CODEGEN: &'static str = "E0400";
+    pub const INTERPRETER_ONLY: &'static str = "E0401";
+    pub const PASS: &'static str = "E0500";
+    
+    pub fn new(code: &'static str, severity: Severity, message: String) -> Self {
+        Self { code, severity, span: None, message, suggestion: None }
+    }
+    
+    pub fn error(code: &'static str, message: String) -> Self {
+        Self::new(code, Severity::Error, message)
+    }
+    
+    pub fn at(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+    
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+    
+    /// ` (did you mean 'x'?)` when one of `candidates` is close enough to
+    /// `name` to be a likely typo, for the end of an unknown-name message
+    pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+        Self::closest_name(name, candidates)
+            .map(|candidate| format!(" (did you mean '{}'?)", candidate))
+            .unwrap_or_default()
+    }
+    
+    /// The candidate nearest to `name` by edit distance, allowing one edit
+    /// per three characters; ties go to the alphabetically first
+    pub fn closest_name<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+        let limit = name.chars().count().max(3) / 3;
+        candidates.into_iter()
+            .filter(|candidate| *candidate != name)
+            .map(|candidate| (Self::edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= limit)
+            .min()
+            .map(|(_, candidate)| candidate)
+    }
+    
+    /// Insertions, deletions, substitutions and swaps of neighbouring
+    /// characters needed to turn `a` into `b`
+    fn edit_distance(a: &str, b: &str) -> usize {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        // rows[i][j] is the distance between a[..i] and b[..j]
+        let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for (i, row) in rows.iter_mut().enumerate() {
+            row[0] = i;
+        }
+        rows[0] = (0..=b.len()).collect();
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                let cost = usize::from(a[i - 1] != b[j - 1]);
+                let mut distance = (rows[i - 1][j] + 1)
+                    .min(rows[i][j - 1] + 1)
+                    .min(rows[i - 1][j - 1] + cost);
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                    distance = distance.min(rows[i - 2][j - 2] + 1);
+                }
+                rows[i][j] = distance;
+            }
+        }
+        rows[a.len()][b.len()]
+    }
+    
+    /// `to_json` with the file the diagnostic belongs to, as `"file"`
+    pub fn to_json_in(&self, file: &str) -> String {
+        format!("{{\"file\":{},{}", Self::json_string(file), &self.to_json()[1..])
+    }
+    
+    /// One line of JSON, for `--error-format=json`
+    pub fn to_json(&self) -> String {
+        let span = match self.span {
+            Some(span) => format!("{{\"line\":{},\"column\":{}}}", span.line, span.column),
+            None => "null".to_string(),
+        };
+        let suggestion = self.suggestion.as_deref().map_or("null".to_string(), Self::json_string);
+        format!("{{\"code\":\"{}\",\"severity\":\"{}\",\"span\":{},\"message\":{},\"suggestion\":{}}}",
+                self.code, self.severity.name(), span, Self::json_string(&self.message), suggestion)
+    }
+    
+    fn json_string(text: &str) -> String {
+        let mut quoted = String::from("\"");
+        for ch in text.chars() {
+            match ch {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                ch if (ch as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+                ch => quoted.push(ch),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+    
+    /// The errors of a failed compilation as the single message `compile`
+    /// reports, e.g. `Semantic errors: ["..."]`. They all come from the
+    /// stage that failed.
+    pub fn summary(diagnostics: &[Diagnostic]) -> String {
+        let errors: Vec<&Diagnostic> = diagnostics.iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .collect();
+        let messages: Vec<&str> = errors.iter().map(|error| error.message.as_str()).collect();
+        match errors.first().map(|error| error.code) {
+            Some(Self::PARSE) => format!("Parse error: {}", messages[0]),
+            Some(Self::COMPTIME) => format!("Comptime error: {}", messages[0]),
+            Some(code) if code.starts_with("E03") => format!("Semantic errors: {:?}", messages),
+            Some(_) => format!("Codegen errors: {:?}", messages),
+            None => "Compilation failed".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(span) = self.span {
+            write!(f, " at line {}, column {}", span.line, span.column)?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything one compilation produced: the IR when it succeeded, every
+/// warning and error, and the stage stats
+#[derive(Debug, Clone)]
+pub struct CompileReport {
+    pub ir: Option<String>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub stats: CompileStats,
+    /// Where each generated statement came from, when the compiler was
+    /// built with `source_map(true)` and compilation succeeded
+    pub source_map: Option<SourceMap>,
+}
+
+/// What one compilation did: how much it produced at each stage and how long
+/// each stage took, in the order they ran
+#[derive(Debug, Clone, Default)]
+pub struct CompileStats {
+    pub tokens: usize,
+    pub ast_nodes: usize,
+    pub stages: Vec<(&'static str, Duration)>,
+    pub ir_bytes: usize,
+    /// Warnings, plus the errors of the stage that failed if one did
+    pub diagnostics: usize,
+}
+
+impl CompileStats {
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, elapsed)| *elapsed).sum()
+    }
+    
+    fn time<T>(&mut self, stage: &'static str, run: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = run();
+        self.stages.push((stage, start.elapsed()));
+        result
+    }
+    
+    fn count_nodes(node: &ASTNode) -> usize {
+        1 + node.children().into_iter().map(Self::count_nodes).sum::<usize>()
+    }
+}
+
+impl fmt::Display for CompileStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "tokens       {}", self.tokens)?;
+        writeln!(f, "ast nodes    {}", self.ast_nodes)?;
+        writeln!(f, "ir bytes     {}", self.ir_bytes)?;
+        writeln!(f, "diagnostics  {}", self.diagnostics)?;
+        for (stage, elapsed) in &self.stages {
+            writeln!(f, "{:<12} {:>10.3} ms", stage, elapsed.as_secs_f64() * 1000.0)?;
+        }
+        write!(f, "{:<12} {:>10.3} ms", "total", self.total().as_secs_f64() * 1000.0)
+    }
+}
+
+/// Side table from lines of generated IR back to the Flux source, so tools
+/// running the output can report original line numbers. Each statement's
+/// code maps to where the statement starts, up to the next statement's;
+/// a function's prologue maps to its declaration, and the rest of
+/// `@flux_main` (with the `@main` entry point that calls it) to the top of
+/// the file. Runtime helpers and spawned tasks are left unmapped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    pub mappings: Vec<SourceMapping>,
+}
+
+/// One run of generated lines: the function's symbol, the 1-based IR lines
+/// and the source position they came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMapping {
+    pub symbol: String,
+    /// `None` for top-level code
+    pub function: Option<String>,
+    pub output_start: usize,
+    pub output_end: usize,
+    pub span: Span,
+}
+
+impl SourceMap {
+    /// Maps the functions defined in `ir`, given where each Flux function
+    /// was declared, splitting them at the `; flux line:column` marks
+    /// `CodeGenerator::with_source_marks` left before statements
+    pub fn build(ir: &str, functions: &HashMap<String, Span>) -> Self {
+        let mut mappings = Vec::new();
+        let mut open: Option<(String, Option<String>, usize, Span)> = None;
+        
+        for (index, line) in ir.lines().enumerate() {
+            if let Some(signature) = line.strip_prefix("define ") {
+                let symbol = signature.split('@').nth(1)
+                    .and_then(|rest| rest.split('(').next())
+                    .unwrap_or_default();
+                let top_level = Span { line: 1, column: 1 };
+                open = match symbol {
+                    "flux_main" | "main" => Some((format!("@{}", symbol), None, index + 1, top_level)),
+                    _ => functions.iter()
+                        .find(|(name, _)| {
+                            symbol == name.as_str()
+                                || symbol == format!("flux_user_{}", CodeGenerator::ascii_name(name))
+                        })
+                        .map(|(name, span)| (format!("@{}", symbol), Some(name.clone()), index + 1, *span)),
+                };
+            } else if line == "}" && let Some((symbol, function, output_start, span)) = open.take() {
+                mappings.push(SourceMapping { symbol, function, output_start, output_end: index + 1, span });
+            } else if let Some(mark) = line.strip_prefix("; flux ")
+                && let Some((line, column)) = mark.split_once(':')
+                && let (Ok(line), Ok(column)) = (line.parse(), column.parse())
+                && let Some((symbol, function, output_start, span)) = open.take()
+            {
+                mappings.push(SourceMapping {
+                    symbol: symbol.clone(), function: function.clone(), output_start, output_end: index, span,
+                });
+                open = Some((symbol, function, index + 1, Span { line, column }));
+            }
+        }
+        Self { mappings }
+    }
+    
+    /// The mapping covering 1-based IR line `output_line`, if any
+    pub fn lookup(&self, output_line: usize) -> Option<&SourceMapping> {
+        self.mappings.iter()
+            .find(|mapping| (mapping.output_start..=mapping.output_end).contains(&output_line))
+    }
+    
+    /// The map as one JSON object, naming `source` as the file the spans
+    /// point into, for `flux build --source-map`
+    pub fn to_json(&self, source: &str) -> String {
+        let mappings: Vec<String> = self.mappings.iter().map(|mapping| {
+            let function = mapping.function.as_deref().map_or("null".to_string(), Diagnostic::json_string);
+            format!("{{\"symbol\":{},\"function\":{},\"output\":{{\"start\":{},\"end\":{}}},\"line\":{},\"column\":{}}}",
+                    Diagnostic::json_string(&mapping.symbol), function, mapping.output_start, mapping.output_end,
+                    mapping.span.line, mapping.span.column)
+        }).collect();
+        format!("{{\"version\":1,\"source\":{},\"mappings\":[{}]}}", Diagnostic::json_string(source), mappings.join(","))
+    }
+}
+
+/// Configures a `FluxCompiler`:
+/// `FluxCompiler::builder().optimization(2).backend(Backend::Llvm).debug_info(true).build()`
+#[derive(Debug, Clone)]
+pub struct FluxCompilerBuilder {
+    debug: bool,
+    dump_state: bool,
+    optimization: u8,
+    backend: Backend,
+    debug_info: bool,
+    source_map: bool,
+    defines: HashMap<String, String>,
+}
+
+impl Default for FluxCompilerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FluxCompilerBuilder {
+    pub fn new() -> Self {
+        Self {
+            debug: false,
+            dump_state: false,
+            optimization: 0,
+            backend: Backend::Llvm,
+            debug_info: false,
+            source_map: false,
+            defines: HashMap::new(),
+        }
+    }
+    
+    /// Prints every intermediate stage while compiling
+    pub fn verbose(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+    
+    /// 0 disables optimization; 1 and above fold constant expressions, drop
+    /// the timelines of temporal variables whose history is never read and
+    /// clean up the generated IR
+    pub fn optimization(mut self, level: u8) -> Self {
+        self.optimization = level;
+        self
+    }
+    
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+    
+    /// Attaches a DWARF compile unit to the generated module
+    pub fn debug_info(mut self, enabled: bool) -> Self {
+        self.debug_info = enabled;
+        self
+    }
+    
+    /// Fills `CompileReport::source_map` with where each generated
+    /// statement came from, marking the statements in the IR
+    pub fn source_map(mut self, enabled: bool) -> Self {
+        self.source_map = enabled;
+        self
+    }
+    
+    /// Makes compiled programs report their final top-level variables on
+    /// stderr, as `flux verify` compares them against the interpreter
+    pub fn state_dump(mut self, enabled: bool) -> Self {
+        self.dump_state = enabled;
+        self
+    }
+    
+    /// Sets a flag for `#pragma if` sections, as `--define name=value` does
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+    
+    pub fn build(self) -> FluxCompiler {
+        FluxCompiler {
+            debug: self.debug,
+            dump_state: self.dump_state,
+            optimization: self.optimization,
+            backend: self.backend,
+            debug_info: self.debug_info,
+            source_map: self.source_map,
+            defines: self.defines,
+            passes: Vec::new(),
+        }
+    }
+}
+
+/// A custom pass registered with `FluxCompiler::add_pass`, for lints or
+/// lowering a DSL without forking the compiler. Passes run in the order
+/// they were added, after semantic analysis and before codegen, and may
+/// rewrite the program; whatever they leave is analysed again. An error
+/// diagnostic stops compilation once the reporting pass returns.
+pub trait AstPass {
+    /// The stage name the pass is timed under in `CompileStats`
+    fn name(&self) -> &'static str;
+    
+    fn run(&self, ast: &mut ASTNode, diagnostics: &mut Vec<Diagnostic>);
+}
+
+/// Drives a program through every stage. `compile` runs them all; the
+/// staged entry points (`tokenize`, `parse_tokens`, `analyze`, `lower`)
+/// hand back each intermediate artifact so embedders can inspect or
+/// rewrite it before continuing.
+pub struct FluxCompiler {
+    debug: bool,
+    dump_state: bool,
+    optimization: u8,
+    backend: Backend,
+    debug_info: bool,
+    source_map: bool,
+    defines: HashMap<String, String>,
+    passes: Vec<Box<dyn AstPass>>,
+}
+
+impl FluxCompiler {
+    pub fn new(debug: bool) -> Self {
+        Self::builder().verbose(debug).build()
+    }
+    
+    pub fn builder() -> FluxCompilerBuilder {
+        FluxCompilerBuilder::new()
+    }
+    
+    pub fn optimization_level(&self) -> u8 {
+        self.optimization
+    }
+    
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+    
+    /// Runs `pass` on every program this compiler analyses, after the
+    /// passes added before it
+    pub fn add_pass(&mut self, pass: Box<dyn AstPass>) {
+        self.passes.push(pass);
+    }
+    
+    pub fn compile_file(&self, filename: &str) -> Result<String, String> {
+        let source = fs::read_to_string(filename)
+            .map_err(|e| format!("Failed to read file {}: {}", filename, e))?;
+        
+        let report = self.compile_named(&source, Some(filename));
+        Self::into_result(report.ir, &report.diagnostics)
+    }
+    
+    fn lexer(&self, source: &str) -> Lexer {
+        Lexer::new(source).with_defines(self.defines.clone())
+    }
+    
+    /// Lexical analysis
+    pub fn tokenize(&self, source: &str) -> Vec<TokenType> {
+        let mut lexer = self.lexer(source);
+        let tokens = lexer.tokenize();
+        for (span, error) in lexer.errors() {
+            eprintln!("{} at line {}, column {}", error, span.line, span.column);
+        }
+        
+        if self.debug {
+            println!("Tokens: {:?}\n", tokens);
+        }
+        
+        tokens
+    }
+    
+    /// Syntax analysis, including macro expansion
+    pub fn parse_tokens(&self, tokens: Vec<TokenType>) -> Result<ASTNode, String> {
+        let ast = Parser::new(tokens).parse()
+            .map_err(|e| format!("Parse error: {}", e))?;
+        
+        if self.debug {
+            println!("AST: {:#?}\n", ast);
+        }
+        
+        Ok(ast)
+    }
+    
+    /// Lexes and parses `source` without analysing or compiling it
+    pub fn parse(&self, source: &str) -> Result<ASTNode, String> {
+        let mut lexer = self.lexer(source);
+        let tokens = lexer.tokenize();
+        if let Some((span, error)) = lexer.errors().first() {
+            return Err(format!("Parse error: {} at line {}, column {}", error, span.line, span.column));
+        }
+        
+        if self.debug {
+            println!("Tokens: {:?}\n", tokens);
+        }
+        self.parse_tokens(tokens)
+    }
+    
+    /// Runs `comptime` blocks and, when optimizing, folds constants in place,
+    /// then type-checks the result and runs any added passes over it
+    pub fn analyze<'a>(&self, ast: &'a mut ASTNode) -> Result<TypedAST<'a>, String> {
+        self.analyze_with(ast, &mut SemanticAnalyzer::new())
+    }
+    
+    /// Analyses `ast` like `analyze`, starting from what `analyzer` learned
+    /// from earlier programs and keeping what it learns from this one
+    fn analyze_with<'a>(&self, ast: &'a mut ASTNode, analyzer: &mut SemanticAnalyzer) -> Result<TypedAST<'a>, String> {
+        let mut warnings = Vec::new();
+        let typed_ast = self.analyze_timed(ast, analyzer, &mut CompileStats::default(), &mut warnings);
+        for warning in &warnings {
+            eprintln!("{}", warning);
+        }
+        typed_ast.map_err(|errors| Diagnostic::summary(&errors))
+    }
+    
+    fn analyze_timed<'a>(&self, ast: &'a mut ASTNode, analyzer: &mut SemanticAnalyzer, stats: &mut CompileStats,
+                         warnings: &mut Vec<Diagnostic>) -> Result<TypedAST<'a>, Vec<Diagnostic>> {
+        let mut comptime = ComptimeEvaluator::new();
+        stats.time("comptime", || comptime.evaluate(ast))
+            .map_err(|e| vec![Diagnostic::error(Diagnostic::COMPTIME, e)])?;
+        
+        if self.optimization > 0 {
+            let demoted = stats.time("optimize", || {
+                ASTOptimizer::propagate_constants(ast, comptime.constants());
+                ASTOptimizer::optimize(ast);
+                TimelineDemotion::demote(ast)
+            });
+            if self.debug && !demoted.is_empty() {
+                println!("Demoted unread timelines: {}\n", demoted.join(", "));
+            }
+        }
+        
+        if !self.passes.is_empty() {
+            // Passes only see programs that check out; what they hand
+            // back is analysed below like any other
+            self.check_semantics(ast, &mut analyzer.clone(), stats, warnings)?;
+            for pass in &self.passes {
+                let mut reported = Vec::new();
+                stats.time(pass.name(), || pass.run(ast, &mut reported));
+                let (errors, rest): (Vec<_>, Vec<_>) = reported.into_iter()
+                    .partition(|diagnostic| diagnostic.severity == Severity::Error);
+                warnings.extend(rest);
+                if !errors.is_empty() {
+                    return Err(errors);
+                }
+            }
+            // Nodes a pass rebuilt can sit where freed statements were, so
+            // what it hands back isn't pointed into the source
+            analyzer.spans.clear();
+        }
+        
+        let typed_ast = self.check_semantics(ast, analyzer, stats, warnings)?;
+        
+        if self.debug {
+            println!("Semantic analysis passed\n");
+        }
+        
+        Ok(typed_ast)
+    }
+    
+    fn check_semantics<'a>(&self, ast: &'a ASTNode, analyzer: &mut SemanticAnalyzer, stats: &mut CompileStats,
+                           warnings: &mut Vec<Diagnostic>) -> Result<TypedAST<'a>, Vec<Diagnostic>> {
+        let typed_ast = stats.time("semantic", || analyzer.analyze(ast));
+        for warning in analyzer.warnings() {
+            // Analysing again after passes repeats the first run's warnings
+            if !warnings.iter().any(|seen| seen.message == warning.message) {
+                warnings.push(warning.clone());
+            }
+        }
+        typed_ast
+    }
+    
+    /// Generates code for the configured backend
+    pub fn lower(&self, typed_ast: &TypedAST) -> Result<String, String> {
+        self.lower_named(typed_ast, "main.flux", HashMap::new(), &mut CompileStats::default())
+            .map_err(|errors| Diagnostic::summary(&errors))
+    }
+    
+    fn lower_named(&self, typed_ast: &TypedAST, source_name: &str, statement_spans: HashMap<*const ASTNode, Span>,
+                   stats: &mut CompileStats) -> Result<String, Vec<Diagnostic>> {
+        let Backend::Llvm = self.backend;
+        
+        let mut generator = CodeGenerator::new();
+        if self.dump_state {
+            generator = generator.with_state_dump();
+        }
+        if self.source_map {
+            generator = generator.with_source_marks(statement_spans);
+        }
+        let mut llvm_ir = stats.time("codegen", || generator.generate(typed_ast))?;
+        if self.optimization > 0 {
+            llvm_ir = stats.time("peephole", || IRPeephole::optimize(&llvm_ir));
+        }
+        
+        if self.debug_info {
+            llvm_ir.push_str(&self.debug_metadata(source_name));
+        }
+        
+        if self.debug {
+            println!("Generated LLVM IR:\n{}", llvm_ir);
+        }
+        
+        Ok(llvm_ir)
+    }
+    
+    fn debug_metadata(&self, source_name: &str) -> String {
+        let path = std::path::Path::new(source_name);
+        let filename = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let directory = path.parent()
+            .map(|dir| dir.to_string_lossy())
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or(".".into());
+        
+        let mut metadata = String::from("\n; Debug information\n");
+        metadata.push_str("!llvm.dbg.cu = !{!0}\n");
+        metadata.push_str("!llvm.module.flags = !{!2, !3}\n");
+        metadata.push_str(&format!(
+            "!0 = distinct !DICompileUnit(language: DW_LANG_C, file: !1, producer: \"flux {}\", isOptimized: {}, runtimeVersion: 0, emissionKind: FullDebug)\n",
+            env!("CARGO_PKG_VERSION"), self.optimization > 0));
+        metadata.push_str(&format!("!1 = !DIFile(filename: {:?}, directory: {:?})\n", filename, directory));
+        metadata.push_str("!2 = !{i32 2, !\"Debug Info Version\", i32 3}\n");
+        metadata.push_str("!3 = !{i32 2, !\"Dwarf Version\", i32 4}\n");
+        metadata
+    }
+    
+    pub fn compile(&self, source: &str) -> Result<String, String> {
+        self.compile_with_stats(source).0
+    }
+    
+    /// Compiles `source` like `compile`, also reporting what each stage
+    /// produced and how long it took; the stats cover the stages that ran
+    /// even when compilation fails
+    pub fn compile_with_stats(&self, source: &str) -> (Result<String, String>, CompileStats) {
+        let report = self.compile_report(source);
+        (Self::into_result(report.ir, &report.diagnostics), report.stats)
+    }
+    
+    /// Compiles `source`, keeping every diagnostic in structured form rather
+    /// than folding them into one message or printing warnings
+    pub fn compile_report(&self, source: &str) -> CompileReport {
+        self.compile_named(source, Some("main.flux"))
+    }
+    
+    /// Lexes, parses and analyses `source` without generating code, for
+    /// `flux check`; the report never holds IR
+    pub fn check(&self, source: &str) -> CompileReport {
+        self.compile_named(source, None)
+    }
+    
+    fn into_result(ir: Option<String>, diagnostics: &[Diagnostic]) -> Result<String, String> {
+        for warning in diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Warning) {
+            eprintln!("{}", warning);
+        }
+        ir.ok_or_else(|| Diagnostic::summary(diagnostics))
+    }
+    
+    /// Runs the pipeline on `source`, generating code for module
+    /// `source_name`, or stopping after analysis when there is none
+    fn compile_named(&self, source: &str, source_name: Option<&str>) -> CompileReport {
+        let mut stats = CompileStats::default();
+        let mut diagnostics = Vec::new();
+        let mut functions = HashMap::new();
+        let ir = self.run_stages(source, source_name, &mut stats, &mut diagnostics, &mut functions);
+        stats.diagnostics = diagnostics.len();
+        
+        if self.debug {
+            println!("Compile stats:\n{}\n", stats);
+        }
+        
+        let source_map = ir.as_deref().filter(|_| self.source_map).map(|ir| SourceMap::build(ir, &functions));
+        CompileReport { ir, diagnostics, stats, source_map }
+    }
+    
+    /// Runs every stage, recording where each function was declared in
+    /// `functions` once the program has parsed
+    fn run_stages(&self, source: &str, source_name: Option<&str>, stats: &mut CompileStats,
+                  diagnostics: &mut Vec<Diagnostic>, functions: &mut HashMap<String, Span>) -> Option<String> {
+        if self.debug {
+            println!("=== FLUX COMPILER DEBUG ===");
+            println!("Source code:\n{}\n", source);
+        }
+        
+        let mut lexer = self.lexer(source);
+        let (tokens, spans) = stats.time("lex", || lexer.tokenize_with_spans());
+        if self.debug {
+            println!("Tokens: {:?}\n", tokens);
+        }
+        stats.tokens = tokens.len();
+        diagnostics.extend(lexer.unexpected().iter().map(|(span, ch)| {
+            Diagnostic::new(Diagnostic::UNEXPECTED_CHARACTER, Severity::Warning, format!("Unexpected character: {}", ch))
+                .at(*span)
+        }));
+        if !lexer.errors().is_empty() {
+            diagnostics.extend(lexer.errors().iter()
+                .map(|(span, error)| Diagnostic::error(Diagnostic::PARSE, error.clone()).at(*span)));
+            return None;
+        }
+        
+        let mut parser = Parser::new(tokens);
+        let mut ast = match stats.time("parse", || parser.parse()) {
+            Ok(ast) => ast,
+            Err(error) => {
+                let span = spans[parser.token_index()];
+                diagnostics.push(Diagnostic::error(Diagnostic::PARSE, error).at(span));
+                return None;
+            }
+        };
+        if self.debug {
+            println!("AST: {:#?}\n", ast);
+        }
+        functions.extend(parser.function_starts().iter().map(|(name, start)| (name.clone(), spans[*start])));
+        stats.ast_nodes = CompileStats::count_nodes(&ast);
+        
+        let statement_spans: HashMap<_, _> = parser.statement_starts().iter().map(|(node, start)| (*node, spans[*start])).collect();
+        let mut analyzer = SemanticAnalyzer::new().with_spans(statement_spans.clone());
+        let lowered = self.analyze_timed(&mut ast, &mut analyzer, stats, diagnostics).and_then(|typed_ast| match source_name {
+            Some(source_name) => self.lower_named(&typed_ast, source_name, statement_spans, stats).map(Some),
+            None => Ok(None),
+        });
+        match lowered {
+            Ok(None) => None,
+            Ok(Some(llvm_ir)) => {
+                stats.ir_bytes = llvm_ir.len();
+                Some(llvm_ir)
+            }
+            Err(errors) => {
+                diagnostics.extend(errors);
+                None
+            }
+        }
+    }
+}
+
+// ============================================================================
+// DOCUMENTATION GENERATOR
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+impl DocFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "markdown" | "md" => Some(DocFormat::Markdown),
+            "html" => Some(DocFormat::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Renders the functions, classes and constants of a module, together with
+/// their `///` doc comments, as Markdown or HTML
+pub struct DocGenerator {
+    format: DocFormat,
+}
+
+impl DocGenerator {
+    pub fn new(format: DocFormat) -> Self {
+        Self { format }
+    }
+    
+    /// Documents a set of parsed modules, given as (module name, AST) pairs
+    pub fn generate(&self, modules: &[(String, ASTNode)]) -> String {
+        let mut output = String::new();
+        
+        if self.format == DocFormat::Html {
+            output.push_str("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Flux Documentation</title></head>\n<body>\n");
+        }
+        
+        for (module, ast) in modules {
+            self.document_module(&mut output, module, ast);
+        }
+        
+        if self.format == DocFormat::Html {
+            output.push_str("</body>\n</html>\n");
+        }
+        
+        output
+    }
+    
+    fn document_module(&self, output: &mut String, module: &str, ast: &ASTNode) {
+        let ASTNode::Program(statements) = ast else {
+            return;
+        };
+        
+        let functions: Vec<&ASTNode> = statements.iter()
+            .filter(|stmt| matches!(stmt, ASTNode::FunctionDecl { .. }))
+            .collect();
+        let classes: Vec<&ASTNode> = statements.iter()
+            .filter(|stmt| matches!(stmt, ASTNode::ClassDecl { .. }))
+            .collect();
+        let constants: Vec<&ASTNode> = statements.iter()
+            .filter(|stmt| matches!(stmt, ASTNode::VarDecl { is_const: true, .. }))
+            .collect();
+        
+        self.heading(output, 1, module);
+        
+        if !functions.is_empty() {
+            self.heading(output, 2, "Functions");
+            for function in functions {
+                self.document_function(output, function, 3);
+            }
+        }
+        
+        if !classes.is_empty() {
+            self.heading(output, 2, "Classes");
+            for class in classes {
+                if let ASTNode::ClassDecl { name, superclass, methods, doc } = class {
+                    let title = match superclass {
+                        Some(parent) => format!("{} extends {}", name, parent),
+                        None => name.clone(),
+                    };
+                    self.heading(output, 3, &title);
+                    self.paragraph(output, doc.as_deref());
+                    
+                    for method in methods {
+                        self.document_function(output, method, 4);
+                    }
+                }
+            }
+        }
+        
+        if !constants.is_empty() {
+            self.heading(output, 2, "Constants");
+            for constant in constants {
+                if let ASTNode::VarDecl { name, value, doc, .. } = constant {
+                    let title = match value.as_ref() {
+                        ASTNode::Number(n) => format!("{} = {}", name, n),
+                        ASTNode::String(s) => format!("{} = {:?}", name, s),
+                        ASTNode::Boolean(b) => format!("{} = {}", name, b),
+                        _ => name.clone(),
+                    };
+                    self.heading(output, 3, &title);
+                    self.paragraph(output, doc.as_deref());
+                }
+            }
+        }
+    }
+    
+    fn document_function(&self, output: &mut String, function: &ASTNode, level: usize) {
+        if let ASTNode::FunctionDecl { name, params, doc, .. } = function {
+            let signature = format!("{}({})", name, params.join(", "));
+            self.heading(output, level, &signature);
+            self.paragraph(output, doc.as_deref());
+        }
+    }
+    
+    fn heading(&self, output: &mut String, level: usize, text: &str) {
+        match self.format {
+            DocFormat::Markdown => {
+                output.push_str(&format!("{} {}\n\n", "#".repeat(level), text));
+            }
+            DocFormat::Html => {
+                output.push_str(&format!("<h{0}>{1}</h{0}>\n", level, Self::escape_html(text)));
+            }
+        }
+    }
+    
+    fn paragraph(&self, output: &mut String, doc: Option<&str>) {
+        let text = doc.unwrap_or("_Undocumented._");
+        
+        match self.format {
+            DocFormat::Markdown => {
+                output.push_str(text);
+                output.push_str("\n\n");
+            }
+            DocFormat::Html => {
+                let text = doc.map(Self::escape_html)
+                    .unwrap_or_else(|| "<em>Undocumented.</em>".to_string());
+                output.push_str(&format!("<p>{}</p>\n", text.replace('\n', "<br>\n")));
+            }
+        }
+    }
+    
+    fn escape_html(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+}
+
+// ============================================================================
+// GRAPHVIZ OUTPUT - Parse trees and control-flow graphs
+// ============================================================================
+
+/// Renders a parse tree, or the control-flow graph of generated IR, in the
+/// Graphviz DOT language
+pub struct DotGenerator;
+
+impl DotGenerator {
+    /// One node per AST node, with edges to its children in source order
+    pub fn ast(ast: &ASTNode) -> String {
+        let mut output = String::from("digraph ast {\n  node [shape=box, fontname=\"monospace\"];\n");
+        let mut next_id = 0;
+        Self::ast_node(ast, &mut next_id, &mut output);
+        output.push_str("}\n");
+        output
+    }
+    
+    fn ast_node(node: &ASTNode, next_id: &mut usize, output: &mut String) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        output.push_str(&format!("  n{} [label=\"{}\"];\n", id, Self::escape(&Self::ast_label(node))));
+        
+        for child in node.children() {
+            let child_id = Self::ast_node(child, next_id, output);
+            output.push_str(&format!("  n{} -> n{};\n", id, child_id));
+        }
+        id
+    }
+    
+    fn ast_label(node: &ASTNode) -> String {
+        match node {
+            ASTNode::Program(_) => "Program".to_string(),
+            ASTNode::Block(_) => "Block".to_string(),
+            ASTNode::VarDecl { name, is_const, is_temporal, clock, .. } => format!("{}{} {}",
+                match clock {
+                    Some(clock) => format!("temporal({}) ", clock),
+                    None if *is_temporal => "temporal ".to_string(),
+                    None => String::new(),
+                },
+                if *is_const { "const" } else { "let" }, name),
+            ASTNode::Assignment { name, .. } => format!("{} =", name),
+            ASTNode::FieldAssign { field, .. } => format!(".{} =", field),
+            ASTNode::FunctionDecl { name, params, .. } => format!("func {}({})", name, params.join(", ")),
+            ASTNode::ClassDecl { name, .. } => format!("class {}", name),
+            ASTNode::Return(_) => "return".to_string(),
+            ASTNode::Defer(_) => "defer".to_string(),
+            ASTNode::If { else_ifs, else_branch, .. } => format!("if{}{}",
+                if else_ifs.is_empty() { String::new() } else { format!(" + {} else if", else_ifs.len()) },
+                if else_branch.is_some() { " + else" } else { "" }),
+            ASTNode::While { .. } => "while".to_string(),
+            ASTNode::Loop(_) => "loop".to_string(),
+            ASTNode::DoWhile { .. } => "do ... while".to_string(),
+            ASTNode::Labeled { label, .. } => format!("{}:", label),
+            ASTNode::Match { .. } => "match".to_string(),
+            ASTNode::Comptime(_) => "comptime".to_string(),
+            ASTNode::Spawn(_) => "spawn".to_string(),
+            ASTNode::Watch { var, .. } => format!("on change {}", var),
+            ASTNode::Binary { operator, .. } | ASTNode::Unary { operator, .. } => operator.clone(),
+            ASTNode::Call { .. } => "call".to_string(),
+            ASTNode::MemberAccess { property, .. } => format!(".{}", property),
+            ASTNode::New { class, .. } => format!("new {}", class),
+            ASTNode::Index { .. } => "index".to_string(),
+            ASTNode::TemporalAccess { var, .. } => format!("{}[t]", var),
+            ASTNode::Pipeline(_) => "|".to_string(),
+            ASTNode::ParallelStage(_) => "||>".to_string(),
+            ASTNode::Array(_) => "[...]".to_string(),
+            ASTNode::Range { .. } => "..".to_string(),
+            ASTNode::Alternatives(_) => "case ,".to_string(),
+            // Leaves render as their source text
+            ASTNode::Number(_) | ASTNode::String(_) | ASTNode::Boolean(_)
+            | ASTNode::Identifier(_) | ASTNode::Break(_) | ASTNode::ClockDomain(_) | ASTNode::Pragma(_) => node.to_string(),
+        }
+    }
+    
+    /// One cluster per function, with a node per basic block listing its
+    /// instructions and an edge per branch target
+    pub fn cfg(ir: &str) -> String {
+        let mut output = String::from("digraph cfg {\n  node [shape=box, fontname=\"monospace\"];\n");
+        let mut lines = ir.lines();
+        let mut function_index = 0;
+        
+        while let Some(line) = lines.next() {
+            let Some(signature) = line.strip_prefix("define ") else {
+                continue;
+            };
+            let name = signature.split_whitespace()
+                .find_map(|word| word.strip_prefix('@'))
+                .and_then(|word| word.split('(').next())
+                .unwrap_or("?");
+            
+            // Basic blocks as (label, instructions)
+            let mut blocks: Vec<(String, Vec<&str>)> = Vec::new();
+            for line in lines.by_ref().take_while(|line| *line != "}") {
+                if let Some(label) = line.strip_suffix(':').filter(|label| !label.starts_with(' ')) {
+                    blocks.push((label.to_string(), Vec::new()));
+                } else if let Some((_, instructions)) = blocks.last_mut() {
+                    instructions.push(line.trim());
+                }
+            }
+            
+            output.push_str(&format!("  subgraph cluster_{} {{\n    label=\"@{}\";\n", function_index, Self::escape(name)));
+            for (label, instructions) in &blocks {
+                let mut text = format!("{}:\\l", label);
+                for instruction in instructions {
+                    text.push_str(&format!("  {}\\l", Self::escape(instruction)));
+                }
+                output.push_str(&format!("    f{}_{} [label=\"{}\"];\n", function_index, label, text));
+            }
+            for (label, instructions) in &blocks {
+                let Some(branch) = instructions.last().and_then(|last| last.strip_prefix("br ")) else {
+                    continue;
+                };
+                let targets: Vec<&str> = branch.split("label %").skip(1)
+                    .map(|target| target.trim_end_matches([',', ' ']))
+                    .collect();
+                let edge_labels: &[&str] = if targets.len() == 2 { &["true", "false"] } else { &[""] };
+                for (target, edge_label) in targets.iter().zip(edge_labels) {
+                    output.push_str(&format!("    f{}_{} -> f{}_{}", function_index, label, function_index, target));
+                    if !edge_label.is_empty() {
+                        output.push_str(&format!(" [label=\"{}\"]", edge_label));
+                    }
+                    output.push_str(";\n");
+                }
+            }
+            output.push_str("  }\n");
+            function_index += 1;
+        }
+        
+        output.push_str("}\n");
+        output
+    }
+    
+    fn escape(text: &str) -> String {
+        text.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+}
+
+// ============================================================================
+// DIFFERENTIAL TESTING - Interpreter vs. compiled backend
+// ============================================================================
+
+/// Printed output and final top-level variables of one program run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunResult {
+    pub output: String,
+    pub variables: HashMap<String, String>,
+    /// The status passed to `exit`, or 0 when the program ran to the end
+    pub exit_code: i32,
+    /// The message of the `panic` that stopped the program, if one did
+    pub panic: Option<String>,
+}
+
+impl RunResult {
+    /// How the program stopped, for reporting a difference in it
+    fn ending(&self) -> String {
+        match &self.panic {
+            Some(message) => format!("panicked with {:?}", message),
+            None if self.exit_code != 0 => format!("exited with status {}", self.exit_code),
+            None => "finished".to_string(),
+        }
+    }
+}
+
+/// One observable difference between the interpreter and compiled code
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    Output { line: usize, interpreted: Option<String>, compiled: Option<String> },
+    Variable { name: String, interpreted: Option<String>, compiled: String },
+    /// The programs stopped differently: by exit status or panic
+    Ending { interpreted: String, compiled: String },
+    Failure { backend: &'static str, message: String },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |text: &Option<String>| match text {
+            Some(text) => format!("{:?}", text),
+            None => "nothing".to_string(),
+        };
+        match self {
+            Mismatch::Output { line, interpreted, compiled } => write!(f,
+                "output line {}: interpreter printed {}, compiled code printed {}",
+                line, show(interpreted), show(compiled)),
+            Mismatch::Variable { name, interpreted, compiled } => write!(f,
+                "variable '{}': interpreter ended with {}, compiled code with {:?}",
+                name, show(interpreted), compiled),
+            Mismatch::Ending { interpreted, compiled } => write!(f,
+                "interpreter {}, compiled code {}", interpreted, compiled),
+            Mismatch::Failure { backend, message } => write!(f, "{} failed: {}", backend, message),
+        }
+    }
+}
+
+/// Runs programs through both the interpreter and the LLVM backend (via
+/// `lli`) and reports where they disagree. Numbers are compared by value,
+/// since the backends format them differently (`10` vs `10.000000`).
+pub struct Verifier {
+    lli: String,
+}
+
+impl Verifier {
+    pub fn new(lli: &str) -> Self {
+        Self { lli: lli.to_string() }
+    }
+    
+    pub fn verify(&self, source: &str) -> Vec<Mismatch> {
+        let interpreted = Self::interpret(source);
+        let compiled = self.run_compiled(source);
+        
+        match (interpreted, compiled) {
+            (Ok(interpreted), Ok(compiled)) => Self::compare(&interpreted, &compiled),
+            (interpreted, compiled) => {
+                let mut failures = Vec::new();
+                if let Err(message) = interpreted {
+                    failures.push(Mismatch::Failure { backend: "interpreter", message });
+                }
+                if let Err(message) = compiled {
+                    failures.push(Mismatch::Failure { backend: "compiled code", message });
+                }
+                failures
+            }
+        }
+    }
+    
+    pub fn interpret(source: &str) -> Result<RunResult, String> {
+        let ast = FluxCompiler::new(false).parse(source)?;
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        let panic = match interpreter.run_main(&ast, &[]) {
+            Ok(_) => None,
+            Err(error) => match error.strip_prefix("panic: ") {
+                Some(message) => Some(message.to_string()),
+                None => return Err(error),
+            },
+        };
+        
+        let variables = interpreter.globals().iter()
+            .filter(|(_, value)| matches!(value, FluxValue::Number(_) | FluxValue::String(_) | FluxValue::Boolean(_)))
+            .map(|(name, value)| (name.clone(), value.to_display_string()))
+            .collect();
+        Ok(RunResult {
+            output: interpreter.take_output(),
+            variables,
+            exit_code: interpreter.exit_code().unwrap_or(0),
+            panic,
+        })
+    }
+    
+    pub fn run_compiled(&self, source: &str) -> Result<RunResult, String> {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+        
+        let ir = FluxCompiler::builder().state_dump(true).build().compile(source)?;
+        
+        // lli reads the module from stdin when given no file
+        let mut child = Command::new(&self.lli)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run {}: {}", self.lli, e))?;
+        child.stdin.take()
+            .ok_or("lli stdin unavailable")?
+            .write_all(ir.as_bytes())
+            .map_err(|e| format!("Failed to send IR to {}: {}", self.lli, e))?;
+        let result = child.wait_with_output()
+            .map_err(|e| format!("Failed to run {}: {}", self.lli, e))?;
+        
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let panic = stderr.lines().find_map(|line| line.strip_prefix("panic: ")).map(str::to_string);
+        // `exit` stops the program quietly, before the state dump; anything
+        // else that stops it with a message, such as lli rejecting the
+        // module, is a failure
+        let exit_code = match result.status.code() {
+            _ if panic.is_some() => 0,
+            Some(code) if code == 0 || stderr.trim().is_empty() => code,
+            _ => return Err(format!("{} exited with {}: {}", self.lli, result.status, stderr.trim())),
+        };
+        
+        let variables = stderr.lines()
+            .filter_map(|line| line.split_once('='))
+            .filter(|(name, _)| name.chars().all(|c| c.is_alphanumeric() || c == '_'))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Ok(RunResult {
+            output: String::from_utf8_lossy(&result.stdout).into_owned(),
+            variables,
+            exit_code,
+            panic,
+        })
+    }
+    
+    /// Diffs output line by line, every variable the compiled program
+    /// reported, and how each program stopped; interpreter-only variables
+    /// (nested or non-scalar) are skipped
+    pub fn compare(interpreted: &RunResult, compiled: &RunResult) -> Vec<Mismatch> {
+        let mut mismatches = Vec::new();
+        
+        let interpreted_lines: Vec<&str> = interpreted.output.lines().collect();
+        let compiled_lines: Vec<&str> = compiled.output.lines().collect();
+        for line in 0..interpreted_lines.len().max(compiled_lines.len()) {
+            let left = interpreted_lines.get(line).copied();
+            let right = compiled_lines.get(line).copied();
+            if left.map(Self::normalize) != right.map(Self::normalize) {
+                mismatches.push(Mismatch::Output {
+                    line: line + 1,
+                    interpreted: left.map(str::to_string),
+                    compiled: right.map(str::to_string),
+                });
+            }
+        }
+        
+        let mut names: Vec<&String> = compiled.variables.keys().collect();
+        names.sort();
+        for name in names {
+            let value = &compiled.variables[name];
+            let expected = interpreted.variables.get(name);
+            if expected.map(|text| Self::normalize(text)) != Some(Self::normalize(value)) {
+                mismatches.push(Mismatch::Variable {
+                    name: name.clone(),
+                    interpreted: expected.cloned(),
+                    compiled: value.clone(),
+                });
+            }
+        }
+        
+        if (interpreted.exit_code, &interpreted.panic) != (compiled.exit_code, &compiled.panic) {
+            mismatches.push(Mismatch::Ending { interpreted: interpreted.ending(), compiled: compiled.ending() });
+        }
+        
+        mismatches
+    }
+    
+    /// Rewrites every number in a line in one canonical form
+    pub fn normalize(line: &str) -> String {
+        let chars: Vec<char> = line.chars().collect();
+        let mut normalized = String::new();
+        let mut i = 0;
+        
+        while i < chars.len() {
+            let after_word = i > 0 && (chars[i - 1].is_alphanumeric() || chars[i - 1] == '_');
+            let signed = chars[i] == '-' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit());
+            if after_word || !(chars[i].is_ascii_digit() || signed) {
+                normalized.push(chars[i]);
+                i += 1;
+                continue;
+            }
+            
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && matches!(chars[i], 'e' | 'E') {
+                let mut end = i + 1;
+                if end < chars.len() && matches!(chars[end], '+' | '-') {
+                    end += 1;
+                }
+                if end < chars.len() && chars[end].is_ascii_digit() {
+                    i = end;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            
+            let number: String = chars[start..i].iter().collect();
+            match number.parse::<f64>() {
+                Ok(value) => normalized.push_str(&value.to_string()),
+                Err(_) => normalized.push_str(&number),
+            }
+        }
+        
+        normalized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_lexer_basic() {
+        let mut lexer = Lexer::new("let x = 42");
+        let tokens = lexer.tokenize();
+        
+        assert!(matches!(tokens[0], TokenType::Let));
+        assert!(matches!(tokens[1], TokenType::Identifier(_)));
+        assert!(matches!(tokens[2], TokenType::Assign));
+        assert!(matches!(tokens[3], TokenType::Number(42.0)));
+    }
+    
+    #[test]
+    fn test_parser_var_decl() {
+        let tokens = vec![
+            TokenType::Let,
+            TokenType::Identifier("x".to_string()),
+            TokenType::Assign,
+            TokenType::Number(42.0),
+            TokenType::EOF,
+        ];
+        
+        let mut parser = Parser::new(tokens);
+        let ast = parser.parse().unwrap();
+        
+        if let ASTNode::Program(statements) = ast {
+            assert_eq!(statements.len(), 1);
+            if let ASTNode::VarDecl { name, .. } = &statements[0] {
+                assert_eq!(name, "x");
+            } else {
+                panic!("Expected VarDecl");
+            }
+        } else {
+            panic!("Expected Program");
+        }
+    }
+    
+    #[test]
+    fn test_temporal_variables() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+temporal let x = 10
+let y = x[0]
+        "#;
+        
+        // Should compile without errors
+        assert!(compiler.compile(source).is_ok());
+    }
+    
+    #[test]
+    fn test_immutable_reassignment_error() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+const x = 10
+x = 20  # This should cause an error
+        "#;
+        
+        // Should fail due to const reassignment
+        assert!(compiler.compile(source).is_err());
+    }
+    
+    #[test]
+    fn test_pipeline_operations() {
+        let tokens = vec![
+            TokenType::Identifier("x".to_string()),
+            TokenType::Pipe,
+            TokenType::Identifier("double".to_string()),
+            TokenType::Pipe,
+            TokenType::Identifier("add_ten".to_string()),
+            TokenType::EOF,
+        ];
+        
+        let mut parser = Parser::new(tokens);
+        let expr = parser.parse_expression().unwrap();
+        
+        if let ASTNode::Pipeline(exprs) = expr {
+            assert_eq!(exprs.len(), 3);
+        } else {
+            panic!("Expected Pipeline");
+        }
+    }
+    
+    #[test]
+    fn test_pragma_handling() {
+        let mut lexer = Lexer::new("#pragma braces\nlet x = 10");
+        let tokens = lexer.tokenize();
+        
+        assert!(lexer.use_braces);
+        assert!(matches!(tokens[0], TokenType::Pragma(_)));
+    }
+    
+    #[test]
+    fn test_macro_expansion_is_hygienic() {
+        let source = r#"
+macro repeat(n, body) {
+    let i = 0
+    while i < n {
+        body
+        i = i + 1
+    }
+}
+let i = 100
+repeat 3 {
+    print(i)
+}
+        "#;
+        
+        let mut parser = Parser::new(Lexer::new(source).tokenize());
+        let ast = parser.parse().unwrap();
+        
+        let ASTNode::Program(statements) = &ast else {
+            panic!("Expected Program");
+        };
+        let ASTNode::Block(expanded) = &statements[2] else {
+            panic!("Expected expanded macro block");
+        };
+        
+        // The macro's counter is renamed, the caller's `i` is left alone
+        if let ASTNode::VarDecl { name, .. } = &expanded[0] {
+            assert_ne!(name, "i");
+        } else {
+            panic!("Expected VarDecl");
+        }
+        
+        let compiler = FluxCompiler::new(false);
+        assert!(compiler.compile(source).is_ok());
+        
+        // Each splice of a block argument gets its own locals
+        let twice = "macro twice(body) {\n    body\n    body\n}\ntwice {\n    let z = 1\n    print(z)\n}\n";
+        assert!(compiler.compile(twice).is_ok());
+    }
+    
+    #[test]
+    fn test_comptime_folds_to_literals() {
+        let source = r#"
+func square(x) {
+    return x * x
+}
+const area = square(4) + 1
+let table = comptime {
+    let base = 2
+    base * area
+}
+        "#;
+        
+        let mut ast = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        ComptimeEvaluator::new().evaluate(&mut ast).unwrap();
+        
+        let ASTNode::Program(statements) = &ast else {
+            panic!("Expected Program");
+        };
+        assert!(matches!(&statements[1], ASTNode::VarDecl { value, .. } 
+                         if matches!(value.as_ref(), ASTNode::Number(n) if *n == 17.0)));
+        assert!(matches!(&statements[2], ASTNode::VarDecl { value, .. } 
+                         if matches!(value.as_ref(), ASTNode::Number(n) if *n == 34.0)));
+    }
+    
+    #[test]
+    fn test_comptime_rejects_impure_code() {
+        let compiler = FluxCompiler::new(false);
+        let source = "let x = comptime { print(1) }";
+        
+        let error = compiler.compile(source).unwrap_err();
+        assert!(error.contains("impure"));
+        
+        // Runaway compile-time code fails the build instead of hanging it
+        let spin = compiler.compile("let x = comptime {\n    let i = 0\n    while i < 1 {\n        i = i\n    }\n    i\n}\n").unwrap_err();
+        assert!(spin.contains("comptime block didn't finish at compile time: Step limit of 1000000 exceeded"));
+        let deep = compiler.compile("func f(n) {\n    if n == 0 {\n        return 0\n    }\n    return f(n - 1) + 1\n}\nconst x = f(3000)\n").unwrap_err();
+        assert!(deep.contains("const 'x' didn't finish at compile time: Call depth limit of 256 exceeded"));
+        
+        // Tasks and channels could block the build; exit and panic would end it
+        let blocked = compiler.check("let x = comptime {\n    let c = channel()\n    recv(c)\n}\n");
+        assert_eq!(blocked.diagnostics[0].message, "comptime block calls impure function 'channel'");
+        for (body, impure) in [("spawn {\n        print(1)\n    }\n    1", "spawn"), ("exit(3)\n    1", "exit"), ("panic(\"no\")", "panic")] {
+            let error = compiler.compile(&format!("let x = comptime {{\n    {}\n}}\n", body)).unwrap_err();
+            assert!(error.contains(&format!("calls impure function '{}'", impure)), "{}", error);
+        }
+    }
+    
+    #[test]
+    fn test_doc_comments_attach_to_declarations() {
+        let source = r#"
+/// Doubles a number.
+/// Works on negatives too.
+func double(x) {
+    return x * 2
+}
+// Plain comments are dropped
+/// Ratio of a circle's circumference to its diameter
+const PI = 3.14
+        "#;
+        
+        let ast = FluxCompiler::new(false).parse(source).unwrap();
+        let ASTNode::Program(statements) = &ast else {
+            panic!("Expected Program");
+        };
+        
+        if let ASTNode::FunctionDecl { doc, .. } = &statements[0] {
+            assert_eq!(doc.as_deref(), Some("Doubles a number.\nWorks on negatives too."));
+        } else {
+            panic!("Expected FunctionDecl");
+        }
+        
+        let markdown = DocGenerator::new(DocFormat::Markdown)
+            .generate(&[("geometry".to_string(), ast.clone())]);
+        assert!(markdown.contains("### double(x)"));
+        assert!(markdown.contains("### PI = 3.14"));
+        
+        let html = DocGenerator::new(DocFormat::Html)
+            .generate(&[("geometry".to_string(), ast)]);
+        assert!(html.contains("<h3>PI = 3.14</h3>"));
+    }
+    
+    #[test]
+    fn test_typed_ast_drives_lowering() {
+        let source = r#"
+let name = "Flux"
+let ready = 1 < 2
+let label = name + "!"
+        "#;
+        
+        let ast = FluxCompiler::new(false).parse(source).unwrap();
+        let typed = SemanticAnalyzer::new().analyze(&ast).unwrap();
+        
+        let ASTNode::Program(statements) = &ast else {
+            panic!("Expected Program");
+        };
+        let ASTNode::VarDecl { value, .. } = &statements[2] else {
+            panic!("Expected VarDecl");
+        };
+        assert_eq!(typed.type_of(value), FluxType::String);
+        
+        let ir = CodeGenerator::new().generate(&typed).unwrap();
+        assert!(ir.contains("%v_main_name = alloca i8*"));
+        assert!(ir.contains("%v_main_ready = alloca i1"));
+        assert!(ir.contains("call i8* @strcat"));
+    }
+    
+    #[test]
+    fn test_missing_return_reports_branch() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+func sign(x) {
+    if x > 0 {
+        return 1
+    } else {
+        if x < 0 {
+            return -1
+        }
+    }
+}
+        "#;
+        
+        let error = compiler.compile(source).unwrap_err();
+        assert!(error.contains("missing return in the implicit else branch of `if x < 0`"));
+        
+        let complete = r#"
+func sign(x) {
+    if x > 0 {
+        return 1
+    }
+    return 0
+}
+        "#;
+        assert!(compiler.compile(complete).is_ok());
+        
+        // Paths ending the program need no return
+        let ended = "func f(x) {\n    if x { return 1 }\n    panic(\"unreachable\")\n}\nfunc g(x) {\n    if x { return 1 } else { exit(2) }\n}";
+        assert!(compiler.compile(ended).is_ok());
+    }
+    
+    #[test]
+    fn test_conflicting_return_types() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+func describe(x) {
+    if x > 0 {
+        return "positive"
+    }
+    return 0
+}
+        "#;
+        
+        let error = compiler.compile(source).unwrap_err();
+        assert!(error.contains("returns conflicting types: string in the then branch of `if x > 0`, but number in the function body"));
+    }
+    
+    #[test]
+    fn test_nesting_depth_limit() {
+        let deep = format!("let x = {}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        let error = FluxCompiler::new(false).parse(&deep).unwrap_err();
+        assert!(error.contains("Nesting depth exceeds the limit of 128 levels"));
+        
+        let negated = format!("let x = {}1", "-".repeat(10_000));
+        assert!(FluxCompiler::new(false).parse(&negated).is_err());
+        
+        // Nesting up to the limit still parses and compiles
+        let nested = format!("let x = {}1{}", "(".repeat(120), ")".repeat(120));
+        assert!(FluxCompiler::new(false).compile(&nested).is_ok());
+        
+        // Every later pass recurses down an operator chain, so a long one
+        // is rejected up front rather than overflowing the stack there
+        let chain = format!("let x = 1{}", " + 1".repeat(20_000));
+        let report = FluxCompiler::new(false).check(&chain);
+        assert_eq!(report.diagnostics.len(), 1);
+        assert!(report.diagnostics[0].message.contains("Nesting depth exceeds the limit of 128 levels"));
+        assert!(Engine::new().eval(&chain).unwrap_err().contains("limit of 128 levels"));
+        
+        let chain = format!("let x = 1{}\nx", " + 1".repeat(120));
+        assert!(FluxCompiler::new(false).check(&chain).diagnostics.is_empty());
+        assert!(FluxCompiler::new(false).compile(&chain).is_ok());
+        // Pipelines stay flat, so only their stages count
+        let pipeline = format!("let x = 1{}", " | abs".repeat(1_000));
+        assert!(FluxCompiler::new(false).check(&pipeline).diagnostics.is_empty());
+        
+        let tokens = Lexer::new("let x = ((1))").tokenize();
+        assert!(Parser::new(tokens).with_max_depth(3).parse().is_err());
+    }
+    
+    #[test]
+    fn test_print_formats_by_type() {
+        let compiler = FluxCompiler::new(false);
+        
+        let ir = compiler.compile("print(\"hello\")").unwrap();
+        assert!(ir.contains("@.str_str, i32 0, i32 0), i8*"));
+        
+        let ir = compiler.compile("print(1 < 2)").unwrap();
+        assert!(ir.contains("select i1"));
+        assert!(ir.contains("@.str_bool_true"));
+        
+        let ir = compiler.compile("print(\"x = \", 4, \" \", true)").unwrap();
+        assert!(ir.contains("c\"%s%s%s%s\\0A\\00\""));
+        assert!(ir.contains("call i8* @flux_number_text(double"));
+    }
+    
+    #[test]
+    fn test_numeric_format() {
+        let source = "print(3)\nprintln(\"{} {:.1}\", 2.5, 2.25)\n#pragma numeric_format fixed 2\nprint(3)\nset_precision(-1)\nprint(0.1)";
+        let ast = FluxCompiler::new(false).parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.take_output(), "3\n2.5 2.2\n3.00\n0.1\n");
+        
+        let ir = FluxCompiler::new(false).compile(source).unwrap();
+        assert!(ir.contains("store i32 %"));
+        assert!(ir.contains("@flux_precision = internal global i32 -1"));
+        assert_eq!(NumericFormat::from_pragma(" fixed 3"), Ok(NumericFormat::Fixed(3)));
+        assert!(FluxCompiler::new(false).parse("#pragma numeric_format hex").is_err());
+    }
+    
+    #[test]
+    fn test_format_builtin() {
+        let format = FluxStdLib::get_builtin_functions()["format"];
+        let result = format(vec![
+            FluxValue::String("x = {}, y = {:.2}, [{:4}] {{ok}}".to_string()),
+            FluxValue::Number(3.0),
+            FluxValue::Number(2.0 / 3.0),
+            FluxValue::String("ab".to_string()),
+        ]).unwrap();
+        assert_eq!(result, FluxValue::String("x = 3, y = 0.67, [ab  ] {ok}".to_string()));
+        
+        let compiler = FluxCompiler::new(false);
+        let ir = compiler.compile("let s = format(\"{:.2}%\", 12.5)").unwrap();
+        assert!(ir.contains("c\"%.2f%%\\00\""));
+        assert!(ir.contains("@snprintf(i8* null, i64 0"));
+        
+        // Widths count characters in both backends, not bytes
+        let wide = format(vec![FluxValue::String("[{:3}]".to_string()), FluxValue::String("日本".to_string())]).unwrap();
+        assert_eq!(wide, FluxValue::String("[日本 ]".to_string()));
+        let ir = compiler.compile("let s = format(\"[{:3}]\", \"日本\")").unwrap();
+        assert!(ir.contains("c\"[%-*s]\\00\""));
+        assert!(ir.contains("call i64 @flux_utf8_length(i8* %"));
+        
+        assert!(compiler.compile("println(\"{} {}\", 1)").is_err());
+    }
+    
+    #[test]
+    fn test_temporal_introspection() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+temporal let x = 10
+x = 20
+x = 30
+let past = history(x)
+let updates = changes(x)
+let first = value_at(x, 0)
+        "#;
+        
+        let ast = compiler.parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("past").unwrap().to_display_string(), "[10, 20, 30]");
+        assert_eq!(interpreter.lookup("updates"), Some(&FluxValue::Number(2.0)));
+        assert_eq!(interpreter.lookup("first"), Some(&FluxValue::Number(10.0)));
+        
+        let ir = compiler.compile(source).unwrap();
+        assert!(ir.contains("call i8* @flux_temporal_history(%temporal_var* %v_main_x.timeline, i32 0)"));
+        assert!(ir.contains("call double @flux_temporal_changes"));
+        assert!(ir.contains("define internal i8* @flux_temporal_at"));
+        
+        assert!(compiler.compile("let y = 1\nlet n = changes(y)").is_err());
+    }
+    
+    #[test]
+    fn test_relative_temporal_access() {
+        let mut manager = TemporalManager::new();
+        manager.create_temporal_var("x".to_string(), FluxValue::Number(1.0));
+        for value in [2.0, 3.0] {
+            manager.advance_time();
+            manager.update_temporal_var("x", FluxValue::Number(value)).unwrap();
+        }
+        assert_eq!(manager.get_at_time("x", -1), Some(FluxValue::Number(2.0)));
+        assert_eq!(manager.get_at_time("x", -2), Some(FluxValue::Number(1.0)));
+        assert_eq!(manager.get_at_time("x", -3), None);
+        assert_eq!(manager.get_at_time("x", 1), Some(FluxValue::Number(2.0)));
+        
+        let compiler = FluxCompiler::new(false);
+        assert!(compiler.compile("temporal let x = 1\nx = 2\nlet prev = x[-1]").is_ok());
+        assert!(compiler.compile("let y = 1\nlet prev = y[-1]").is_err());
+        
+        // Stepping back past the first value stops the program, as in the
+        // interpreter, instead of reading an empty box
+        let source = "temporal let t = \"a\"\nprint(t[-5] + \"x\")";
+        let error = Interpreter::new().run(&compiler.parse(source).unwrap()).unwrap_err();
+        assert_eq!(error, "Variable 't' has fewer than 5 past values");
+        let ir = compiler.compile(source).unwrap();
+        assert!(ir.contains("c\"Variable '%s' has fewer than %g past values\\0A\\00\""));
+        assert!(ir.contains("call i8* @flux_temporal_at(%temporal_var* %v_main_t.timeline, double %t5, i8* %t6)"));
+        assert!(!ir.contains("flux_temporal_zero"));
+        assert!(compiler.compile("temporal let x = 1\nlet prev = x[-0.5]").is_err());
+    }
+    
+    #[test]
+    fn test_change_watchers() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+temporal let x = 1
+let seen = 0
+on change x {
+    seen = seen + 1
+    if x > 5 {
+        x = 5
+    }
+}
+x = 2
+x = 10
+        "#;
+        
+        // The clamping update inside the watcher doesn't re-trigger it
+        let ast = compiler.parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("seen"), Some(&FluxValue::Number(2.0)));
+        assert_eq!(interpreter.lookup("x"), Some(&FluxValue::Number(5.0)));
+        
+        let ir = compiler.compile(source).unwrap();
+        assert_eq!(ir.matches("call void @flux_temporal_record").count(), 5);
+        
+        let error = compiler.compile("let y = 1\non change y { y = 2 }").unwrap_err();
+        assert!(error.contains("Cannot watch 'y': it is not a temporal variable"));
+    }
+    
+    #[test]
+    fn test_spawn_and_join() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+func square(n) {
+    return n * n
+}
+let base = 3
+let task = spawn {
+    let inner = spawn { square(base) }
+    join(inner) + 1
+}
+let result = join(task)
+        "#;
+        
+        let ast = compiler.parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("result"), Some(&FluxValue::Number(10.0)));
+        
+        let ir = compiler.compile(source).unwrap();
+        assert!(ir.contains("define internal i8* @flux_task_1_entry(i8* %env)"));
+        assert!(ir.contains("call i32 @pthread_join"));
+        
+        // Tasks print into the spawner's captured output
+        let mut engine = Engine::new();
+        engine.capture_output();
+        engine.eval("let t = spawn {\n    print(\"in task\")\n    1\n}\nprint(join(t))").unwrap();
+        assert_eq!(engine.take_output(), "in task\n1\n");
+        let interpreted = Verifier::interpret("let t = spawn {\n    print(\"in task\")\n}\njoin(t)").unwrap();
+        assert_eq!(interpreted.output, "in task\n");
+        
+        // `exit` in a task ends the program that joins it, with its code
+        let exited = Verifier::interpret("let t = spawn {\n    print(\"bye\")\n    exit(3)\n}\njoin(t)\nprint(\"after\")").unwrap();
+        assert_eq!((exited.output.as_str(), exited.exit_code), ("bye\n", 3));
+        let exited = Verifier::interpret("#pragma braces\nfunc stop(n) { exit(n) }\nlet codes = [4] ||> stop").unwrap();
+        assert_eq!(exited.exit_code, 4);
+        
+        // A task's result can only be taken once
+        let scheduler = TaskScheduler::new(1);
+        let id = scheduler.spawn(|| Ok(FluxValue::Boolean(true)));
+        assert_eq!(scheduler.join(id), Ok(FluxValue::Boolean(true)));
+        assert!(scheduler.join(id).is_err());
+    }
+    
+    #[test]
+    fn test_channels_between_tasks() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+let ch = channel()
+let producer = spawn {
+    let i = 1
+    while i <= 4 {
+        send(ch, i * 10)
+        i = i + 1
+    }
+}
+let total = 0
+let n = 0
+while n < 4 {
+    total = total + recv(ch)
+    n = n + 1
+}
+join(producer)
+        "#;
+        
+        let ast = compiler.parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("total"), Some(&FluxValue::Number(100.0)));
+        
+        let ir = compiler.compile(source).unwrap();
+        assert!(ir.contains("call void @flux_channel_send(i8* "));
+        assert!(ir.contains("call i8* @flux_channel_recv(i8* "));
+        
+        assert!(compiler.compile("let ch = channel()\nsend(ch)").is_err());
+    }
+    
+    #[test]
+    fn test_parallel_pipeline() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+func scale(n, factor) {
+    return n * factor
+}
+func inc(n) {
+    return n + 1
+}
+let mapped = [1, 2, 3, 4, 5] ||> scale(10) ||> inc
+let scalar = 4 ||> inc
+        "#;
+        
+        let ast = compiler.parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("mapped").unwrap().to_display_string(), "[11, 21, 31, 41, 51]");
+        assert_eq!(interpreter.lookup("scalar"), Some(&FluxValue::Number(5.0)));
+        
+        // Workers print into the caller's captured output
+        let mut engine = Engine::new();
+        engine.capture_output();
+        engine.eval("func show(n) {\n    print(n)\n    return n\n}\nlet shown = [7] ||> show").unwrap();
+        assert_eq!(engine.take_output(), "7\n");
+        
+        // Scalars compile as ordinary stages; arrays stay interpreter-only
+        assert!(compiler.compile("func inc(n) {\n    return n + 1\n}\nlet y = 4 ||> inc").is_ok());
+        let error = compiler.compile(source).unwrap_err();
+        assert!(error.contains("only supported by the interpreter"));
+    }
+
+    #[test]
+    fn test_differential_verification() {
+        assert_eq!(Verifier::normalize("x = 10.000000"), Verifier::normalize("x = 10"));
+        assert_eq!(Verifier::normalize("v2 is -2.50"), "v2 is -2.5");
+    
+        let interpreted = Verifier::interpret("let x = 4\nlet y = x * 2\nprintln(\"y = {}\", y)").unwrap();
+        assert_eq!(interpreted.output, "y = 8\n");
+        assert_eq!(interpreted.variables.get("y"), Some(&"8".to_string()));
+    
+        let compiled = RunResult {
+            output: "y = 8.000000\nextra\n".to_string(),
+            variables: [("x".to_string(), "4.000000".to_string()), ("y".to_string(), "9.000000".to_string())]
+                .into_iter().collect(),
+            exit_code: 2,
+            panic: None,
+        };
+        let mismatches = Verifier::compare(&interpreted, &compiled);
+        assert_eq!(mismatches, vec![
+            Mismatch::Output { line: 2, interpreted: None, compiled: Some("extra".to_string()) },
+            Mismatch::Variable { name: "y".to_string(), interpreted: Some("8".to_string()), compiled: "9.000000".to_string() },
+            Mismatch::Ending { interpreted: "finished".to_string(), compiled: "exited with status 2".to_string() },
+        ]);
+        
+        // Exiting or panicking the same way in both is a match
+        let exited = Verifier::interpret("print(1)\nexit(3)\nprint(2)").unwrap();
+        assert_eq!((exited.output.as_str(), exited.exit_code), ("1\n", 3));
+        let panicked = Verifier::interpret("panic(\"bad input\")").unwrap();
+        assert_eq!(panicked.panic.as_deref(), Some("bad input"));
+        let compiled = RunResult { panic: Some("bad input".to_string()), ..RunResult::default() };
+        assert!(Verifier::compare(&panicked, &compiled).is_empty());
+    }
+    
+    #[test]
+    fn test_pretty_values() {
+        let point: HashMap<String, FluxValue> = [
+            ("y".to_string(), FluxValue::Number(2.0)),
+            ("x".to_string(), FluxValue::Array(vec![FluxValue::String("a".to_string())])),
+        ].into_iter().collect();
+        let value = FluxValue::Object(point);
+        assert_eq!(value.to_string(), "{x: [a], y: 2}");
+        assert_eq!(value.pretty(), "{x: [\"a\"], y: 2}");
+
+        let long = FluxValue::Array((0..40).map(|n| FluxValue::Number(n as f64 * 1000.0)).collect());
+        assert_eq!(long.pretty(), "[0, 1000, 2000, 3000, 4000, 5000, 6000, 7000, 8000, 9000, ... 30 more]");
+        let nested = FluxValue::Array(vec![long.clone(), long]);
+        assert_eq!(nested.pretty().lines().count(), 4);
+        assert!(nested.pretty().starts_with("[\n  [0, 1000"));
+
+        let mut repl = FluxRepl::new();
+        assert_eq!(repl.evaluate("temporal let t = 0").unwrap(), None);
+        for n in 1..12 {
+            repl.evaluate(&format!("t = {}", n)).unwrap();
+        }
+        assert_eq!(repl.evaluate("t * 2").unwrap(), Some("22".to_string()));
+        assert_eq!(repl.evaluate("t").unwrap(), Some("11\n  timeline: 0 → ... → 5 → 6 → 7 → 8 → 9 → 10 → 11 (12 values)".to_string()));
+    }
+
+    #[test]
+    fn test_staged_compiler_api() {
+        let compiler = FluxCompiler::builder()
+            .optimization(2)
+            .backend(Backend::Llvm)
+            .debug_info(true)
+            .build();
+        
+        let tokens = compiler.tokenize("let x = 2 * 3 + 1\nprintln(\"{}\", x)");
+        assert!(tokens.contains(&TokenType::Let));
+        let mut ast = compiler.parse_tokens(tokens).unwrap();
+        
+        // Embedders can rewrite the tree between stages
+        let typed = compiler.analyze(&mut ast).unwrap();
+        let ASTNode::Program(statements) = typed.ast else { panic!("expected a program") };
+        assert!(matches!(&statements[0], ASTNode::VarDecl { value, .. } if matches!(**value, ASTNode::Number(n) if n == 7.0)));
+        assert_eq!(typed.type_of(statements[0].children()[0]), FluxType::Number);
+        
+        let ir = compiler.lower(&typed).unwrap();
+        assert!(ir.contains("!llvm.dbg.cu = !{!0}"));
+        assert!(ir.contains("isOptimized: true"));
+        assert!(!FluxCompiler::new(false).compile("let x = 1").unwrap().contains("!DICompileUnit"));
+    }
+    
+    #[test]
+    fn test_embedding_engine() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        
+        let mut engine = Engine::new();
+        engine.register_fn("host_log", move |args| {
+            sink.lock().unwrap().push(FluxStdLib::print_text(args));
+            Ok(FluxValue::Null)
+        });
+        engine.set("limit", 3.0);
+        
+        engine.eval("func scaled(n) {\n    return n * limit\n}\nlet total = scaled(4)").unwrap();
+        engine.eval("host_log(\"total\", total)").unwrap();
+        assert_eq!(engine.get("total"), Some(FluxValue::Number(12.0)));
+        assert_eq!(*log.lock().unwrap(), vec!["total12".to_string()]);
+        
+        // Host-side writes are seen by later snippets and calls
+        engine.set("limit", 10.0);
+        assert_eq!(engine.call("scaled", vec![2.0.into()]), Ok(FluxValue::Number(20.0)));
+        assert_eq!(engine.eval("[1, 2] ||> host_missing").unwrap_err(),
+                   "Semantic errors: [\"Undefined function 'host_missing'\"]");
+        
+        // Snippets are checked against what earlier ones declared
+        engine.eval("const name = \"a\"\nlet frozen = \"a\"\nfreeze frozen").unwrap();
+        assert!(engine.eval("name = name + \"b\"").unwrap_err().contains("Cannot reassign to const variable 'name'"));
+        assert!(engine.eval("frozen = \"b\"").unwrap_err().contains("Cannot modify frozen variable 'frozen'"));
+        assert!(engine.eval("let name = \"b\"").unwrap_err().contains("Variable 'name' already declared"));
+        assert_eq!(engine.get("name"), Some(FluxValue::String("a".to_string())));
+        // A rejected snippet declares nothing, and others may be redeclared
+        assert!(engine.eval("let fresh = 1\nlimit = missing").is_err());
+        assert!(engine.eval("fresh").unwrap_err().contains("Undefined variable 'fresh'"));
+        assert_eq!(engine.eval("let total = 1\ntotal + limit").unwrap(), 11.0.into());
+    }
+    
+    #[test]
+    fn test_new_instances_and_gc() {
+        let source = r#"
+class Point {
+    func init(x, y) {
+        this.x = x
+        this.y = y
+    }
+    func sum() {
+        return this.x + this.y
+    }
+}
+class Point3 extends Point {
+    func init(x, y, z) {
+        this.x = x
+        this.y = y
+        this.z = z
+    }
+}
+let p = new Point(1, 2)
+let total = p.sum() + new Point3(1, 2, 3).sum()
+p.next = new Point(5, 5)
+let i = 0
+while i < 50 {
+    let cycle = new Point(i, i)
+    cycle.self = cycle
+    i = i + 1
+}
+        "#;
+        
+        let ast = FluxCompiler::new(false).parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.runtime().set_gc_threshold(0);
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("total"), Some(&FluxValue::Number(6.0)));
+        
+        // Only `p` and the object hanging off it survive; loop garbage,
+        // including self-referencing instances, is reclaimed
+        assert_eq!(interpreter.runtime().live_objects(), 2);
+        assert_eq!(interpreter.collect_garbage(), 0);
+        
+        let error = interpreter.run(&FluxCompiler::new(false).parse("new Point(1)").unwrap()).unwrap_err();
+        assert_eq!(error, "Function 'Point.init' expects 2 arguments, found 1");
+        assert!(FluxCompiler::new(false).compile(source).unwrap_err().contains("only supported by the interpreter"));
+        
+        // Garbage made inside a call is reclaimed while it runs, and the
+        // caller's locals and half-built arguments survive
+        let churn = "func churn(n) {\n    let j = 0\n    while j < n {\n        let waste = new Point(j, j)\n        j = j + 1\n    }\n    return n\n}\nfunc outer() {\n    let kept = new Point(7, 8)\n    let pair = [new Point(1, 1), churn(200)]\n    return kept.sum() + pair[0].sum() + pair[1]\n}\nlet result = outer()\n";
+        let mut interpreter = Interpreter::new();
+        interpreter.runtime().set_gc_threshold(0);
+        interpreter.run(&FluxCompiler::new(false).parse(&format!("{}\n{}", source, churn)).unwrap()).unwrap();
+        assert_eq!(interpreter.lookup("result"), Some(&FluxValue::Number(217.0)));
+        assert!(interpreter.runtime().heap.len() < 10);
+    }
+    
+    #[test]
+    fn test_chars_and_string_indexing() {
+        let source = r#"
+temporal let t = 1
+t = 2
+let word = "flux"
+let first = word[0]
+let previous = t[-1]
+let code = char_code('x')
+let next = from_char_code(char_code(word[3]) + 1)
+let quote = '\''
+        "#;
+        
+        let ast = FluxCompiler::new(false).parse(source).unwrap();
+        let ASTNode::Program(statements) = &ast else { panic!("expected a program") };
+        assert!(matches!(&statements[3], ASTNode::VarDecl { value, .. } if matches!(**value, ASTNode::Index { .. })));
+        assert!(matches!(&statements[4], ASTNode::VarDecl { value, .. } if matches!(**value, ASTNode::TemporalAccess { .. })));
+        
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("first"), Some(&FluxValue::String("f".to_string())));
+        assert_eq!(interpreter.lookup("previous"), Some(&FluxValue::Number(1.0)));
+        assert_eq!(interpreter.lookup("code"), Some(&FluxValue::Number(120.0)));
+        assert_eq!(interpreter.lookup("next"), Some(&FluxValue::String("y".to_string())));
+        assert_eq!(interpreter.lookup("quote"), Some(&FluxValue::String("'".to_string())));
+        
+        let ir = FluxCompiler::new(false).compile(source).unwrap();
+        assert!(ir.contains("call i8* @flux_char_at(i8*"));
+        assert!(FluxCompiler::new(false).parse("let c = 'ab'").unwrap_err().contains("exactly one character"));
+        assert!(FluxCompiler::new(false).compile("let n = 5\nlet d = n[0]").unwrap_err().contains("Cannot index"));
+    }
+    
+    #[test]
+    fn test_loop_do_while_and_break() {
+        let source = r#"
+let i = 0
+loop {
+    i = i + 1
+    if i == 3 { break }
+}
+let j = 10
+do { j = j + 1 } while j < 5
+func first_power(limit) {
+    let n = 1
+    loop {
+        n = n * 2
+        if n > limit { return n }
+    }
+}
+let p = first_power(20)
+        "#;
+        
+        let ast = FluxCompiler::new(false).parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("i"), Some(&FluxValue::Number(3.0)));
+        assert_eq!(interpreter.lookup("j"), Some(&FluxValue::Number(11.0)));
+        assert_eq!(interpreter.lookup("p"), Some(&FluxValue::Number(32.0)));
+        
+        // A `loop` without `break` never falls through, so first_power returns on all paths
+        let ir = FluxCompiler::new(false).compile(source).unwrap();
+        assert!(ir.contains("define internal double @flux_user_first_power"));
+        assert!(FluxCompiler::new(false).compile("break").unwrap_err().contains("`break` outside of a loop"));
+        assert!(FluxCompiler::new(false).compile("func f() { loop { break } }\nlet x = f()").is_ok());
+        
+        // Neither does `while true`, unless it breaks
+        assert!(FluxCompiler::new(false).compile("func f(x) { while true { return 1 } }").is_ok());
+        let error = FluxCompiler::new(false).compile("func f(x) { while true { if x { break } return 1 } }").unwrap_err();
+        assert!(error.contains("missing return in the exit path of `while true`"), "{}", error);
+    }
+    
+    #[test]
+    fn test_else_if_chains_stay_flat() {
+        let source = r#"
+func sign(n) {
+    if n > 0 {
+        return 1
+    } else if n < 0 {
+        return -1
+    } else if false {
+        return 99
+    } else {
+        return 0
+    }
+}
+let a = sign(5)
+let b = sign(-2)
+let c = sign(0)
+        "#;
+        
+        let mut ast = FluxCompiler::new(false).parse(source).unwrap();
+        let ASTNode::Program(statements) = &ast else { panic!("expected a program") };
+        let ASTNode::FunctionDecl { body, .. } = &statements[0] else { panic!("expected a function") };
+        assert!(matches!(&body[0], ASTNode::If { else_ifs, else_branch: Some(_), .. } if else_ifs.len() == 2));
+        
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("b"), Some(&FluxValue::Number(-1.0)));
+        assert_eq!(interpreter.lookup("c"), Some(&FluxValue::Number(0.0)));
+        
+        // The optimizer drops the arm that can never run
+        ASTOptimizer::optimize(&mut ast);
+        let ASTNode::Program(statements) = &ast else { panic!("expected a program") };
+        let ASTNode::FunctionDecl { body, .. } = &statements[0] else { panic!("expected a function") };
+        assert!(matches!(&body[0], ASTNode::If { else_ifs, .. } if else_ifs.len() == 1));
+        
+        let error = FluxCompiler::new(false)
+            .compile("func f(n) {\n if n > 0 { return 1 } else if n < 0 { print(n) } else { return 0 }\n}")
+            .unwrap_err();
+        assert!(error.contains("the branch of `else if n < 0`"), "{}", error);
+    }
+    
+    #[test]
+    fn test_string_constants_are_interned() {
+        let source = r#"
+let greeting = "héllo"
+print("héllo")
+print(greeting + "héllo")
+print("tab\there")
+        "#;
+        
+        let ir = FluxCompiler::new(false).compile(source).unwrap();
+        assert_eq!(ir.matches(r#"c"h\C3\A9llo\00""#).count(), 1);
+        assert!(ir.contains("@.str.1 = private unnamed_addr constant [7 x i8]"));
+        assert!(ir.contains(r#"constant [9 x i8] c"tab\09here\00""#));
+        // Globals precede the functions that use them
+        assert!(ir.find("@.str.1 =").unwrap() < ir.find("define").unwrap());
+    }
+    
+    #[test]
+    fn test_names_are_mangled() {
+        let source = r#"
+func main(arg0) {
+    let t1 = arg0 * 2
+    return t1
+}
+let t2 = main(4)
+        "#;
+        
+        let ir = FluxCompiler::new(false).compile(source).unwrap();
+        assert!(ir.contains("define internal double @flux_user_main(double %arg0)"));
+        assert!(ir.contains("%v_main_arg0 = alloca double"));
+        assert!(ir.contains("%v_main_t1 = alloca double"));
+        assert!(ir.contains("%v_main_t2 = alloca double"));
+        assert!(ir.contains("call double @flux_user_main("));
+        assert_eq!(ir.matches("define weak i32 @main()").count(), 1);
+    }
+    
+    #[test]
+    fn test_allocas_hoisted_with_scoped_lifetimes() {
+        let source = r#"
+let i = 0
+while i < 3 {
+    let step = i * 2
+    i = i + 1
+}
+        "#;
+        
+        let ir = FluxCompiler::new(false).compile(source).unwrap();
+        let main = &ir[ir.find("define void @flux_main()").unwrap()..];
+        let main = &main[..main.find("\n}").unwrap()];
+        // Every slot is allocated before the first branch
+        let first_branch = main.find("  br ").unwrap();
+        assert!(main.find("%v_main_step = alloca double").unwrap() < first_branch);
+        assert_eq!(main.matches("alloca").count(), 2);
+        
+        let start = main.find("@llvm.lifetime.start.p0i8(i64 8").unwrap();
+        let end = main.find("@llvm.lifetime.end.p0i8(i64 8").unwrap();
+        assert!(first_branch < start && start < end);
+    }
+    
+    #[test]
+    fn test_exported_functions_are_public() {
+        let source = r#"
+func helper(x) {
+    return x * 10
+}
+export func scale(a, b) {
+    return helper(a) + b
+}
+let y = scale(2, 3)
+        "#;
+        
+        let ir = FluxCompiler::new(false).compile(source).unwrap();
+        assert!(ir.contains("define double @scale(double %arg0, double %arg1)"));
+        assert!(ir.contains("define internal double @flux_user_helper("));
+        assert!(ir.contains("call double @scale("));
+        
+        let error = FluxCompiler::new(false).compile("export func main() { return 1 }").unwrap_err();
+        assert!(error.contains("Cannot export 'main'"), "{}", error);
+        let error = FluxCompiler::new(false).compile("export func printf(x) { return x }").unwrap_err();
+        assert!(error.contains("Cannot export 'printf': the name is reserved for the runtime"), "{}", error);
+        
+        // Every C function a module can declare is reserved
+        let runtime = "#pragma braces\n#pragma checked_math\nfunc main(args) {\n    temporal let t = 1\n    t = 2\n    let c = channel()\n    let task = spawn { send(c, t[-1] / 2) }\n    join(task)\n    set_precision(2)\n    println(\"{} {}\", recv(c), args)\n    print(len(\"abc\"[1]))\n}";
+        let ir = FluxCompiler::new(false).compile(runtime).unwrap();
+        let declared: Vec<&str> = ir.lines()
+            .filter_map(|line| line.strip_prefix("declare "))
+            .filter_map(|line| line.split('@').nth(1)?.split('(').next())
+            .filter(|name| !name.starts_with("llvm."))
+            .collect();
+        assert!(declared.contains(&"pthread_create"));
+        for name in declared {
+            assert!(CodeGenerator::EXTERNAL_SYMBOLS.contains(&name), "{} isn't reserved", name);
+        }
+        assert!(FluxCompiler::new(false).parse("export let x = 1").is_err());
+    }
+    
+    #[test]
+    fn test_named_checkpoints() {
+        let source = r#"
+temporal let balance = 100
+balance = 150
+checkpoint("before_fee")
+balance = balance - 30
+let saved = balance["before_fee"]
+let via_builtin = value_at(balance, "before_fee")
+        "#;
+        
+        let ast = FluxCompiler::new(false).parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("saved"), Some(&FluxValue::Number(150.0)));
+        assert_eq!(interpreter.lookup("via_builtin"), Some(&FluxValue::Number(150.0)));
+        assert_eq!(interpreter.temporal_manager().checkpoint_time("before_fee"), Some(1));
+        
+        let ir = FluxCompiler::new(false).compile(source).unwrap();
+        assert!(ir.contains(", double* @flux_checkpoint.0\n"));
+        assert!(ir.contains("@flux_checkpoint.0 = internal global double -1.0"));
+        assert!(ir.contains("call double @flux_checkpoint_time(double"));
+        
+        let mut missing = Interpreter::new();
+        let error = missing.run(&FluxCompiler::new(false).parse("temporal let x = 1\nlet y = x[\"later\"]").unwrap());
+        assert_eq!(error.unwrap_err(), "Checkpoint 'later' has not been reached");
+        assert!(FluxCompiler::new(false).compile("temporal let x = 1\nlet y = x[true]").is_err());
+    }
+    
+    #[test]
+    fn test_snapshot_and_rollback() {
+        let source = r#"
+temporal let balance = 100
+let note = "initial"
+let restores = 0
+on change balance {
+    restores = restores + 1
+}
+let saved = snapshot(true)
+balance = 40
+balance = 10
+note = "spent"
+rollback(saved)
+        "#;
+        
+        let ast = FluxCompiler::new(false).parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("balance"), Some(&FluxValue::Number(100.0)));
+        assert_eq!(interpreter.lookup("note"), Some(&FluxValue::String("initial".to_string())));
+        // The rollback is itself an update, so history and watchers see it;
+        // watchers run after the plain globals are restored
+        assert_eq!(interpreter.temporal_manager().changes("balance"), Some(3));
+        assert_eq!(interpreter.lookup("restores"), Some(&FluxValue::Number(1.0)));
+        
+        let mut interpreter = Interpreter::new();
+        let error = interpreter.run(&FluxCompiler::new(false).parse("rollback(7)").unwrap()).unwrap_err();
+        assert_eq!(error, "Unknown snapshot #7");
+        assert!(FluxCompiler::new(false).compile("let s = snapshot()").unwrap_err().contains("only supported by the interpreter"));
+    }
+    
+    #[test]
+    fn test_precedence_pragma() {
+        let compiler = FluxCompiler::new(false);
+        let Ok(ASTNode::Program(statements)) = compiler.parse("a | f == b\n#pragma precedence | above ==\na | f == b\na + b | f") else {
+            panic!("expected a program");
+        };
+        assert_eq!(statements[0].to_string(), "a | f == b");
+        assert!(matches!(&statements[0], ASTNode::Pipeline(stages) if matches!(stages[1], ASTNode::Binary { .. })));
+        assert!(matches!(&statements[1], ASTNode::Binary { left, .. } if matches!(**left, ASTNode::Pipeline(_))));
+        assert!(matches!(&statements[2], ASTNode::Pipeline(stages) if matches!(stages[0], ASTNode::Binary { .. })));
+        
+        let error = compiler.parse("#pragma precedence | over ==").unwrap_err();
+        assert!(error.contains("Expected 'above', 'below' or 'with'"));
+        assert!(compiler.parse("#pragma precedence ** above +").unwrap_err().contains("Unknown operator '**'"));
+    }
+    
+    #[test]
+    fn test_postfix_bang_is_lexed_separately() {
+        let tokens = Lexer::new("n! !n f()! a!=b").tokenize();
+        assert_eq!(tokens[1], TokenType::Bang);
+        assert_eq!(tokens[2], TokenType::Not);
+        assert_eq!(tokens[7], TokenType::Bang);
+        assert_eq!(tokens[9], TokenType::NotEqual);
+        
+        let error = FluxCompiler::new(false).parse("print(n!)").unwrap_err();
+        assert!(error.contains("Unexpected postfix '!' after `n`"));
+    }
+    
+    #[test]
+    fn test_match_and_if_as_values() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+let status = 404
+let message = match status {
+    200 => "OK"
+    404 => "Not Found"
+    default => "Unknown"
+}
+let size = if status > 400 { 2 } else { 1 }
+        "#;
+        
+        let ast = compiler.parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("message"), Some(&FluxValue::String("Not Found".to_string())));
+        assert_eq!(interpreter.lookup("size"), Some(&FluxValue::Number(2.0)));
+        
+        let ir = compiler.compile(source).unwrap();
+        assert!(ir.contains("%v_main_message = alloca i8*"));
+        
+        let error = compiler.compile("let a = match 3 { 1 => \"x\" }").unwrap_err();
+        assert!(error.contains("`match 3` is used as a value, so it needs a `default` case"));
+        let error = compiler.compile("let b = if true { 1 } else { \"s\" }").unwrap_err();
+        assert!(error.contains("conflicting types: number in the then branch of `if true`, but string in the else branch"));
+        let error = compiler.compile("let c = if true { let z = 1 } else { 2 }").unwrap_err();
+        assert!(error.contains("the then branch of `if true` ends without a value"));
+    }
+    
+    #[test]
+    fn test_labeled_break_leaves_outer_loop() {
+        let compiler = FluxCompiler::new(false);
+        let source = r#"
+let found = 0
+let i = 0
+outer: while i < 5 {
+    let j = 0
+    while j < 5 {
+        if i * j == 6 {
+            found = i * 10 + j
+            break outer
+        }
+        j = j + 1
+    }
+    i = i + 1
+}
+        "#;
+        
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&compiler.parse(source).unwrap()).unwrap();
+        assert_eq!(interpreter.lookup("found"), Some(&FluxValue::Number(23.0)));
+        assert_eq!(interpreter.lookup("i"), Some(&FluxValue::Number(2.0)));
+        assert!(compiler.compile(source).is_ok());
+        
+        let error = compiler.compile("loop { break outer }").unwrap_err();
+        assert!(error.contains("Unknown loop label 'outer' in `break outer`"));
+        assert!(compiler.parse("name: let x = 1").unwrap_err().contains("Only loops can be labeled"));
+        
+        // `break outer` from the inner loop means the outer `loop` can end
+        let nested = "func f(x) {\n loop { loop { if x { break } return 1 } }\n}\nf(1)";
+        assert!(compiler.compile(nested).is_ok());
+        let error = compiler.compile(&nested.replace("loop { loop", "outer: loop { loop").replace("break", "break outer")).unwrap_err();
+        assert!(error.contains("Function 'f'"));
+    }
+    
+    #[test]
+    fn test_unread_timelines_are_demoted() {
+        let source = r#"
+temporal let counter = 0
+temporal let level = 1
+temporal let kept = 2
+counter = counter + 1
+level = 5
+kept = 3
+print(level[0])
+        "#;
+        
+        let mut ast = FluxCompiler::new(false).parse(source).unwrap();
+        assert_eq!(TimelineDemotion::demote(&mut ast), vec!["counter", "kept"]);
+        
+        let optimized = FluxCompiler::builder().optimization(1).build();
+        let ir = optimized.compile(&format!("#pragma keep_timeline kept\n{}", source)).unwrap();
+        assert!(!ir.contains("%v_main_counter.timeline"));
+        assert!(ir.contains("%v_main_level.timeline"));
+        assert!(ir.contains("%v_main_kept.timeline"));
+        
+        // Without optimization, or with every timeline kept, nothing changes
+        assert!(FluxCompiler::new(false).compile(source).unwrap().contains("%v_main_counter.timeline"));
+        let ir = optimized.compile(&format!("#pragma keep_timeline\n{}", source)).unwrap();
+        assert!(ir.contains("%v_main_counter.timeline"));
+    }
+    
+    #[test]
+    fn test_peephole_cleans_up_ir() {
+        let ir = "  %t1 = fadd double 0.0, 0x4024000000000000
+  store double %t1, double* %v_main_x
+  %t2 = load double, double* %v_main_x
+  %t3 = load double, double* %v_main_x
+  %t4 = fadd double %t2, %t3
+L1:
+  %t5 = load double, double* %v_main_x
+@.str.1 = private unnamed_addr constant [4 x i8] c\"%t1\\00\"
+";
+        assert_eq!(IRPeephole::optimize(ir), "  store double 0x4024000000000000, double* %v_main_x
+  %t4 = fadd double 0x4024000000000000, 0x4024000000000000
+L1:
+  %t5 = load double, double* %v_main_x
+@.str.1 = private unnamed_addr constant [4 x i8] c\"%t1\\00\"
+");
+        
+        let source = "let x = 10\nlet y = x + 1\nprint(y)";
+        let ir = FluxCompiler::builder().optimization(1).build().compile(source).unwrap();
+        assert!(!ir.contains("fadd double 0.0, "));
+        assert!(ir.contains("store double 0x4024000000000000, double* %v_main_x"));
+    }
+    
+    #[test]
+    fn test_repl_temporal_debugger() {
+        let mut repl = FluxRepl::new();
+        for line in ["temporal let x = 1", "let y = 10", "x = 2", "x = 3", "temporal let z = 7"] {
+            repl.evaluate(line).unwrap();
+        }
+        
+        assert_eq!(repl.evaluate(":timeline x").unwrap(), Some("time  value\n   0  1\n   1  2\n   2  3".to_string()));
+        assert_eq!(repl.evaluate(":at 1 x + y").unwrap(), Some("12".to_string()));
+        assert!(repl.evaluate(":at 0 z").unwrap_err().contains("Undefined variable 'z'"));
+        // Evaluating in the past leaves the present alone
+        assert_eq!(repl.evaluate("x + 0").unwrap(), Some("3".to_string()));
+        
+        assert_eq!(repl.evaluate(":rewind 2").unwrap(), Some("Rewound to time 0".to_string()));
+        assert_eq!(repl.evaluate("x + y").unwrap(), Some("11".to_string()));
+        assert!(repl.evaluate("z").is_err());
+        assert!(repl.evaluate(":rewind 1").unwrap_err().contains("the clock is at 0"));
+        assert!(repl.evaluate(":timeline y").unwrap_err().contains("not a temporal variable"));
+        
+        // Lines are checked against the session like `flux run` checks a file
+        repl.evaluate("const c = 1").unwrap();
+        assert!(repl.evaluate("c = 2").unwrap_err().contains("Cannot reassign to const variable 'c'"));
+        assert_eq!(repl.evaluate("c + 0").unwrap(), Some("1".to_string()));
+        assert!(repl.evaluate("let z = comptime { print(1) }").unwrap_err().contains("comptime block calls impure function 'print'"));
+        assert!(repl.evaluate("undeclared + 1").unwrap_err().contains("Undefined variable 'undeclared'"));
+        repl.evaluate("let y = 5").unwrap();
+        assert_eq!(repl.evaluate("y").unwrap(), Some("5".to_string()));
+    }
+    
+    #[test]
+    fn test_graphviz_output() {
+        let compiler = FluxCompiler::new(false);
+        let ast = compiler.parse("#pragma braces\nlet x = 1 + 2\nprint(\"a\\\"b\")").unwrap();
+        let dot = DotGenerator::ast(&ast);
+        assert!(dot.starts_with("digraph ast {"));
+        assert!(dot.contains("n1 [label=\"let x\"];\n  n2 [label=\"+\"];"));
+        assert!(dot.contains("n0 -> n1;"));
+        assert!(dot.contains("[label=\"\\\"a\\\\\\\"b\\\"\"]"));
+        
+        let ir = compiler.compile("#pragma braces\nlet i = 0\nwhile i < 3 { i = i + 1 }").unwrap();
+        let dot = DotGenerator::cfg(&ir);
+        let main = &dot[dot.find("label=\"@flux_main\"").unwrap()..];
+        assert!(main.contains("f0_L1 -> f0_L2 [label=\"true\"];"));
+        assert!(main.contains("f0_L1 -> f0_L3 [label=\"false\"];"));
+        assert!(main.contains("f0_L2 -> f0_L1;"));
+    }
+    
+    #[test]
+    fn test_compile_stats() {
+        let compiler = FluxCompiler::builder().optimization(1).build();
+        let (result, stats) = compiler.compile_with_stats("#pragma braces\nlet x = 1 + 2\nprint(x)");
+        let ir = result.unwrap();
+        assert_eq!(stats.ast_nodes, 8);
+        assert_eq!(stats.ir_bytes, ir.len());
+        assert_eq!(stats.diagnostics, 0);
+        let stages: Vec<_> = stats.stages.iter().map(|(stage, _)| *stage).collect();
+        assert_eq!(stages, ["lex", "parse", "comptime", "optimize", "semantic", "codegen", "peephole"]);
+        assert!(stats.to_string().contains("ast nodes    8"));
+        
+        let (result, stats) = FluxCompiler::new(false).compile_with_stats("const x = 1\nx = 2\nx = 3");
+        assert!(result.is_err());
+        assert_eq!(stats.diagnostics, 2);
+        assert_eq!(stats.stages.last().unwrap().0, "semantic");
+    }
+    
+    #[test]
+    fn test_freeze_is_deep() {
+        let source = r#"
+class Point {
+    func init(x, y) {
+        this.x = x
+        this.y = y
+    }
+    func move(dx) {
+        this.x = this.x + dx
+    }
+}
+let p = new Point(1, 2)
+p.next = new Point(3, 4)
+let inner = p.next
+freeze p
+let frozen = [is_frozen(p), is_frozen(inner), is_frozen(new Point(0, 0)), is_frozen(1)]
+        "#;
+        
+        let compiler = FluxCompiler::new(false);
+        let ast = compiler.parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.lookup("frozen").unwrap().to_string(), "[true, true, false, true]");
+        
+        // Aliases and methods can't get around it either
+        for (mutation, field) in [("inner.y = 0", "y"), ("p.move(1)", "x")] {
+            let ast = compiler.parse(mutation).unwrap();
+            assert_eq!(interpreter.run(&ast).unwrap_err(),
+                       format!("Cannot set field '{}' on a frozen instance of 'Point'", field));
+        }
+        
+        let mut ast = compiler.parse(&format!("{}\np.next.x = 5\np = 0", source)).unwrap();
+        let errors = compiler.analyze(&mut ast).err().unwrap();
+        assert!(errors.contains("Cannot modify field 'x' of frozen object 'p'"));
+        assert!(errors.contains("Cannot modify frozen variable 'p'"));
+        assert!(compiler.parse("freeze 1").unwrap_err().contains("Expected a variable name after 'freeze'"));
+    }
+    
+    #[test]
+    fn test_main_receives_arguments() {
+        let source = "#pragma braces\nprint(\"setup\")\nfunc main(args) {\n    print(args)\n}";
+        let compiler = FluxCompiler::new(false);
+        let ast = compiler.parse(source).unwrap();
+        assert_eq!(ast.entry_params(), Some(1));
+        
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        interpreter.run_main(&ast, &["a".to_string(), "b".to_string()]).unwrap();
+        assert_eq!(interpreter.take_output(), "setup\n[a, b]\n");
+        
+        let ir = compiler.compile(source).unwrap();
+        assert!(ir.contains("define internal double @flux_user_main(i8* %arg0)"));
+        assert!(ir.contains("define weak i32 @main(i32 %argc, i8** %argv)"));
+        assert!(ir.contains("  call void @flux_main()\n  %args = call i8* @flux_args_text(i32 %argc, i8** %argv)\n"));
+        
+        // Compiled code only has the arguments rendered as text, so it
+        // can't index them like the interpreter's array
+        let indexed = "#pragma braces\nfunc main(args) {\n    print(args[0])\n}";
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        interpreter.run_main(&compiler.parse(indexed).unwrap(), &["foo".to_string()]).unwrap();
+        assert_eq!(interpreter.take_output(), "foo\n");
+        let error = compiler.compile(indexed).unwrap_err();
+        assert!(error.contains("`args[0]`: array indexing is only supported by the interpreter"), "{}", error);
+        
+        // A program that calls `main` itself keeps running top to bottom
+        assert_eq!(compiler.parse("func main() {\n}\nmain()").unwrap().entry_params(), None);
+        let errors = compiler.compile("func main(a, b) {\n}").unwrap_err();
+        assert!(errors.contains("`func main` takes at most one parameter"));
+    }
+    
+    #[test]
+    fn test_exit_and_panic() {
+        let source = "#pragma braces\nfunc check(x) {\n    if x > 2 {\n        exit(3)\n    }\n    return x\n}\nprint(check(1))\nprint(check(5))\nprint(\"unreachable\")";
+        let compiler = FluxCompiler::new(false);
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        assert_eq!(interpreter.run(&compiler.parse(source).unwrap()), Ok(FluxValue::Null));
+        assert_eq!(interpreter.take_output(), "1\n");
+        assert_eq!(interpreter.exit_code(), Some(3));
+        
+        let ir = compiler.compile(source).unwrap();
+        assert!(ir.contains("  call void @exit(i32 %"));
+        
+        let source = "panic(\"bad input\")";
+        assert_eq!(Interpreter::new().run(&compiler.parse(source).unwrap()).unwrap_err(), "panic: bad input");
+        let ir = compiler.compile(source).unwrap();
+        assert!(ir.contains("c\"panic: %s\\0A\\00\""));
+        assert!(ir.contains("  call void @abort()"));
+        assert!(compiler.compile("exit()").unwrap_err().contains("exit() takes exactly 1 argument, found 0"));
+    }
+    
+    #[test]
+    fn test_unicode_identifiers_and_strings() {
+        let tokens = Lexer::new("let café = 名前 + x\u{303}").tokenize();
+        assert_eq!(tokens[1], TokenType::Identifier("café".to_string()));
+        assert_eq!(tokens[3], TokenType::Identifier("名前".to_string()));
+        assert_eq!(tokens[5], TokenType::Identifier("x\u{303}".to_string()));
+        
+        let mut lexer = Lexer::new("名前 = é");
+        while lexer.current_char != Some('=') {
+            lexer.advance();
+        }
+        assert_eq!(lexer.column, 6);
+        
+        let source = "let s = \"héllo 名前\"\nprint(len(s))\nprint(byte_len(s))\nprint(s[7])";
+        let compiler = FluxCompiler::new(false);
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        interpreter.run(&compiler.parse(source).unwrap()).unwrap();
+        assert_eq!(interpreter.take_output(), "8\n13\n前\n");
+        
+        let ir = compiler.compile(&format!("{}\nlet ü = 1", source)).unwrap();
+        assert!(ir.contains("call i64 @flux_utf8_length(i8* %"));
+        assert!(ir.contains("call i64 @strlen(i8* %"));
+        assert!(ir.contains("%v_main_$fc$ = alloca double"));
+    }
+    
+    #[test]
+    fn test_json_diagnostics() {
+        let compiler = FluxCompiler::new(false);
+        
+        let report = compiler.compile_report("#pragma braces\nlet x = (1 +\n");
+        assert!(report.ir.is_none());
+        assert_eq!(report.diagnostics.len(), 1);
+        assert_eq!(report.diagnostics[0].code, Diagnostic::PARSE);
+        assert_eq!(report.diagnostics[0].span, Some(Span { line: 3, column: 1 }));
+        
+        let report = compiler.compile_report("#pragma braces\nconst x = 1 @\nx = 2\n");
+        let codes: Vec<&str> = report.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec![Diagnostic::UNEXPECTED_CHARACTER, Diagnostic::IMMUTABLE]);
+        assert_eq!(report.diagnostics[0].severity, Severity::Warning);
+        assert_eq!(report.diagnostics[0].span, Some(Span { line: 2, column: 13 }));
+        assert_eq!(report.diagnostics[1].to_json(),
+                   "{\"code\":\"E0303\",\"severity\":\"error\",\"span\":{\"line\":3,\"column\":1},\
+                    \"message\":\"Cannot reassign to const variable 'x'\",\
+                    \"suggestion\":\"declare it with `let` to allow reassignment\"}");
+        assert_eq!(Diagnostic::summary(&report.diagnostics),
+                   "Semantic errors: [\"Cannot reassign to const variable 'x'\"]");
+        
+        // Errors point at the innermost statement they're about, with a
+        // code for their kind
+        let source = "#pragma braces\nfunc f(n) {\n    if n > 1 {\n        break\n    }\n    match n {\n        1 => print(m)\n    }\n}\n";
+        let errors: Vec<(&str, Option<Span>)> = compiler.compile_report(source).diagnostics.iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.span))
+            .collect();
+        assert_eq!(errors, vec![
+            (Diagnostic::MISPLACED, Some(Span { line: 4, column: 9 })),
+            (Diagnostic::UNDEFINED_NAME, Some(Span { line: 7, column: 14 })),
+        ]);
+        
+        let report = compiler.compile_report("let items = [1, 2]\nprint(items[0])");
+        assert_eq!(report.diagnostics[0].code, Diagnostic::INTERPRETER_ONLY);
+        assert_eq!(report.diagnostics[0].suggestion.as_deref(), Some("run the program with `flux run` instead"));
+        
+        let quoted = Diagnostic::error(Diagnostic::CODEGEN, "a \"b\"\\\n".to_string());
+        assert!(quoted.to_json().contains(r#""message":"a \"b\"\\\n""#));
+    }
+    
+    #[test]
+    fn test_did_you_mean_suggestions() {
+        assert_eq!(Diagnostic::closest_name("cuont", ["count", "amount"]), Some("count"));
+        assert_eq!(Diagnostic::closest_name("x", ["y", "xs"]), Some("xs"));
+        assert_eq!(Diagnostic::closest_name("total", ["count"]), None);
+        
+        let report = FluxCompiler::new(false).compile_report("#pragma braces\nlet count = 1\ncuont = 2\n");
+        assert_eq!(report.diagnostics[0].message, "Undefined variable 'cuont' (did you mean 'count'?)");
+        assert_eq!(report.diagnostics[0].suggestion.as_deref(), Some("replace it with `count`"));
+        
+        // Reads and calls are checked too, against variables, functions and builtins
+        let source = "#pragma braces\nfunc square(n) { return n * n }\nprint(cuont)\nlet z = prnt(1)\nlet w = squre(2)\nlet x = foo(1)\n";
+        let messages: Vec<String> = FluxCompiler::new(false).compile_report(source).diagnostics.into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(messages, [
+            "Undefined variable 'cuont'",
+            "Undefined function 'prnt' (did you mean 'print'?)",
+            "Undefined function 'squre' (did you mean 'square'?)",
+            "Undefined function 'foo'",
+        ]);
+        
+        // Functions may read globals declared before they're called, but
+        // their own locals end with them. Compiled functions can't see
+        // globals yet, which is reported rather than read as zero
+        let later = "#pragma braces\nfunc show() { let shown = total print(shown) }\nlet total = 3\nshow()\n";
+        assert!(FluxCompiler::new(false).check(later).diagnostics.is_empty());
+        let error = FluxCompiler::new(false).compile(later).unwrap_err();
+        assert!(error.contains("`total`: reading a variable declared outside the function is only supported by the interpreter"));
+        let error = FluxCompiler::new(false).compile("#pragma braces\nlet g = 1\nfunc f() { g = 2 }\nf()\n").unwrap_err();
+        assert!(error.contains("`g = 2`: assigning a variable declared outside the function"));
+        let leaked = "#pragma braces\nfunc f() { let y = 1 }\nprint(y)\n";
+        assert!(FluxCompiler::new(false).compile(leaked).unwrap_err().contains("Undefined variable 'y'"));
+        
+        // Builtins the compiler has no lowering for are reported, not
+        // emitted as calls to missing functions
+        let error = FluxCompiler::new(false).compile("print(sqrt(4))").unwrap_err();
+        assert!(error.contains("sqrt() is only supported by the interpreter"), "{}", error);
+        
+        let mut parser = Parser::new(Lexer::new("#pragma braces\nfunc square(n) { return n * n }\nsqure(3)\n").tokenize());
+        let error = Interpreter::new().run(&parser.parse().unwrap()).unwrap_err();
+        assert_eq!(error, "Undefined function 'squre' (did you mean 'square'?)");
+    }
+    
+    #[test]
+    fn test_record_and_replay_inputs() {
+        let source = "#pragma braces\nprint(random())\nprint(time() > 0)\nlet missing = read_file(\"/nonexistent/flux\")\n";
+        let ast = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        
+        let mut recorder = Interpreter::new();
+        recorder.set_input_trace(InputTrace::recording());
+        recorder.capture_output();
+        assert!(recorder.run(&ast).unwrap_err().starts_with("Cannot read file '/nonexistent/flux'"));
+        let recorded = recorder.take_output();
+        let trace = recorder.take_input_trace();
+        assert_eq!(trace.events().len(), 3);
+        
+        let mut replayer = Interpreter::new();
+        replayer.set_input_trace(InputTrace::from_bytes(&trace.to_bytes()).unwrap());
+        replayer.capture_output();
+        assert!(replayer.run(&ast).is_err());
+        assert_eq!(replayer.take_output(), recorded);
+        assert_eq!(replayer.take_input_trace().remaining(), 0);
+        
+        // Inputs must be asked for in the recorded order
+        let swapped = Parser::new(Lexer::new("print(time())").tokenize()).parse().unwrap();
+        let mut replayer = Interpreter::new();
+        replayer.set_input_trace(InputTrace::from_bytes(&trace.to_bytes()).unwrap());
+        assert!(replayer.run(&swapped).unwrap_err().starts_with("Replay diverged: the program called time()"));
+        assert!(InputTrace::from_bytes(b"FLUXTRACE1\x01").is_err());
+    }
+    
+    #[test]
+    fn test_strict_const() {
+        let compiler = FluxCompiler::new(false);
+        let source = "#pragma braces\n#pragma strict_const\nconst width = 4 * 2\nconst area = width * width\nfunc scale(width) {\n    return width * area\n}\nprint(scale(2))";
+        let mut ast = compiler.parse(source).unwrap();
+        let mut comptime = ComptimeEvaluator::new();
+        comptime.evaluate(&mut ast).unwrap();
+        assert!(matches!(comptime.constants().get("area"), Some(ASTNode::Number(n)) if *n == 64.0));
+        
+        // The parameter shadows the constant; `area` folds into the product
+        ASTOptimizer::propagate_constants(&mut ast, comptime.constants());
+        let ASTNode::Program(statements) = &ast else { unreachable!() };
+        let ASTNode::FunctionDecl { body, .. } = &statements[3] else { panic!("expected scale") };
+        assert_eq!(body[0].to_string(), "return width * 64");
+        
+        let error = compiler.compile("#pragma strict_const\nlet n = 3\nconst m = n * 2").unwrap_err();
+        assert!(error.contains("const 'm' must be a compile-time constant: Undefined variable 'n'"), "{}", error);
+        let error = compiler.compile("#pragma strict_const\nconst r = random()").unwrap_err();
+        assert!(error.contains("calls impure function 'random'"), "{}", error);
+        assert!(compiler.compile("let n = 3\nconst m = n * 2\nprint(m)").is_ok());
+        
+        // Only the pragma switches strict mode on; a call of the same name
+        // is an ordinary call
+        let shadowed = "#pragma braces\nfunc strict_const() { return 0 }\nstrict_const()\nlet n = 3\nconst m = n * 2\nprint(m)";
+        assert!(compiler.compile(shadowed).is_ok());
+        let error = compiler.compile("strict_const()\nlet n = 3\nconst m = n * 2").unwrap_err();
+        assert!(error.contains("Undefined function 'strict_const'"), "{}", error);
+        let ast = compiler.parse("#pragma strict_const").unwrap();
+        assert!(matches!(&ast, ASTNode::Program(statements) if matches!(statements.as_slice(), [ASTNode::Pragma(name)] if name == "strict_const")));
+    }
+    
+    #[test]
+    fn test_string_builder() {
+        let source = "#pragma braces\nlet b = new StringBuilder(\"n=\")\nlet i = 0\nwhile i < 3 {\n    b.append(i, \",\")\n    i = i + 1\n}\nlet s = \"\"\nlet t = s\ns = s + \"x\" + 1\nprint(b.to_string())\nprint(b.length())\nprint(s + t + \"|\")\nfreeze b\nb.append(\"!\")";
+        let ast = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        let error = interpreter.run(&ast).unwrap_err();
+        assert_eq!(interpreter.take_output(), "n=0,1,2,\n8\nx1|\n");
+        assert_eq!(error, "Cannot set field 'text' on a frozen instance of 'StringBuilder'");
+    }
+    
+    #[test]
+    fn test_timelines_share_unchanged_elements() {
+        let row = |i: usize| FluxValue::Array(vec![FluxValue::String(format!("row {}", i)), FluxValue::Number(i as f64)]);
+        let mut table: Vec<FluxValue> = (0..100).map(row).collect();
+        let mut manager = TemporalManager::new();
+        manager.create_temporal_var("table".to_string(), FluxValue::Array(table.clone()));
+        assert_eq!(manager.stored_nodes(), 1 + 100 * 3);
+        
+        for version in 1..=10 {
+            table[0] = FluxValue::Number(version as f64);
+            manager.advance_time();
+            manager.update_temporal_var("table", FluxValue::Array(table.clone())).unwrap();
+        }
+        // Each update adds a new outer array and its one changed element
+        assert_eq!(manager.stored_nodes(), 1 + 100 * 3 + 10 * 2);
+        assert_eq!(manager.get_at_time("table", 0).unwrap(), FluxValue::Array((0..100).map(row).collect()));
+        assert_eq!(manager.current_value("table"), Some(FluxValue::Array(table)));
+        
+        // An unchanged value is the previous version itself
+        let last = manager.current_value("table").unwrap();
+        manager.update_temporal_var("table", last).unwrap();
+        assert_eq!(manager.stored_nodes(), 1 + 100 * 3 + 10 * 2);
+        assert_eq!(manager.changes("table"), Some(11));
+    }
+    
+    #[test]
+    fn test_check_skips_codegen() {
+        let compiler = FluxCompiler::new(false);
+        
+        // Interpreter-only features are fine, since the program can still run
+        let report = compiler.check("let items = [1, 2]\nprint(items[0])");
+        assert!(report.ir.is_none());
+        assert!(report.diagnostics.is_empty());
+        let stages: Vec<&str> = report.stats.stages.iter().map(|(stage, _)| *stage).collect();
+        assert_eq!(stages, vec!["lex", "parse", "comptime", "semantic"]);
+        assert!(compiler.compile("let items = [1, 2]\nprint(items[0])").is_err());
+        
+        let report = compiler.check("const x = 1\nx = 2\nbreak");
+        assert_eq!(report.diagnostics.len(), 2);
+        assert_eq!((report.diagnostics[1].code, report.diagnostics[1].span), (Diagnostic::MISPLACED, Some(Span { line: 3, column: 1 })));
+        assert_eq!(report.diagnostics[0].to_json_in("a\\b.flux"),
+                   "{\"file\":\"a\\\\b.flux\",\"code\":\"E0303\",\"severity\":\"error\",\"span\":{\"line\":2,\"column\":1},\
+                    \"message\":\"Cannot reassign to const variable 'x'\",\
+                    \"suggestion\":\"declare it with `let` to allow reassignment\"}");
+    }
+    
+    #[test]
+    fn test_conditional_pragmas() {
+        let source = "#pragma if debug\nprint(1)\n#pragma if level == 2\nprint(2)\n#pragma else\nprint(3)\n#pragma endif\n#pragma else\nprint(4)\n#pragma endif\nprint(5)";
+        let calls = |compiler: &FluxCompiler| -> Vec<String> {
+            match compiler.parse(source).unwrap() {
+                ASTNode::Program(statements) => statements.iter().map(|s| s.to_string()).collect(),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(calls(&FluxCompiler::new(false)), vec!["print(4)", "print(5)"]);
+        let debug = FluxCompiler::builder().define("debug", "true").build();
+        assert_eq!(calls(&debug), vec!["print(1)", "print(3)", "print(5)"]);
+        let level = FluxCompiler::builder().define("debug", "1").define("level", "2").build();
+        assert_eq!(calls(&level), vec!["print(1)", "print(2)", "print(5)"]);
+        assert_eq!(calls(&FluxCompiler::builder().define("debug", "false").build()), vec!["print(4)", "print(5)"]);
+        
+        let compiler = FluxCompiler::new(false);
+        let report = compiler.check("#pragma if debug\nlet x = 1\n#pragma else\n#pragma else");
+        let messages: Vec<&str> = report.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec!["Second `#pragma else` in one `#pragma if` section",
+                                  "`#pragma if` is never closed by `#pragma endif`"]);
+        assert_eq!(report.diagnostics[0].span.map(|s| s.line), Some(4));
+        assert!(compiler.parse("#pragma endif").unwrap_err().contains("without a matching `#pragma if`"));
+        assert!(compiler.parse("#pragma if a b\n#pragma endif").is_err());
+    }
+    
+    #[test]
+    fn test_match_ranges_and_alternatives() {
+        let source = "#pragma braces\nfunc status(code) {\n    print(match code {\n        case 200..299 => \"success\"\n        case 301, 302, 307 => \"redirect\"\n        case 400..499, 500 => \"error\"\n        default => \"other\"\n    })\n}\nstatus(204)\nstatus(302)\nstatus(450)\nstatus(500)\nstatus(600)\nstatus(1.5)";
+        let ast = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.take_output(), "success\nredirect\nerror\nerror\nother\nother\n");
+        
+        let condition = PatternMatcher::condition(&ASTNode::Identifier("x".to_string()),
+            &Parser::new(Lexer::new("match 0 { 1..2, 5 => 0 }").tokenize()).parse().map(|ast| match ast {
+                ASTNode::Program(mut statements) => match statements.remove(0) {
+                    ASTNode::Match { mut cases, .. } => cases.remove(0).0,
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            }).unwrap());
+        assert_eq!(condition.to_string(), "((x >= 1) && (x <= 2)) || (x == 5)");
+        
+        let report = FluxCompiler::new(false).check("let c = 250\nmatch c {\n    case 200..299 => print(1)\n    case 250 => print(2)\n    case 280..320, 7 => print(3)\n    case 9..1 => print(4)\n}");
+        let warnings: Vec<&str> = report.diagnostics.iter()
+            .filter(|d| d.code == Diagnostic::OVERLAPPING_PATTERN && d.severity == Severity::Warning)
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(warnings, vec![
+            "case `250` of `match c` never matches: case `200..299` comes first and covers it",
+            "case `280..320` of `match c` overlaps case `200..299`, which comes first",
+            "range `9..1` in `match c` is empty, so it never matches",
+        ]);
+        
+        // Every alternative of a multi-value case is checked, against all earlier cases
+        let report = FluxCompiler::new(false).check("let c = 250\nmatch c {\n    case 200..299 => print(1)\n    case 300..320 => print(2)\n    case 300..399 => print(3)\n    case 301, 350, 250, 7, 7 => print(4)\n}");
+        let warnings: Vec<&str> = report.diagnostics.iter()
+            .filter(|d| d.code == Diagnostic::OVERLAPPING_PATTERN && d.severity == Severity::Warning)
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(warnings, vec![
+            "case `300..399` of `match c` overlaps case `300..320`, which comes first",
+            "case `301` of `match c` never matches: case `300..320` comes first and covers it",
+            "case `350` of `match c` never matches: case `300..399` comes first and covers it",
+            "case `250` of `match c` never matches: case `200..299` comes first and covers it",
+            "case `7` of `match c` never matches: case `7` comes first and covers it",
+        ]);
+    }
+    
+    #[test]
+    fn test_reflection_builtins() {
+        let source = "#pragma braces\nclass Shape {\n    func init(name) { this.name = name }\n    func area() { return 0 }\n}\nclass Circle extends Shape {\n    func init(r) {\n        this.r = r\n        this.name = \"circle\"\n    }\n    func grow() { this.r = this.r + 1 }\n}\nlet c = new Circle(2)\nc.tag = 1\nprintln(\"{} {} {} {}\", typeof(c), typeof(1), typeof(\"a\"), typeof([1]))\nprint(fields(c))\nprint(methods(c))\nprint(methods(\"Shape\"))\nfields(1)";
+        let ast = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        let error = interpreter.run(&ast).unwrap_err();
+        assert_eq!(interpreter.take_output(),
+                   "Circle number string array\n[name, r, tag]\n[area, grow, init]\n[area, init]\n");
+        assert_eq!(error, "fields() expects an instance or object, found number");
+        
+        // Compiled code only has statically typed values
+        let compiler = FluxCompiler::new(false);
+        let ir = compiler.compile("let x = 3\nprint(typeof(x > 1))").unwrap();
+        assert!(ir.contains("c\"boolean\\00\""));
+        assert!(compiler.compile("print(typeof([1]))").unwrap_err().contains("only known to the interpreter"));
+    }
+    
+    #[test]
+    fn test_defer_runs_on_every_exit() {
+        let source = "#pragma braces\nfunc work(n) {\n    print(n)\n    defer print(n + 100)\n    if n > 1 {\n        return n * 10\n    }\n    defer print(n + 200)\n    return n\n}\ndefer print(999)\nprint(work(2))\nprint(work(1))";
+        let compiler = FluxCompiler::new(false);
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        interpreter.run(&compiler.parse(source).unwrap()).unwrap();
+        assert_eq!(interpreter.take_output(), "2\n102\n20\n1\n201\n101\n1\n999\n");
+        
+        // Compiled functions route their returns through a cleanup block
+        let ir = compiler.compile(source).unwrap();
+        assert_eq!(ir.matches("store i1 true").count(), 3);
+        assert_eq!(ir.matches("ret double").count(), 1);
+        
+        // Defers also run when the function fails, and the first error wins
+        let failing = "#pragma braces\nfunc f() {\n    defer print(\"cleanup\")\n    defer panic(\"second\")\n    panic(\"first\")\n    return 0\n}\nf()";
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        let error = interpreter.run(&compiler.parse(failing).unwrap()).unwrap_err();
+        assert_eq!((error.as_str(), interpreter.take_output().as_str()), ("panic: first", "cleanup\n"));
+        
+        // Compiled, a panic unwinds through each caller's cleanup block
+        // before aborting, and only the first one is reported
+        let ir = compiler.compile(&format!("{}\nfunc g() {{\n    defer print(\"g cleanup\")\n    f()\n    return 0\n}}\ng()", failing)).unwrap();
+        assert!(ir.contains("@flux_panicking = internal global i1 false"));
+        assert_eq!(ir.matches("store i1 true, i1* @flux_panic_reported").count(), 2);
+        assert_eq!(ir.matches("call void @abort()").count(), 1);
+        let ir = compiler.compile("#pragma braces\nfunc f() {\n    panic(\"first\")\n    return 0\n}\nf()").unwrap();
+        assert!(!ir.contains("@flux_panicking") && ir.contains("call void @abort()"));
+        
+        let errors = compiler.compile("#pragma braces\nfunc f() {\n    while 1 {\n        defer print(1)\n    }\n    return 0\n}").unwrap_err();
+        assert!(errors.contains("`defer print(1)` must be a statement directly in a function body"));
+    }
+    
+    #[test]
+    fn test_sandbox_limits() {
+        let spin = "#pragma braces\nlet i = 0\nwhile true { i = i + 1 }";
+        let mut engine = Engine::new();
+        engine.set_limits(Limits::new().max_steps(1000));
+        assert_eq!(engine.eval(spin).unwrap_err(), "Step limit of 1000 exceeded");
+        assert_eq!(engine.limit_exceeded(), Some(LimitExceeded::Steps(1000)));
+        // Each eval starts a fresh budget
+        assert_eq!(engine.eval("1 + 2").unwrap(), 3.0.into());
+        assert_eq!(engine.limit_exceeded(), None);
+        
+        engine.set_limits(Limits::new().timeout(Duration::from_millis(20)));
+        assert_eq!(engine.eval(spin).unwrap_err(), "Time limit of 20ms exceeded");
+        
+        engine.set_limits(Limits::new().max_heap_bytes(4096));
+        let hoard = "#pragma braces\nclass Cell {\n    func init(v) { this.v = v }\n}\nlet head = new Cell(0)\nwhile true { head = new Cell(head) }";
+        assert!(engine.eval(hoard).is_err());
+        assert_eq!(engine.limit_exceeded(), Some(LimitExceeded::HeapBytes(4096)));
+        
+        // Garbage is collected before the heap limit is enforced
+        let churn = "#pragma braces\nclass P {\n    func init(v) { this.v = v }\n}\nlet i = 0\nwhile i < 5000 {\n    let p = new P(i)\n    i = i + 1\n}\ni";
+        engine.set_limits(Limits::new().max_heap_bytes(64 * 1024));
+        assert_eq!(engine.eval(churn).unwrap(), 5000.0.into());
+        
+        // Strings and arrays count too, as they're built
+        let doubling = "#pragma braces\nlet s = \"x\"\nlet i = 0\nwhile i < 26 {\n    s = s + s\n    i = i + 1\n}\nlen(s)";
+        assert_eq!(engine.eval(doubling).unwrap_err(), "Heap limit of 65536 bytes exceeded");
+        assert_eq!(engine.limit_exceeded(), Some(LimitExceeded::HeapBytes(64 * 1024)));
+        let nested = "#pragma braces\nlet a = [1]\nwhile true { a = [a, a] }";
+        assert_eq!(engine.eval(nested).unwrap_err(), "Heap limit of 65536 bytes exceeded");
+        assert_eq!(engine.eval("let small = \"ab\" + \"cd\"\nsmall").unwrap(), "abcd".into());
+        
+        // Runaway recursion stops at the default depth instead of
+        // overflowing the stack
+        engine.set_limits(Limits::new().max_steps(100_000_000));
+        assert_eq!(engine.eval("#pragma braces\nfunc f(n) { return f(n + 1) }\nf(0)").unwrap_err(),
+                   format!("Call depth limit of {} exceeded", Limits::DEFAULT_CALL_DEPTH));
+        engine.set_limits(Limits::new().max_call_depth(10));
+        assert_eq!(engine.eval("#pragma braces\nfunc down(n) { if n == 0 { return 0 } return down(n - 1) }\ndown(9)").unwrap(), 0.0.into());
+        assert_eq!(engine.limit_exceeded(), None);
+        assert_eq!(engine.call("down", vec![10.0.into()]).unwrap_err(), "Call depth limit of 10 exceeded");
+        
+        // Each call gets a fresh budget too
+        engine.eval("#pragma braces\nfunc g(n) { let k = 0 while k < n { k = k + 1 } return k }").unwrap();
+        engine.set_limits(Limits::new().max_steps(50));
+        assert_eq!(engine.call("g", vec![100.0.into()]).unwrap_err(), "Step limit of 50 exceeded");
+        assert_eq!(engine.call("g", vec![1.0.into()]).unwrap(), 1.0.into());
+        assert_eq!(engine.limit_exceeded(), None);
+        
+        // Runs without limits still stop runaway recursion with an error,
+        // and have the stack for the deepest expressions the parser allows
+        let recursion = "#pragma braces\nfunc f(n) { if n == 0 { return 0 } return f(n - 1) + 1 }\nfunc main() { return f(5000) }";
+        let ast = FluxCompiler::new(false).parse(recursion).unwrap();
+        assert_eq!(Interpreter::new().run_main(&ast, &[]).unwrap_err(),
+                   format!("Call depth limit of {} exceeded", Limits::DEFAULT_CALL_DEPTH));
+        assert_eq!(Engine::new().eval("#pragma braces\nfunc f(n) { if n == 0 { return 0 } return f(n - 1) + 1 }\nf(900)").unwrap(),
+                   900.0.into());
+        let chain = format!("let x = 1{}\nx", " + 1".repeat(120));
+        assert_eq!(Engine::new().eval(&chain).unwrap(), 121.0.into());
+        assert!(FluxRepl::new().evaluate("#pragma braces\nfunc g(n) { return g(n + 1) }\ng(0)").is_err());
+        
+        let mut engine = Engine::new();
+        engine.register_fn("host_value", |_| Ok(7.0.into()));
+        engine.set_limits(Limits::new().allow_builtins(["len"]));
+        assert_eq!(engine.eval("len(\"abc\") + host_value()").unwrap(), 10.0.into());
+        assert_eq!(engine.eval("read_file(\"/etc/passwd\")").unwrap_err(),
+                   "Builtin 'read_file' is not allowed in this sandbox");
+        assert_eq!(engine.limit_exceeded(), Some(LimitExceeded::Builtin("read_file".to_string())));
+    }
+    
+    #[test]
+    fn test_source_map_points_functions_at_declarations() {
+        let source = "let x = 2\n\nfunc square(n) {\n    return n * n\n}\nexport func cube(n) {\n    return n * square(n)\n}\nprint(cube(x))\n";
+        let report = FluxCompiler::builder().source_map(true).build().compile_report(source);
+        let ir = report.ir.unwrap();
+        let map = report.source_map.unwrap();
+        
+        let square = map.mappings.iter().find(|mapping| mapping.symbol == "@flux_user_square").unwrap();
+        assert_eq!(square.function.as_deref(), Some("square"));
+        assert_eq!(square.span, Span { line: 3, column: 1 });
+        let lines: Vec<&str> = ir.lines().collect();
+        assert!(lines[square.output_start - 1].starts_with("define internal double @flux_user_square"));
+        
+        // Statements map to their own lines, up to the end of the function
+        let multiply = map.lookup(lines.iter().position(|line| line.contains("fmul")).unwrap() + 1).unwrap();
+        assert_eq!((multiply.function.as_deref(), multiply.span), (Some("square"), Span { line: 4, column: 5 }));
+        assert_eq!(lines[multiply.output_end - 1], "}");
+        assert_eq!(map.mappings.iter().find(|mapping| mapping.symbol == "@cube").unwrap().span.line, 6);
+        let call = map.lookup(lines.iter().position(|line| line.contains("call double @cube")).unwrap() + 1).unwrap();
+        assert_eq!((call.symbol.as_str(), call.function.as_deref(), call.span.line), ("@flux_main", None, 9));
+        assert_eq!(map.lookup(1), None);
+        assert!(map.to_json("main.flux").starts_with("{\"version\":1,\"source\":\"main.flux\",\"mappings\":[{\"symbol\":"));
+        
+        assert!(FluxCompiler::new(false).compile_report(source).source_map.is_none());
+    }
+    
+    #[test]
+    fn test_hot_reload_keeps_state_and_rebinds_callers() {
+        let mut repl = FluxRepl::new();
+        repl.evaluate("func f(x) { return x + 1 }").unwrap();
+        repl.evaluate("func g(x) { return f(x) * 2 }").unwrap();
+        repl.evaluate("let calls = 0").unwrap();
+        assert_eq!(repl.evaluate("g(1)").unwrap().as_deref(), Some("4"));
+        repl.evaluate("func f(x) { return x + 10 }").unwrap();
+        assert_eq!(repl.evaluate("g(1)").unwrap().as_deref(), Some("22"));
+        assert_eq!(repl.evaluate("3 | f").unwrap().as_deref(), Some("13"));
+        
+        let path = std::env::temp_dir().join(format!("flux_hot_reload_{}.flux", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "func f(x) {\n    return x * 100\n}\nlet calls = 99\n").unwrap();
+        assert_eq!(repl.watch(path).unwrap(), format!("Watching {}: loaded f", path));
+        assert_eq!(repl.evaluate("g(1)").unwrap().as_deref(), Some("200"));
+        // Top-level code in the file never runs, so the session's state stays
+        assert_eq!(repl.evaluate("calls").unwrap().as_deref(), Some("0"));
+        assert!(repl.reload_watched().is_empty());
+        
+        fs::write(path, "func f(x) {\n    return x - 1\n}\n").unwrap();
+        let reloaded = repl.reload_watched();
+        assert_eq!(reloaded, vec![Ok(format!("Reloaded f from {}", path))]);
+        assert_eq!(repl.evaluate("g(5)").unwrap().as_deref(), Some("8"));
+        
+        // A broken edit keeps the old definitions and is reported once
+        fs::write(path, "func f(\n").unwrap();
+        assert!(repl.reload_watched()[0].is_err());
+        assert!(repl.reload_watched().is_empty());
+        assert_eq!(repl.evaluate("g(5)").unwrap().as_deref(), Some("8"));
+        fs::remove_file(path).unwrap();
+    }
+    
+    #[test]
+    fn test_clock_domains_tick_separately() {
+        let source = "timeline domain sensor_clock\ntemporal let a = 1\ntemporal(sensor_clock) let reading = 10\na = 2\na = 3\nreading = 11\ncheckpoint(\"mid\")\nreading = 12\na = 4\n";
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&FluxCompiler::new(false).parse(source).unwrap()).unwrap();
+        let temporal = interpreter.temporal_manager();
+        assert_eq!(temporal.current_time(), 3);
+        assert_eq!(temporal.domain_time("sensor_clock"), Some(2));
+        assert_eq!(temporal.domain_of("reading"), Some("sensor_clock"));
+        assert_eq!(temporal.timeline("reading").unwrap().iter().map(|(time, _)| *time).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(temporal.get_at_time("reading", 1), Some(FluxValue::Number(11.0)));
+        assert_eq!(temporal.get_at_time("a", 1), Some(FluxValue::Number(2.0)));
+        // A checkpoint reads each variable's own clock
+        assert_eq!(temporal.checkpoint_time_for("mid", "reading"), Some(1));
+        assert_eq!(temporal.checkpoint_time_for("mid", "a"), Some(2));
+        
+        let program = format!("{}print(reading[\"mid\"])\nprint(a[\"mid\"])\nprint(reading[0])\n", source);
+        let ir = FluxCompiler::new(false).compile(&program).unwrap();
+        assert!(ir.contains("@flux_clock.sensor_clock = internal global double 0.0"));
+        assert!(ir.contains("double* @flux_checkpoint.0.sensor_clock\n"));
+        assert!(ir.contains(", double* @flux_clock.sensor_clock)\n"));
+        
+        let errors = FluxCompiler::new(false).check("temporal(nope) let x = 1\nif true {\n    timeline domain d\n}\n");
+        let messages: Vec<&str> = errors.diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).collect();
+        assert!(messages[0].starts_with("Unknown clock domain 'nope' for 'x'"));
+        assert_eq!(messages[1], "`timeline domain d` must be at the top level");
+    }
+    
+    #[test]
+    fn test_checked_math() {
+        let run = |source: &str| {
+            let ast = FluxCompiler::new(false).parse(source).unwrap();
+            Interpreter::new().run(&ast)
+        };
+        assert_eq!(run("let z = 0\n1 / z").unwrap(), FluxValue::Number(f64::INFINITY));
+        assert_eq!(run("#pragma checked_math\nlet z = 0\n1 / z").unwrap_err(), "Division by zero in `1 / z`");
+        assert_eq!(run("#pragma checked_math\nlet z = 0\n7 % z").unwrap_err(), "Division by zero in `7 % z`");
+        // The pragma covers code before it too
+        let overflow = "func grow(x) {\n    return x * 1000000000000\n}\nlet x = 1\nloop {\n    x = grow(x)\n}\n#pragma checked_math\n";
+        assert_eq!(run(overflow).unwrap_err(), "`x * 1000000000000` overflowed to infinity");
+        assert_eq!(run("#pragma checked_math\n6 / 4 + 1").unwrap(), FluxValue::Number(2.5));
+        // A call named like the pragma is just a call
+        let shadowed = "#pragma braces\nfunc checked_math() { return 0 }\nchecked_math()\nlet z = 0\n1 / z";
+        assert_eq!(run(shadowed).unwrap(), FluxValue::Number(f64::INFINITY));
+        assert!(!FluxCompiler::new(false).compile(shadowed).unwrap().contains("flux_checked"));
+        assert!(run("checked_math()").unwrap_err().contains("Undefined function 'checked_math'"));
+        
+        let ir = FluxCompiler::new(false).compile("#pragma checked_math\nlet z = 0\nprint(10 / z)\nprint(z + 1)").unwrap();
+        assert!(ir.contains("call double @flux_checked_divisor(double "));
+        assert_eq!(ir.matches("call double @flux_checked_result(double ").count(), 2);
+        assert!(ir.contains("define internal double @flux_checked_result(double %value, i8* %expr)"));
+        assert!(!FluxCompiler::new(false).compile("let z = 0\nprint(10 / z)").unwrap().contains("flux_checked"));
+    }
+    
+    #[test]
+    fn test_pipeline_placeholders() {
+        let compiler = FluxCompiler::new(false);
+        let ast = compiler.parse("95 | add(_, 10) | clamp(0, _, 100)\n3 | add(1) | scale(2, _) | show").unwrap();
+        let ASTNode::Program(statements) = &ast else { unreachable!() };
+        assert_eq!(statements[0].to_string(), "clamp(0, add(95, 10), 100)");
+        assert_eq!(statements[1].to_string(), "scale(2, 3 | add(1)) | show");
+        
+        let source = "func add(a, b) {\n    return a + b\n}\nfunc clamp(low, x, high) {\n    if x > high {\n        return high\n    }\n    return x\n}\n";
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&compiler.parse(source).unwrap()).unwrap();
+        assert_eq!(interpreter.run(&compiler.parse("95 | add(_, 10) | clamp(0, _, 100)").unwrap()).unwrap(), FluxValue::Number(100.0));
+        assert_eq!(interpreter.run(&compiler.parse("2 | add(_, 1) | add(10)").unwrap()).unwrap(), FluxValue::Number(13.0));
+        
+        assert!(compiler.parse("1 | add(_, _)").unwrap_err().contains("has 2 `_` placeholders"));
+        assert!(compiler.parse("[1, 2] ||> add(_, 1)").unwrap_err().contains("`||>` stage"));
+        let stray = compiler.check("func add(a, b) {\n    return a + b\n}\nprint(add(_, 1))\n");
+        assert!(stray.diagnostics[0].message.starts_with("`_` only stands for the piped value"));
+    }
+    
+    #[test]
+    fn test_custom_passes() {
+        struct BanCalls(&'static str);
+        impl BanCalls {
+            fn visit(&self, node: &ASTNode, diagnostics: &mut Vec<Diagnostic>) {
+                if let ASTNode::Call { callee, .. } = node
+                    && matches!(callee.as_ref(), ASTNode::Identifier(name) if name == self.0)
+                {
+                    diagnostics.push(Diagnostic::error(Diagnostic::PASS, format!("`{}` is banned", self.0)));
+                }
+                node.children().into_iter().for_each(|child| self.visit(child, diagnostics));
+            }
+        }
+        impl AstPass for BanCalls {
+            fn name(&self) -> &'static str {
+                "ban-calls"
+            }
+            fn run(&self, ast: &mut ASTNode, diagnostics: &mut Vec<Diagnostic>) {
+                self.visit(ast, diagnostics);
+            }
+        }
+        
+        // Replaces `trace(x)` with `x`
+        struct StripTrace;
+        impl AstPass for StripTrace {
+            fn name(&self) -> &'static str {
+                "strip-trace"
+            }
+            fn run(&self, ast: &mut ASTNode, _: &mut Vec<Diagnostic>) {
+                fn strip(node: &mut ASTNode) {
+                    if let ASTNode::Call { callee, args } = node
+                        && matches!(callee.as_ref(), ASTNode::Identifier(name) if name == "trace")
+                        && args.len() == 1
+                    {
+                        *node = args.remove(0);
+                    }
+                    node.children_mut().into_iter().for_each(strip);
+                }
+                strip(ast);
+            }
+        }
+        
+        let source = "func trace(x) {\n    print(x)\n    return x\n}\nprint(trace(1) + trace(2))\n";
+        let mut compiler = FluxCompiler::new(false);
+        compiler.add_pass(Box::new(StripTrace));
+        let (ir, stats) = compiler.compile_with_stats(source);
+        assert!(stats.stages.iter().any(|(stage, _)| *stage == "strip-trace"));
+        let ir = ir.unwrap();
+        assert!(ir.contains("define internal double @flux_user_trace"));
+        assert!(!ir.contains("call double @flux_user_trace"));
+        assert!(FluxCompiler::new(false).compile(source).unwrap().contains("call double @flux_user_trace"));
+        
+        compiler.add_pass(Box::new(BanCalls("trace")));
+        assert!(compiler.check(source).diagnostics.is_empty());
+        compiler.add_pass(Box::new(BanCalls("print")));
+        let report = compiler.check(source);
+        assert_eq!(report.diagnostics[0].code, Diagnostic::PASS);
+        assert_eq!(report.diagnostics[0].message, "`print` is banned");
+        assert!(compiler.compile(source).is_err());
+    }
+    
+    #[test]
+    fn test_signatures_are_inferred() {
+        let source = r#"
+func name() { return "flux" }
+func yes() { return 1 < 2 }
+func greet(who) { return "hi " + who }
+print(greet(name()))
+print(yes())
+let task = spawn { name() }
+print(join(task))
+print("v=" + 1/3)
+        "#;
+        
+        let ir = FluxCompiler::new(false).compile(source).unwrap();
+        assert!(ir.contains("define internal i8* @flux_user_name()"));
+        assert!(ir.contains("define internal i1 @flux_user_yes()"));
+        assert!(ir.contains("define internal i8* @flux_user_greet(i8* %arg0)"));
+        assert!(ir.contains("define internal i8* @flux_task_0(i8* %env)"));
+        assert!(ir.contains("call i8* @flux_number_string(double"));
+        
+        // One parameter can't be both a number and a string once compiled
+        let conflicting = "func id(x) { return x }\nprint(id(1))\nprint(id(\"a\"))";
+        let error = FluxCompiler::new(false).compile(conflicting).unwrap_err();
+        assert!(error.contains("A string can't be used where compiled code expects a number"));
+    }
+    
+    #[test]
+    fn test_macro_arity_error() {
+        let source = "macro twice(a, b) { a b } twice(1)";
+        let mut parser = Parser::new(Lexer::new(source).tokenize());
+        assert!(parser.parse().is_err());
+    }
+}
+
+// ============================================================================
+// ADVANCED FEATURES IMPLEMENTATION
+// ============================================================================
+
+/// Temporal Variable Manager - Handles time-based variable tracking
+#[derive(Clone)]
+pub struct TemporalManager {
+    timelines: HashMap<String, Vec<(usize, Arc<TimelineValue>)>>,
+    watchers: HashMap<String, Vec<Vec<ASTNode>>>,
+    /// Named points in time recorded by `checkpoint("label")`
+    checkpoints: HashMap<String, ClockReading>,
+    /// States captured by `snapshot()`, indexed by handle
+    snapshots: Vec<TemporalSnapshot>,
+    current_time: usize,
+    /// Clocks declared with `timeline domain name`, which advance only when
+    /// a variable recording against them is updated
+    domains: HashMap<String, usize>,
+    /// The domain each `temporal(domain)` variable records against; the
+    /// rest use the default clock
+    var_domains: HashMap<String, String>,
+}
+
+/// Every clock at one moment: the default clock and each domain's
+#[derive(Debug, Clone, PartialEq)]
+struct ClockReading {
+    time: usize,
+    domains: HashMap<String, usize>,
+}
+
+impl ClockReading {
+    /// The reading of `domain`'s clock, or the default clock's for `None`.
+    /// A domain declared after the reading was taken hadn't started yet.
+    fn of(&self, domain: Option<&str>) -> Option<usize> {
+        match domain {
+            Some(domain) => self.domains.get(domain).copied(),
+            None => Some(self.time),
+        }
+    }
+}
+
+/// A version of a temporal variable. Arrays and objects hold their elements
+/// behind `Arc`s, and each update reuses the previous version's node for
+/// every element that didn't change, so a long history of a large value
+/// keeps one copy of its unchanged parts instead of one per update.
+/// Instances are already shared, as handles to the GC heap.
+#[derive(Debug, PartialEq)]
+enum TimelineValue {
+    Scalar(FluxValue),
+    Array(Vec<Arc<TimelineValue>>),
+    Object(HashMap<String, Arc<TimelineValue>>),
+}
+
+impl TimelineValue {
+    /// `value` as a new version following `previous`
+    fn share(value: &FluxValue, previous: Option<&Arc<TimelineValue>>) -> Arc<TimelineValue> {
+        if let Some(previous) = previous
+            && previous.holds(value)
+        {
+            return Arc::clone(previous);
+        }
+        
+        Arc::new(match (value, previous.map(Arc::as_ref)) {
+            (FluxValue::Array(items), Some(TimelineValue::Array(old))) => TimelineValue::Array(
+                items.iter().enumerate().map(|(i, item)| Self::share(item, old.get(i))).collect()),
+            (FluxValue::Array(items), _) => TimelineValue::Array(
+                items.iter().map(|item| Self::share(item, None)).collect()),
+            (FluxValue::Object(fields), Some(TimelineValue::Object(old))) => TimelineValue::Object(
+                fields.iter().map(|(key, field)| (key.clone(), Self::share(field, old.get(key)))).collect()),
+            (FluxValue::Object(fields), _) => TimelineValue::Object(
+                fields.iter().map(|(key, field)| (key.clone(), Self::share(field, None))).collect()),
+            (scalar, _) => TimelineValue::Scalar(scalar.clone()),
+        })
+    }
+    
+    /// Whether this version is equal to `value`, without rebuilding it
+    fn holds(&self, value: &FluxValue) -> bool {
+        match (self, value) {
+            (TimelineValue::Scalar(scalar), value) => scalar == value,
+            (TimelineValue::Array(items), FluxValue::Array(values)) => items.len() == values.len()
+                && items.iter().zip(values).all(|(item, value)| item.holds(value)),
+            (TimelineValue::Object(fields), FluxValue::Object(values)) => fields.len() == values.len()
+                && fields.iter().all(|(key, field)| values.get(key).is_some_and(|value| field.holds(value))),
+            _ => false,
+        }
+    }
+    
+    fn to_value(&self) -> FluxValue {
+        match self {
+            TimelineValue::Scalar(scalar) => scalar.clone(),
+            TimelineValue::Array(items) => FluxValue::Array(items.iter().map(|item| item.to_value()).collect()),
+            TimelineValue::Object(fields) => FluxValue::Object(
+                fields.iter().map(|(key, field)| (key.clone(), field.to_value())).collect()),
+        }
+    }
+    
+    /// Heap handles inside this version, visiting each shared node once
+    fn trace(self: &Arc<Self>, seen: &mut HashSet<*const TimelineValue>, handles: &mut Vec<usize>) {
+        if !seen.insert(Arc::as_ptr(self)) {
+            return;
+        }
+        match self.as_ref() {
+            TimelineValue::Scalar(scalar) => scalar.trace(handles),
+            TimelineValue::Array(items) => items.iter().for_each(|item| item.trace(seen, handles)),
+            TimelineValue::Object(fields) => fields.values().for_each(|field| field.trace(seen, handles)),
+        }
+    }
+}
+
+/// The clocks at a `snapshot()`, plus plain globals when those were included
+#[derive(Clone)]
+struct TemporalSnapshot {
+    clocks: ClockReading,
+    plain: HashMap<String, FluxValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FluxValue {
+    Number(f64),
+    String(String),
+    Boolean(bool),
+    Object(HashMap<String, FluxValue>),
+    Array(Vec<FluxValue>),
+    /// Handle to a task started with `spawn`
+    Task(usize),
+    /// Handle to a channel created with `channel()`
+    Channel(usize),
+    /// Handle to a class instance on the `FluxRuntime` heap
+    Instance(usize),
+    Null,
+}
+
+impl FluxValue {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            FluxValue::Number(n) => *n != 0.0,
+            FluxValue::String(s) => !s.is_empty(),
+            FluxValue::Boolean(b) => *b,
+            FluxValue::Object(_) => true,
+            FluxValue::Array(items) => !items.is_empty(),
+            FluxValue::Task(_) | FluxValue::Channel(_) | FluxValue::Instance(_) => true,
+            FluxValue::Null => false,
+        }
+    }
+    
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            FluxValue::Number(_) => "number",
+            FluxValue::String(_) => "string",
+            FluxValue::Boolean(_) => "boolean",
+            FluxValue::Object(_) => "object",
+            FluxValue::Array(_) => "array",
+            FluxValue::Task(_) => "task",
+            FluxValue::Channel(_) => "channel",
+            FluxValue::Instance(_) => "instance",
+            FluxValue::Null => "null",
+        }
+    }
+    
+    pub fn to_display_string(&self) -> String {
+        self.to_string()
+    }
+    
+    /// Estimated bytes the value holds, counting the text of strings and
+    /// keys and everything inside arrays and objects
+    pub fn size(&self) -> usize {
+        std::mem::size_of::<FluxValue>() + match self {
+            FluxValue::String(text) => text.len(),
+            FluxValue::Array(items) => items.iter().map(FluxValue::size).sum(),
+            FluxValue::Object(fields) => fields.iter().map(|(key, value)| key.len() + value.size()).sum(),
+            _ => 0,
+        }
+    }
+    
+    /// Collects the heap handles this value refers to directly, for the GC
+    pub fn trace(&self, handles: &mut Vec<usize>) {
+        match self {
+            FluxValue::Instance(handle) => handles.push(*handle),
+            FluxValue::Array(items) => items.iter().for_each(|item| item.trace(handles)),
+            FluxValue::Object(fields) => fields.values().for_each(|value| value.trace(handles)),
+            _ => {}
+        }
+    }
+    
+    /// Multi-line rendering for the REPL: strings are quoted, long arrays
+    /// are truncated and containers that don't fit on one line are split
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+    
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        let items: Vec<(Option<&String>, &FluxValue)> = match self {
+            FluxValue::String(s) => {
+                out.push_str(&format!("{:?}", s));
+                return;
+            }
+            FluxValue::Array(items) => items.iter().map(|item| (None, item)).collect(),
+            FluxValue::Object(fields) => {
+                let mut fields: Vec<_> = fields.iter().collect();
+                fields.sort_by(|a, b| a.0.cmp(b.0));
+                fields.into_iter().map(|(key, value)| (Some(key), value)).collect()
+            }
+            other => {
+                out.push_str(&other.to_string());
+                return;
+            }
+        };
+        
+        let (open, close) = if matches!(self, FluxValue::Array(_)) { ('[', ']') } else { ('{', '}') };
+        let hidden = items.len().saturating_sub(PRETTY_MAX_ITEMS);
+        let rendered: Vec<String> = items.iter()
+            .take(PRETTY_MAX_ITEMS)
+            .map(|(key, value)| {
+                let mut item = key.map(|key| format!("{}: ", key)).unwrap_or_default();
+                value.write_pretty(&mut item, indent + 1);
+                item
+            })
+            .chain((hidden > 0).then(|| format!("... {} more", hidden)))
+            .collect();
+        
+        let flat = rendered.join(", ");
+        if !flat.contains('\n') && indent * 2 + flat.len() + 2 <= PRETTY_WIDTH {
+            out.push_str(&format!("{}{}{}", open, flat, close));
+            return;
+        }
+        
+        let padding = "  ".repeat(indent + 1);
+        out.push(open);
+        out.push('\n');
+        for item in rendered {
+            out.push_str(&format!("{}{},\n", padding, item));
+        }
+        out.push_str(&"  ".repeat(indent));
+        out.push(close);
+    }
+}
+
+/// Line width and element count after which `FluxValue::pretty` wraps or truncates
+const PRETTY_WIDTH: usize = 72;
+const PRETTY_MAX_ITEMS: usize = 10;
+
+impl From<f64> for FluxValue {
+    fn from(n: f64) -> Self {
+        FluxValue::Number(n)
+    }
+}
+
+impl From<bool> for FluxValue {
+    fn from(b: bool) -> Self {
+        FluxValue::Boolean(b)
+    }
+}
+
+impl From<&str> for FluxValue {
+    fn from(s: &str) -> Self {
+        FluxValue::String(s.to_string())
+    }
+}
+
+impl From<String> for FluxValue {
+    fn from(s: String) -> Self {
+        FluxValue::String(s)
+    }
+}
+
+impl<T: Into<FluxValue>> From<Vec<T>> for FluxValue {
+    fn from(items: Vec<T>) -> Self {
+        FluxValue::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl fmt::Display for FluxValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FluxValue::Number(n) => write!(f, "{}", n),
+            FluxValue::String(s) => write!(f, "{}", s),
+            FluxValue::Boolean(b) => write!(f, "{}", b),
+            FluxValue::Object(fields) => {
+                let mut fields: Vec<_> = fields.iter().collect();
+                fields.sort_by(|a, b| a.0.cmp(b.0));
+                let fields: Vec<String> = fields.iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+            FluxValue::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            FluxValue::Task(id) => write!(f, "<task {}>", id),
+            FluxValue::Channel(id) => write!(f, "<channel {}>", id),
+            FluxValue::Instance(handle) => write!(f, "<instance #{}>", handle),
+            FluxValue::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl Default for TemporalManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TemporalManager {
+    pub fn new() -> Self {
+        Self {
+            timelines: HashMap::new(),
+            watchers: HashMap::new(),
+            checkpoints: HashMap::new(),
+            snapshots: Vec::new(),
+            current_time: 0,
+            domains: HashMap::new(),
+            var_domains: HashMap::new(),
+        }
+    }
+    
+    pub fn create_temporal_var(&mut self, name: String, initial_value: FluxValue) {
+        self.var_domains.remove(&name);
+        let timeline = vec![(self.current_time, TimelineValue::share(&initial_value, None))];
+        self.timelines.insert(name, timeline);
+    }
+    
+    /// Declares a temporal variable recording against clock `domain`
+    pub fn create_temporal_var_in(&mut self, name: String, initial_value: FluxValue, domain: &str) -> Result<(), String> {
+        let time = *self.domains.get(domain)
+            .ok_or_else(|| format!("Unknown clock domain '{}' for temporal variable '{}'", domain, name))?;
+        self.var_domains.insert(name.clone(), domain.to_string());
+        self.timelines.insert(name, vec![(time, TimelineValue::share(&initial_value, None))]);
+        Ok(())
+    }
+    
+    /// Declares a clock domain starting at 0; declaring one again keeps
+    /// its clock
+    pub fn add_domain(&mut self, name: &str) {
+        self.domains.entry(name.to_string()).or_insert(0);
+    }
+    
+    pub fn has_domain(&self, name: &str) -> bool {
+        self.domains.contains_key(name)
+    }
+    
+    /// Where `domain`'s clock is
+    pub fn domain_time(&self, domain: &str) -> Option<usize> {
+        self.domains.get(domain).copied()
+    }
+    
+    /// The domain a temporal variable records against, or `None` for the
+    /// default clock
+    pub fn domain_of(&self, name: &str) -> Option<&str> {
+        self.var_domains.get(name).map(String::as_str)
+    }
+    
+    /// The time on the clock `name` records against
+    fn time_of(&self, name: &str) -> usize {
+        self.domain_of(name)
+            .and_then(|domain| self.domain_time(domain))
+            .unwrap_or(self.current_time)
+    }
+    
+    /// Ticks the clock `name` records against, as each update does
+    pub fn advance_clock_of(&mut self, name: &str) {
+        match self.var_domains.get(name).and_then(|domain| self.domains.get_mut(domain)) {
+            Some(time) => *time += 1,
+            None => self.advance_time(),
+        }
+    }
+    
+    fn read_clocks(&self) -> ClockReading {
+        ClockReading { time: self.current_time, domains: self.domains.clone() }
+    }
+    
+    pub fn update_temporal_var(&mut self, name: &str, value: FluxValue) -> Result<(), String> {
+        let now = self.time_of(name);
+        if let Some(timeline) = self.timelines.get_mut(name) {
+            let version = TimelineValue::share(&value, timeline.last().map(|(_, previous)| previous));
+            timeline.push((now, version));
+            Ok(())
+        } else {
+            Err(format!("Temporal variable '{}' not found", name))
+        }
+    }
+    
+    /// Looks up a value by absolute timestamp on the variable's clock, or
+    /// for negative timestamps by stepping back that many updates from the
+    /// current value
+    pub fn get_at_time(&self, name: &str, timestamp: i64) -> Option<FluxValue> {
+        let timeline = self.timelines.get(name)?;
+        if timestamp < 0 {
+            let steps = timestamp.unsigned_abs() as usize;
+            return timeline.len()
+                .checked_sub(steps + 1)
+                .map(|index| timeline[index].1.to_value());
+        }
+        
+        // Find the latest value at or before the requested timestamp
+        let timestamp = timestamp as usize;
+        timeline.iter()
+            .rev()
+            .find(|(time, _)| *time <= timestamp)
+            .map(|(_, value)| value.to_value())
+    }
+    
+    pub fn current_value(&self, name: &str) -> Option<FluxValue> {
+        self.timelines.get(name)?.last().map(|(_, value)| value.to_value())
+    }
+    
+    /// Every value the variable has held, oldest first
+    pub fn history(&self, name: &str) -> Option<Vec<FluxValue>> {
+        self.timelines.get(name)
+            .map(|timeline| timeline.iter().map(|(_, value)| value.to_value()).collect())
+    }
+    
+    /// Renders a timeline oldest to newest; past `limit` values only the
+    /// first and the most recent ones are kept, e.g. `1 → ... → 4 → 5 (6 values)`
+    pub fn render_timeline(&self, name: &str, limit: usize) -> Option<String> {
+        let timeline = self.timelines.get(name)?;
+        let values: Vec<String> = timeline.iter().map(|(_, value)| value.to_value().to_string()).collect();
+        let shown = if values.len() > limit.max(2) {
+            let recent = &values[values.len() - (limit.max(2) - 1)..];
+            format!("{} → ... → {}", values[0], recent.join(" → "))
+        } else {
+            values.join(" → ")
+        };
+        Some(format!("{} ({} values)", shown, values.len()))
+    }
+    
+    /// Heap handles referred to by any timeline or snapshot, for the GC's
+    /// roots. Versions sharing an element trace it once.
+    pub fn handles(&self) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        let mut handles = Vec::new();
+        for (_, version) in self.timelines.values().flatten() {
+            version.trace(&mut seen, &mut handles);
+        }
+        for value in self.snapshots.iter().flat_map(|snapshot| snapshot.plain.values()) {
+            value.trace(&mut handles);
+        }
+        handles
+    }
+    
+    /// Distinct version nodes across all timelines; shared ones count once
+    pub fn stored_nodes(&self) -> usize {
+        fn count(node: &Arc<TimelineValue>, seen: &mut HashSet<*const TimelineValue>) {
+            if !seen.insert(Arc::as_ptr(node)) {
+                return;
+            }
+            match node.as_ref() {
+                TimelineValue::Scalar(_) => {}
+                TimelineValue::Array(items) => items.iter().for_each(|item| count(item, seen)),
+                TimelineValue::Object(fields) => fields.values().for_each(|field| count(field, seen)),
+            }
+        }
+        
+        let mut seen = HashSet::new();
+        for (_, version) in self.timelines.values().flatten() {
+            count(version, &mut seen);
+        }
+        seen.len()
+    }
+    
+    /// Number of updates since the variable was declared
+    pub fn changes(&self, name: &str) -> Option<usize> {
+        self.timelines.get(name).map(|timeline| timeline.len() - 1)
+    }
+    
+    /// Registers a block to run after each update to a temporal variable
+    pub fn add_watcher(&mut self, name: &str, body: Vec<ASTNode>) -> Result<(), String> {
+        if !self.timelines.contains_key(name) {
+            return Err(format!("Cannot watch '{}': it is not a temporal variable", name));
+        }
+        self.watchers.entry(name.to_string()).or_default().push(body);
+        Ok(())
+    }
+    
+    /// Watcher bodies for a variable, in registration order
+    pub fn watchers(&self, name: &str) -> Vec<Vec<ASTNode>> {
+        self.watchers.get(name).cloned().unwrap_or_default()
+    }
+    
+    pub fn has_timeline(&self, name: &str) -> bool {
+        self.timelines.contains_key(name)
+    }
+    
+    pub fn advance_time(&mut self) {
+        self.current_time += 1;
+    }
+    
+    pub fn current_time(&self) -> usize {
+        self.current_time
+    }
+    
+    /// Each recorded update as (time, value), oldest first
+    pub fn timeline(&self, name: &str) -> Option<Vec<(usize, FluxValue)>> {
+        self.timelines.get(name)
+            .map(|timeline| timeline.iter().map(|(time, value)| (*time, value.to_value())).collect())
+    }
+    
+    /// Steps the default clock back to `time`, forgetting every update,
+    /// checkpoint and snapshot made after it. Unlike `rollback`, the undone
+    /// history is gone. Variables on clock domains keep theirs. Returns the
+    /// variables declared after `time`, whose timelines are dropped entirely.
+    pub fn rewind_to(&mut self, time: usize) -> Result<Vec<String>, String> {
+        if time > self.current_time {
+            return Err(format!("Time {} is in the future; the clock is at {}", time, self.current_time));
+        }
+        
+        let mut dropped = Vec::new();
+        let var_domains = &self.var_domains;
+        self.timelines.retain(|name, timeline| {
+            if var_domains.contains_key(name) {
+                return true;
+            }
+            timeline.retain(|(updated, _)| *updated <= time);
+            if timeline.is_empty() {
+                dropped.push(name.clone());
+            }
+            !timeline.is_empty()
+        });
+        for name in &dropped {
+            self.watchers.remove(name);
+        }
+        self.checkpoints.retain(|_, checkpoint| checkpoint.time <= time);
+        self.snapshots.retain(|snapshot| snapshot.clocks.time <= time);
+        self.current_time = time;
+        
+        dropped.sort();
+        Ok(dropped)
+    }
+    
+    /// Names the current moment on every clock; reusing a label moves it
+    /// forward. Returns the default clock's time.
+    pub fn checkpoint(&mut self, label: &str) -> usize {
+        self.checkpoints.insert(label.to_string(), self.read_clocks());
+        self.current_time
+    }
+    
+    /// The default clock's time at a checkpoint
+    pub fn checkpoint_time(&self, label: &str) -> Option<usize> {
+        self.checkpoints.get(label).map(|checkpoint| checkpoint.time)
+    }
+    
+    /// The time at a checkpoint on the clock variable `name` records against
+    pub fn checkpoint_time_for(&self, label: &str, name: &str) -> Option<usize> {
+        let checkpoint = self.checkpoints.get(label)?;
+        Some(checkpoint.of(self.domain_of(name)).unwrap_or(0))
+    }
+    
+    /// Records the current moment, along with any plain variables the
+    /// caller wants restored too, and returns a handle for `rollback`
+    pub fn take_snapshot(&mut self, plain: HashMap<String, FluxValue>) -> usize {
+        self.snapshots.push(TemporalSnapshot { clocks: self.read_clocks(), plain });
+        self.snapshots.len() - 1
+    }
+    
+    /// Returns every timeline to its value at the snapshot, reading each
+    /// one's own clock. The restored values are recorded as one new update,
+    /// ticking every clock once, so history keeps what was undone. Yields
+    /// the names of the temporal variables that changed and the saved plain
+    /// variables.
+    pub fn rollback(&mut self, handle: usize) -> Result<(Vec<String>, HashMap<String, FluxValue>), String> {
+        let snapshot = self.snapshots.get(handle).cloned()
+            .ok_or_else(|| format!("Unknown snapshot #{}", handle))?;
+        
+        let mut names: Vec<String> = self.timelines.keys().cloned().collect();
+        names.sort();
+        
+        self.advance_time();
+        for time in self.domains.values_mut() {
+            *time += 1;
+        }
+        let mut restored = Vec::new();
+        for name in names {
+            // Variables declared after the snapshot, or on a domain declared
+            // after it, have nothing to return to
+            let Some(saved) = snapshot.clocks.of(self.domain_of(&name))
+                .and_then(|time| self.get_at_time(&name, time as i64))
+            else {
+                continue;
+            };
+            if self.current_value(&name).as_ref() != Some(&saved) {
+                self.update_temporal_var(&name, saved)?;
+                restored.push(name);
+            }
+        }
+        Ok((restored, snapshot.plain))
+    }
+    
+    pub fn freeze_variable(&mut self, name: &str) -> Result<(), String> {
+        // In a full implementation, this would mark the variable as frozen
+        // preventing further updates
+        if self.timelines.contains_key(name) {
+            Ok(())
+        } else {
+            Err(format!("Variable '{}' not found", name))
+        }
+    }
+}
+
+/// Pipeline Processor - Handles functional composition
+pub struct PipelineProcessor;
+
+impl PipelineProcessor {
+    pub fn process(expressions: &[ASTNode]) -> Result<ASTNode, String> {
+        if expressions.is_empty() {
+            return Err("Empty pipeline".to_string());
+        }
+        
+        let mut result = expressions[0].clone();
+        
+        for expr in &expressions[1..] {
+            // In a full implementation, this would properly chain function calls
+            // For now, we create a nested call structure
+            result = ASTNode::Call {
+                callee: Box::new(expr.clone()),
+                args: vec![result],
+            };
+        }
+        
+        Ok(result)
+    }
+}
+
+/// Advanced Pattern Matcher
+pub struct PatternMatcher;
+
+impl PatternMatcher {
+    pub fn compile_match(expr: &ASTNode, cases: &[(ASTNode, Vec<ASTNode>)]) -> Result<ASTNode, String> {
+        // Convert match expression to if-else chain
+        if cases.is_empty() {
+            return Err("Match expression must have at least one case".to_string());
+        }
+        
+        let mut result = None;
+        
+        for (pattern, body) in cases.iter().rev() {
+            let condition = Self::condition(expr, pattern);
+            
+            if let Some(else_branch) = result {
+                result = Some(ASTNode::If {
+                    condition: Box::new(condition),
+                    then_branch: body.clone(),
+                    else_ifs: Vec::new(),
+                    else_branch: Some(vec![else_branch]),
+                });
+            } else {
+                result = Some(ASTNode::If {
+                    condition: Box::new(condition),
+                    then_branch: body.clone(),
+                    else_ifs: Vec::new(),
+                    else_branch: None,
+                });
+            }
+        }
+        
+        result.ok_or_else(|| "Failed to compile match expression".to_string())
+    }
+    
+    /// Binds the subject of a `match` while its range and multiple-value
+    /// patterns are tested; `$` cannot start an identifier in source
+    pub const SUBJECT: &'static str = "$match";
+    
+    /// The comparisons `pattern` stands for: `default` always holds, a range
+    /// becomes `subject >= start && subject <= end`, alternatives are joined
+    /// with `||` and any other pattern is compared with `==`
+    pub fn condition(subject: &ASTNode, pattern: &ASTNode) -> ASTNode {
+        let binary = |left: ASTNode, operator: &str, right: ASTNode| ASTNode::Binary {
+            left: Box::new(left),
+            operator: operator.to_string(),
+            right: Box::new(right),
+        };
+        match pattern {
+            ASTNode::Identifier(name) if name == "default" => ASTNode::Boolean(true),
+            ASTNode::Range { start, end } => binary(
+                binary(subject.clone(), ">=", start.as_ref().clone()),
+                "&&",
+                binary(subject.clone(), "<=", end.as_ref().clone()),
+            ),
+            ASTNode::Alternatives(patterns) => patterns.iter()
+                .map(|pattern| Self::condition(subject, pattern))
+                .reduce(|chain, condition| binary(chain, "||", condition))
+                .unwrap_or(ASTNode::Boolean(false)),
+            _ => binary(subject.clone(), "==", pattern.clone()),
+        }
+    }
+    
+    /// Warnings for cases whose literal values an earlier case already
+    /// matches, in part or entirely, and for empty ranges
+    pub fn overlaps(expr: &ASTNode, cases: &[(ASTNode, Vec<ASTNode>)]) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut earlier: Vec<(&ASTNode, PatternBounds)> = Vec::new();
+        for (pattern, _) in cases {
+            let alternatives = match pattern {
+                ASTNode::Alternatives(patterns) => patterns.iter().collect(),
+                _ => vec![pattern],
+            };
+            // Alternatives are tried in order too, so each is checked against
+            // the ones before it in its own case as well
+            for alternative in alternatives {
+                let Some(bound) = PatternBounds::of(alternative) else {
+                    continue;
+                };
+                if bound.is_empty() {
+                    warnings.push(format!("range `{}` in `match {}` is empty, so it never matches", alternative, expr));
+                    continue;
+                }
+                let covering = earlier.iter().find(|(_, other)| other.contains(&bound));
+                match covering.or_else(|| earlier.iter().find(|(_, other)| other.intersects(&bound))) {
+                    Some((other, covering)) if covering.contains(&bound) => warnings.push(format!(
+                        "case `{}` of `match {}` never matches: case `{}` comes first and covers it",
+                        alternative, expr, other)),
+                    Some((other, _)) => warnings.push(format!(
+                        "case `{}` of `match {}` overlaps case `{}`, which comes first",
+                        alternative, expr, other)),
+                    None => {}
+                }
+                earlier.push((alternative, bound));
+            }
+        }
+        warnings
+    }
+}
+
+/// The literal values a pattern alternative matches, as an inclusive range
+#[derive(Debug, Clone, PartialEq)]
+enum PatternBounds {
+    Numbers(f64, f64),
+    String(String),
+}
+
+impl PatternBounds {
+    fn of(pattern: &ASTNode) -> Option<Self> {
+        match pattern {
+            ASTNode::Number(n) => Some(PatternBounds::Numbers(*n, *n)),
+            ASTNode::String(s) => Some(PatternBounds::String(s.clone())),
+            ASTNode::Range { start, end } => match (start.as_ref(), end.as_ref()) {
+                (ASTNode::Number(start), ASTNode::Number(end)) => Some(PatternBounds::Numbers(*start, *end)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    
+    fn is_empty(&self) -> bool {
+        matches!(self, PatternBounds::Numbers(start, end) if start > end)
+    }
+    
+    fn intersects(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PatternBounds::Numbers(a, b), PatternBounds::Numbers(c, d)) => a <= d && c <= b,
+            _ => self == other,
+        }
+    }
+    
+    fn contains(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PatternBounds::Numbers(a, b), PatternBounds::Numbers(c, d)) => a <= c && d <= b,
+            _ => self == other,
+        }
+    }
+}
+
+/// A class instance living on the `FluxRuntime` heap
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeapObject {
+    pub class: String,
+    pub fields: HashMap<String, FluxValue>,
+    /// Set by `freeze`; a frozen instance rejects every field update
+    pub frozen: bool,
+}
+
+impl HeapObject {
+    /// Rough footprint used to decide when to collect
+    fn size(&self) -> usize {
+        std::mem::size_of::<HeapObject>()
+            + self.fields.len() * std::mem::size_of::<(String, FluxValue)>()
+    }
+}
+
+/// Memory Management for Generated Code
+pub struct FluxRuntime {
+    heap: Vec<Option<HeapObject>>,
+    free: Vec<usize>,
+    gc_threshold: usize,
+    allocated: usize,
+    /// Most bytes the heap may hold, for sandboxed interpreters
+    heap_limit: Option<usize>,
+    scheduler: Arc<TaskScheduler>,
+}
+
+impl Default for FluxRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FluxRuntime {
+    pub fn new() -> Self {
+        Self {
+            heap: Vec::new(),
+            free: Vec::new(),
+            gc_threshold: 512 * 1024, // GC trigger at 512KB
+            allocated: 0,
+            heap_limit: None,
+            scheduler: Arc::new(TaskScheduler::new(TaskScheduler::default_workers())),
+        }
+    }
+    
+    /// Scheduler shared by every task spawned through this runtime
+    pub fn scheduler(&self) -> Arc<TaskScheduler> {
+        Arc::clone(&self.scheduler)
+    }
+    
+    pub fn spawn<F>(&self, job: F) -> usize
+    where
+        F: FnOnce() -> Result<FluxValue, Interrupt> + Send + 'static,
+    {
+        self.scheduler.spawn(job)
+    }
+    
+    pub fn join(&self, task: usize) -> Result<FluxValue, Interrupt> {
+        self.scheduler.join(task)
+    }
+    
+    /// Places an object on the heap, reusing a freed slot when possible,
+    /// and returns its handle
+    pub fn allocate(&mut self, object: HeapObject) -> usize {
+        self.allocated += object.size();
+        match self.free.pop() {
+            Some(handle) => {
+                self.heap[handle] = Some(object);
+                handle
+            }
+            None => {
+                self.heap.push(Some(object));
+                self.heap.len() - 1
+            }
+        }
+    }
+    
+    pub fn get(&self, handle: usize) -> Option<&HeapObject> {
+        self.heap.get(handle).and_then(Option::as_ref)
+    }
+    
+    pub fn set_field(&mut self, handle: usize, field: &str, value: FluxValue) -> Result<(), String> {
+        let object = self.heap.get_mut(handle)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| format!("Dangling object reference #{}", handle))?;
+        if object.frozen {
+            return Err(format!("Cannot set field '{}' on a frozen instance of '{}'", field, object.class));
+        }
+        if object.fields.insert(field.to_string(), value).is_none() {
+            self.allocated += std::mem::size_of::<(String, FluxValue)>();
+        }
+        Ok(())
+    }
+    
+    /// Appends `text` to a string field without copying what's already
+    /// there, creating the field if needed
+    pub fn append_to_field(&mut self, handle: usize, field: &str, text: &str) -> Result<(), String> {
+        let object = self.heap.get_mut(handle)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| format!("Dangling object reference #{}", handle))?;
+        if object.frozen {
+            return Err(format!("Cannot set field '{}' on a frozen instance of '{}'", field, object.class));
+        }
+        match object.fields.entry(field.to_string()).or_insert_with(|| FluxValue::String(String::new())) {
+            FluxValue::String(existing) => existing.push_str(text),
+            other => return Err(format!("Cannot append to field '{}' holding {}", field, other.type_name())),
+        }
+        self.allocated += text.len();
+        Ok(())
+    }
+    
+    /// Freezes every instance reachable from `value`, so neither it nor
+    /// anything it refers to can be updated afterwards
+    pub fn freeze(&mut self, value: &FluxValue) {
+        match value {
+            FluxValue::Instance(handle) => {
+                let Some(object) = self.heap.get_mut(*handle).and_then(Option::as_mut) else {
+                    return;
+                };
+                // Already frozen instances also end cycles
+                if object.frozen {
+                    return;
+                }
+                object.frozen = true;
+                let fields: Vec<FluxValue> = object.fields.values().cloned().collect();
+                for field in &fields {
+                    self.freeze(field);
+                }
+            }
+            FluxValue::Array(items) => items.iter().for_each(|item| self.freeze(item)),
+            FluxValue::Object(fields) => fields.values().for_each(|field| self.freeze(field)),
+            _ => {}
+        }
+    }
+    
+    /// Whether nothing reachable from `value` can be updated. Numbers,
+    /// strings and the like are always frozen; channels never are.
+    pub fn is_frozen(&self, value: &FluxValue) -> bool {
+        match value {
+            FluxValue::Instance(handle) => self.get(*handle).is_none_or(|object| object.frozen),
+            FluxValue::Array(items) => items.iter().all(|item| self.is_frozen(item)),
+            FluxValue::Object(fields) => fields.values().all(|field| self.is_frozen(field)),
+            FluxValue::Channel(_) => false,
+            _ => true,
+        }
+    }
+    
+    /// Objects currently on the heap
+    pub fn live_objects(&self) -> usize {
+        self.heap.len() - self.free.len()
+    }
+    
+    pub fn should_collect(&self) -> bool {
+        self.allocated > self.gc_threshold
+    }
+    
+    pub fn set_gc_threshold(&mut self, bytes: usize) {
+        self.gc_threshold = bytes;
+    }
+    
+    /// Estimated bytes held by live and not yet collected objects
+    pub fn heap_bytes(&self) -> usize {
+        self.allocated
+    }
+    
+    pub fn set_heap_limit(&mut self, bytes: Option<usize>) {
+        self.heap_limit = bytes;
+    }
+    
+    /// The heap limit, once the heap has grown past it
+    pub fn exceeded_heap_limit(&self) -> Option<usize> {
+        self.heap_limit.filter(|&limit| self.allocated > limit)
+    }
+    
+    /// Mark-and-sweep: frees every object not reachable from `roots`, then
+    /// lets the heap grow to twice the surviving size before the next run.
+    /// Returns how many objects were freed.
+    pub fn garbage_collect<'v>(&mut self, roots: impl IntoIterator<Item = &'v FluxValue>) -> usize {
+        let mut marked = vec![false; self.heap.len()];
+        let mut pending = Vec::new();
+        for root in roots {
+            root.trace(&mut pending);
+        }
+        
+        while let Some(handle) = pending.pop() {
+            if marked.get(handle).copied() != Some(false) {
+                continue;
+            }
+            marked[handle] = true;
+            if let Some(object) = &self.heap[handle] {
+                for value in object.fields.values() {
+                    value.trace(&mut pending);
+                }
+            }
+        }
+        
+        let mut freed = 0;
+        self.allocated = 0;
+        for (handle, slot) in self.heap.iter_mut().enumerate() {
+            match slot {
+                Some(_) if !marked[handle] => {
+                    *slot = None;
+                    self.free.push(handle);
+                    freed += 1;
+                }
+                Some(object) => self.allocated += object.size(),
+                None => {}
+            }
+        }
+        
+        self.gc_threshold = self.gc_threshold.max(self.allocated * 2);
+        freed
+    }
+}
+
+/// A task's body; it fails with how it stopped, so an `exit` inside it
+/// reaches whoever joins it
+type TaskJob = Box<dyn FnOnce() -> Result<FluxValue, Interrupt> + Send>;
+
+/// Unbounded FIFO shared by the tasks holding its handle
+#[derive(Default)]
+struct FluxChannel {
+    queue: Mutex<VecDeque<FluxValue>>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    queue: VecDeque<usize>,
+    pending: HashMap<usize, TaskJob>,
+    running: HashSet<usize>,
+    finished: HashMap<usize, Result<FluxValue, Interrupt>>,
+    next_task: usize,
+    shutdown: bool,
+}
+
+/// Small fixed-size thread pool running `spawn`ed tasks. Workers start on
+/// the first spawn; joining a task nobody has picked up yet runs it on the
+/// joining thread, so tasks that join each other can't starve the pool.
+pub struct TaskScheduler {
+    state: Arc<(Mutex<SchedulerState>, Condvar)>,
+    channels: Mutex<Vec<Arc<FluxChannel>>>,
+    workers: usize,
+    started: Mutex<bool>,
+}
+
+impl TaskScheduler {
+    pub fn new(workers: usize) -> Self {
+        Self {
+            state: Arc::new((Mutex::new(SchedulerState::default()), Condvar::new())),
+            channels: Mutex::new(Vec::new()),
+            workers: workers.max(1),
+            started: Mutex::new(false),
+        }
+    }
+    
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+    
+    pub fn default_workers() -> usize {
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+    }
+    
+    pub fn spawn<F>(&self, job: F) -> usize
+    where
+        F: FnOnce() -> Result<FluxValue, Interrupt> + Send + 'static,
+    {
+        self.start_workers();
+        
+        let (lock, ready) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        let task = state.next_task;
+        state.next_task += 1;
+        state.pending.insert(task, Box::new(job));
+        state.queue.push_back(task);
+        ready.notify_all();
+        task
+    }
+    
+    /// Waits for a task and takes its result; each task can be joined once
+    pub fn join(&self, task: usize) -> Result<FluxValue, Interrupt> {
+        let (lock, ready) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        
+        if let Some(job) = state.pending.remove(&task) {
+            drop(state);
+            return job();
+        }
+        
+        loop {
+            if let Some(result) = state.finished.remove(&task) {
+                return result;
+            }
+            if !state.running.contains(&task) {
+                return Err(Interrupt::Error(format!("Task {} does not exist or was already joined", task)));
+            }
+            state = ready.wait(state).unwrap();
+        }
+    }
+    
+    pub fn channel(&self) -> usize {
+        let mut channels = self.channels.lock().unwrap();
+        channels.push(Arc::new(FluxChannel::default()));
+        channels.len() - 1
+    }
+    
+    pub fn send(&self, channel: usize, value: FluxValue) -> Result<(), String> {
+        let channel = self.find_channel(channel)?;
+        channel.queue.lock().unwrap().push_back(value);
+        channel.ready.notify_one();
+        Ok(())
+    }
+    
+    /// Blocks until a value is available
+    pub fn recv(&self, channel: usize) -> Result<FluxValue, String> {
+        let channel = self.find_channel(channel)?;
+        let mut queue = channel.queue.lock().unwrap();
+        loop {
+            if let Some(value) = queue.pop_front() {
+                return Ok(value);
+            }
+            queue = channel.ready.wait(queue).unwrap();
+        }
+    }
+    
+    /// Values held by unjoined task results and channel queues, or `None`
+    /// while any task is still queued or running
+    pub fn retained_values(&self) -> Option<Vec<FluxValue>> {
+        let state = self.state.0.lock().unwrap();
+        if !state.pending.is_empty() || !state.running.is_empty() {
+            return None;
+        }
+        
+        let mut values: Vec<FluxValue> = state.finished.values()
+            .filter_map(|result| result.as_ref().ok().cloned())
+            .collect();
+        for channel in self.channels.lock().unwrap().iter() {
+            values.extend(channel.queue.lock().unwrap().iter().cloned());
+        }
+        Some(values)
+    }
+    
+    fn find_channel(&self, channel: usize) -> Result<Arc<FluxChannel>, String> {
+        self.channels.lock().unwrap()
+            .get(channel)
+            .cloned()
+            .ok_or_else(|| format!("Channel {} does not exist", channel))
+    }
+    
+    fn start_workers(&self) {
+        let mut started = self.started.lock().unwrap();
+        if *started {
+            return;
+        }
+        *started = true;
+        
+        for _ in 0..self.workers {
+            let state = Arc::clone(&self.state);
+            thread::spawn(move || Self::work(&state));
+        }
+    }
+    
+    fn work(state: &(Mutex<SchedulerState>, Condvar)) {
+        let (lock, ready) = state;
+        loop {
+            let mut guard = lock.lock().unwrap();
+            while guard.queue.is_empty() && !guard.shutdown {
+                guard = ready.wait(guard).unwrap();
+            }
+            let Some(task) = guard.queue.pop_front() else {
+                return;
+            };
+            // A joiner may already have taken the job to run it inline
+            let Some(job) = guard.pending.remove(&task) else {
+                continue;
+            };
+            guard.running.insert(task);
+            drop(guard);
+            
+            let result = job();
+            
+            let mut guard = lock.lock().unwrap();
+            guard.running.remove(&task);
+            guard.finished.insert(task, result);
+            ready.notify_all();
+        }
+    }
+}
+
+impl Drop for TaskScheduler {
+    fn drop(&mut self) {
+        // Workers drain whatever is still queued, then exit
+        let (lock, ready) = &*self.state;
+        lock.lock().unwrap().shutdown = true;
+        ready.notify_all();
+    }
+}
+
+/// Most recent values the REPL shows when echoing a timeline
+const REPL_TIMELINE_LIMIT: usize = 8;
+
+/// Interactive REPL for Flux Language
+pub struct FluxRepl {
+    compiler: FluxCompiler,
+    /// Checks each line against what earlier lines declared, as `Engine`
+    /// does for its snippets
+    analyzer: SemanticAnalyzer,
+    interpreter: Interpreter,
+    #[allow(dead_code)] // Will back execution once the REPL runs compiled code
+    runtime: FluxRuntime,
+    history: Vec<String>,
+    /// Files reloaded before each line when they change, with the source
+    /// last loaded from each
+    watched: Vec<(String, String)>,
+}
+
+impl Default for FluxRepl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FluxRepl {
+    pub fn new() -> Self {
+        Self {
+            compiler: FluxCompiler::new(false),
+            analyzer: SemanticAnalyzer::new(),
+            interpreter: Interpreter::new(),
+            runtime: FluxRuntime::new(),
+            history: Vec::new(),
+            watched: Vec::new(),
+        }
+    }
+    
+    pub fn run(&mut self) {
+        println!("Flux Language REPL v1.0");
+        println!("Type 'exit' to quit, 'help' for commands");
+        println!();
+        
+        loop {
+            print!("flux> ");
+            std::io::Write::flush(&mut std::io::stdout()).unwrap();
+            
+            let mut input = String::new();
+            if std::io::stdin().read_line(&mut input).unwrap() == 0 {
+                println!();
+                break;
+            }
+            let input = input.trim();
+            
+            for reloaded in self.reload_watched() {
+                match reloaded {
+                    Ok(message) => println!("{}", message),
+                    Err(error) => println!("✗ Error: {}", error),
+                }
+            }
+            
+            match input {
+                "exit" | "quit" => {
+                    println!("Goodbye!");
+                    break;
+                }
+                "help" => {
+                    self.show_help();
+                }
+                "history" => {
+                    self.show_history();
+                }
+                "clear" => {
+                    print!("\x1B[2J\x1B[1;1H"); // Clear screen
+                }
+                "" => continue,
+                _ => {
+                    self.execute_command(input);
+                    if let Some(code) = self.interpreter.exit_code() {
+                        std::process::exit(code);
+                    }
+                }
+            }
+        }
+    }
+    
+    fn execute_command(&mut self, input: &str) {
+        self.history.push(input.to_string());
+        
+        match self.evaluate(input) {
+            Ok(Some(echo)) => println!("{}", echo),
+            Ok(None) => {}
+            Err(error) => println!("✗ Error: {}", error),
+        }
+    }
+    
+    /// Runs one line against the session's state and renders its value;
+    /// statements that produce nothing (declarations, loops) echo nothing
+    pub fn evaluate(&mut self, input: &str) -> Result<Option<String>, String> {
+        if let Some(command) = input.strip_prefix(':') {
+            return self.debugger_command(command).map(Some);
+        }
+        
+        let mut ast = self.compiler.parse(input)?;
+        let mut analyzer = self.analyzer.clone();
+        analyzer.release_redeclared(&ast);
+        self.compiler.analyze_with(&mut ast, &mut analyzer)?;
+        self.analyzer = analyzer;
+        let value = self.interpreter.run(&ast)?;
+        
+        // A bare temporal variable also shows how it got there
+        if let ASTNode::Program(statements) = &ast
+            && let [ASTNode::Identifier(name)] = statements.as_slice()
+            && let Some(timeline) = self.interpreter.temporal_manager().render_timeline(name, REPL_TIMELINE_LIMIT)
+        {
+            return Ok(Some(format!("{}\n  timeline: {}", value.pretty(), timeline)));
+        }
+        
+        Ok((value != FluxValue::Null).then(|| value.pretty()))
+    }
+    
+    /// Loads the functions and classes of the file at `path` into the
+    /// session and reloads them whenever the file changes; top-level code
+    /// in the file never runs
+    pub fn watch(&mut self, path: &str) -> Result<String, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read file {}: {}", path, e))?;
+        let names = self.load_declarations(path, &source)?;
+        self.watched.retain(|(watched, _)| watched != path);
+        self.watched.push((path.to_string(), source));
+        Ok(if names.is_empty() {
+            format!("Watching {}, which declares no functions or classes", path)
+        } else {
+            format!("Watching {}: loaded {}", path, names.join(", "))
+        })
+    }
+    
+    /// Reloads each watched file whose contents changed since it was last
+    /// loaded. A file that no longer parses keeps its old definitions and
+    /// isn't retried until it changes again.
+    pub fn reload_watched(&mut self) -> Vec<Result<String, String>> {
+        let mut results = Vec::new();
+        for index in 0..self.watched.len() {
+            let path = self.watched[index].0.clone();
+            let source = match fs::read_to_string(&path) {
+                Ok(source) if source != self.watched[index].1 => source,
+                Ok(_) => continue,
+                Err(e) => {
+                    results.push(Err(format!("Failed to read file {}: {}", path, e)));
+                    continue;
+                }
+            };
+            results.push(self.load_declarations(&path, &source)
+                .map(|names| format!("Reloaded {} from {}", names.join(", "), path)));
+            self.watched[index].1 = source;
+        }
+        results
+    }
+    
+    fn load_declarations(&mut self, path: &str, source: &str) -> Result<Vec<String>, String> {
+        let ast = self.compiler.parse(source).map_err(|e| format!("{}: {}", path, e))?;
+        // Only the declarations are checked, since nothing else runs
+        let ASTNode::Program(statements) = ast else {
+            return Ok(Vec::new());
+        };
+        let mut declarations = ASTNode::Program(statements.into_iter()
+            .filter(|stmt| matches!(stmt, ASTNode::FunctionDecl { .. } | ASTNode::ClassDecl { .. }))
+            .collect());
+        let mut analyzer = self.analyzer.clone();
+        self.compiler.analyze_with(&mut declarations, &mut analyzer).map_err(|e| format!("{}: {}", path, e))?;
+        self.analyzer = analyzer;
+        self.interpreter.reload(&declarations).map_err(|e| format!("{}: {}", path, e))
+    }
+    
+    /// `:watch file` starts watching a file, `:watch off` stops watching
+    /// them all and a bare `:watch` lists them
+    fn watch_command(&mut self, args: &str) -> Result<String, String> {
+        match args {
+            "" if self.watched.is_empty() => Ok("Not watching any files".to_string()),
+            "" => Ok(format!("Watching {}", self.watched.iter()
+                .map(|(path, _)| path.as_str())
+                .collect::<Vec<_>>()
+                .join(", "))),
+            "off" => {
+                self.watched.clear();
+                Ok("Stopped watching".to_string())
+            }
+            path => self.watch(path),
+        }
+    }
+    
+    /// `:limit steps n`, `:limit heap bytes`, `:limit depth calls`,
+    /// `:limit timeout ms` or `:limit off`; a bare `:limit` shows the
+    /// limits in force
+    fn limit_command(&mut self, args: &str) -> Result<String, String> {
+        let mut limits = self.interpreter.limits().clone();
+        match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [] => {}
+            ["off"] => {
+                self.interpreter.remove_limits();
+                return Ok("Limits removed".to_string());
+            }
+            [kind, amount] => {
+                let amount: u64 = amount.parse()
+                    .map_err(|_| format!("Expected a number after :limit {}, found '{}'", kind, amount))?;
+                match *kind {
+                    "steps" => limits.max_steps = Some(amount),
+                    "heap" => limits.max_heap_bytes = Some(amount as usize),
+                    "depth" => limits.max_call_depth = Some(amount as usize),
+                    "timeout" => limits.timeout = Some(Duration::from_millis(amount)),
+                    _ => return Err(format!("Unknown limit '{}'; try steps, heap, depth or timeout", kind)),
+                }
+                self.interpreter.set_limits(limits.clone());
+            }
+            _ => return Err("Usage: :limit [steps <n> | heap <bytes> | depth <calls> | timeout <ms> | off]".to_string()),
+        }
+        
+        let mut shown = Vec::new();
+        if let Some(steps) = limits.max_steps {
+            shown.push(format!("steps {}", steps));
+        }
+        if let Some(bytes) = limits.max_heap_bytes {
+            shown.push(format!("heap {} bytes", bytes));
+        }
+        if let Some(depth) = limits.max_call_depth {
+            shown.push(format!("depth {} calls", depth));
+        }
+        if let Some(timeout) = limits.timeout {
+            shown.push(format!("timeout {}ms", timeout.as_millis()));
+        }
+        Ok(if shown.is_empty() { "No limits".to_string() } else { format!("Limits: {}", shown.join(", ")) })
+    }
+    
+    /// Temporal debugger: `:timeline x`, `:rewind n` and `:at t expr`
+    fn debugger_command(&mut self, command: &str) -> Result<String, String> {
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        let args = args.trim();
+        
+        match name {
+            "timeline" => {
+                let timeline = self.interpreter.temporal_manager().timeline(args)
+                    .ok_or_else(|| format!("'{}' is not a temporal variable", args))?;
+                let width = timeline.iter()
+                    .map(|(time, _)| time.to_string().len())
+                    .max()
+                    .unwrap_or(0)
+                    .max("time".len());
+                let mut table = format!("{:>width$}  value", "time");
+                for (time, value) in timeline {
+                    table.push_str(&format!("\n{:>width$}  {}", time, value.pretty()));
+                }
+                Ok(table)
+            }
+            "rewind" => {
+                let steps: usize = args.parse()
+                    .map_err(|_| format!("Expected a number of steps after :rewind, found '{}'", args))?;
+                let now = self.interpreter.temporal_manager().current_time();
+                let time = now.checked_sub(steps)
+                    .ok_or_else(|| format!("Cannot rewind {} steps: the clock is at {}", steps, now))?;
+                self.interpreter.rewind_to(time)?;
+                Ok(format!("Rewound to time {}", time))
+            }
+            "at" => {
+                let (time, expr) = args.split_once(' ')
+                    .ok_or_else(|| "Usage: :at <time> <expression>".to_string())?;
+                let time: usize = time.parse()
+                    .map_err(|_| format!("Expected a time after :at, found '{}'", time))?;
+                let ast = self.compiler.parse(expr)?;
+                Ok(self.interpreter.evaluate_at(time, &ast)?.pretty())
+            }
+            "limit" => self.limit_command(args),
+            "watch" => self.watch_command(args),
+            _ => Err(format!("Unknown command ':{}'; try :timeline, :rewind, :at, :limit or :watch", name)),
+        }
+    }
+    
+    fn show_help(&self) {
+        println!("Flux Language Commands:");
+        println!("  exit/quit     - Exit the REPL");
+        println!("  help          - Show this help");
+        println!("  history       - Show command history");
+        println!("  clear         - Clear screen");
+        println!("  :timeline x   - Show every update to temporal variable x");
+        println!("  :rewind n     - Step logical time back n ticks");
+        println!("  :at t expr    - Evaluate expr as of logical time t");
+        println!("  :limit steps n - Cap each line's steps (also heap <bytes>, depth <calls>, timeout <ms>, off)");
+        println!("  :watch file   - Load file's functions and classes, reloading them when it changes");
+        println!("  func f() {{ ... }} - Redefining f updates every caller, keeping variables");
+        println!();
+        println!("Language Features:");
+        println!("  let x = 10           - Immutable variable");
+        println!("  const y = 20         - Constant variable");
+        println!("  temporal let z = 5   - Temporal variable");
+        println!("  timeline domain net  - A separate clock; temporal(net) let p = 0 records against it");
+        println!("  on change z {{ ... }}  - Run a block after each update to z");
+        println!("  checkpoint(\"a\")      - Name this moment; z[\"a\"] reads z as it was then");
+        println!("  let s = snapshot()   - Save temporal values; rollback(s) restores them");
+        println!("  s[0] / 'a'           - Index a string / character literal");
+        println!("  x | func1 | func2    - Pipeline operations");
+        println!("  x | clamp(0, _, 9)   - `_` marks where the piped value goes");
+        println!("  [1, 2] ||> func1     - Map a stage over an array in parallel");
+        println!("  match x {{ ... }}      - Pattern matching (case 1..9 / case 1, 2)");
+        println!("  loop {{ ... break }}   - Repeat until break (also do {{ ... }} while c)");
+        println!("  new Foo(1, 2)        - Create an instance, running Foo's init");
+        println!("  new StringBuilder()  - Build a string with b.append(x); b.to_string()");
+        println!("  freeze p             - Make p and everything it refers to read-only");
+        println!("  macro m(a) {{ ... }}   - Parse-time macro");
+        println!("  comptime {{ ... }}     - Evaluate at compile time");
+        println!("  spawn {{ ... }}        - Run a block as a task; join(t) waits for it");
+        println!("  send(ch, v) / recv(ch) - Pass messages over a channel()");
+        println!("  exit(1) / panic(\"msg\") - Stop with an exit code or an error");
+        println!("  defer close(f)       - Run when the function returns or fails");
+        println!("  time() / random() / input() / read_file(p) - Outside inputs (flux run --record/--replay)");
+        println!("  typeof(x) / fields(p) / methods(p) - Inspect a value's type and class");
+        println!("  #pragma braces       - Use brace syntax");
+        println!("  #pragma indent       - Use indentation syntax");
+        println!("  #pragma numeric_format fixed 2 - Print numbers with 2 decimals");
+        println!("  #pragma precedence | above == - Bind pipelines tighter than ==");
+        println!("  #pragma keep_timeline x - Keep x's history when optimizing");
+        println!("  #pragma strict_const - Require const initializers to be compile-time constants");
+        println!("  #pragma checked_math - Fail on division by zero, NaN or infinity");
+        println!();
+    }
+    
+    fn show_history(&self) {
+        println!("Command History:");
+        for (i, cmd) in self.history.iter().enumerate() {
+            println!("  {}: {}", i + 1, cmd);
+        }
+        println!();
+    }
+}
+
+// ============================================================================
+// OPTIMIZATION PASSES
+// ============================================================================
+
+/// AST Optimizer - Performs compile-time optimizations
+pub struct ASTOptimizer;
+
+impl ASTOptimizer {
+    /// Replaces reads of folded constants with their values, so constant
+    /// folding can see through them. Parameters shadow constants of the same
+    /// name inside their function, and arguments that name a variable
+    /// rather than read it are left alone.
+    pub fn propagate_constants(ast: &mut ASTNode, constants: &HashMap<String, ASTNode>) {
+        if constants.is_empty() {
+            return;
+        }
+        
+        match ast {
+            ASTNode::Identifier(name) => {
+                if let Some(value) = constants.get(name) {
+                    *ast = value.clone();
+                }
+            }
+            
+            ASTNode::FunctionDecl { params, body, .. } => {
+                let mut visible = constants.clone();
+                for param in params.iter() {
+                    visible.remove(param);
+                }
+                for stmt in body {
+                    Self::propagate_constants(stmt, &visible);
+                }
+            }
+            
+            ASTNode::Call { callee, args } => {
+                let named = match callee.as_ref() {
+                    ASTNode::Identifier(name) if name == "keep_timeline" => args.len(),
+                    ASTNode::Identifier(name) if matches!(name.as_str(), "freeze" | "history" | "changes" | "value_at") => 1,
+                    _ => 0,
+                };
+                for arg in args.iter_mut().skip(named) {
+                    Self::propagate_constants(arg, constants);
+                }
+            }
+            
+            // Stages after the first name the functions to call
+            ASTNode::Pipeline(stages) => {
+                for (i, stage) in stages.iter_mut().enumerate() {
+                    let stage = match stage {
+                        ASTNode::ParallelStage(inner) => inner.as_mut(),
+                        stage => stage,
+                    };
+                    match stage {
+                        ASTNode::Identifier(_) if i > 0 => {}
+                        ASTNode::Call { args, .. } if i > 0 => {
+                            for arg in args {
+                                Self::propagate_constants(arg, constants);
+                            }
+                        }
+                        stage => Self::propagate_constants(stage, constants),
+                    }
+                }
+            }
+            
+            other => {
+                for child in other.children_mut() {
+                    Self::propagate_constants(child, constants);
+                }
+            }
+        }
+    }
+    
+    pub fn optimize(ast: &mut ASTNode) {
+        match ast {
+            ASTNode::Program(statements) | ASTNode::Block(statements) => {
+                for stmt in statements {
+                    Self::optimize(stmt);
+                }
+            }
+            
+            ASTNode::Binary { left, operator, right } => {
+                Self::optimize(left);
+                Self::optimize(right);
+                
+                // Constant folding
+                if let (ASTNode::Number(l), ASTNode::Number(r)) = (left.as_ref(), right.as_ref()) {
+                    let result = match operator.as_str() {
+                        "+" => *l + *r,
+                        "-" => *l - *r,
+                        "*" => *l * *r,
+                        "/" if *r != 0.0 => *l / *r,
+                        _ => return,
+                    };
+                    // Overflow is left for run time, which checked math reports
+                    if !result.is_finite() {
+                        return;
+                    }
+                    
+                    // Replace the entire binary operation with the computed result
+                    *ast = ASTNode::Number(result);
+                }
+            }
+            
+            ASTNode::Unary { operator, operand } => {
+                Self::optimize(operand);
+                
+                if let ASTNode::Number(n) = operand.as_ref() {
+                    let result = match operator.as_str() {
+                        "-" => -*n,
+                        _ => return,
+                    };
+                    
+                    *ast = ASTNode::Number(result);
+                }
+            }
+            
+            ASTNode::If { condition, then_branch, else_ifs, else_branch } => {
+                Self::optimize(condition);
+                
+                // Arms that can never run drop out of the chain, and an arm
+                // that always runs becomes the final else
+                for (else_condition, body) in else_ifs.iter_mut() {
+                    Self::optimize(else_condition);
+                    for stmt in body {
+                        Self::optimize(stmt);
+                    }
+                }
+                else_ifs.retain(|(else_condition, _)| !matches!(else_condition, ASTNode::Boolean(false)));
+                let always = else_ifs.iter()
+                    .position(|(else_condition, _)| matches!(else_condition, ASTNode::Boolean(true)));
+                if let Some(always) = always {
+                    let (_, body) = else_ifs.drain(always..).next().unwrap();
+                    *else_branch = Some(body);
+                }
+                
+                // Dead code elimination for constant conditions
+                if let ASTNode::Boolean(cond) = condition.as_ref() {
+                    if *cond {
+                        // Condition is always true, replace with then branch
+                        for stmt in then_branch {
+                            Self::optimize(stmt);
+                        }
+                    } else if let Some(else_stmts) = else_branch {
+                        // Condition is always false, replace with else branch
+                        for stmt in else_stmts {
+                            Self::optimize(stmt);
+                        }
+                    }
+                } else {
+                    // Optimize branches
+                    for stmt in then_branch {
+                        Self::optimize(stmt);
+                    }
+                    
+                    if let Some(else_stmts) = else_branch {
+                        for stmt in else_stmts {
+                            Self::optimize(stmt);
+                        }
+                    }
+                }
+            }
+            
+            other => {
+                for child in other.children_mut() {
+                    Self::optimize(child);
+                }
+            }
+        }
+    }
+}
+
+/// Demotes temporal variables whose history is never read to plain ones, so
+/// compiled code stops recording every assignment. A variable keeps its
+/// timeline if it's indexed with `x[t]`, passed to `history`, `changes` or
+/// `value_at`, watched with `on change`, or named by `keep_timeline`
+/// (`#pragma keep_timeline x`); `snapshot`/`rollback` keep every timeline.
+pub struct TimelineDemotion {
+    read: HashSet<String>,
+    keep_all: bool,
+}
+
+impl TimelineDemotion {
+    /// Rewrites the declarations in place and returns the demoted names
+    pub fn demote(ast: &mut ASTNode) -> Vec<String> {
+        let mut analysis = Self { read: HashSet::new(), keep_all: false };
+        analysis.collect(ast);
+        if analysis.keep_all {
+            return Vec::new();
+        }
+        
+        let mut demoted = Vec::new();
+        analysis.rewrite(ast, &mut demoted);
+        demoted
+    }
+    
+    fn collect(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::TemporalAccess { var, .. } | ASTNode::Watch { var, .. } => {
+                self.read.insert(var.clone());
+            }
+            ASTNode::Call { callee, args } => match callee.as_ref() {
+                ASTNode::Identifier(name) if matches!(name.as_str(), "history" | "changes" | "value_at") => {
+                    if let Some(ASTNode::Identifier(var)) = args.first() {
+                        self.read.insert(var.clone());
+                    }
+                }
+                ASTNode::Identifier(name) if name == "keep_timeline" => {
+                    self.keep_all |= args.is_empty();
+                    self.read.extend(args.iter().filter_map(|arg| match arg {
+                        ASTNode::Identifier(var) => Some(var.clone()),
+                        _ => None,
+                    }));
+                }
+                ASTNode::Identifier(name) if matches!(name.as_str(), "snapshot" | "rollback") => {
+                    self.keep_all = true;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        
+        for child in node.children() {
+            self.collect(child);
+        }
+    }
+    
+    fn rewrite(&self, node: &mut ASTNode, demoted: &mut Vec<String>) {
+        if let ASTNode::VarDecl { name, is_temporal, clock, .. } = node
+            && *is_temporal
+            && !self.read.contains(name)
+        {
+            *is_temporal = false;
+            *clock = None;
+            demoted.push(name.clone());
+        }
+        
+        for child in node.children_mut() {
+            self.rewrite(child, demoted);
+        }
+    }
+}
+
+/// Line-oriented cleanup of generated IR. Constants materialized with
+/// `fadd double 0.0, C` are used directly, and a load straight after a store
+/// to (or load from) the same slot reuses the value already in hand.
+pub struct IRPeephole;
+
+impl IRPeephole {
+    pub fn optimize(ir: &str) -> String {
+        let mut replacements: HashMap<String, String> = HashMap::new();
+        let mut kept: Vec<&str> = Vec::new();
+        // The slot and value of the previous line, if it was a store or load
+        let mut last_access: Option<(&str, &str, String)> = None;
+        
+        for line in ir.lines() {
+            // Source map marks sit between instructions without touching them
+            if line.starts_with("; flux ") {
+                kept.push(line);
+                continue;
+            }
+            let access = if let Some((reg, constant)) = Self::materialized_constant(line) {
+                replacements.insert(reg.to_string(), constant.to_string());
+                continue;
+            } else if let Some((reg, ty, slot)) = Self::load(line) {
+                if let Some((last_ty, last_slot, value)) = &last_access
+                    && *last_ty == ty
+                    && *last_slot == slot
+                {
+                    replacements.insert(reg.to_string(), value.clone());
+                    continue;
+                }
+                Some((ty, slot, reg.to_string()))
+            } else {
+                Self::store(line).map(|(ty, value, slot)| (ty, slot, value.to_string()))
+            };
+            
+            last_access = access;
+            kept.push(line);
+        }
+        
+        let mut output = String::with_capacity(ir.len());
+        for line in kept {
+            // Global constants hold user text, which may look like a register
+            if line.starts_with('@') {
+                output.push_str(line);
+            } else {
+                output.push_str(&Self::substitute(line, &replacements));
+            }
+            output.push('\n');
+        }
+        output
+    }
+    
+    /// `%tN = fadd double 0.0, C` with a literal `C`
+    fn materialized_constant(line: &str) -> Option<(&str, &str)> {
+        let (reg, constant) = line.trim().split_once(" = fadd double 0.0, ")?;
+        (reg.starts_with('%') && !constant.starts_with('%') && !constant.contains(' ')).then_some((reg, constant))
+    }
+    
+    /// `%r = load T, T* P`, as (register, type, slot)
+    fn load(line: &str) -> Option<(&str, &str, &str)> {
+        let (reg, rest) = line.trim().split_once(" = load ")?;
+        let (ty, rest) = rest.split_once(", ")?;
+        let slot = rest.strip_prefix(ty)?.strip_prefix("* ")?;
+        (!slot.contains(' ')).then_some((reg, ty, slot))
+    }
+    
+    /// `store T V, T* P`, as (type, value, slot)
+    fn store(line: &str) -> Option<(&str, &str, &str)> {
+        let rest = line.trim().strip_prefix("store ")?;
+        let (ty, rest) = rest.split_once(' ')?;
+        let (value, rest) = rest.split_once(", ")?;
+        let slot = rest.strip_prefix(ty)?.strip_prefix("* ")?;
+        (!value.contains(' ') && !slot.contains(' ')).then_some((ty, value, slot))
+    }
+    
+    /// Replaces whole register names, following chains of replacements
+    fn substitute(line: &str, replacements: &HashMap<String, String>) -> String {
+        let is_name_char = |ch: char| ch.is_ascii_alphanumeric() || ch == '_' || ch == '.' || ch == '$';
+        let mut result = String::with_capacity(line.len());
+        let mut rest = line;
+        
+        while let Some(start) = rest.find('%') {
+            result.push_str(&rest[..start]);
+            let name_len = rest[start + 1..].find(|ch: char| !is_name_char(ch)).unwrap_or(rest.len() - start - 1);
+            let mut name = &rest[start..start + 1 + name_len];
+            while let Some(replacement) = replacements.get(name) {
+                name = replacement;
+            }
+            result.push_str(name);
+            rest = &rest[start + 1 + name_len..];
+        }
+        
+        result.push_str(rest);
+        result
+    }
+}
+
+// ============================================================================
+// FLUX STANDARD LIBRARY
+// ============================================================================
+
+/// Signature shared by every native builtin
+pub type BuiltinFn = fn(Vec<FluxValue>) -> Result<FluxValue, String>;
+
+/// Native callback registered by a host program through `Engine::register_fn`
+pub type HostFn = Arc<dyn Fn(&[FluxValue]) -> Result<FluxValue, String> + Send + Sync>;
+
+/// How `print`, `println` and bare `{}` placeholders render numbers; set by
+/// `#pragma numeric_format auto|fixed [digits]` or `set_precision(n)`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NumericFormat {
+    /// Shortest text that reads back as the same number: `3`, `2.5`
+    #[default]
+    Auto,
+    /// A fixed number of decimal places: `3.00`
+    Fixed(usize),
+}
+
+impl NumericFormat {
+    /// Parses the arguments of `#pragma numeric_format`
+    pub fn from_pragma(args: &str) -> Result<Self, String> {
+        let words: Vec<&str> = args.split_whitespace().collect();
+        match words.as_slice() {
+            ["auto"] => Ok(NumericFormat::Auto),
+            ["fixed"] => Ok(NumericFormat::Fixed(6)),
+            ["fixed", digits] => digits.parse()
+                .map(NumericFormat::Fixed)
+                .map_err(|_| format!("Invalid precision '{}' in #pragma numeric_format", digits)),
+            _ => Err(format!("Expected 'auto' or 'fixed [digits]' after #pragma numeric_format, found '{}'", args.trim())),
+        }
+    }
+    
+    /// The `set_precision` argument selecting this format; negative means auto
+    pub fn precision_code(&self) -> i64 {
+        match self {
+            NumericFormat::Auto => -1,
+            NumericFormat::Fixed(digits) => *digits as i64,
+        }
+    }
+    
+    pub fn from_precision_code(code: f64) -> Self {
+        if code < 0.0 {
+            NumericFormat::Auto
+        } else {
+            NumericFormat::Fixed(code as usize)
+        }
+    }
+    
+    pub fn render(&self, n: f64) -> String {
+        match self {
+            NumericFormat::Auto => n.to_string(),
+            NumericFormat::Fixed(digits) => format!("{:.*}", digits, n),
+        }
+    }
+}
+
+/// A piece of a `format()` template such as `"x = {}, y = {:8.2}"`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatPiece {
+    Literal(String),
+    Placeholder { width: Option<usize>, precision: Option<usize> },
+}
+
+/// Built-in functions and utilities for Flux language
+pub struct FluxStdLib;
+
+impl FluxStdLib {
+    pub fn get_builtin_functions() -> HashMap<String, BuiltinFn> {
+        let mut functions = HashMap::new();
+        
+        functions.insert("print".to_string(), Self::print as BuiltinFn);
+        functions.insert("len".to_string(), Self::len as BuiltinFn);
+        functions.insert("byte_len".to_string(), Self::byte_len as BuiltinFn);
+        functions.insert("abs".to_string(), Self::abs as BuiltinFn);
+        functions.insert("max".to_string(), Self::max as BuiltinFn);
+        functions.insert("min".to_string(), Self::min as BuiltinFn);
+        functions.insert("sqrt".to_string(), Self::sqrt as BuiltinFn);
+        functions.insert("format".to_string(), Self::format as BuiltinFn);
+        functions.insert("println".to_string(), Self::println as BuiltinFn);
+        functions.insert("char_code".to_string(), Self::char_code as BuiltinFn);
+        functions.insert("from_char_code".to_string(), Self::from_char_code as BuiltinFn);
+        
+        functions
+    }
+    
+    /// Splits a template into literal text and `{}` / `{:W.P}` placeholders;
+    /// `{{` and `}}` stand for literal braces
+    pub fn parse_format(template: &str) -> Result<Vec<FormatPiece>, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => return Err("unterminated '{' in format template".to_string()),
+                        }
+                    }
+                    
+                    if !literal.is_empty() {
+                        pieces.push(FormatPiece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Self::parse_format_spec(&spec)?);
+                }
+                '}' => return Err("unmatched '}' in format template".to_string()),
+                _ => literal.push(ch),
+            }
+        }
+        
+        if !literal.is_empty() {
+            pieces.push(FormatPiece::Literal(literal));
+        }
+        
+        Ok(pieces)
+    }
+    
+    fn parse_format_spec(spec: &str) -> Result<FormatPiece, String> {
+        let invalid = || format!("invalid format specifier '{{{}}}'", spec);
+        
+        let Some(spec) = spec.strip_prefix(':') else {
+            return if spec.is_empty() {
+                Ok(FormatPiece::Placeholder { width: None, precision: None })
+            } else {
+                Err(invalid())
+            };
+        };
+        
+        let (width, precision) = match spec.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (spec, None),
+        };
+        
+        let width = if width.is_empty() {
+            None
+        } else {
+            Some(width.parse().map_err(|_| invalid())?)
+        };
+        let precision = match precision {
+            Some(precision) => Some(precision.parse().map_err(|_| invalid())?),
+            None => None,
+        };
+        
+        Ok(FormatPiece::Placeholder { width, precision })
+    }
+    
+    pub fn format(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        Self::format_with(args, NumericFormat::Auto)
+    }
+    
+    /// `format()` with bare `{}` placeholders rendering numbers as `numeric`
+    pub fn format_with(args: Vec<FluxValue>, numeric: NumericFormat) -> Result<FluxValue, String> {
+        let mut args = args.into_iter();
+        let Some(FluxValue::String(template)) = args.next() else {
+            return Err("format() requires a template string as its first argument".to_string());
+        };
+        
+        let mut output = String::new();
+        for piece in Self::parse_format(&template)? {
+            match piece {
+                FormatPiece::Literal(text) => output.push_str(&text),
+                FormatPiece::Placeholder { width, precision } => {
+                    let value = args.next()
+                        .ok_or_else(|| "format() has more placeholders than arguments".to_string())?;
+                    let width = width.unwrap_or(0);
+                    
+                    // Numbers align right and strings left, as in Rust
+                    let text = match (&value, precision) {
+                        (FluxValue::Number(n), Some(precision)) => format!("{:>width$.precision$}", n),
+                        (FluxValue::Number(n), None) => format!("{:>width$}", numeric.render(*n)),
+                        (other, _) => format!("{:<width$}", other.to_display_string()),
+                    };
+                    output.push_str(&text);
+                }
+            }
+        }
+        
+        if args.next().is_some() {
+            return Err("format() has more arguments than placeholders".to_string());
+        }
+        
+        Ok(FluxValue::String(output))
+    }
+    
+    fn println(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        let FluxValue::String(text) = Self::format(args)? else {
+            unreachable!("format() always produces a string");
+        };
+        println!("{}", text);
+        Ok(FluxValue::Boolean(true))
+    }
+    
+    /// The line `print(args...)` writes, without its newline
+    pub fn print_text(args: &[FluxValue]) -> String {
+        Self::print_text_with(args, NumericFormat::Auto)
+    }
+    
+    pub fn print_text_with(args: &[FluxValue], numeric: NumericFormat) -> String {
+        args.iter()
+            .map(|arg| match arg {
+                FluxValue::Number(n) => numeric.render(*n),
+                other => other.to_display_string(),
+            })
+            .collect()
+    }
+    
+    fn print(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        println!("{}", Self::print_text(&args));
+        Ok(FluxValue::Boolean(true))
+    }
+    
+    fn char_code(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        let [FluxValue::String(text)] = args.as_slice() else {
+            return Err("char_code() takes one single-character string".to_string());
+        };
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Ok(FluxValue::Number(ch as u32 as f64)),
+            _ => Err(format!("char_code() expects a single character, found {:?}", text)),
+        }
+    }
+    
+    fn from_char_code(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        let [FluxValue::Number(code)] = args.as_slice() else {
+            return Err("from_char_code() takes one number".to_string());
+        };
+        char::from_u32(*code as u32)
+            .filter(|_| code.fract() == 0.0 && *code >= 0.0)
+            .map(|ch| FluxValue::String(ch.to_string()))
+            .ok_or_else(|| format!("{} is not a valid character code", code))
+    }
+    
+    /// Size of a string's UTF-8 encoding
+    fn byte_len(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        let [FluxValue::String(text)] = args.as_slice() else {
+            return Err("byte_len() takes one string".to_string());
+        };
+        Ok(FluxValue::Number(text.len() as f64))
+    }
+    
+    fn len(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        if args.len() != 1 {
+            return Err("len() takes exactly one argument".to_string());
+        }
+        
+        match &args[0] {
+            // Characters, matching what indexing counts
+            FluxValue::String(s) => Ok(FluxValue::Number(s.chars().count() as f64)),
+            FluxValue::Object(obj) => Ok(FluxValue::Number(obj.len() as f64)),
+            FluxValue::Array(items) => Ok(FluxValue::Number(items.len() as f64)),
+            _ => Err("len() can only be called on strings, arrays or objects".to_string()),
+        }
+    }
+    
+    fn abs(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        if args.len() != 1 {
+            return Err("abs() takes exactly one argument".to_string());
+        }
+        
+        match &args[0] {
+            FluxValue::Number(n) => Ok(FluxValue::Number(n.abs())),
+            _ => Err("abs() can only be called on numbers".to_string()),
+        }
+    }
+    
+    fn max(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        if args.is_empty() {
+            return Err("max() requires at least one argument".to_string());
+        }
+        
+        let mut max_val = match &args[0] {
+            FluxValue::Number(n) => *n,
+            _ => return Err("max() can only be called on numbers".to_string()),
+        };
+        
+        for arg in &args[1..] {
+            match arg {
+                FluxValue::Number(n) => {
+                    if *n > max_val {
+                        max_val = *n;
+                    }
+                }
+                _ => return Err("max() can only be called on numbers".to_string()),
+            }
+        }
+        
+        Ok(FluxValue::Number(max_val))
+    }
+    
+    fn min(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        if args.is_empty() {
+            return Err("min() requires at least one argument".to_string());
+        }
+        
+        let mut min_val = match &args[0] {
+            FluxValue::Number(n) => *n,
+            _ => return Err("min() can only be called on numbers".to_string()),
+        };
+        
+        for arg in &args[1..] {
+            match arg {
+                FluxValue::Number(n) => {
+                    if *n < min_val {
+                        min_val = *n;
+                    }
+                }
+                _ => return Err("min() can only be called on numbers".to_string()),
+            }
+        }
+        
+        Ok(FluxValue::Number(min_val))
+    }
+    
+    fn sqrt(args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        if args.len() != 1 {
+            return Err("sqrt() takes exactly one argument".to_string());
+        }
+        
+        match &args[0] {
+            FluxValue::Number(n) => {
+                if *n < 0.0 {
+                    Err("sqrt() cannot be called on negative numbers".to_string())
+                } else {
+                    Ok(FluxValue::Number(n.sqrt()))
+                }
+            }
+            _ => Err("sqrt() can only be called on numbers".to_string()),
+        }
+    }
+}
+
+// ============================================================================
+// INTERPRETER - Tree-walking evaluation
+// ============================================================================
+
+/// A value the program took from outside: the clock, the random number
+/// generator, standard input or the file system
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent {
+    Time(f64),
+    Random(f64),
+    /// A line read by `input()`, or `None` at end of input
+    Input(Option<String>),
+    ReadFile { path: String, contents: Result<String, String> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TraceMode {
+    Live,
+    Record,
+    /// Handing back recorded events; holds the index of the next one
+    Replay(usize),
+}
+
+/// Source of the nondeterministic builtins' values. Recording keeps every
+/// value handed out; replaying hands the recorded values back in order, so
+/// a run can be repeated exactly for debugging.
+#[derive(Debug, Clone)]
+pub struct InputTrace {
+    mode: TraceMode,
+    events: Vec<TraceEvent>,
+    rng_state: u64,
+}
+
+impl Default for InputTrace {
+    fn default() -> Self {
+        Self::live()
+    }
+}
+
+impl InputTrace {
+    const MAGIC: &'static [u8] = b"FLUXTRACE1";
+    
+    pub fn live() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self { mode: TraceMode::Live, events: Vec::new(), rng_state: nanos | 1 }
+    }
+    
+    pub fn recording() -> Self {
+        Self { mode: TraceMode::Record, ..Self::live() }
+    }
+    
+    pub fn replaying(events: Vec<TraceEvent>) -> Self {
+        Self { mode: TraceMode::Replay(0), events, ..Self::live() }
+    }
+    
+    pub fn events(&self) -> &[TraceEvent] {
+        &self.events
+    }
+    
+    /// Recorded events a replay hasn't handed out yet
+    pub fn remaining(&self) -> usize {
+        match self.mode {
+            TraceMode::Replay(next) => self.events.len() - next,
+            _ => 0,
+        }
+    }
+    
+    /// The next value for builtin `name`: a fresh one from `live`, or the
+    /// recorded one, which must be of the kind `expected` accepts
+    fn next(&mut self, name: &str, expected: impl Fn(&TraceEvent) -> bool,
+            live: impl FnOnce(&mut Self) -> TraceEvent) -> Result<TraceEvent, String> {
+        match self.mode {
+            TraceMode::Live => Ok(live(self)),
+            TraceMode::Record => {
+                let event = live(self);
+                self.events.push(event.clone());
+                Ok(event)
+            }
+            TraceMode::Replay(next) => {
+                let event = self.events.get(next)
+                    .ok_or_else(|| format!("Replay trace ran out of inputs at {}()", name))?;
+                if !expected(event) {
+                    return Err(format!("Replay diverged: the program called {}() where the trace recorded {:?}", name, event));
+                }
+                self.mode = TraceMode::Replay(next + 1);
+                Ok(event.clone())
+            }
+        }
+    }
+    
+    fn random(&mut self) -> f64 {
+        // xorshift64*, keeping the top 53 bits as the fraction
+        self.rng_state ^= self.rng_state >> 12;
+        self.rng_state ^= self.rng_state << 25;
+        self.rng_state ^= self.rng_state >> 27;
+        (self.rng_state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+    
+    /// The trace file format: a magic header, then one tagged event after
+    /// another, with numbers as little-endian f64 and strings length-prefixed
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn push_str(bytes: &mut Vec<u8>, text: &str) {
+            bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(text.as_bytes());
+        }
+        
+        let mut bytes = Self::MAGIC.to_vec();
+        for event in &self.events {
+            match event {
+                TraceEvent::Time(seconds) => {
+                    bytes.push(0);
+                    bytes.extend_from_slice(&seconds.to_le_bytes());
+                }
+                TraceEvent::Random(value) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+                TraceEvent::Input(Some(line)) => {
+                    bytes.push(2);
+                    push_str(&mut bytes, line);
+                }
+                TraceEvent::Input(None) => bytes.push(3),
+                TraceEvent::ReadFile { path, contents } => {
+                    let (tag, text) = match contents {
+                        Ok(text) => (4, text),
+                        Err(error) => (5, error),
+                    };
+                    bytes.push(tag);
+                    push_str(&mut bytes, path);
+                    push_str(&mut bytes, text);
+                }
+            }
+        }
+        bytes
+    }
+    
+    /// Reads a trace written by `to_bytes`, ready to replay
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut rest = bytes.strip_prefix(Self::MAGIC).ok_or("Not a Flux trace file")?;
+        let mut take = |count: usize| -> Result<&[u8], String> {
+            if rest.len() < count {
+                return Err("Trace file is truncated".to_string());
+            }
+            let (taken, remainder) = rest.split_at(count);
+            rest = remainder;
+            Ok(taken)
+        };
+        
+        let mut events = Vec::new();
+        while let Ok(tag) = take(1) {
+            let tag = tag[0];
+            let mut number = || -> Result<f64, String> {
+                Ok(f64::from_le_bytes(take(8)?.try_into().unwrap()))
+            };
+            let event = match tag {
+                0 => TraceEvent::Time(number()?),
+                1 => TraceEvent::Random(number()?),
+                3 => TraceEvent::Input(None),
+                2 | 4 | 5 => {
+                    let mut string = || -> Result<String, String> {
+                        let len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+                        String::from_utf8(take(len)?.to_vec()).map_err(|_| "Trace file holds invalid UTF-8".to_string())
+                    };
+                    match tag {
+                        2 => TraceEvent::Input(Some(string()?)),
+                        _ => {
+                            let path = string()?;
+                            let text = string()?;
+                            TraceEvent::ReadFile { path, contents: if tag == 4 { Ok(text) } else { Err(text) } }
+                        }
+                    }
+                }
+                other => return Err(format!("Unknown trace event tag {}", other)),
+            };
+            events.push(event);
+        }
+        Ok(Self::replaying(events))
+    }
+}
+
+/// Resource limits for running untrusted code, for embedders and the REPL.
+/// Each `Interpreter::run` starts a fresh budget; unset limits don't apply,
+/// except call depth, which every run caps.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    /// Bytes of class instances on the `FluxRuntime` heap, plus those of
+    /// each string, array or object as it's built
+    pub max_heap_bytes: Option<usize>,
+    /// Deepest nesting of function calls. Runaway recursion would overflow
+    /// the host's stack, so unset means `DEFAULT_CALL_DEPTH` rather than
+    /// no limit.
+    pub max_call_depth: Option<usize>,
+    /// Checked between evaluation steps, so a blocking `input()`, `recv()`
+    /// or `join()` can outlast it
+    pub timeout: Option<Duration>,
+    /// Builtins scripts may call, or `None` for all of them. User and host
+    /// functions are always callable.
+    pub allowed_builtins: Option<HashSet<String>>,
+}
+
+impl Limits {
+    pub const DEFAULT_CALL_DEPTH: usize = 1000;
+    
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+    
+    pub fn max_heap_bytes(mut self, bytes: usize) -> Self {
+        self.max_heap_bytes = Some(bytes);
+        self
+    }
+    
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = Some(depth);
+        self
+    }
+    
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    
+    pub fn allow_builtins<'n>(mut self, names: impl IntoIterator<Item = &'n str>) -> Self {
+        self.allowed_builtins = Some(names.into_iter().map(str::to_string).collect());
+        self
+    }
+}
+
+/// The limit a sandboxed run hit
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitExceeded {
+    Steps(u64),
+    HeapBytes(usize),
+    CallDepth(usize),
+    Timeout(Duration),
+    Builtin(String),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::Steps(steps) => write!(f, "Step limit of {} exceeded", steps),
+            LimitExceeded::HeapBytes(bytes) => write!(f, "Heap limit of {} bytes exceeded", bytes),
+            LimitExceeded::CallDepth(depth) => write!(f, "Call depth limit of {} exceeded", depth),
+            LimitExceeded::Timeout(timeout) => write!(f, "Time limit of {}ms exceeded", timeout.as_millis()),
+            LimitExceeded::Builtin(name) => write!(f, "Builtin '{}' is not allowed in this sandbox", name),
+        }
+    }
+}
+
+/// One run's budget, shared with the tasks it spawns
+struct Sandbox {
+    limits: Limits,
+    steps: AtomicU64,
+    deadline: Option<Instant>,
+    /// Set by the first limit hit; every later step fails too, so the
+    /// run unwinds without doing more work
+    tripped: AtomicBool,
+    exceeded: Mutex<Option<LimitExceeded>>,
+}
+
+impl Sandbox {
+    /// How often, in steps, the clock is read
+    const CLOCK_INTERVAL: u64 = 256;
+    
+    /// Stack reserved per allowed call, generous enough for unoptimized
+    /// builds, where each call takes several large `eval` frames
+    const STACK_PER_CALL: usize = 128 * 1024;
+    
+    fn new(limits: Limits) -> Self {
+        Self {
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            limits,
+            steps: AtomicU64::new(0),
+            tripped: AtomicBool::new(false),
+            exceeded: Mutex::new(None),
+        }
+    }
+    
+    fn step(&self) -> Result<(), String> {
+        if self.tripped.load(Ordering::Relaxed) {
+            return Err(self.exceeded().map(|limit| limit.to_string()).unwrap_or_default());
+        }
+        let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(max) = self.limits.max_steps
+            && steps > max
+        {
+            return Err(self.exceed(LimitExceeded::Steps(max)));
+        }
+        if steps.is_multiple_of(Self::CLOCK_INTERVAL)
+            && let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout)
+            && Instant::now() >= deadline
+        {
+            return Err(self.exceed(LimitExceeded::Timeout(timeout)));
+        }
+        Ok(())
+    }
+    
+    /// Records `limit` unless another was hit first, returning the error
+    /// message for the run
+    fn exceed(&self, limit: LimitExceeded) -> String {
+        let mut exceeded = self.exceeded.lock().unwrap();
+        self.tripped.store(true, Ordering::Relaxed);
+        exceeded.get_or_insert(limit).to_string()
+    }
+    
+    fn exceeded(&self) -> Option<LimitExceeded> {
+        self.exceeded.lock().unwrap().clone()
+    }
+    
+    fn max_call_depth(&self) -> usize {
+        self.limits.max_call_depth.unwrap_or(Limits::DEFAULT_CALL_DEPTH)
+    }
+    
+    /// Stack for the thread a sandboxed run evaluates on, enough to reach
+    /// the call depth limit before the stack runs out
+    fn stack_size(&self) -> usize {
+        (self.max_call_depth() + 16) * Self::STACK_PER_CALL
+    }
+}
+
+/// Non-local exits that unwind through statement evaluation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Interrupt {
+    Return(FluxValue),
+    /// `break`, with the label of the loop it targets if one was named
+    Break(Option<String>),
+    /// `exit(code)`, which unwinds the whole program
+    Exit(i32),
+    Error(String),
+}
+
+impl Interrupt {
+    /// Reports an interrupt that reached a boundary it can't unwind past
+    fn into_error(self) -> String {
+        match self {
+            Interrupt::Return(value) => format!("Unexpected return of {}", value),
+            Interrupt::Break(None) => "`break` outside of a loop".to_string(),
+            Interrupt::Break(Some(label)) => format!("Unknown loop label '{}' in `break {}`", label, label),
+            Interrupt::Exit(code) => format!("exit({}) outside of the main program", code),
+            Interrupt::Error(message) => message,
+        }
+    }
+}
+
+impl From<String> for Interrupt {
+    fn from(message: String) -> Self {
+        Interrupt::Error(message)
+    }
+}
+
+/// A user-defined function as seen by the interpreter
+#[derive(Debug, Clone)]
+pub struct FluxFunction {
+    params: Vec<String>,
+    body: Vec<ASTNode>,
+}
+
+/// A class as seen by the interpreter; methods are looked up here first,
+/// then along the superclass chain
+#[derive(Debug, Clone)]
+pub struct FluxClass {
+    superclass: Option<String>,
+    methods: HashMap<String, FluxFunction>,
+    /// Fields the methods assign through `this`, in declaration order,
+    /// for `fields()`
+    fields: Vec<String>,
+}
+
+impl FluxClass {
+    fn declared_fields(methods: &[ASTNode]) -> Vec<String> {
+        fn collect(node: &ASTNode, fields: &mut Vec<String>) {
+            if let ASTNode::FieldAssign { object, field, .. } = node
+                && matches!(object.as_ref(), ASTNode::Identifier(name) if name == "this")
+                && !fields.contains(field)
+            {
+                fields.push(field.clone());
+            }
+            for child in node.children() {
+                collect(child, fields);
+            }
+        }
+        let mut fields = Vec::new();
+        for method in methods {
+            collect(method, &mut fields);
+        }
+        fields
+    }
+}
+
+/// Evaluates Flux programs directly from the AST
+pub struct Interpreter {
+    scopes: Vec<HashMap<String, FluxValue>>,
+    /// Callers' local scopes while a function runs, kept here rather than
+    /// on the Rust stack so the collector sees them
+    suspended: Vec<Vec<HashMap<String, FluxValue>>>,
+    /// Values evaluated but not yet stored in a variable, such as the
+    /// arguments gathered for a call; roots alongside the scopes
+    temporaries: Vec<FluxValue>,
+    functions: HashMap<String, FluxFunction>,
+    classes: HashMap<String, FluxClass>,
+    /// Heap for class instances, shared with tasks' snapshots
+    runtime: Arc<Mutex<FluxRuntime>>,
+    builtins: HashMap<String, BuiltinFn>,
+    /// Host callbacks; these shadow builtins of the same name
+    host_functions: HashMap<String, HostFn>,
+    temporal_manager: TemporalManager,
+    /// Variables whose watchers are running; updates from inside a watcher
+    /// don't re-trigger that variable's watchers
+    notifying: HashSet<String>,
+    scheduler: Arc<TaskScheduler>,
+    /// When set, `print`/`println` append here instead of writing to
+    /// stdout; shared with tasks and `parallel_map` workers
+    captured: Option<Arc<Mutex<String>>>,
+    numeric_format: NumericFormat,
+    /// Set by `#pragma checked_math`: arithmetic that divides by zero or
+    /// produces NaN or infinity fails instead of carrying on
+    checked_math: bool,
+    exit_code: Option<i32>,
+    /// Where `time`, `random`, `input` and `read_file` get their values;
+    /// shared with tasks so a trace covers the whole program
+    inputs: Arc<Mutex<InputTrace>>,
+    /// Expressions from `defer`, one list per running function and one for
+    /// the program's top level
+    deferred: Vec<Vec<ASTNode>>,
+    /// The current run's budget
+    sandbox: Arc<Sandbox>,
+    /// Function calls in progress, for the sandbox's depth limit
+    call_depth: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// Built-in class backing efficient string building; a user class of
+    /// the same name takes its place
+    const STRING_BUILDER: &'static str = "StringBuilder";
+    
+    /// Builtins evaluated by the interpreter itself rather than FluxStdLib
+    const INTRINSICS: &'static [&'static str] = &[
+        "print", "println", "set_precision", "checkpoint", "snapshot", "rollback", "exit", "panic",
+        "history", "changes", "value_at", "keep_timeline", "freeze", "is_frozen",
+        "join", "channel", "send", "recv", "time", "random", "input", "read_file",
+        "typeof", "fields", "methods",
+    ];
+    
+    pub fn new() -> Self {
+        let runtime = FluxRuntime::new();
+        Self {
+            scopes: vec![HashMap::new()],
+            suspended: Vec::new(),
+            temporaries: Vec::new(),
+            functions: HashMap::new(),
+            classes: HashMap::new(),
+            scheduler: runtime.scheduler(),
+            runtime: Arc::new(Mutex::new(runtime)),
+            builtins: FluxStdLib::get_builtin_functions(),
+            host_functions: HashMap::new(),
+            temporal_manager: TemporalManager::new(),
+            notifying: HashSet::new(),
+            captured: None,
+            numeric_format: NumericFormat::Auto,
+            checked_math: false,
+            exit_code: None,
+            inputs: Arc::new(Mutex::new(InputTrace::live())),
+            deferred: Vec::new(),
+            // Even unlimited runs cap call depth, so runaway recursion
+            // fails with an error rather than overflowing the stack
+            sandbox: Arc::new(Sandbox::new(Limits::new())),
+            call_depth: 0,
+        }
+    }
+    
+    /// Records or replays the program's nondeterministic inputs
+    pub fn set_input_trace(&mut self, trace: InputTrace) {
+        self.inputs = Arc::new(Mutex::new(trace));
+    }
+    
+    /// The trace in use, leaving live inputs in its place
+    pub fn take_input_trace(&mut self) -> InputTrace {
+        std::mem::take(&mut *self.inputs.lock().unwrap())
+    }
+    
+    /// Collects printed output for `take_output` instead of writing it
+    pub fn capture_output(&mut self) {
+        self.captured = Some(Arc::new(Mutex::new(String::new())));
+    }
+    
+    pub fn take_output(&mut self) -> String {
+        self.captured.as_ref()
+            .map(|captured| std::mem::take(&mut *captured.lock().unwrap()))
+            .unwrap_or_default()
+    }
+    
+    /// Top-level variables and their current values
+    pub fn globals(&self) -> &HashMap<String, FluxValue> {
+        &self.scopes[0]
+    }
+    
+    /// The heap holding class instances
+    pub fn runtime(&self) -> std::sync::MutexGuard<'_, FluxRuntime> {
+        self.runtime.lock().unwrap()
+    }
+    
+    /// Frees instances unreachable from variables, values being evaluated,
+    /// timelines, channels and unjoined task results. Skipped while tasks
+    /// are running, since their snapshots hold roots this interpreter can't
+    /// see. Returns how many objects were freed.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.collect_garbage_holding(&[])
+    }
+    
+    /// `collect_garbage`, also keeping what `held` refers to
+    fn collect_garbage_holding(&mut self, held: &[FluxValue]) -> usize {
+        let Some(retained) = self.scheduler.retained_values() else {
+            return 0;
+        };
+        let timelines: Vec<FluxValue> = self.temporal_manager.handles().into_iter()
+            .map(FluxValue::Instance)
+            .collect();
+        let roots = self.scopes.iter()
+            .chain(self.suspended.iter().flatten())
+            .flat_map(|scope| scope.values())
+            .chain(&self.temporaries)
+            .chain(held)
+            .chain(&timelines)
+            .chain(&retained);
+        self.runtime.lock().unwrap().garbage_collect(roots)
+    }
+    
+    /// Puts `object` on the heap, first collecting garbage once enough has
+    /// been allocated since the last collection. `held` are values the
+    /// caller has in hand that no variable refers to yet.
+    fn allocate(&mut self, object: HeapObject, held: &[FluxValue]) -> Result<usize, Interrupt> {
+        if self.runtime().should_collect() {
+            self.collect_garbage_holding(held);
+        }
+        let handle = self.runtime().allocate(object);
+        self.check_heap(held)?;
+        Ok(handle)
+    }
+    
+    /// Evaluates each of `nodes`, keeping the values reachable for the
+    /// collector until all of them are done
+    fn eval_values(&mut self, nodes: &[ASTNode]) -> Result<Vec<FluxValue>, Interrupt> {
+        let mark = self.temporaries.len();
+        for node in nodes {
+            match self.eval(node) {
+                Ok(value) => self.temporaries.push(value),
+                Err(interrupt) => {
+                    self.temporaries.truncate(mark);
+                    return Err(interrupt);
+                }
+            }
+        }
+        Ok(self.temporaries.split_off(mark))
+    }
+    
+    /// Evaluates `node` while keeping `held` reachable, returning both
+    fn eval_holding(&mut self, held: FluxValue, node: &ASTNode) -> Result<(FluxValue, FluxValue), Interrupt> {
+        self.temporaries.push(held);
+        let result = self.eval(node);
+        let held = self.temporaries.pop().unwrap_or(FluxValue::Null);
+        result.map(|value| (held, value))
+    }
+    
+    /// Runs a whole program, returning the value of its last statement.
+    /// A call to `exit` stops it early and sets `exit_code`.
+    pub fn run(&mut self, ast: &ASTNode) -> Result<FluxValue, String> {
+        self.fresh_budget();
+        let result = self.sandboxed(|interpreter| interpreter.eval(ast));
+        self.finish(result)
+    }
+    
+    /// Starts the sandbox's budget over, for the next run or host call
+    fn fresh_budget(&mut self) {
+        self.sandbox = Arc::new(Sandbox::new(self.sandbox.limits.clone()));
+    }
+    
+    /// Runs `body` on a thread whose stack fits the call depth limit, so
+    /// deep recursion fails with an error instead of overflowing the
+    /// host's stack
+    fn sandboxed<T: Send>(&mut self, body: impl FnOnce(&mut Self) -> T + Send) -> T {
+        let stack_size = self.sandbox.stack_size();
+        thread::scope(|scope| {
+            let evaluation = thread::Builder::new()
+                .stack_size(stack_size)
+                .spawn_scoped(scope, || body(self))
+                .expect("failed to start the sandbox thread");
+            evaluation.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+    
+    /// Sandboxes later runs
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.runtime().set_heap_limit(limits.max_heap_bytes);
+        self.sandbox = Arc::new(Sandbox::new(limits));
+    }
+    
+    /// Goes back to the default limits, where only call depth is capped
+    pub fn remove_limits(&mut self) {
+        self.set_limits(Limits::new());
+    }
+    
+    pub fn limits(&self) -> &Limits {
+        &self.sandbox.limits
+    }
+    
+    /// The limit that stopped the last run, if one did
+    pub fn limit_exceeded(&self) -> Option<LimitExceeded> {
+        self.sandbox.exceeded()
+    }
+    
+    /// Every builtin a script can call, for building allow-lists
+    pub fn builtin_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.builtins.keys().cloned()
+            .chain(Self::INTRINSICS.iter().map(|name| name.to_string()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+    
+    /// Fails for a builtin outside the sandbox's allow-list; the pragma
+    /// marker `keep_timeline` does nothing when called, so it's exempt
+    fn check_allowed(&self, name: &str) -> Result<(), Interrupt> {
+        if let Some(allowed) = &self.sandbox.limits.allowed_builtins
+            && !allowed.contains(name)
+            && name != "keep_timeline"
+            && !self.functions.contains_key(name)
+            && !self.host_functions.contains_key(name)
+            && (self.builtins.contains_key(name) || Self::INTRINSICS.contains(&name))
+        {
+            return Err(Interrupt::Error(self.sandbox.exceed(LimitExceeded::Builtin(name.to_string()))));
+        }
+        Ok(())
+    }
+    
+    /// Fails once the heap outgrows a sandbox's limit even after a
+    /// collection; `held` are values in hand that must survive it
+    fn check_heap(&mut self, held: &[FluxValue]) -> Result<(), Interrupt> {
+        if self.runtime().exceeded_heap_limit().is_none() {
+            return Ok(());
+        }
+        self.collect_garbage_holding(held);
+        match self.runtime().exceeded_heap_limit() {
+            Some(limit) => Err(Interrupt::Error(self.sandbox.exceed(LimitExceeded::HeapBytes(limit)))),
+            None => Ok(()),
+        }
+    }
+    
+    /// Fails once `value`, a string, array or object just built, would
+    /// take the heap past its limit
+    fn check_size(&self, value: FluxValue) -> Result<FluxValue, Interrupt> {
+        if matches!(value, FluxValue::String(_) | FluxValue::Array(_) | FluxValue::Object(_)) {
+            self.check_bytes(value.size())?;
+        }
+        Ok(value)
+    }
+    
+    fn check_bytes(&self, bytes: usize) -> Result<(), Interrupt> {
+        match self.sandbox.limits.max_heap_bytes {
+            Some(limit) if self.runtime().heap_bytes() + bytes > limit => {
+                Err(Interrupt::Error(self.sandbox.exceed(LimitExceeded::HeapBytes(limit))))
+            }
+            _ => Ok(()),
+        }
+    }
+    
+    /// The code passed to `exit`, once the program has called it
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+    
+    fn finish(&mut self, result: Result<FluxValue, Interrupt>) -> Result<FluxValue, String> {
+        match result {
+            Ok(value) | Err(Interrupt::Return(value)) => Ok(value),
+            Err(Interrupt::Exit(code)) => {
+                self.exit_code = Some(code);
+                Ok(FluxValue::Null)
+            }
+            Err(interrupt) => Err(interrupt.into_error()),
+        }
+    }
+    
+    /// Runs a program as the `flux run` driver does: the top-level
+    /// statements, then its `func main` if it has one, which receives
+    /// `args` as an array of strings and supplies the result
+    pub fn run_main(&mut self, ast: &ASTNode, args: &[String]) -> Result<FluxValue, String> {
+        let value = self.run(ast)?;
+        let values = match ast.entry_params() {
+            _ if self.exit_code.is_some() => return Ok(value),
+            None => return Ok(value),
+            Some(0) => Vec::new(),
+            Some(_) => vec![FluxValue::Array(args.iter().cloned().map(FluxValue::String).collect())],
+        };
+        let result = self.sandboxed(|interpreter| interpreter.call_function("main", values));
+        self.finish(result)
+    }
+    
+    /// Installs the top-level function and class declarations of `ast`
+    /// without running anything else, for hot-reloading. Calls go through
+    /// the function table by name, so existing callers, pipelines and
+    /// `on change` handlers pick up the new bodies while variables keep
+    /// their values. Returns the names declared; declarations missing from
+    /// `ast` stay as they were.
+    pub fn reload(&mut self, ast: &ASTNode) -> Result<Vec<String>, String> {
+        let ASTNode::Program(statements) = ast else {
+            return Ok(Vec::new());
+        };
+        let mut names = Vec::new();
+        for statement in statements {
+            if let ASTNode::FunctionDecl { name, .. } | ASTNode::ClassDecl { name, .. } = statement {
+                let result = self.eval(statement);
+                self.finish(result)?;
+                names.push(name.clone());
+            }
+        }
+        Ok(names)
+    }
+    
+    /// Evaluates a block in its own scope, returning its last value
+    pub fn run_block(&mut self, statements: &[ASTNode]) -> Result<FluxValue, String> {
+        self.run_task(statements).map_err(Interrupt::into_error)
+    }
+    
+    /// Runs a `spawn` body like `run_block`, except that an `exit` stays
+    /// an interrupt for the joiner to pass on
+    fn run_task(&mut self, statements: &[ASTNode]) -> Result<FluxValue, Interrupt> {
+        self.fresh_budget();
+        self.scopes.push(HashMap::new());
+        let result = self.sandboxed(|interpreter| interpreter.eval_block(statements));
+        self.scopes.pop();
+        
+        match result {
+            Ok(value) | Err(Interrupt::Return(value)) => Ok(value),
+            Err(Interrupt::Exit(code)) => Err(Interrupt::Exit(code)),
+            Err(interrupt) => Err(Interrupt::Error(interrupt.into_error())),
+        }
+    }
+    
+    pub fn define(&mut self, name: &str, value: FluxValue) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), value);
+        }
+    }
+    
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut FluxValue> {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+    }
+    
+    /// Whether evaluating `node` can't call into user code
+    fn runs_no_code(node: &ASTNode) -> bool {
+        !matches!(node, ASTNode::Call { .. } | ASTNode::New { .. } | ASTNode::Pipeline(_) | ASTNode::Spawn(_))
+            && node.children().into_iter().all(Self::runs_no_code)
+    }
+    
+    pub fn lookup(&self, name: &str) -> Option<&FluxValue> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+    
+    /// Defines or overwrites a top-level variable
+    pub fn set_global(&mut self, name: &str, value: FluxValue) {
+        self.scopes[0].insert(name.to_string(), value);
+    }
+    
+    pub fn register_host_fn(&mut self, name: &str, callback: HostFn) {
+        self.host_functions.insert(name.to_string(), callback);
+    }
+    
+    pub fn temporal_manager(&self) -> &TemporalManager {
+        &self.temporal_manager
+    }
+    
+    /// Steps temporal state back to logical `time`: each temporal variable
+    /// takes the value it held then, and those declared later go away.
+    /// Watchers don't run, and plain variables are left as they are.
+    pub fn rewind_to(&mut self, time: usize) -> Result<(), String> {
+        let dropped = self.temporal_manager.rewind_to(time)?;
+        for scope in &mut self.scopes {
+            scope.retain(|name, _| !dropped.contains(name));
+            for (name, value) in scope.iter_mut() {
+                if let Some(past) = self.temporal_manager.current_value(name) {
+                    *value = past;
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Evaluates `ast` as if at logical `time`, on a copy of the session so
+    /// no variable it touches changes here
+    pub fn evaluate_at(&self, time: usize, ast: &ASTNode) -> Result<FluxValue, String> {
+        let mut past = self.snapshot();
+        past.rewind_to(time)?;
+        past.run(ast)
+    }
+    
+    fn assign(&mut self, name: &str, value: FluxValue) -> Result<(), String> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(slot) = scope.get_mut(name) {
+                *slot = value;
+                return Ok(());
+            }
+        }
+        Err(format!("Undefined variable '{}'{}", name, self.did_you_mean_variable(name)))
+    }
+    
+    fn did_you_mean_variable(&self, name: &str) -> String {
+        Diagnostic::did_you_mean(name, self.scopes.iter().flat_map(|scope| scope.keys().map(String::as_str)))
+    }
+    
+    fn eval_block(&mut self, statements: &[ASTNode]) -> Result<FluxValue, Interrupt> {
+        let mut last = FluxValue::Null;
+        for stmt in statements {
+            last = self.eval(stmt)?;
+        }
+        Ok(last)
+    }
+    
+    fn eval_scoped(&mut self, statements: &[ASTNode]) -> Result<FluxValue, Interrupt> {
+        self.scopes.push(HashMap::new());
+        let result = self.eval_block(statements);
+        self.scopes.pop();
+        result
+    }
+    
+    fn eval(&mut self, node: &ASTNode) -> Result<FluxValue, Interrupt> {
+        self.sandbox.step().map_err(Interrupt::Error)?;
+        match node {
+            // Allocations collect as they go; between top-level statements
+            // the heap is collected too, so a statement's last garbage
+            // doesn't wait for the next allocation
+            ASTNode::Program(statements) => self.with_defers(|interpreter| {
+                // Sticks for the rest of a REPL session once a line sets it
+                interpreter.checked_math |= node.checks_math();
+                let mut last = FluxValue::Null;
+                for stmt in statements {
+                    last = interpreter.eval(stmt)?;
+                    if interpreter.runtime().should_collect() {
+                        interpreter.collect_garbage();
+                    }
+                }
+                Ok(last)
+            }),
+            
+            ASTNode::Defer(value) => match self.deferred.last_mut() {
+                Some(deferred) => {
+                    deferred.push(value.as_ref().clone());
+                    Ok(FluxValue::Null)
+                }
+                None => Err(Interrupt::Error(format!("`{}` outside of a function", node))),
+            },
+            
+            ASTNode::Block(statements) => self.eval_block(statements),
+            
+            ASTNode::VarDecl { name, value, is_temporal, clock, .. } => {
+                let value = self.eval(value)?;
+                match clock {
+                    Some(clock) if *is_temporal => {
+                        self.temporal_manager.create_temporal_var_in(name.clone(), value.clone(), clock)?;
+                    }
+                    _ if *is_temporal => self.temporal_manager.create_temporal_var(name.clone(), value.clone()),
+                    _ => {}
+                }
+                self.define(name, value);
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::Assignment { name, value } => {
+                // `s = s + tail` appends in place rather than copying `s`, so
+                // building a string in a loop stays linear. Only tails that
+                // can't run code qualify, since they can't change `s` first.
+                if let ASTNode::Binary { left, operator, right } = value.as_ref()
+                    && operator == "+"
+                    && matches!(left.as_ref(), ASTNode::Identifier(left) if left == name)
+                    && !self.temporal_manager.has_timeline(name)
+                    && Self::runs_no_code(right)
+                    && matches!(self.lookup(name), Some(FluxValue::String(_)))
+                {
+                    let tail = self.eval(right)?.to_display_string();
+                    if let Some(FluxValue::String(text)) = self.lookup_mut(name) {
+                        text.push_str(&tail);
+                        let grown = std::mem::size_of::<FluxValue>() + text.len();
+                        self.check_bytes(grown)?;
+                    }
+                    return Ok(FluxValue::Null);
+                }
+                
+                let value = self.eval(value)?;
+                let temporal = self.temporal_manager.has_timeline(name);
+                if temporal {
+                    self.temporal_manager.advance_clock_of(name);
+                    self.temporal_manager.update_temporal_var(name, value.clone())?;
+                }
+                self.assign(name, value)?;
+                if temporal {
+                    self.notify_watchers(name)?;
+                }
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::Watch { var, body } => {
+                self.temporal_manager.add_watcher(var, body.clone())?;
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::ClockDomain(name) => {
+                self.temporal_manager.add_domain(name);
+                Ok(FluxValue::Null)
+            }
+            
+            // Constants are checked by the comptime pass and checked math
+            // was switched on when the program started
+            ASTNode::Pragma(_) => Ok(FluxValue::Null),
+            
+            ASTNode::FunctionDecl { name, params, body, .. } => {
+                self.functions.insert(name.clone(), FluxFunction {
+                    params: params.clone(),
+                    body: body.clone(),
+                });
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::ClassDecl { name, superclass, methods, .. } => {
+                if let Some(parent) = superclass
+                    && !self.classes.contains_key(parent)
+                {
+                    return Err(Interrupt::Error(format!("Unknown superclass '{}' for class '{}'", parent, name)));
+                }
+                
+                let fields = FluxClass::declared_fields(methods);
+                let methods = methods.iter()
+                    .filter_map(|method| match method {
+                        ASTNode::FunctionDecl { name, params, body, .. } => Some((name.clone(), FluxFunction {
+                            params: params.clone(),
+                            body: body.clone(),
+                        })),
+                        _ => None,
+                    })
+                    .collect();
+                self.classes.insert(name.clone(), FluxClass { superclass: superclass.clone(), methods, fields });
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::New { class, args } => {
+                if class == Self::STRING_BUILDER && !self.classes.contains_key(class) {
+                    return self.new_string_builder(args);
+                }
+                if !self.classes.contains_key(class) {
+                    return Err(Interrupt::Error(format!("Unknown class '{}'", class)));
+                }
+                let values = self.eval_values(args)?;
+                let handle = self.allocate(HeapObject { class: class.clone(), fields: HashMap::new(), frozen: false }, &values)?;
+                let instance = FluxValue::Instance(handle);
+                match self.find_method(class, "init") {
+                    Some(init) => {
+                        self.invoke(&format!("{}.init", class), init, values, Some(instance.clone()))?;
+                    }
+                    None if !values.is_empty() => {
+                        return Err(Interrupt::Error(format!(
+                            "Class '{}' has no init method but was given {} arguments", class, values.len())));
+                    }
+                    None => {}
+                }
+                Ok(instance)
+            }
+            
+            ASTNode::FieldAssign { object, field, value } => {
+                let object = self.eval(object)?;
+                let (object, value) = self.eval_holding(object, value)?;
+                let FluxValue::Instance(handle) = object else {
+                    return Err(Interrupt::Error(format!("Cannot set field '{}' on a non-instance", field)));
+                };
+                self.runtime().set_field(handle, field, value)?;
+                self.check_heap(&[FluxValue::Instance(handle)])?;
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::Return(value) => {
+                let value = self.eval(value)?;
+                Err(Interrupt::Return(value))
+            }
+            
+            ASTNode::If { condition, then_branch, else_ifs, else_branch } => {
+                if self.eval(condition)?.is_truthy() {
+                    return self.eval_scoped(then_branch);
+                }
+                for (condition, body) in else_ifs {
+                    if self.eval(condition)?.is_truthy() {
+                        return self.eval_scoped(body);
+                    }
+                }
+                match else_branch {
+                    Some(else_stmts) => self.eval_scoped(else_stmts),
+                    None => Ok(FluxValue::Null),
+                }
+            }
+            
+            ASTNode::While { condition, body } => {
+                while self.eval(condition)?.is_truthy() {
+                    match self.eval_scoped(body) {
+                        Err(Interrupt::Break(None)) => break,
+                        result => { result?; }
+                    }
+                }
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::Loop(body) => {
+                loop {
+                    match self.eval_scoped(body) {
+                        Err(Interrupt::Break(None)) => break,
+                        result => { result?; }
+                    }
+                }
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::DoWhile { body, condition } => {
+                loop {
+                    match self.eval_scoped(body) {
+                        Err(Interrupt::Break(None)) => break,
+                        result => { result?; }
+                    }
+                    if !self.eval(condition)?.is_truthy() {
+                        break;
+                    }
+                }
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::Labeled { label, body } => match self.eval(body) {
+                Err(Interrupt::Break(Some(target))) if target == *label => Ok(FluxValue::Null),
+                result => result,
+            },
+            
+            ASTNode::Break(label) => Err(Interrupt::Break(label.clone())),
+            
+            ASTNode::Binary { left, operator, right } => {
+                let left = self.eval(left)?;
+                
+                // Short-circuit logical operators
+                match operator.as_str() {
+                    "&&" if !left.is_truthy() => return Ok(FluxValue::Boolean(false)),
+                    "||" if left.is_truthy() => return Ok(FluxValue::Boolean(true)),
+                    _ => {}
+                }
+                
+                let (left, right) = self.eval_holding(left, right)?;
+                if !self.checked_math {
+                    return self.check_size(Self::binary_op(operator, left, right)?);
+                }
+                if matches!(operator.as_str(), "/" | "%") && right == FluxValue::Number(0.0) {
+                    return Err(Interrupt::Error(format!("Division by zero in `{}`", node)));
+                }
+                match Self::binary_op(operator, left, right)? {
+                    FluxValue::Number(n) if n.is_nan() => Err(Interrupt::Error(format!("`{}` produced NaN", node))),
+                    FluxValue::Number(n) if n.is_infinite() => {
+                        Err(Interrupt::Error(format!("`{}` overflowed to infinity", node)))
+                    }
+                    result => self.check_size(result),
+                }
+            }
+            
+            ASTNode::Unary { operator, operand } => {
+                let operand = self.eval(operand)?;
+                match (operator.as_str(), operand) {
+                    ("-", FluxValue::Number(n)) => Ok(FluxValue::Number(-n)),
+                    ("!", value) => Ok(FluxValue::Boolean(!value.is_truthy())),
+                    (op, value) => Err(Interrupt::Error(format!(
+                        "Cannot apply unary '{}' to {}", op, value.type_name()))),
+                }
+            }
+            
+            ASTNode::Call { callee, args } => {
+                if let ASTNode::MemberAccess { object, property } = callee.as_ref() {
+                    return self.call_method(object, property, args);
+                }
+                
+                let ASTNode::Identifier(name) = callee.as_ref() else {
+                    return Err(Interrupt::Error("Only named functions can be called".to_string()));
+                };
+                self.check_allowed(name)?;
+                
+                if matches!(name.as_str(), "history" | "changes" | "value_at")
+                    && !self.functions.contains_key(name)
+                {
+                    return self.temporal_builtin(name, args);
+                }
+                
+                // Compiler hint; every timeline is kept here anyway
+                if name == "keep_timeline" && !self.functions.contains_key(name) {
+                    return Ok(FluxValue::Null);
+                }
+                
+                if matches!(name.as_str(), "freeze" | "is_frozen") && !self.functions.contains_key(name) {
+                    let [arg] = args.as_slice() else {
+                        return Err(Interrupt::Error(format!("{}() takes exactly 1 argument, found {}", name, args.len())));
+                    };
+                    let value = self.eval(arg)?;
+                    if name == "is_frozen" {
+                        return Ok(FluxValue::Boolean(self.runtime().is_frozen(&value)));
+                    }
+                    self.runtime().freeze(&value);
+                    return Ok(FluxValue::Null);
+                }
+                
+                if matches!(name.as_str(), "join" | "channel" | "send" | "recv")
+                    && !self.functions.contains_key(name)
+                {
+                    let values = self.eval_values(args)?;
+                    return self.task_builtin(name, values);
+                }
+                
+                let values = self.eval_values(args)?;
+                self.call_function(name, values)
+            }
+            
+            ASTNode::MemberAccess { object, property } => {
+                match self.eval(object)? {
+                    FluxValue::Object(fields) => fields.get(property)
+                        .cloned()
+                        .ok_or_else(|| Interrupt::Error(format!("Unknown property '{}'", property))),
+                    FluxValue::Instance(handle) => {
+                        let runtime = self.runtime();
+                        let object = runtime.get(handle)
+                            .ok_or_else(|| Interrupt::Error(format!("Dangling object reference #{}", handle)))?;
+                        object.fields.get(property).cloned().ok_or_else(|| Interrupt::Error(format!(
+                            "Instance of '{}' has no field '{}'", object.class, property)))
+                    }
+                    other => Err(Interrupt::Error(format!(
+                        "Cannot access property '{}' on {}", property, other.type_name()))),
+                }
+            }
+            
+            ASTNode::Number(n) => Ok(FluxValue::Number(*n)),
+            ASTNode::String(s) => Ok(FluxValue::String(s.clone())),
+            ASTNode::Boolean(b) => Ok(FluxValue::Boolean(*b)),
+            
+            ASTNode::Identifier(name) => self.lookup(name)
+                .cloned()
+                .ok_or_else(|| Interrupt::Error(format!(
+                    "Undefined variable '{}'{}", name, self.did_you_mean_variable(name)))),
+            
+            ASTNode::TemporalAccess { var, timestamp } => self.value_at(var, timestamp),
+            
+            // Parsed apart from its declaration (e.g. a later REPL line), a
+            // temporal variable's `x[i]` still means temporal access
+            ASTNode::Index { object, index } => match object.as_ref() {
+                ASTNode::Identifier(var) if self.temporal_manager.has_timeline(var) => self.value_at(var, index),
+                _ => {
+                    let object = self.eval(object)?;
+                    let (object, index) = self.eval_holding(object, index)?;
+                    Ok(Self::index(object, index)?)
+                }
+            },
+            
+            ASTNode::Pipeline(stages) => {
+                let Some((first, rest)) = stages.split_first() else {
+                    return Err(Interrupt::Error("Empty pipeline".to_string()));
+                };
+                
+                let mut value = self.eval(first)?;
+                for stage in rest {
+                    value = match stage {
+                        ASTNode::ParallelStage(inner) => {
+                            let (name, extra) = self.pipeline_stage(inner)?;
+                            match value {
+                                FluxValue::Array(items) => FluxValue::Array(self.parallel_map(&name, items, extra)?),
+                                value => self.call_stage(&name, value, extra)?,
+                            }
+                        }
+                        _ => {
+                            self.temporaries.push(value);
+                            let stage = self.pipeline_stage(stage);
+                            let value = self.temporaries.pop().unwrap_or(FluxValue::Null);
+                            let (name, extra) = stage?;
+                            self.call_stage(&name, value, extra)?
+                        }
+                    };
+                }
+                Ok(value)
+            }
+            
+            ASTNode::Match { expr, cases } => {
+                let subject = self.eval(expr)?;
+                
+                for (pattern, body) in cases {
+                    let matched = match pattern {
+                        ASTNode::Identifier(name) if name == "default" => true,
+                        ASTNode::Range { .. } | ASTNode::Alternatives(_) => {
+                            // The desugared comparisons read the subject
+                            // from a name no program can spell
+                            let condition = PatternMatcher::condition(&ASTNode::Identifier(PatternMatcher::SUBJECT.to_string()), pattern);
+                            self.scopes.push(HashMap::from([(PatternMatcher::SUBJECT.to_string(), subject.clone())]));
+                            let matched = self.eval(&condition);
+                            self.scopes.pop();
+                            matched?.is_truthy()
+                        }
+                        _ => self.eval(pattern)? == subject,
+                    };
+                    if matched {
+                        return self.eval_scoped(body);
+                    }
+                }
+                Ok(FluxValue::Null)
+            }
+            
+            ASTNode::Comptime(body) => self.eval_scoped(body),
+            
+            ASTNode::Array(items) => {
+                let items = self.eval_values(items)?;
+                self.check_size(FluxValue::Array(items))
+            }
+            
+            ASTNode::ParallelStage(_) => {
+                Err(Interrupt::Error("'||>' stages can only appear in a pipeline".to_string()))
+            }
+            
+            ASTNode::Range { .. } | ASTNode::Alternatives(_) => {
+                Err(Interrupt::Error(format!("'{}' can only appear as a match pattern", node)))
+            }
+            
+            ASTNode::Spawn(body) => {
+                let mut task = self.snapshot();
+                let body = body.clone();
+                let id = self.scheduler.spawn(move || task.run_task(&body));
+                Ok(FluxValue::Task(id))
+            }
+        }
+    }
+    
+    /// Introspection builtins take the temporal variable itself, not its value
+    fn temporal_builtin(&mut self, name: &str, args: &[ASTNode]) -> Result<FluxValue, Interrupt> {
+        let var = match args.first() {
+            Some(ASTNode::Identifier(var)) if self.temporal_manager.has_timeline(var) => var,
+            _ => return Err(Interrupt::Error(format!("{}() expects a temporal variable", name))),
+        };
+        
+        match (name, &args[1..]) {
+            ("history", []) => Ok(FluxValue::Array(self.temporal_manager.history(var).unwrap_or_default())),
+            ("changes", []) => Ok(FluxValue::Number(
+                self.temporal_manager.changes(var).unwrap_or_default() as f64)),
+            ("value_at", [timestamp]) => self.value_at(&var.clone(), timestamp),
+            _ => Err(Interrupt::Error(format!("Wrong number of arguments to {}()", name))),
+        }
+    }
+    
+    /// Builtins whose results come from outside the program, through the
+    /// input trace so they can be recorded and replayed
+    fn input_builtin(&mut self, name: &str, args: &[FluxValue]) -> Result<FluxValue, String> {
+        let mut inputs = self.inputs.lock().unwrap();
+        let event = match (name, args) {
+            ("time", []) => inputs.next(name, |event| matches!(event, TraceEvent::Time(_)), |_| {
+                let elapsed = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default();
+                TraceEvent::Time(elapsed.as_secs_f64())
+            })?,
+            ("random", []) => inputs.next(name, |event| matches!(event, TraceEvent::Random(_)),
+                                          |inputs| TraceEvent::Random(inputs.random()))?,
+            ("input", []) => inputs.next(name, |event| matches!(event, TraceEvent::Input(_)), |_| {
+                let mut line = String::new();
+                match std::io::stdin().read_line(&mut line) {
+                    Ok(0) | Err(_) => TraceEvent::Input(None),
+                    Ok(_) => {
+                        let trimmed = line.strip_suffix('\n').unwrap_or(&line);
+                        TraceEvent::Input(Some(trimmed.strip_suffix('\r').unwrap_or(trimmed).to_string()))
+                    }
+                }
+            })?,
+            ("read_file", [FluxValue::String(path)]) => inputs.next(name,
+                |event| matches!(event, TraceEvent::ReadFile { path: recorded, .. } if recorded == path),
+                |_| TraceEvent::ReadFile {
+                    path: path.clone(),
+                    contents: fs::read_to_string(path).map_err(|e| e.to_string()),
+                })?,
+            ("read_file", [_]) => return Err("read_file() expects a path string".to_string()),
+            _ => return Err(format!("Wrong number of arguments to {}()", name)),
+        };
+        
+        match event {
+            TraceEvent::Time(value) | TraceEvent::Random(value) => Ok(FluxValue::Number(value)),
+            TraceEvent::Input(line) => Ok(line.map_or(FluxValue::Null, FluxValue::String)),
+            TraceEvent::ReadFile { path, contents } => contents
+                .map(FluxValue::String)
+                .map_err(|error| format!("Cannot read file '{}': {}", path, error)),
+        }
+    }
+    
+    /// Copy of the visible state for code running on another thread;
+    /// tasks see a snapshot and can't mutate the spawner's variables
+    fn snapshot(&self) -> Interpreter {
+        Interpreter {
+            scopes: vec![self.scopes.iter().flatten()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect()],
+            suspended: Vec::new(),
+            temporaries: Vec::new(),
+            functions: self.functions.clone(),
+            classes: self.classes.clone(),
+            runtime: Arc::clone(&self.runtime),
+            builtins: self.builtins.clone(),
+            host_functions: self.host_functions.clone(),
+            temporal_manager: self.temporal_manager.clone(),
+            notifying: HashSet::new(),
+            scheduler: Arc::clone(&self.scheduler),
+            captured: self.captured.clone(),
+            numeric_format: self.numeric_format,
+            checked_math: self.checked_math,
+            exit_code: None,
+            inputs: Arc::clone(&self.inputs),
+            deferred: Vec::new(),
+            sandbox: Arc::clone(&self.sandbox),
+            call_depth: 0,
+        }
+    }
+    
+    /// Resolves a pipeline stage to the function it calls and any extra
+    /// arguments, which follow the piped value
+    fn pipeline_stage(&mut self, stage: &ASTNode) -> Result<(String, Vec<FluxValue>), Interrupt> {
+        match stage {
+            ASTNode::Identifier(name) => Ok((name.clone(), Vec::new())),
+            ASTNode::Call { callee, args } => {
+                let ASTNode::Identifier(name) = callee.as_ref() else {
+                    return Err(Interrupt::Error("Pipeline stage must be a function".to_string()));
+                };
+                Ok((name.clone(), self.eval_values(args)?))
+            }
+            _ => Err(Interrupt::Error("Pipeline stage must be a function".to_string())),
+        }
+    }
+    
+    fn call_stage(&mut self, name: &str, value: FluxValue, extra: Vec<FluxValue>) -> Result<FluxValue, Interrupt> {
+        let mut args = vec![value];
+        args.extend(extra);
+        self.call_function(name, args)
+    }
+    
+    /// Applies a stage to every item, splitting the array into one chunk per
+    /// worker; values are immutable, so each chunk can run on a snapshot
+    fn parallel_map(&mut self, name: &str, items: Vec<FluxValue>, extra: Vec<FluxValue>) -> Result<Vec<FluxValue>, Interrupt> {
+        let chunk_size = items.len().div_ceil(self.scheduler.workers()).max(1);
+        let mut tasks = Vec::new();
+        
+        for chunk in items.chunks(chunk_size) {
+            let mut worker = self.snapshot();
+            let chunk = chunk.to_vec();
+            let name = name.to_string();
+            let extra = extra.clone();
+            tasks.push(self.scheduler.spawn(move || {
+                let mut mapped = Vec::with_capacity(chunk.len());
+                for item in chunk {
+                    match worker.call_stage(&name, item, extra.clone()) {
+                        Ok(value) | Err(Interrupt::Return(value)) => mapped.push(value),
+                        Err(Interrupt::Exit(code)) => return Err(Interrupt::Exit(code)),
+                        Err(interrupt) => return Err(Interrupt::Error(interrupt.into_error())),
+                    }
+                }
+                Ok(FluxValue::Array(mapped))
+            }));
+        }
+        
+        // Join every chunk before reporting the first failure
+        let results: Vec<_> = tasks.into_iter().map(|task| self.scheduler.join(task)).collect();
+        let mut mapped = Vec::with_capacity(items.len());
+        for result in results {
+            match result? {
+                FluxValue::Array(part) => mapped.extend(part),
+                other => mapped.push(other),
+            }
+        }
+        Ok(mapped)
+    }
+    
+    /// Builtins backed by the shared task scheduler
+    fn task_builtin(&mut self, name: &str, args: Vec<FluxValue>) -> Result<FluxValue, Interrupt> {
+        match (name, args.as_slice()) {
+            // A task that called `exit` ends the joiner too
+            ("join", [FluxValue::Task(id)]) => self.scheduler.join(*id),
+            ("join", [_]) => Err("join() expects a task handle from spawn".to_string().into()),
+            ("channel", []) => Ok(FluxValue::Channel(self.scheduler.channel())),
+            ("send", [FluxValue::Channel(id), value]) => {
+                self.scheduler.send(*id, value.clone())?;
+                Ok(FluxValue::Boolean(true))
+            }
+            ("recv", [FluxValue::Channel(id)]) => Ok(self.scheduler.recv(*id)?),
+            ("send" | "recv", [_, ..]) => Err(format!("{}() expects a channel as its first argument", name).into()),
+            _ => Err(format!("Wrong number of arguments to {}()", name).into()),
+        }
+    }
+    
+    fn notify_watchers(&mut self, name: &str) -> Result<(), Interrupt> {
+        if !self.notifying.insert(name.to_string()) {
+            return Ok(());
+        }
+        
+        let mut result = Ok(());
+        for body in self.temporal_manager.watchers(name) {
+            // `return` ends the watcher rather than the surrounding code
+            match self.eval_scoped(&body) {
+                Ok(_) | Err(Interrupt::Return(_)) => {}
+                Err(interrupt) => {
+                    result = Err(Interrupt::Error(interrupt.into_error()));
+                    break;
+                }
+            }
+        }
+        
+        self.notifying.remove(name);
+        result
+    }
+    
+    fn value_at(&mut self, var: &str, timestamp: &ASTNode) -> Result<FluxValue, Interrupt> {
+        let time = match self.eval(timestamp)? {
+            FluxValue::Number(time) => time,
+            FluxValue::String(label) => self.temporal_manager.checkpoint_time_for(&label, var)
+                .ok_or_else(|| Interrupt::Error(format!("Checkpoint '{}' has not been reached", label)))?
+                as f64,
+            _ => return Err(Interrupt::Error("Temporal index must be a number or checkpoint name".to_string())),
+        };
+        
+        self.temporal_manager.get_at_time(var, time as i64)
+            .ok_or_else(|| Interrupt::Error(if time < 0.0 {
+                format!("Variable '{}' has fewer than {} past values", var, -time)
+            } else {
+                format!("Variable '{}' has no value at time {}", var, time)
+            }))
+    }
+    
+    /// Calls a user-defined function or builtin by name
+    pub fn call_function(&mut self, name: &str, args: Vec<FluxValue>) -> Result<FluxValue, Interrupt> {
+        if let Some(function) = self.functions.get(name).cloned() {
+            return self.invoke(name, function, args, None);
+        }
+        
+        self.check_allowed(name)?;
+        
+        // Output builtins depend on the interpreter's numeric format and
+        // capture state, so they're handled here rather than in FluxStdLib
+        let line = match name {
+            "print" => Some(FluxStdLib::print_text_with(&args, self.numeric_format)),
+            "println" => match FluxStdLib::format_with(args.clone(), self.numeric_format)? {
+                FluxValue::String(text) => Some(text),
+                _ => unreachable!("format() always produces a string"),
+            },
+            "set_precision" => {
+                let [FluxValue::Number(digits)] = args.as_slice() else {
+                    return Err(Interrupt::Error("set_precision() takes one number".to_string()));
+                };
+                self.numeric_format = NumericFormat::from_precision_code(*digits);
+                return Ok(FluxValue::Boolean(true));
+            }
+            "checkpoint" => {
+                let [FluxValue::String(label)] = args.as_slice() else {
+                    return Err(Interrupt::Error("checkpoint() takes one label string".to_string()));
+                };
+                return Ok(FluxValue::Number(self.temporal_manager.checkpoint(label) as f64));
+            }
+            "snapshot" => {
+                // `snapshot(true)` also saves the plain globals
+                let plain = match args.as_slice() {
+                    [] => HashMap::new(),
+                    [flag] if flag.is_truthy() => self.scopes[0].iter()
+                        .filter(|(name, _)| !self.temporal_manager.has_timeline(name))
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect(),
+                    [_] => HashMap::new(),
+                    _ => return Err(Interrupt::Error("snapshot() takes at most one argument".to_string())),
+                };
+                return Ok(FluxValue::Number(self.temporal_manager.take_snapshot(plain) as f64));
+            }
+            "exit" => {
+                let [FluxValue::Number(code)] = args.as_slice() else {
+                    return Err(Interrupt::Error("exit() takes one number".to_string()));
+                };
+                return Err(Interrupt::Exit(*code as i32));
+            }
+            "panic" => {
+                let [message] = args.as_slice() else {
+                    return Err(Interrupt::Error("panic() takes one message".to_string()));
+                };
+                return Err(Interrupt::Error(format!("panic: {}", message)));
+            }
+            "rollback" => {
+                let [FluxValue::Number(handle)] = args.as_slice() else {
+                    return Err(Interrupt::Error("rollback() expects a handle from snapshot()".to_string()));
+                };
+                let (restored, plain) = self.temporal_manager.rollback(*handle as usize)?;
+                for (name, value) in plain {
+                    self.set_global(&name, value);
+                }
+                for name in &restored {
+                    // A timeline can outlive the function that declared it
+                    if let Some(value) = self.temporal_manager.current_value(name)
+                        && self.lookup(name).is_some()
+                    {
+                        self.assign(name, value)?;
+                    }
+                }
+                for name in restored {
+                    self.notify_watchers(&name)?;
+                }
+                return Ok(FluxValue::Boolean(true));
+            }
+            _ => None,
+        };
+        if let Some(line) = line {
+            match &self.captured {
+                Some(captured) => {
+                    let mut captured = captured.lock().unwrap();
+                    captured.push_str(&line);
+                    captured.push('\n');
+                }
+                None => println!("{}", line),
+            }
+            return Ok(FluxValue::Boolean(true));
+        }
+        
+        if let Some(host) = self.host_functions.get(name) {
+            let result = host(&args).map_err(Interrupt::Error)?;
+            return self.check_size(result);
+        }
+        
+        if matches!(name, "time" | "random" | "input" | "read_file") {
+            let result = self.input_builtin(name, &args).map_err(Interrupt::Error)?;
+            return self.check_size(result);
+        }
+        
+        if matches!(name, "typeof" | "fields" | "methods") {
+            return self.reflect(name, &args).map_err(Interrupt::Error);
+        }
+        
+        if let Some(builtin) = self.builtins.get(name) {
+            let result = builtin(args).map_err(Interrupt::Error)?;
+            return self.check_size(result);
+        }
+        
+        let candidates = self.functions.keys()
+            .chain(self.builtins.keys())
+            .chain(self.host_functions.keys())
+            .map(String::as_str)
+            .chain(Self::INTRINSICS.iter().copied());
+        Err(Interrupt::Error(format!("Undefined function '{}'{}", name, Diagnostic::did_you_mean(name, candidates))))
+    }
+    
+    /// `typeof(x)` names an instance's class, or else the value's type;
+    /// `fields(x)` and `methods(x)` list what an instance or object holds
+    /// and what its class, or the class named by a string, can do
+    fn reflect(&self, name: &str, args: &[FluxValue]) -> Result<FluxValue, String> {
+        let [value] = args else {
+            return Err(format!("{}() takes exactly 1 argument, found {}", name, args.len()));
+        };
+        let class = match value {
+            FluxValue::Instance(handle) => Some(self.runtime().get(*handle)
+                .map(|object| object.class.clone())
+                .ok_or_else(|| format!("Dangling object reference #{}", handle))?),
+            _ => None,
+        };
+        let names = |names: Vec<String>| FluxValue::Array(names.into_iter().map(FluxValue::String).collect());
+        
+        match (name, value) {
+            ("typeof", _) => Ok(FluxValue::String(class.unwrap_or_else(|| value.type_name().to_string()))),
+            ("fields", FluxValue::Instance(handle)) => {
+                let mut present: Vec<String> = self.runtime().get(*handle)
+                    .map(|object| object.fields.keys().cloned().collect())
+                    .unwrap_or_default();
+                present.sort();
+                // Declared fields first, parents before children, then any
+                // added from outside the class
+                let mut fields: Vec<String> = Vec::new();
+                for class in self.class_chain(class.as_deref().unwrap_or_default()).into_iter().rev() {
+                    for field in &class.fields {
+                        if present.contains(field) && !fields.contains(field) {
+                            fields.push(field.clone());
+                        }
+                    }
+                }
+                present.retain(|field| !fields.contains(field));
+                fields.extend(present);
+                Ok(names(fields))
+            }
+            ("fields", FluxValue::Object(map)) => {
+                let mut fields: Vec<String> = map.keys().cloned().collect();
+                fields.sort();
+                Ok(names(fields))
+            }
+            ("methods", FluxValue::Instance(_) | FluxValue::String(_)) => {
+                let class = class.unwrap_or_else(|| value.to_display_string());
+                if class == Self::STRING_BUILDER && !self.classes.contains_key(&class) {
+                    return Ok(names(["append", "clear", "length", "to_string"].map(String::from).to_vec()));
+                }
+                if !self.classes.contains_key(&class) {
+                    return Err(format!("methods() found no class named '{}'", class));
+                }
+                let mut methods: Vec<String> = self.class_chain(&class).into_iter()
+                    .flat_map(|class| class.methods.keys().cloned())
+                    .collect();
+                methods.sort();
+                methods.dedup();
+                Ok(names(methods))
+            }
+            _ => Err(format!("{}() expects an instance{}, found {}", name,
+                             if name == "fields" { " or object" } else { " or class name" }, value.type_name())),
+        }
+    }
+    
+    /// `class` followed by its superclasses
+    fn class_chain(&self, class: &str) -> Vec<&FluxClass> {
+        let mut chain = Vec::new();
+        let mut current = self.classes.get(class);
+        while let Some(class) = current {
+            chain.push(class);
+            current = class.superclass.as_ref().and_then(|parent| self.classes.get(parent));
+        }
+        chain
+    }
+    
+    /// Runs `body`, then the expressions it deferred, newest first. They run
+    /// after returns and errors too, but not after `exit`; the first error
+    /// wins, so a failing defer can't hide the one that triggered it.
+    fn with_defers(&mut self, body: impl FnOnce(&mut Self) -> Result<FluxValue, Interrupt>) -> Result<FluxValue, Interrupt> {
+        self.deferred.push(Vec::new());
+        let mut result = body(self);
+        let deferred = self.deferred.pop().unwrap_or_default();
+        if matches!(result, Err(Interrupt::Exit(_))) || deferred.is_empty() {
+            return result;
+        }
+        // The value being returned must outlive anything the defers allocate
+        let mark = self.temporaries.len();
+        if let Ok(value) | Err(Interrupt::Return(value)) = &result {
+            self.temporaries.push(value.clone());
+        }
+        for value in deferred.iter().rev() {
+            if let Err(interrupt) = self.eval(value)
+                && !matches!(result, Err(Interrupt::Error(_) | Interrupt::Exit(_)))
+            {
+                result = Err(interrupt);
+            }
+        }
+        self.temporaries.truncate(mark);
+        result
+    }
+    
+    /// Runs a user-defined function or method; methods see `this`
+    fn invoke(&mut self, name: &str, function: FluxFunction, args: Vec<FluxValue>, this: Option<FluxValue>) -> Result<FluxValue, Interrupt> {
+        if function.params.len() != args.len() {
+            return Err(Interrupt::Error(format!(
+                "Function '{}' expects {} arguments, found {}",
+                name, function.params.len(), args.len())));
+        }
+        
+        if self.call_depth >= self.sandbox.max_call_depth() {
+            return Err(Interrupt::Error(self.sandbox.exceed(LimitExceeded::CallDepth(self.sandbox.max_call_depth()))));
+        }
+        
+        // Each call sees the globals plus its own fresh locals
+        let mut locals: HashMap<String, FluxValue> = function.params.into_iter().zip(args).collect();
+        if let Some(this) = this {
+            locals.insert("this".to_string(), this);
+        }
+        let saved = self.scopes.split_off(1);
+        self.suspended.push(saved);
+        self.scopes.push(locals);
+        self.call_depth += 1;
+        let result = self.with_defers(|interpreter| interpreter.eval_block(&function.body));
+        self.call_depth -= 1;
+        self.scopes.truncate(1);
+        self.scopes.extend(self.suspended.pop().unwrap_or_default());
+        
+        match result {
+            Ok(_) => Ok(FluxValue::Null),
+            Err(Interrupt::Return(value)) => Ok(value),
+            Err(exit @ Interrupt::Exit(_)) => Err(exit),
+            Err(interrupt) => Err(Interrupt::Error(interrupt.into_error())),
+        }
+    }
+    
+    fn find_method(&self, class: &str, method: &str) -> Option<FluxFunction> {
+        let mut current = self.classes.get(class);
+        while let Some(class) = current {
+            if let Some(function) = class.methods.get(method) {
+                return Some(function.clone());
+            }
+            current = class.superclass.as_ref().and_then(|parent| self.classes.get(parent));
+        }
+        None
+    }
+    
+    fn call_method(&mut self, object: &ASTNode, method: &str, args: &[ASTNode]) -> Result<FluxValue, Interrupt> {
+        let receiver = self.eval(object)?;
+        let FluxValue::Instance(handle) = receiver else {
+            return Err(Interrupt::Error(format!("Cannot call method '{}' on {}", method, receiver.type_name())));
+        };
+        let class = self.runtime().get(handle)
+            .map(|object| object.class.clone())
+            .ok_or_else(|| Interrupt::Error(format!("Dangling object reference #{}", handle)))?;
+        self.temporaries.push(receiver);
+        let values = self.eval_values(args);
+        let receiver = self.temporaries.pop().unwrap_or(FluxValue::Null);
+        let values = values?;
+        if class == Self::STRING_BUILDER && !self.classes.contains_key(&class) {
+            let result = self.string_builder_method(handle, method, values).map_err(Interrupt::Error)?;
+            self.check_heap(&[receiver])?;
+            return Ok(result);
+        }
+        let function = self.find_method(&class, method)
+            .ok_or_else(|| Interrupt::Error(format!("Class '{}' has no method '{}'", class, method)))?;
+        self.invoke(&format!("{}.{}", class, method), function, values, Some(receiver))
+    }
+    
+    /// `new StringBuilder()` or `new StringBuilder(initial)`: a heap object
+    /// whose `text` field grows in place, so building a string from many
+    /// pieces takes time linear in its length
+    fn new_string_builder(&mut self, args: &[ASTNode]) -> Result<FluxValue, Interrupt> {
+        let text = match args {
+            [] => String::new(),
+            [initial] => self.eval(initial)?.to_display_string(),
+            _ => return Err(Interrupt::Error(format!(
+                "{} takes at most one argument, found {}", Self::STRING_BUILDER, args.len()))),
+        };
+        let fields = HashMap::from([("text".to_string(), FluxValue::String(text))]);
+        let handle = self.allocate(HeapObject { class: Self::STRING_BUILDER.to_string(), fields, frozen: false }, &[])?;
+        Ok(FluxValue::Instance(handle))
+    }
+    
+    /// `append(values...)` returns the builder itself so calls can chain
+    fn string_builder_method(&mut self, handle: usize, method: &str, args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        let text = || -> Result<String, String> {
+            match self.runtime().get(handle).and_then(|object| object.fields.get("text")) {
+                Some(FluxValue::String(text)) => Ok(text.clone()),
+                _ => Err(format!("{} #{} has lost its text", Self::STRING_BUILDER, handle)),
+            }
+        };
+        match (method, args.as_slice()) {
+            ("append", _) => {
+                let mut runtime = self.runtime();
+                for arg in &args {
+                    runtime.append_to_field(handle, "text", &arg.to_display_string())?;
+                }
+                Ok(FluxValue::Instance(handle))
+            }
+            ("to_string", []) => text().map(FluxValue::String),
+            ("length", []) => text().map(|text| FluxValue::Number(text.chars().count() as f64)),
+            ("clear", []) => {
+                self.runtime().set_field(handle, "text", FluxValue::String(String::new()))?;
+                Ok(FluxValue::Instance(handle))
+            }
+            ("to_string" | "length" | "clear", _) => Err(format!(
+                "{}.{}() takes no arguments, found {}", Self::STRING_BUILDER, method, args.len())),
+            _ => Err(format!("Class '{}' has no method '{}'", Self::STRING_BUILDER, method)),
+        }
+    }
+    
+    /// Strings index by character, yielding a one-character string
+    fn index(object: FluxValue, index: FluxValue) -> Result<FluxValue, String> {
+        let FluxValue::Number(position) = index else {
+            return Err(format!("Index must be a number, found {}", index.type_name()));
+        };
+        let out_of_range = |length: usize| format!("Index {} out of range for length {}", position, length);
+        if position < 0.0 || position.fract() != 0.0 {
+            return Err(format!("Index {} must be a non-negative whole number", position));
+        }
+        let position = position as usize;
+        
+        match object {
+            FluxValue::String(text) => text.chars()
+                .nth(position)
+                .map(|ch| FluxValue::String(ch.to_string()))
+                .ok_or_else(|| out_of_range(text.chars().count())),
+            FluxValue::Array(items) => {
+                let length = items.len();
+                items.into_iter().nth(position).ok_or_else(|| out_of_range(length))
+            }
+            other => Err(format!("Cannot index into {}", other.type_name())),
+        }
+    }
+    
+    fn binary_op(operator: &str, left: FluxValue, right: FluxValue) -> Result<FluxValue, String> {
+        use FluxValue::{Boolean, Number};
+        
+        match (operator, left, right) {
+            ("+", Number(l), Number(r)) => Ok(Number(l + r)),
+            ("-", Number(l), Number(r)) => Ok(Number(l - r)),
+            ("*", Number(l), Number(r)) => Ok(Number(l * r)),
+            ("/", Number(l), Number(r)) => Ok(Number(l / r)),
+            ("%", Number(l), Number(r)) => Ok(Number(l % r)),
+            ("<", Number(l), Number(r)) => Ok(Boolean(l < r)),
+            (">", Number(l), Number(r)) => Ok(Boolean(l > r)),
+            ("<=", Number(l), Number(r)) => Ok(Boolean(l <= r)),
+            (">=", Number(l), Number(r)) => Ok(Boolean(l >= r)),
+            ("+", FluxValue::String(l), r) => Ok(FluxValue::String(format!("{}{}", l, r.to_display_string()))),
+            ("+", l, FluxValue::String(r)) => Ok(FluxValue::String(format!("{}{}", l.to_display_string(), r))),
+            ("==", l, r) => Ok(Boolean(l == r)),
+            ("!=", l, r) => Ok(Boolean(l != r)),
+            ("&&", l, r) => Ok(Boolean(l.is_truthy() && r.is_truthy())),
+            ("||", l, r) => Ok(Boolean(l.is_truthy() || r.is_truthy())),
+            (op, l, r) => Err(format!("Cannot apply '{}' to {} and {}", 
+                                      op, l.type_name(), r.type_name())),
+        }
+    }
+}
+
+// ============================================================================
+// EMBEDDING API - Flux as a scripting engine for host programs
+// ============================================================================
+
+/// Runs Flux snippets inside a host Rust program. Globals persist between
+/// `eval` calls, and the host can expose native functions to scripts:
+///
+/// ```
+/// let mut engine = flux::Engine::new();
+/// engine.register_fn("host_double", |args| match args {
+///     [flux::FluxValue::Number(n)] => Ok((n * 2.0).into()),
+///     _ => Err("host_double expects a number".to_string()),
+/// });
+/// engine.set("base", 20.0);
+/// assert_eq!(engine.eval("host_double(base) + 2").unwrap(), 42.0.into());
+/// ```
+pub struct Engine {
+    compiler: FluxCompiler,
+    /// Checks each snippet against what earlier ones declared, so `const`
+    /// and `freeze` hold across `eval` calls
+    analyzer: SemanticAnalyzer,
+    interpreter: Interpreter,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            compiler: FluxCompiler::new(false),
+            analyzer: SemanticAnalyzer::new(),
+            interpreter: Interpreter::new(),
+        }
+    }
+    
+    /// Makes `callback` callable from scripts as `name(...)`. Callbacks may
+    /// run on task worker threads, so they must be `Send + Sync`.
+    pub fn register_fn<F>(&mut self, name: &str, callback: F) -> &mut Self
+    where
+        F: Fn(&[FluxValue]) -> Result<FluxValue, String> + Send + Sync + 'static,
+    {
+        self.interpreter.register_host_fn(name, Arc::new(callback));
+        self.analyzer.declare_builtin(name);
+        self
+    }
+    
+    /// Checks and runs a snippet against the engine's state, the way
+    /// `flux run` does a file, returning the value of its last statement.
+    /// A snippet may redeclare an earlier one's variables unless they're
+    /// `const` or frozen
+    pub fn eval(&mut self, source: &str) -> Result<FluxValue, String> {
+        let mut ast = self.compiler.parse(source)?;
+        // A snippet that doesn't check out declares nothing
+        let mut analyzer = self.analyzer.clone();
+        analyzer.release_redeclared(&ast);
+        self.compiler.analyze_with(&mut ast, &mut analyzer)?;
+        self.analyzer = analyzer;
+        self.interpreter.run(&ast)
+    }
+    
+    pub fn get(&self, name: &str) -> Option<FluxValue> {
+        self.interpreter.globals().get(name).cloned()
+    }
+    
+    /// Defines or overwrites a global visible to later snippets
+    pub fn set(&mut self, name: &str, value: impl Into<FluxValue>) {
+        self.analyzer.declare_global(name);
+        self.interpreter.set_global(name, value.into());
+    }
+    
+    /// Limits every later `eval` and `call`, each of which gets a fresh
+    /// budget
+    pub fn set_limits(&mut self, limits: Limits) -> &mut Self {
+        self.interpreter.set_limits(limits);
+        self
+    }
+    
+    /// Which limit stopped the last `eval` or `call`, if one did
+    pub fn limit_exceeded(&self) -> Option<LimitExceeded> {
+        self.interpreter.limit_exceeded()
+    }
+    
+    /// Calls a Flux function (or builtin) defined by earlier snippets
+    pub fn call(&mut self, name: &str, args: Vec<FluxValue>) -> Result<FluxValue, String> {
+        self.interpreter.fresh_budget();
+        self.interpreter.sandboxed(|interpreter| interpreter.call_function(name, args))
+            .map_err(Interrupt::into_error)
+    }
+    
+    /// Collects `print`/`println` output for `take_output` instead of
+    /// writing it to stdout
+    pub fn capture_output(&mut self) -> &mut Self {
+        self.interpreter.capture_output();
+        self
+    }
+    
+    pub fn take_output(&mut self) -> String {
+        self.interpreter.take_output()
+    }
+}
+
+// ============================================================================
+// COMPILE-TIME EVALUATION
+// ============================================================================
+
+/// Folds `comptime { ... }` blocks and pure `const` initializers into literals
+/// by running them through the interpreter before code generation
+pub struct ComptimeEvaluator {
+    interpreter: Interpreter,
+    functions: HashMap<String, Vec<ASTNode>>,
+    depth: usize,
+    /// Set by `#pragma strict_const`: every later `const` must fold
+    strict_const: bool,
+    /// Folded top-level constants, for the optimizer to propagate
+    constants: HashMap<String, ASTNode>,
+}
+
+impl Default for ComptimeEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ComptimeEvaluator {
+    /// Builtins with side effects that must not run during compilation;
+    /// tasks and channels could block it forever, and `exit`/`panic` would
+    /// end the compiler rather than the program
+    const IMPURE_BUILTINS: &'static [&'static str] = &[
+        "print", "println", "set_precision", "checkpoint", "snapshot", "rollback",
+        "time", "random", "input", "read_file", "channel", "send", "recv", "join",
+        "exit", "panic",
+    ];
+    
+    /// Budget for each block or initializer, so a runaway loop or
+    /// recursion fails the build instead of hanging or crashing it
+    const MAX_STEPS: u64 = 1_000_000;
+    const MAX_CALL_DEPTH: usize = 256;
+    
+    pub fn new() -> Self {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(Limits::new().max_steps(Self::MAX_STEPS).max_call_depth(Self::MAX_CALL_DEPTH));
+        Self {
+            interpreter,
+            functions: HashMap::new(),
+            depth: 0,
+            strict_const: false,
+            constants: HashMap::new(),
+        }
+    }
+    
+    pub fn evaluate(&mut self, ast: &mut ASTNode) -> Result<(), String> {
+        // Under checked math, constants that would fail at run time are
+        // left for run time to report
+        self.interpreter.checked_math = ast.checks_math();
+        self.fold(ast)
+    }
+    
+    /// The top-level `const`s folded so far, with their literal values
+    pub fn constants(&self) -> &HashMap<String, ASTNode> {
+        &self.constants
+    }
+    
+    fn fold(&mut self, node: &mut ASTNode) -> Result<(), String> {
+        match node {
+            ASTNode::Comptime(body) => {
+                let impure = body.iter().find_map(|stmt| self.find_impure_call(stmt, &mut Vec::new()));
+                if let Some(name) = impure {
+                    return Err(format!("comptime block calls impure function '{}'", name));
+                }
+                
+                let value = self.interpreter.run_block(body)
+                    .map_err(|error| self.stopped("comptime block", error))?;
+                *node = Self::to_literal(&value).ok_or_else(|| {
+                    format!("comptime block produced {}, expected a number, string or boolean",
+                            value.type_name())
+                })?;
+                Ok(())
+            }
+            
+            ASTNode::FunctionDecl { name, body, .. } => {
+                self.depth += 1;
+                for stmt in body.iter_mut() {
+                    self.fold(stmt)?;
+                }
+                self.depth -= 1;
+                
+                // Make the (folded) function callable from later comptime code
+                self.functions.insert(name.clone(), body.clone());
+                self.interpreter.run(node)?;
+                Ok(())
+            }
+            
+            ASTNode::Pragma(name) if name == "strict_const" => {
+                self.strict_const = true;
+                Ok(())
+            }
+            
+            ASTNode::VarDecl { name, value, is_const: true, is_temporal, .. } => {
+                self.fold(value)?;
+                
+                if let Some(impure) = self.find_impure_call(value, &mut Vec::new()) {
+                    if self.strict_const {
+                        return Err(format!(
+                            "const '{}' must be a compile-time constant, but its initializer calls impure function '{}'",
+                            name, impure));
+                    }
+                    return Ok(());
+                }
+                
+                // Initializers that depend on runtime values are left as they
+                // are, unless strict_const requires them to fold
+                let folded = self.interpreter.run(value).and_then(|result| match Self::to_literal(&result) {
+                    Some(literal) => Ok((result, literal)),
+                    None => Err(format!("its initializer produced {}", result.type_name())),
+                });
+                match folded {
+                    Ok((result, literal)) => {
+                        **value = literal.clone();
+                        if self.depth == 0 {
+                            self.interpreter.define(name, result);
+                            if !*is_temporal {
+                                self.constants.insert(name.clone(), literal);
+                            }
+                        }
+                    }
+                    Err(reason) if self.interpreter.limit_exceeded().is_some() => {
+                        return Err(self.stopped(&format!("const '{}'", name), reason));
+                    }
+                    Err(reason) if self.strict_const => {
+                        return Err(format!("const '{}' must be a compile-time constant: {}", name, reason));
+                    }
+                    Err(_) => {}
+                }
+                Ok(())
+            }
+            
+            _ => {
+                for child in node.children_mut() {
+                    self.fold(child)?;
+                }
+                Ok(())
+            }
+        }
+    }
+    
+    /// `error` from evaluating `what`, saying so when it hit the budget
+    fn stopped(&self, what: &str, error: String) -> String {
+        match self.interpreter.limit_exceeded() {
+            Some(_) => format!("{} didn't finish at compile time: {}", what, error),
+            None => error,
+        }
+    }
+    
+    /// Returns the name of the first impure function reachable from `node`,
+    /// or `spawn` for a task started there
+    fn find_impure_call(&self, node: &ASTNode, visiting: &mut Vec<String>) -> Option<String> {
+        let called = match node {
+            ASTNode::Spawn(_) => return Some("spawn".to_string()),
+            ASTNode::Call { callee, .. } => match callee.as_ref() {
+                ASTNode::Identifier(name) => Some(name),
+                _ => None,
+            },
+            ASTNode::Pipeline(stages) => stages.iter().skip(1).find_map(|stage| match stage {
+                ASTNode::Identifier(name) => Some(name),
+                ASTNode::ParallelStage(inner) => match inner.as_ref() {
+                    ASTNode::Identifier(name) => Some(name),
+                    _ => None,
+                },
+                _ => None,
+            }),
+            _ => None,
+        };
+        
+        if let Some(name) = called {
+            if Self::IMPURE_BUILTINS.contains(&name.as_str()) {
+                return Some(name.clone());
+            }
+            if let Some(body) = self.functions.get(name)
+                && !visiting.contains(name)
+            {
+                visiting.push(name.clone());
+                if let Some(impure) = body.iter().find_map(|stmt| self.find_impure_call(stmt, visiting)) {
+                    return Some(impure);
+                }
+            }
+        }
+        
+        node.children()
+            .into_iter()
+            .find_map(|child| self.find_impure_call(child, visiting))
+    }
+    
+    fn to_literal(value: &FluxValue) -> Option<ASTNode> {
+        match value {
+            FluxValue::Number(n) => Some(ASTNode::Number(*n)),
+            FluxValue::String(s) => Some(ASTNode::String(s.clone())),
+            FluxValue::Boolean(b) => Some(ASTNode::Boolean(*b)),
+            _ => None,
+        }
+    }
+}